@@ -0,0 +1,414 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The content addressed cache of the runtime binaries built by cargo.
+
+use crate::{to_hex, wasm_project::offline_build, RuntimeTarget, WasmBuilderError};
+
+use sp_crypto_hashing::blake2_256;
+use std::{
+	collections::BTreeMap,
+	env, fs,
+	io::Write,
+	path::{Path, PathBuf},
+	process::{self, Command, Stdio},
+};
+
+/// The size the local cache is reduced to after storing a binary, by removing the least recently
+/// used binaries.
+const MAX_CACHE_SIZE: u64 = 4 * 1024 * 1024 * 1024;
+
+/// Returns the directory of the artifact cache, if the cache directory of the user is known.
+fn cache_dir() -> Option<PathBuf> {
+	env::var_os("XDG_CACHE_HOME")
+		.map(PathBuf::from)
+		.or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+		.map(|dir| dir.join("wasm-builder"))
+}
+
+/// Compute the cache key of a runtime binary that is built with the given `inputs`, e.g. the
+/// manifest and the flags of the build, from the source `files`.
+///
+/// The paths in the inputs and of the files are remapped like with `--remap-path-prefix` by the
+/// `remaps`, so the key doesn't depend on where the sources are checked out when the paths don't
+/// end up in the binary.
+pub(crate) fn cache_key(
+	inputs: &[&[u8]],
+	files: impl IntoIterator<Item = PathBuf>,
+	remaps: &[(PathBuf, &str)],
+) -> Result<String, WasmBuilderError> {
+	let files = files
+		.into_iter()
+		.map(|file| (remapped_path(&file, remaps), file))
+		.collect::<BTreeMap<_, _>>();
+
+	let mut preimage = Vec::new();
+	for input in inputs {
+		let mut input = String::from_utf8_lossy(input).into_owned();
+		// The last matching prefix is used, so the more specific ones are replaced first.
+		for (from, to) in remaps.iter().rev() {
+			input = input.replace(&*from.to_string_lossy(), to);
+		}
+		preimage.extend_from_slice(&blake2_256(input.as_bytes()));
+	}
+	for (remapped, file) in files {
+		let content = fs::read(&file).map_err(WasmBuilderError::io(&file))?;
+		preimage.extend_from_slice(&blake2_256(remapped.as_bytes()));
+		preimage.extend_from_slice(&blake2_256(&content));
+	}
+
+	Ok(to_hex(&blake2_256(&preimage)))
+}
+
+/// Returns `path` with its prefix replaced by the last matching one of the `remaps`.
+fn remapped_path(path: &Path, remaps: &[(PathBuf, &str)]) -> String {
+	remaps
+		.iter()
+		.rev()
+		.find_map(|(from, to)| path.strip_prefix(from).ok().map(|rest| Path::new(to).join(rest)))
+		.unwrap_or_else(|| path.to_path_buf())
+		.to_string_lossy()
+		.into_owned()
+}
+
+/// Check that the file at `path` fetched from a backend is a runtime binary for `target`.
+///
+/// The key only covers the inputs of the build, so this is a sanity check against broken or
+/// unrelated uploads, not a proof that the binary was built from these inputs.
+fn check_fetched_blob(target: RuntimeTarget, path: &Path) -> Result<(), String> {
+	let blob = fs::read(path).map_err(|error| error.to_string())?;
+	match target {
+		RuntimeTarget::Wasm | RuntimeTarget::Wasm64 => {
+			for payload in wasmparser::Parser::new(0).parse_all(&blob) {
+				payload
+					.map_err(|error| format!("The fetched binary is no wasm module: {error}"))?;
+			}
+			Ok(())
+		},
+		RuntimeTarget::Riscv if blob.starts_with(b"PVM\0") => Ok(()),
+		RuntimeTarget::Riscv => Err("The fetched binary is no PolkaVM program.".into()),
+	}
+}
+
+/// Returns the path to the cached runtime binary of the given `key`, if it is cached.
+///
+/// If the binary is not cached locally, it is fetched from the `backend` into the local cache,
+/// unless it is not a binary for `target`. Failing to fetch the binary only results in a warning,
+/// so the runtime is built instead.
+pub(crate) fn lookup(
+	key: &str,
+	target: RuntimeTarget,
+	backend: Option<&dyn ArtifactCache>,
+) -> Option<PathBuf> {
+	let dir = cache_dir()?;
+	let path = dir.join(key);
+	if path.is_file() {
+		// The modification time tracks the last use, see `evict`.
+		let _ = filetime::set_file_mtime(&path, filetime::FileTime::now());
+		return Some(path)
+	}
+
+	let backend = backend?;
+	let tmp_path = dir.join(format!("{key}.{}.tmp", process::id()));
+	let result = fs::create_dir_all(&dir)
+		.map_err(|error| error.to_string())
+		.and_then(|_| backend.fetch(key, &tmp_path))
+		.and_then(|found| match found {
+			true => check_fetched_blob(target, &tmp_path).and_then(|_| {
+				fs::rename(&tmp_path, &path).map(|_| true).map_err(|error| error.to_string())
+			}),
+			false => Ok(false),
+		});
+	let _ = fs::remove_file(&tmp_path);
+
+	match result {
+		Ok(found) => found.then_some(path),
+		Err(error) => {
+			build_helper::warning!("Failed to fetch `{key}` from the artifact cache: {error}");
+			None
+		},
+	}
+}
+
+/// Store the runtime binary at `blob_path` in the cache under the given `key`.
+///
+/// The binary is also stored in the `backend`. Failing to store the binary only results in a
+/// warning, as the cache is an optimization.
+pub(crate) fn store(key: &str, blob_path: &Path, backend: Option<&dyn ArtifactCache>) {
+	if let Err(error) = backend.map_or(Ok(()), |backend| backend.store(key, blob_path)) {
+		build_helper::warning!(
+			"Failed to store `{}` in the artifact cache: {error}",
+			blob_path.display()
+		);
+	}
+
+	let Some(dir) = cache_dir() else { return };
+
+	// Copy to a temporary file first, so that concurrent builds never see a partial binary.
+	let tmp_path = dir.join(format!("{key}.{}.tmp", process::id()));
+	let result = fs::create_dir_all(&dir)
+		.and_then(|_| fs::copy(blob_path, &tmp_path))
+		.and_then(|_| fs::rename(&tmp_path, dir.join(key)));
+	if let Err(error) = result {
+		let _ = fs::remove_file(&tmp_path);
+		build_helper::warning!(
+			"Failed to store `{}` in the artifact cache `{}`: {error}",
+			blob_path.display(),
+			dir.display()
+		);
+	}
+
+	evict(&dir, MAX_CACHE_SIZE);
+}
+
+/// Remove the least recently used binaries from the cache `dir` until it is at most `max_size`
+/// bytes large.
+///
+/// The binaries that are in use by a concurrent build may be removed as well, which is fine as it
+/// only copies them.
+fn evict(dir: &Path, max_size: u64) {
+	let Ok(entries) = fs::read_dir(dir) else { return };
+	let mut binaries = entries
+		.filter_map(|entry| {
+			let entry = entry.ok()?;
+			let metadata = entry.metadata().ok().filter(|m| m.is_file())?;
+			Some((metadata.modified().ok()?, metadata.len(), entry.path()))
+		})
+		.collect::<Vec<_>>();
+	binaries.sort();
+
+	let mut size = binaries.iter().map(|(_, len, _)| len).sum::<u64>();
+	for (_, len, path) in binaries {
+		if size <= max_size {
+			break
+		}
+		if fs::remove_file(&path).is_ok() {
+			size -= len;
+		}
+	}
+}
+
+/// A shared backend of the artifact cache, see
+/// [`WasmBuilder::artifact_cache_backend`](crate::WasmBuilder::artifact_cache_backend).
+///
+/// The runtime binaries are addressed by the hex encoded hash of everything they are built from.
+/// As the key is not derived from the binary itself, the fetched binaries can't be verified
+/// against it and are only checked to be runtime binaries. Everyone who can store binaries in the
+/// backend can therefore replace the runtime, so it must be as trusted as the build machine.
+pub trait ArtifactCache: Send + Sync {
+	/// Fetch the runtime binary with the given `key` into the file at `dest`.
+	///
+	/// Returns whether the binary was found.
+	fn fetch(&self, key: &str, dest: &Path) -> Result<bool, String>;
+
+	/// Store the runtime binary at `blob_path` under the given `key`.
+	fn store(&self, key: &str, blob_path: &Path) -> Result<(), String>;
+
+	/// Check that the binaries the cache runs are installed.
+	///
+	/// This is called before the runtime is built, so a missing binary is reported up front with
+	/// a hint how to install it, instead of failing every request. Nothing is checked by default.
+	fn check(&self) -> Result<(), String> {
+		Ok(())
+	}
+}
+
+/// An [`ArtifactCache`] that fetches and stores the runtime binaries over HTTP using `curl`.
+///
+/// The binary of a key is fetched with `GET <url>/<key>` and stored with `PUT <url>/<key>`. If
+/// the `WASM_BUILD_CACHE_TOKEN` environment variable is set, it is sent as bearer token. No
+/// requests are made for offline builds, i.e. if `CARGO_NET_OFFLINE` is set.
+///
+/// Only `https://` URLs are supported, also for redirects, as anyone on the path of a plain HTTP
+/// connection could replace the runtime.
+#[derive(Clone, Debug)]
+pub struct HttpArtifactCache {
+	url: String,
+}
+
+impl HttpArtifactCache {
+	/// Create a new cache for the given base `url`, e.g. `https://cache.example.com/runtimes`.
+	pub fn new(url: impl Into<String>) -> Self {
+		Self { url: url.into().trim_end_matches('/').into() }
+	}
+
+	/// Run `curl` with the given `args` for the object of `key` and return its output.
+	fn curl(&self, key: &str, args: &[&std::ffi::OsStr]) -> Result<String, String> {
+		let token = env::var(crate::WASM_BUILD_CACHE_TOKEN).ok();
+		let mut cmd = Command::new("curl");
+		cmd.args(["--silent", "--show-error", "--location", "--proto", "=https"])
+			.args(["--proto-redir", "=https"])
+			.args(args);
+		if token.is_some() {
+			// Pass the header on `stdin`, so the token doesn't show up in the process list.
+			cmd.args(["--header", "@-"]).stdin(Stdio::piped());
+		}
+		cmd.arg(format!("{}/{key}", self.url))
+			.stdout(Stdio::piped())
+			.stderr(Stdio::piped());
+
+		let mut child = cmd.spawn().map_err(|error| format!("Failed to run `curl`: {error}"))?;
+		if let (Some(token), Some(mut stdin)) = (token, child.stdin.take()) {
+			stdin
+				.write_all(format!("Authorization: Bearer {token}\n").as_bytes())
+				.map_err(|error| error.to_string())?;
+		}
+
+		let output = child.wait_with_output().map_err(|error| error.to_string())?;
+		if !output.status.success() {
+			return Err(String::from_utf8_lossy(&output.stderr).trim().into())
+		}
+		Ok(String::from_utf8_lossy(&output.stdout).into())
+	}
+}
+
+impl ArtifactCache for HttpArtifactCache {
+	fn fetch(&self, key: &str, dest: &Path) -> Result<bool, String> {
+		if offline_build() {
+			return Ok(false)
+		}
+
+		let status = self.curl(
+			key,
+			&[
+				"--output".as_ref(),
+				dest.as_os_str(),
+				"--write-out".as_ref(),
+				"%{http_code}".as_ref(),
+			],
+		)?;
+		match status.trim() {
+			"200" => Ok(true),
+			"404" => Ok(false),
+			status => Err(format!("`GET {}/{key}` returned the HTTP status {status}", self.url)),
+		}
+	}
+
+	fn check(&self) -> Result<(), String> {
+		if !self.url.starts_with("https://") {
+			return Err(format!("`{}` is not an `https://` URL.", self.url))
+		}
+
+		if offline_build() ||
+			Command::new("curl")
+				.arg("--version")
+				.output()
+				.map_or(false, |o| o.status.success())
+		{
+			return Ok(())
+		}
+
+		Err("`curl` is not installed, install it with the package manager of the system.".into())
+	}
+
+	fn store(&self, key: &str, blob_path: &Path) -> Result<(), String> {
+		if offline_build() {
+			return Ok(())
+		}
+
+		self.curl(key, &["--fail".as_ref(), "--upload-file".as_ref(), blob_path.as_os_str()])
+			.map(drop)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn remapped_path_uses_last_matching_prefix() {
+		let remaps =
+			[(PathBuf::from("/home/user"), "/cargo"), (PathBuf::from("/home/user/src"), "/build")];
+
+		assert_eq!(remapped_path(Path::new("/home/user/src/lib.rs"), &remaps), "/build/lib.rs");
+		assert_eq!(
+			remapped_path(Path::new("/home/user/registry/a.rs"), &remaps),
+			"/cargo/registry/a.rs"
+		);
+		assert_eq!(remapped_path(Path::new("/other/lib.rs"), &remaps), "/other/lib.rs");
+		assert_eq!(
+			remapped_path(Path::new("/home/username/lib.rs"), &remaps),
+			"/home/username/lib.rs"
+		);
+	}
+
+	fn checkout(files: &[(&str, &str)]) -> (tempfile::TempDir, Vec<PathBuf>) {
+		let dir = tempfile::tempdir().unwrap();
+		let paths = files
+			.iter()
+			.map(|(name, content)| {
+				let path = dir.path().join(name);
+				fs::create_dir_all(path.parent().unwrap()).unwrap();
+				fs::write(&path, content).unwrap();
+				path
+			})
+			.collect();
+		(dir, paths)
+	}
+
+	#[test]
+	fn cache_key_is_independent_of_the_checkout_location() {
+		let files = [("src/lib.rs", "fn a() {}"), ("Cargo.toml", "[package]")];
+		let (first, first_files) = checkout(&files);
+		let (second, mut second_files) = checkout(&files);
+		second_files.reverse();
+
+		let key = |dir: &Path, files: Vec<PathBuf>| {
+			cache_key(&[b"input"], files, &[(dir.to_path_buf(), "/build")]).unwrap()
+		};
+		assert_eq!(key(first.path(), first_files.clone()), key(second.path(), second_files));
+
+		// Without the remapping, the paths are part of the key.
+		assert_ne!(
+			cache_key(&[b"input"], first_files.clone(), &[]).unwrap(),
+			key(first.path(), first_files.clone()),
+		);
+
+		// The key covers the content of the files and the inputs.
+		assert_ne!(
+			key(first.path(), first_files.clone()),
+			key(first.path(), vec![first_files[0].clone()])
+		);
+		let unchanged = key(first.path(), first_files.clone());
+		assert_ne!(
+			cache_key(&[b"other"], first_files.clone(), &[(first.path().to_path_buf(), "/build")])
+				.unwrap(),
+			unchanged,
+		);
+		fs::write(&first_files[0], "fn b() {}").unwrap();
+		assert_ne!(key(first.path(), first_files), unchanged);
+	}
+
+	#[test]
+	fn evict_removes_the_least_recently_used_binaries() {
+		let (dir, files) = checkout(&[("old", "1234"), ("used", "1234"), ("new", "1234")]);
+		for (file, time) in files.iter().zip([1, 3, 2]) {
+			filetime::set_file_mtime(file, filetime::FileTime::from_unix_time(time, 0)).unwrap();
+		}
+
+		evict(dir.path(), 12);
+		assert!(files.iter().all(|file| file.exists()));
+
+		evict(dir.path(), 8);
+		assert!(!files[0].exists());
+		assert!(files[1].exists() && files[2].exists());
+
+		evict(dir.path(), 5);
+		assert!(files[1].exists());
+		assert!(!files[2].exists());
+	}
+}
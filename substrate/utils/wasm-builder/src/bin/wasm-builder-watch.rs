@@ -0,0 +1,91 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rebuild a runtime whenever one of its sources changes.
+//!
+//! Usage: `wasm-builder-watch <path to the Cargo.toml of the runtime>`
+//!
+//! The runtime is built like from the `build.rs` of a crate in the release profile of the
+//! workspace, so the wasm project and its target directory are shared with the normal build.
+
+use cargo_metadata::MetadataCommand;
+use std::{env, fs, ops::ControlFlow, path::PathBuf, process};
+use substrate_wasm_builder::{watch, WasmBuilder, DEFAULT_POLL_INTERVAL};
+
+fn main() {
+	let mut args = env::args_os().skip(1);
+	let (Some(manifest), None) = (args.next(), args.next()) else {
+		eprintln!("Usage: wasm-builder-watch <path to the Cargo.toml of the runtime>");
+		process::exit(1)
+	};
+
+	let manifest = match fs::canonicalize(PathBuf::from(&manifest)) {
+		Ok(manifest) if manifest.ends_with("Cargo.toml") => manifest,
+		_ => {
+			eprintln!("`{}` is not the path to a `Cargo.toml`.", manifest.to_string_lossy());
+			process::exit(1)
+		},
+	};
+
+	// On Windows, the canonical path is an extended-length path. Continue with the path reported by
+	// cargo, which is also used for the `CARGO_MANIFEST_DIR` of a `build.rs`.
+	let metadata = MetadataCommand::new().manifest_path(&manifest).no_deps().exec();
+	let Some((manifest, package, target_directory)) = metadata.ok().and_then(|metadata| {
+		let package = metadata
+			.packages
+			.iter()
+			.find(|p| fs::canonicalize(&p.manifest_path).map_or(false, |path| path == manifest))?;
+		Some((
+			package.manifest_path.clone().into_std_path_buf(),
+			package.name.clone(),
+			metadata.target_directory.into_std_path_buf(),
+		))
+	}) else {
+		eprintln!("Failed to read the metadata of `{}`.", manifest.display());
+		process::exit(1)
+	};
+
+	// Set up the environment cargo gives to a `build.rs`.
+	let out_dir = target_directory
+		.join("release")
+		.join("build")
+		.join(format!("{package}-wasm-builder-watch"))
+		.join("out");
+	if let Err(error) = fs::create_dir_all(&out_dir) {
+		eprintln!("Failed to create `{}`: {error}", out_dir.display());
+		process::exit(1)
+	}
+	env::set_var("OUT_DIR", &out_dir);
+	env::set_var("CARGO_MANIFEST_DIR", manifest.parent().expect("`Cargo.toml` has a parent; qed"));
+	env::set_var("CARGO_PKG_NAME", &package);
+	if env::var_os("CARGO").is_none() {
+		env::set_var("CARGO", "cargo");
+	}
+
+	watch(WasmBuilder::init_with_defaults, DEFAULT_POLL_INTERVAL, |result| {
+		match result {
+			Ok(output) =>
+				for binary in output.binaries {
+					let path = binary.compact_path.unwrap_or(binary.bloaty_path);
+					println!("Built `{}`", path.display());
+				},
+			Err(error) => eprintln!("{error}"),
+		}
+		println!("Waiting for changes...");
+		ControlFlow::Continue(())
+	});
+}
@@ -16,18 +16,124 @@
 // limitations under the License.
 
 use std::{
-	env,
+	collections::{BTreeMap, HashSet},
+	env, fs, mem,
 	path::{Path, PathBuf},
 	process,
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Mutex,
+	},
+	thread,
+	time::SystemTime,
 };
 
-use crate::RuntimeTarget;
+#[cfg(feature = "signing")]
+use crate::SigningKey;
+use crate::{
+	config::FileConfig,
+	external_project::{self, ExternalProject},
+	fingerprint::{toolchain_binary, BuildFingerprint},
+	version::Version,
+	wasm_project::{
+		create_metadata_command, BuildOptions, EnvPolicy, Lto, OptLevel, PgoConfig, Profile,
+		RustcEnvVar,
+	},
+	watch::{rerun_if_changed, rerun_if_env_changed, Rerun},
+	ArtifactCache, BuildStdMode, RuntimeTarget, RuntimeVersionInfo, ToolchainPolicy,
+	ValidationPolicy, WasmBuilderError,
+};
+
+/// The size of a WASM memory page in bytes.
+const WASM_PAGE_SIZE: u64 = 64 * 1024;
 
-/// Extra information when generating the `metadata-hash`.
+/// The maximum size of the memory of a 32 bit WASM binary in bytes.
+const MAX_WASM_MEMORY: u64 = 4 * 1024 * 1024 * 1024;
+
+/// The configuration of the metadata hash and the extra information of the chain that is part of
+/// it.
+///
+/// See [`WasmBuilder::enable_metadata_hash_with`].
 #[cfg(feature = "metadata-hash")]
-pub(crate) struct MetadataExtraInfo {
-	pub decimals: u8,
+#[derive(Clone, Debug)]
+pub struct MetadataHashConfig {
+	/// The symbol of the native token of the chain.
+	///
+	/// The extra information of RFC78 only describes the native token, in which the fees are
+	/// paid. The other tokens of a chain, e.g. the assets of an asset hub, are not part of the
+	/// metadata hash.
 	pub token_symbol: String,
+	/// The number of decimals of the native token.
+	pub decimals: u8,
+	/// The SS58 prefix of the addresses of the chain.
+	///
+	/// If `None`, the `SS58Prefix` constant of the `System` pallet is used.
+	pub base58_prefix: Option<u16>,
+	/// The name of the chain.
+	///
+	/// If `None`, the `spec_name` of the runtime version is used.
+	pub spec_name: Option<String>,
+	/// The version of the metadata the hash is computed from.
+	///
+	/// Defaults to `15`, which is currently the only supported version, see
+	/// [`Self::metadata_version`].
+	pub metadata_version: u32,
+}
+
+/// The metadata versions `merkleized-metadata` can compute the metadata hash from.
+#[cfg(feature = "metadata-hash")]
+const SUPPORTED_METADATA_VERSIONS: &[u32] = &[15];
+
+#[cfg(feature = "metadata-hash")]
+impl MetadataHashConfig {
+	/// The version the runtime exposes its unstable metadata at.
+	pub const UNSTABLE_METADATA_VERSION: u32 = u32::MAX;
+
+	/// Create the configuration for a chain with the native token `token_symbol`.
+	pub fn new(token_symbol: impl Into<String>, decimals: u8) -> Self {
+		Self {
+			token_symbol: token_symbol.into(),
+			decimals,
+			base58_prefix: None,
+			spec_name: None,
+			metadata_version: 15,
+		}
+	}
+
+	/// Compute the hash from the metadata of the given `version`.
+	///
+	/// The build fails right away if the metadata hash can't be computed from this version. The
+	/// pinned `merkleized-metadata` only supports V15, so V16 and the unstable metadata, i.e.
+	/// [`Self::UNSTABLE_METADATA_VERSION`], need a newer `merkleized-metadata`. The build also
+	/// fails if the runtime doesn't provide the metadata at a supported version.
+	pub fn metadata_version(mut self, version: u32) -> Self {
+		self.metadata_version = version;
+		self
+	}
+
+	/// Returns the configuration if it has a token symbol and the metadata version is one the
+	/// metadata hash can be computed from.
+	fn validated(self) -> Result<Self, WasmBuilderError> {
+		if !SUPPORTED_METADATA_VERSIONS.contains(&self.metadata_version) {
+			let version = match self.metadata_version {
+				Self::UNSTABLE_METADATA_VERSION => "the unstable metadata".into(),
+				version => format!("metadata version {version}"),
+			};
+			return Err(WasmBuilderError::InvalidConfiguration(format!(
+				"The metadata hash can't be computed from {version}, only from version 15. \
+				 Versions after 15 need a newer `merkleized-metadata` than the one \
+				 `substrate-wasm-builder` is built with.",
+			)))
+		}
+
+		if self.token_symbol.trim().is_empty() {
+			return Err(WasmBuilderError::InvalidConfiguration(
+				"The native token of the metadata hash needs a symbol.".into(),
+			))
+		}
+
+		Ok(self)
+	}
 }
 
 /// Returns the manifest dir from the `CARGO_MANIFEST_DIR` env.
@@ -37,6 +143,160 @@ fn get_manifest_dir() -> PathBuf {
 		.into()
 }
 
+/// A runtime binary that was built by the [`WasmBuilder`].
+#[derive(Debug, Clone)]
+pub struct RuntimeBinary {
+	/// The target the binary was built for.
+	pub target: RuntimeTarget,
+	/// The path to the compacted and, if possible, compressed binary.
+	///
+	/// Is `None` if the binary was not compacted, e.g. when building a RISC-V binary or when the
+	/// `dev` profile is used.
+	pub compact_path: Option<PathBuf>,
+	/// The path to the bloaty binary as produced by the compiler.
+	pub bloaty_path: PathBuf,
+	/// The information about how the binary was built.
+	pub build_info: BuildInfo,
+}
+
+impl RuntimeBinary {
+	/// Returns the JSON representation of this binary.
+	fn to_json(&self) -> serde_json::Value {
+		serde_json::json!({
+			"compact_path": self.compact_path,
+			"bloaty_path": self.bloaty_path,
+			"build_info": self.build_info.to_json(),
+		})
+	}
+
+	/// Returns the binary from its JSON representation, see [`Self::to_json`].
+	fn from_json(json: &serde_json::Value) -> Option<Self> {
+		let build_info = BuildInfo::from_json(&json["build_info"])?;
+		Some(Self {
+			target: build_info.target,
+			compact_path: serde_json::from_value(json["compact_path"].clone()).ok()?,
+			bloaty_path: serde_json::from_value(json["bloaty_path"].clone()).ok()?,
+			build_info,
+		})
+	}
+}
+
+/// The artifacts of a runtime build that are passed to [`WasmBuilder::on_build_complete`].
+#[derive(Debug, Clone)]
+pub struct BuildArtifacts {
+	/// The target the runtime was built for.
+	pub target: RuntimeTarget,
+	/// The path to the bloaty binary as produced by the compiler.
+	pub bloaty_path: PathBuf,
+	/// The path to the compacted and, if possible, compressed binary.
+	///
+	/// Is `None` if the binary was not compacted.
+	pub compact_path: Option<PathBuf>,
+	/// The path to the DWARF debug info of the compacted binary.
+	///
+	/// Is `None` if the debug info was not split off, see [`WasmBuilder::split_debug_info`].
+	pub debug_path: Option<PathBuf>,
+	/// The profile the runtime was built with.
+	pub profile: Profile,
+	/// The version of `rustc` the runtime was built with.
+	pub rustc_version: String,
+}
+
+/// Information about how a runtime binary was built.
+///
+/// The information of all binaries is also written to `OUT_DIR`, next to the generated file, e.g.
+/// `wasm_build_info.json` for `wasm_binary.rs`. This makes it possible to audit how a runtime was
+/// built.
+#[derive(Debug, Clone)]
+pub struct BuildInfo {
+	/// The target the binary was built for.
+	pub target: RuntimeTarget,
+	/// The version of `rustc` the binary was built with.
+	pub rustc_version: String,
+	/// The toolchain the binary was built with, the name of the rustup toolchain or the path to
+	/// `cargo` otherwise.
+	pub toolchain: String,
+	/// The profile the binary was built with.
+	pub profile: Profile,
+	/// The features that were enabled for the project.
+	pub features: Vec<String>,
+	/// The `RUSTFLAGS` the binary was built with.
+	pub rustflags: String,
+	/// The BLAKE2-256 hash of the bloaty binary.
+	pub bloaty_blake2_256: [u8; 32],
+	/// The BLAKE2-256 hash of the compacted binary, if the binary was compacted.
+	pub compact_blake2_256: Option<[u8; 32]>,
+	/// The version of the runtime, read from the `runtime_version` section of a WASM binary.
+	///
+	/// Is `None` for RISC-V binaries or when the binary has no such section.
+	pub runtime_version: Option<RuntimeVersionInfo>,
+	/// The signature of the final binary, see `WasmBuilder::sign_with`.
+	///
+	/// Is `None` if the binary was not signed.
+	pub signature: Option<[u8; 64]>,
+}
+
+impl BuildInfo {
+	/// Returns the JSON representation of this build info.
+	fn to_json(&self) -> serde_json::Value {
+		use crate::to_hex;
+
+		serde_json::json!({
+			"target": self.target.rustc_target(),
+			"rustc_version": self.rustc_version.trim(),
+			"toolchain": self.toolchain,
+			"profile": self.profile.name(),
+			"features": self.features,
+			"rustflags": self.rustflags,
+			"bloaty_blake2_256": to_hex(&self.bloaty_blake2_256),
+			"compact_blake2_256": self.compact_blake2_256.as_ref().map(|hash| to_hex(hash)),
+			"runtime_version": self.runtime_version.as_ref().map(RuntimeVersionInfo::to_json),
+			"signature": self.signature.as_ref().map(|signature| to_hex(signature)),
+		})
+	}
+
+	/// Returns the build info from its JSON representation, see [`Self::to_json`].
+	fn from_json(json: &serde_json::Value) -> Option<Self> {
+		fn hash<const N: usize>(hex: &serde_json::Value) -> Option<Option<[u8; N]>> {
+			match hex {
+				serde_json::Value::Null => Some(None),
+				hex => crate::from_hex(hex.as_str()?)?.try_into().ok().map(Some),
+			}
+		}
+
+		let target = json["target"].as_str()?;
+		Some(Self {
+			target: [RuntimeTarget::Wasm, RuntimeTarget::Wasm64, RuntimeTarget::Riscv]
+				.into_iter()
+				.find(|t| t.rustc_target() == target)?,
+			rustc_version: json["rustc_version"].as_str()?.into(),
+			toolchain: json["toolchain"].as_str()?.into(),
+			profile: Profile::from_name(json["profile"].as_str()?)?,
+			features: serde_json::from_value(json["features"].clone()).ok()?,
+			rustflags: json["rustflags"].as_str()?.into(),
+			bloaty_blake2_256: hash(&json["bloaty_blake2_256"])??,
+			compact_blake2_256: hash(&json["compact_blake2_256"])?,
+			runtime_version: match &json["runtime_version"] {
+				serde_json::Value::Null => None,
+				version => Some(RuntimeVersionInfo::from_json(version)?),
+			},
+			signature: hash(&json["signature"])?,
+		})
+	}
+}
+
+/// The output of a successful build, see [`WasmBuilder::try_build`].
+#[derive(Debug, Clone)]
+pub struct BuildOutput {
+	/// The path to the generated file in `OUT_DIR` that contains the binary constants.
+	pub file_path: PathBuf,
+	/// The binaries that were built, one per target.
+	///
+	/// Is empty when the build was skipped, e.g. because `SKIP_WASM_BUILD` is set or a prebuilt
+	/// binary is used.
+	pub binaries: Vec<RuntimeBinary>,
+}
+
 /// First step of the [`WasmBuilder`] to select the project to build.
 pub struct WasmBuilderSelectProject {
 	/// This parameter just exists to make it impossible to construct
@@ -52,17 +312,7 @@ impl WasmBuilderSelectProject {
 	/// Panics if the `CARGO_MANIFEST_DIR` variable is not set. This variable
 	/// is always set by `Cargo` in `build.rs` files.
 	pub fn with_current_project(self) -> WasmBuilder {
-		WasmBuilder {
-			rust_flags: Vec::new(),
-			file_name: None,
-			project_cargo_toml: get_manifest_dir().join("Cargo.toml"),
-			features_to_enable: Vec::new(),
-			disable_runtime_version_section_check: false,
-			export_heap_base: false,
-			import_memory: false,
-			#[cfg(feature = "metadata-hash")]
-			enable_metadata_hash: None,
-		}
+		WasmBuilder::for_project(get_manifest_dir().join("Cargo.toml"))
 	}
 
 	/// Use the given `path` as project for building the WASM binary.
@@ -72,21 +322,82 @@ impl WasmBuilderSelectProject {
 		let path = path.into();
 
 		if path.ends_with("Cargo.toml") && path.exists() {
-			Ok(WasmBuilder {
-				rust_flags: Vec::new(),
-				file_name: None,
-				project_cargo_toml: path,
-				features_to_enable: Vec::new(),
-				disable_runtime_version_section_check: false,
-				export_heap_base: false,
-				import_memory: false,
-				#[cfg(feature = "metadata-hash")]
-				enable_metadata_hash: None,
-			})
+			Ok(WasmBuilder::for_project(path))
 		} else {
 			Err("Project path must point to the `Cargo.toml` of the project")
 		}
 	}
+
+	/// Use the member `package` of the workspace of the current project as project for building
+	/// the WASM binary.
+	///
+	/// Returns an error if the workspace has no member with the given name.
+	///
+	/// # Panics
+	///
+	/// Panics if the `CARGO_MANIFEST_DIR` variable is not set. This variable
+	/// is always set by `Cargo` in `build.rs` files.
+	pub fn with_workspace_member(self, package: &str) -> Result<WasmBuilder, WasmBuilderError> {
+		let metadata = create_metadata_command(get_manifest_dir().join("Cargo.toml"))
+			.no_deps()
+			.exec()
+			.map_err(|e| WasmBuilderError::CargoMetadata(e.to_string()))?;
+
+		metadata
+			.workspace_packages()
+			.into_iter()
+			.find(|p| p.name == package)
+			.map(|p| WasmBuilder::for_project(p.manifest_path.clone().into()))
+			.ok_or_else(|| {
+				WasmBuilderError::PackageNotFound(format!(
+					"The workspace at `{}` has no member named `{package}`.",
+					metadata.workspace_root,
+				))
+			})
+	}
+
+	/// Use the `package` at the revision `rev` of the git repository at `url` as project for
+	/// building the WASM binary.
+	///
+	/// The repository is fetched by cargo, so the usual cargo configuration (e.g. for
+	/// authentication) applies. As the project is not the current crate, no features are
+	/// inferred from the current build; use [`WasmBuilder::set_features`] to enable features.
+	///
+	/// # Panics
+	///
+	/// Panics if the `OUT_DIR` variable is not set. This variable is always set by `Cargo` in
+	/// `build.rs` files.
+	pub fn with_git_project(
+		self,
+		url: &str,
+		rev: &str,
+		package: &str,
+	) -> Result<WasmBuilder, WasmBuilderError> {
+		let project = ExternalProject::Git { url, rev, package };
+		external_project::fetch(&project, &build_helper::out_dir())
+			.map(WasmBuilder::for_external_project)
+	}
+
+	/// Use the `version` of the package `name` published on crates.io as project for building
+	/// the WASM binary.
+	///
+	/// The package is fetched by cargo and unpacked into `OUT_DIR`. As the project is not the
+	/// current crate, no features are inferred from the current build; use
+	/// [`WasmBuilder::set_features`] to enable features.
+	///
+	/// # Panics
+	///
+	/// Panics if the `OUT_DIR` variable is not set. This variable is always set by `Cargo` in
+	/// `build.rs` files.
+	pub fn with_crates_io_project(
+		self,
+		name: &str,
+		version: &str,
+	) -> Result<WasmBuilder, WasmBuilderError> {
+		let project = ExternalProject::CratesIo { name, version };
+		external_project::fetch(&project, &build_helper::out_dir())
+			.map(WasmBuilder::for_external_project)
+	}
 }
 
 /// The builder for building a wasm binary.
@@ -99,30 +410,183 @@ impl WasmBuilderSelectProject {
 /// 2. Select the project to build using the methods of [`WasmBuilderSelectProject`].
 /// 3. Set additional `RUST_FLAGS` or a different name for the file containing the WASM code using
 ///    methods of [`WasmBuilder`].
-/// 4. Build the WASM binary using [`Self::build`].
+/// 4. Build the WASM binary using [`Self::build`] or [`Self::try_build`].
 pub struct WasmBuilder {
 	/// Flags that should be appended to `RUST_FLAGS` env variable.
 	rust_flags: Vec<String>,
+	/// Flags that should be appended to `RUST_FLAGS` after the flags of `WASM_BUILD_RUSTFLAGS`.
+	rust_flags_after_env: Vec<String>,
+	/// Arguments that should be appended to the cargo command.
+	cargo_args: Vec<String>,
 	/// The name of the file that is being generated in `OUT_DIR`.
 	///
 	/// Defaults to `wasm_binary.rs`.
 	file_name: Option<String>,
+	/// The path of the generated file, overrides `file_name`.
+	output_file: Option<PathBuf>,
+	/// The prefix of the constants in the generated file.
+	constant_prefix: Option<String>,
+	/// Should the bytes of the binaries be embedded into the generated file?
+	embed_binary: bool,
+	/// Should the BLAKE2-256 hashes of the binaries be added to the generated file?
+	generate_blake2_256: bool,
+	/// Should the runtime version be added to the generated file?
+	generate_runtime_version: bool,
+	/// Should the `digest.json` of the build be written?
+	generate_digest: bool,
+	/// Should the code size report of the wasm binary be written?
+	emit_size_report: bool,
+	/// Should the wasm build be profiled with `cargo --timings`?
+	emit_timings: bool,
+	/// Should the `RUSTC_WRAPPER` of the outer build be used for the wasm build?
+	use_rustc_wrapper: bool,
+	/// How the `rustc` environment variables of the outer build are passed to the wasm build.
+	rustc_env: BTreeMap<RustcEnvVar, EnvPolicy>,
+	/// Should the runtime binary built by cargo be cached in the artifact cache?
+	artifact_cache: bool,
+	/// The shared backend of the artifact cache.
+	artifact_cache_backend: Option<Box<dyn ArtifactCache>>,
+	/// Should the target directory be shared with the other runtimes of the workspace?
+	shared_target_dir: bool,
+	/// Should `dev` builds be incremental?
+	incremental: bool,
+	/// Should cargo fail instead of updating the `Cargo.lock`?
+	locked: bool,
+	/// Should the runtime be built from its own manifest instead of a generated project?
+	build_in_place: bool,
+	/// Should the runtime only be type-checked, if not overridden by `WASM_BUILD_CHECK_ONLY`?
+	check_only: bool,
+	/// Glob patterns of the paths within the packages that are not watched for changes.
+	rerun_exclusions: Vec<String>,
+	/// The environment variables that are tracked besides the default ones.
+	tracked_env_vars: Vec<String>,
+	/// The default environment variables that are not tracked.
+	untracked_env_vars: Vec<String>,
 	/// The path to the `Cargo.toml` of the project that should be built
 	/// for wasm.
 	project_cargo_toml: PathBuf,
 	/// Features that should be enabled when building the wasm binary.
 	features_to_enable: Vec<String>,
+	/// Features that replace the features inferred from the native build.
+	features_override: Option<Vec<String>>,
+	/// Features that should never be enabled when building the wasm binary.
+	features_to_disable: Vec<String>,
+	/// Should the features enabled by the `default` feature not be forwarded to the wasm build?
+	disable_default_features: bool,
 	/// Should the builder not check that the `runtime_version` section exists in the wasm binary?
 	disable_runtime_version_section_check: bool,
+	/// Should the compaction of the wasm binary be skipped?
+	disable_compact: bool,
+	/// The level `wasm-opt` optimizes the wasm binary with while compacting it.
+	wasm_opt_level: Option<OptLevel>,
+	/// Patterns of the custom sections that should be removed from the compacted wasm binary.
+	strip_custom_sections: Vec<String>,
+	/// Should the DWARF debug info be written to a separate file?
+	split_debug_info: bool,
+	/// Should the name section be kept in release builds?
+	keep_name_section: bool,
+	/// The link time optimization of the `release` and `production` profiles.
+	lto: Option<Lto>,
+	/// The number of codegen units of the `release` and `production` profiles.
+	codegen_units: Option<u32>,
+	/// Should everything that could make the build output differ between machines be pinned?
+	deterministic: bool,
+	/// Should the `build_info` section be added to the wasm binary?
+	embed_build_info: bool,
+	/// Should the `build_info` section contain the time of the build?
+	build_info_timestamp: bool,
+	/// The key to sign the wasm binary with.
+	#[cfg(feature = "signing")]
+	signing_key: Option<SigningKey>,
+	/// The maximum size of the runtime blob.
+	blob_size_limit: Option<usize>,
+	/// The policy the wasm binary is validated against after it was built.
+	validation_policy: Option<ValidationPolicy>,
+	/// The functions the wasm binary needs to export.
+	required_exports: Vec<String>,
+	/// Patterns of the host functions the wasm binary is allowed to import.
+	allowed_host_functions: Option<Vec<String>>,
+	/// Should the text format of the wasm binary be written next to it?
+	#[cfg(feature = "wasmprinter")]
+	emit_wat: bool,
 
 	/// Whether `__heap_base` should be exported (WASM-only).
 	export_heap_base: bool,
-	/// Whether `--import-memory` should be added to the link args (WASM-only).
-	import_memory: bool,
+	/// Whether `--import-memory` or, if `false`, `--export-memory` should be added to the link
+	/// args (WASM-only).
+	import_memory: Option<bool>,
+	/// The size of the stack in bytes (WASM-only).
+	stack_size: Option<u32>,
+	/// The initial and the maximum size of the memory in bytes (WASM-only).
+	memory: Option<(u64, u64)>,
+	/// The target features to enable (`+`) or disable (`-`) (WASM-only).
+	target_features: Vec<String>,
+	/// The arguments passed to the linker of the runtime.
+	link_args: Vec<String>,
+
+	/// The targets to build for, if not selected by the environment.
+	targets: Option<Vec<RuntimeTarget>>,
+	/// The path to a custom target specification that replaces the builtin target.
+	custom_target_spec: Option<PathBuf>,
+	/// The path to an additional cargo configuration file for the build.
+	cargo_config: Option<PathBuf>,
+	/// The linker to use, if not overridden by `WASM_BUILD_LINKER`.
+	linker: Option<String>,
+	/// The linker flavor to use, if not overridden by `WASM_BUILD_LINKER_FLAVOR`.
+	linker_flavor: Option<String>,
+	/// Should the standard library be built, if not overridden by `WASM_BUILD_STD`?
+	build_std: Option<bool>,
+	/// The features of the standard library crates when building them from source.
+	build_std_features: Vec<String>,
+	/// The prebuilt sysroot that replaces building the standard library, if not overridden by
+	/// `WASM_BUILD_SYSROOT`.
+	sysroot: Option<PathBuf>,
+	/// How cargo is told to build the standard library, if not overridden by
+	/// `WASM_BUILD_STD_MODE`.
+	build_std_mode: BuildStdMode,
+	/// Should WASM runtimes be built for `wasm32v1-none` if the toolchain provides it, if not
+	/// overridden by `WASM_BUILD_WASM32V1_NONE`?
+	prefer_wasm32v1_none: bool,
+
+	/// The profile to build with, if not selected by the environment.
+	profile: Option<Profile>,
+
+	/// The toolchain to build with.
+	toolchain: Option<String>,
+	/// The toolchains the runtime is built with again to compare the blobs.
+	verification_toolchains: Vec<String>,
+	/// The `cargo` binary to build with, instead of selecting a toolchain.
+	cargo: Option<PathBuf>,
+	/// The `rustc` binary to build with, instead of selecting a toolchain.
+	rustc: Option<PathBuf>,
+	/// The preference between the installed toolchains when no `toolchain` is pinned.
+	toolchain_policy: ToolchainPolicy,
+	/// Whether a missing `toolchain` should be installed using `rustup`.
+	install_missing_toolchain: bool,
+	/// Whether a missing wasm target of the toolchain should be installed using `rustup`.
+	install_missing_target: bool,
+	/// Whether a missing `rust-src` component of the toolchain should be installed using `rustup`.
+	install_missing_rust_src: bool,
+	/// The oldest version of `rustc` the runtime may be built with.
+	min_rustc_version: Option<String>,
+
+	/// Hooks that are called before the runtime is built.
+	before_build: Vec<Box<dyn Fn(&Path) + Send + Sync>>,
+	/// Hooks that are called after the runtime was built.
+	on_build_complete: Vec<Box<dyn Fn(&BuildArtifacts) + Send + Sync>>,
+	/// The maximum number of targets that are built concurrently.
+	max_parallel: usize,
+	/// The number of builders of [`Self::try_build_all`] that are built concurrently with this
+	/// one, including it.
+	concurrent_builders: usize,
+	/// The configuration of the profile-guided optimization.
+	pgo: Option<PgoConfig>,
+	/// The prebuilt binary that is used instead of building one.
+	prebuilt: Option<Prebuilt>,
 
 	/// Whether to enable the metadata hash generation.
 	#[cfg(feature = "metadata-hash")]
-	enable_metadata_hash: Option<MetadataExtraInfo>,
+	enable_metadata_hash: Option<MetadataHashConfig>,
 }
 
 impl WasmBuilder {
@@ -131,6 +595,103 @@ impl WasmBuilder {
 		WasmBuilderSelectProject { _ignore: () }
 	}
 
+	/// Create a builder with default settings for the project at `project_cargo_toml`.
+	fn for_project(project_cargo_toml: PathBuf) -> Self {
+		let project_cargo_toml = crate::simplified_path(project_cargo_toml);
+
+		WasmBuilder {
+			rust_flags: Vec::new(),
+			rust_flags_after_env: Vec::new(),
+			cargo_args: Vec::new(),
+			file_name: None,
+			output_file: None,
+			constant_prefix: None,
+			embed_binary: true,
+			generate_blake2_256: false,
+			generate_runtime_version: false,
+			generate_digest: false,
+			emit_size_report: false,
+			emit_timings: false,
+			use_rustc_wrapper: true,
+			rustc_env: BTreeMap::new(),
+			artifact_cache: false,
+			artifact_cache_backend: None,
+			shared_target_dir: false,
+			incremental: false,
+			locked: false,
+			build_in_place: false,
+			check_only: false,
+			rerun_exclusions: Vec::new(),
+			tracked_env_vars: Vec::new(),
+			untracked_env_vars: Vec::new(),
+			project_cargo_toml,
+			features_to_enable: Vec::new(),
+			features_override: None,
+			features_to_disable: Vec::new(),
+			disable_default_features: false,
+			disable_runtime_version_section_check: false,
+			disable_compact: false,
+			wasm_opt_level: None,
+			strip_custom_sections: Vec::new(),
+			split_debug_info: false,
+			keep_name_section: false,
+			lto: None,
+			codegen_units: None,
+			deterministic: false,
+			embed_build_info: false,
+			build_info_timestamp: true,
+			#[cfg(feature = "signing")]
+			signing_key: None,
+			blob_size_limit: None,
+			validation_policy: None,
+			required_exports: Vec::new(),
+			allowed_host_functions: None,
+			#[cfg(feature = "wasmprinter")]
+			emit_wat: false,
+			export_heap_base: false,
+			import_memory: None,
+			stack_size: None,
+			target_features: Vec::new(),
+			link_args: Vec::new(),
+			memory: None,
+			targets: None,
+			custom_target_spec: None,
+			cargo_config: None,
+			linker: None,
+			linker_flavor: None,
+			build_std: None,
+			build_std_features: Vec::new(),
+			sysroot: None,
+			build_std_mode: BuildStdMode::default(),
+			prefer_wasm32v1_none: false,
+			profile: None,
+			toolchain: None,
+			verification_toolchains: Vec::new(),
+			cargo: None,
+			rustc: None,
+			toolchain_policy: ToolchainPolicy::default(),
+			install_missing_toolchain: false,
+			install_missing_target: false,
+			install_missing_rust_src: false,
+			min_rustc_version: None,
+			before_build: Vec::new(),
+			on_build_complete: Vec::new(),
+			max_parallel: 1,
+			concurrent_builders: 1,
+			pgo: None,
+			prebuilt: None,
+			#[cfg(feature = "metadata-hash")]
+			enable_metadata_hash: None,
+		}
+	}
+
+	/// Create a builder for a project that is not part of the current build.
+	///
+	/// The features of the native build belong to a different crate and are not forwarded.
+	fn for_external_project(project_cargo_toml: PathBuf) -> Self {
+		Self { features_override: Some(Vec::new()), ..Self::for_project(project_cargo_toml) }
+	}
+
 	/// Build the WASM binary using the recommended default values.
 	///
 	/// This is the same as calling:
@@ -182,11 +743,142 @@ impl WasmBuilder {
 		self
 	}
 
+	/// Set the path of the generated file and the name of the runtime binaries.
+	///
+	/// A relative `path` is interpreted relative to `OUT_DIR`. The file stem of `path` is used as
+	/// name for the runtime binaries, e.g. `my_runtime_v105.rs` generates
+	/// `my_runtime_v105.compact.wasm` and `my_runtime_v105.compact.compressed.wasm`. Missing
+	/// parent directories of `path` are created.
+	///
+	/// Overrides the file name set by [`Self::set_file_name`].
+	pub fn output_file(mut self, path: impl Into<PathBuf>) -> Self {
+		self.output_file = Some(path.into());
+		self
+	}
+
+	/// Set the prefix of the constants in the generated file.
+	///
+	/// The constants are named `{prefix}_WASM_BINARY`, `{prefix}_WASM_BINARY_BLOATY` etc. This
+	/// makes it possible to include multiple generated files into the same module.
+	///
+	/// The `prefix` needs to be a valid Rust identifier.
+	pub fn set_constant_prefix(mut self, prefix: impl Into<String>) -> Self {
+		self.constant_prefix = Some(prefix.into());
+		self
+	}
+
+	/// Set whether the bytes of the binaries should be embedded into the generated file.
+	///
+	/// By default the binaries are embedded using `include_bytes!`, e.g. as `WASM_BINARY`. When
+	/// set to `false`, these constants are not generated and the generated file only contains the
+	/// paths of the binaries (`WASM_BINARY_PATH` etc.) and the hashes and the version if enabled
+	/// by [`Self::generate_blake2_256_hash`] and [`Self::generate_runtime_version`]. Consumers
+	/// then need to read the binary from disk, which avoids bloating every crate that includes
+	/// the generated file.
+	pub fn embed_binary(mut self, embed: bool) -> Self {
+		self.embed_binary = embed;
+		self
+	}
+
+	/// Add the BLAKE2-256 hash of the binary to the generated file.
+	///
+	/// The hash is available as `WASM_BINARY_BLAKE2_256` (or `RISCV_BINARY_BLAKE2_256`) with type
+	/// `Option<[u8; 32]>`. It is the hash of the final binary after the compaction and
+	/// compression, i.e. of `WASM_BINARY`. This can be used to compare the on-chain `:code` with
+	/// the runtime the node ships with.
+	pub fn generate_blake2_256_hash(mut self) -> Self {
+		self.generate_blake2_256 = true;
+		self
+	}
+
+	/// Add the version of the runtime to the generated file.
+	///
+	/// The version is read from the `runtime_version` section of the WASM binary and is available
+	/// as `SPEC_NAME` (`Option<&str>`), `SPEC_VERSION` and `IMPL_VERSION` (`Option<u32>`). This
+	/// makes it possible to check the version of the embedded runtime without executing it. The
+	/// full version is also part of the [`BuildInfo`].
+	pub fn generate_runtime_version(mut self) -> Self {
+		self.generate_runtime_version = true;
+		self
+	}
+
+	/// Write a `digest.json` next to the WASM binary.
+	///
+	/// The digest follows the format of the JSON summary of srtool, so existing tooling that
+	/// verifies runtime releases can consume it. It contains the size, the BLAKE2-256 hash and the
+	/// hash of the `System::set_code` proposal of the compacted and of the compressed binary,
+	/// together with the `rustc` version, the cargo arguments and the enabled features. The
+	/// proposal hash assumes that `frame_system` is the first pallet of the runtime.
+	pub fn generate_digest(mut self) -> Self {
+		self.generate_digest = true;
+		self
+	}
+
 	/// Instruct the linker to import the memory into the WASM binary.
 	///
 	/// This adds `-C link-arg=--import-memory` to `RUST_FLAGS`.
 	pub fn import_memory(mut self) -> Self {
-		self.import_memory = true;
+		self.import_memory = Some(true);
+		self
+	}
+
+	/// Set whether the WASM binary imports its memory or exports it.
+	///
+	/// This adds `-C link-arg=--import-memory` or `-C link-arg=--export-memory` to `RUST_FLAGS`,
+	/// as different executors expect different conventions. After the build, the binary is
+	/// checked to actually import or export its memory.
+	pub fn set_import_memory(mut self, import: bool) -> Self {
+		self.import_memory = Some(import);
+		self
+	}
+
+	/// Set the size of the stack of the WASM binary in bytes.
+	///
+	/// This adds `-C link-arg=-zstack-size=<bytes>` to `RUST_FLAGS`. The size needs to be a
+	/// multiple of 16 bytes. The stack is part of the memory, so the memory needs to be big enough
+	/// to hold it.
+	pub fn stack_size(mut self, bytes: u32) -> Self {
+		self.stack_size = Some(bytes);
+		self
+	}
+
+	/// Set the initial and the maximum size of the memory of the WASM binary in bytes.
+	///
+	/// This adds `-C link-arg=--initial-memory=<initial>` and `-C link-arg=--max-memory=<max>` to
+	/// `RUST_FLAGS`. Both sizes need to be multiples of the WASM page size of 64 KiB and the
+	/// `initial` size can't exceed the `max` size of at most 4 GiB.
+	pub fn memory(mut self, initial: u64, max: u64) -> Self {
+		self.memory = Some((initial, max));
+		self
+	}
+
+	/// Pass the given arguments to the linker of the runtime, e.g. `["--gc-sections"]`.
+	///
+	/// Each argument is passed as `-C link-arg=<arg>` in `RUST_FLAGS`, which only apply to the
+	/// build of the runtime. So unlike setting them in the `RUSTFLAGS` of the main build, the
+	/// arguments don't leak into the native build. The arguments need to be understood by the
+	/// linker of each target the runtime is built for, which is `rust-lld` unless
+	/// [`Self::linker`] is used.
+	pub fn link_args(mut self, args: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+		self.link_args.extend(args.into_iter().map(|arg| arg.as_ref().to_owned()));
+		self
+	}
+
+	/// Enable or disable the given WASM target features, e.g. `["-sign-ext", "-bulk-memory"]`.
+	///
+	/// Each feature needs to be prefixed with `+` to enable or `-` to disable it and is named
+	/// like the LLVM target feature, e.g. `sign-ext`, `bulk-memory` or `multivalue`. The features
+	/// are passed as `-C target-feature=<features>` in `RUST_FLAGS`. After the build, the WASM
+	/// binary is validated to not use any of the disabled features, e.g. to support older
+	/// executors that reject sign extension instructions. If it does, the error names the crates
+	/// whose functions use them.
+	///
+	/// The precompiled standard library isn't affected by these flags, so the disabled features
+	/// are only guaranteed to be absent if the standard library is built from source, see
+	/// [`Self::build_std`].
+	pub fn target_features(mut self, features: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+		self.target_features
+			.extend(features.into_iter().map(|feature| feature.as_ref().to_owned()));
 		self
 	}
 
@@ -198,6 +890,29 @@ impl WasmBuilder {
 		self
 	}
 
+	/// Append the given `flags` to `RUST_FLAGS` of this project.
+	///
+	/// In contrast to [`Self::append_to_rust_flags`], the `flags` are appended after the flags of
+	/// the `WASM_BUILD_RUSTFLAGS` environment variable. So, they take precedence over the
+	/// environment variable when they conflict, e.g. when both set the same `-C` option. This
+	/// makes it possible to configure the flags of a single project, while `WASM_BUILD_RUSTFLAGS`
+	/// applies to all projects of the workspace.
+	///
+	/// `flags` are appended as is, so they need to be valid flags.
+	pub fn append_rustflags(mut self, flags: impl Into<String>) -> Self {
+		self.rust_flags_after_env.push(flags.into());
+		self
+	}
+
+	/// Append the given `args` to the cargo command that builds the runtime.
+	///
+	/// This can be used to pass arguments like `--timings` or `--config` to cargo. The arguments
+	/// of the `WASM_BUILD_CARGO_ARGS` environment variable are appended after the given `args`.
+	pub fn append_cargo_args(mut self, args: impl IntoIterator<Item = impl Into<String>>) -> Self {
+		self.cargo_args.extend(args.into_iter().map(Into::into));
+		self
+	}
+
 	/// Enable the given feature when building the wasm binary.
 	///
 	/// `feature` needs to be a valid feature that is defined in the project `Cargo.toml`.
@@ -206,6 +921,40 @@ impl WasmBuilder {
 		self
 	}
 
+	/// Set the exact features that should be enabled when building the wasm binary.
+	///
+	/// By default all features that are enabled for the native build, except `std` and `default`,
+	/// are also enabled for the wasm build. Calling this function replaces this inferred set, so
+	/// the wasm build no longer depends on the features enabled by the native build. The
+	/// `runtime-wasm` feature and features added by [`Self::enable_feature`] are still enabled.
+	///
+	/// Each feature needs to be a valid feature that is defined in the project `Cargo.toml`.
+	pub fn set_features(mut self, features: impl IntoIterator<Item = impl Into<String>>) -> Self {
+		self.features_override = Some(features.into_iter().map(Into::into).collect());
+		self
+	}
+
+	/// Disable the given feature when building the wasm binary.
+	///
+	/// The feature will not be enabled for the wasm build, regardless of whether it is enabled for
+	/// the native build, set by [`Self::set_features`] or enabled by [`Self::enable_feature`].
+	pub fn disable_feature(mut self, feature: impl Into<String>) -> Self {
+		self.features_to_disable.push(feature.into());
+		self
+	}
+
+	/// Disable the default features of the project when building the wasm binary.
+	///
+	/// The wasm project always depends on the project with `default-features = false`. However,
+	/// features that are enabled for the native build are forwarded to the wasm build, which
+	/// includes the features enabled by the `default` feature. Calling this function excludes
+	/// all features that are (transitively) enabled by the `default` feature from this
+	/// forwarding. Features can still be enabled explicitly by using [`Self::enable_feature`].
+	pub fn disable_default_features(mut self) -> Self {
+		self.disable_default_features = true;
+		self
+	}
+
 	/// Enable generation of the metadata hash.
 	///
 	/// This will compile the runtime once, fetch the metadata, build the metadata hash and
@@ -214,10 +963,22 @@ impl WasmBuilder {
 	///
 	/// - `token_symbol`: The symbol of the main native token of the chain.
 	/// - `decimals`: The number of decimals of the main native token.
+	///
+	/// Use [`Self::enable_metadata_hash_with`] to override the information taken from the
+	/// runtime.
+	#[cfg(feature = "metadata-hash")]
+	pub fn enable_metadata_hash(self, token_symbol: impl Into<String>, decimals: u8) -> Self {
+		self.enable_metadata_hash_with(MetadataHashConfig::new(token_symbol, decimals))
+	}
+
+	/// Enable generation of the metadata hash with the given configuration.
+	///
+	/// Like [`Self::enable_metadata_hash`], but also for runtimes that don't expose their SS58
+	/// prefix as a constant, or to compute the hash from another metadata version. The build
+	/// fails if the token symbol of `config` is empty.
 	#[cfg(feature = "metadata-hash")]
-	pub fn enable_metadata_hash(mut self, token_symbol: impl Into<String>, decimals: u8) -> Self {
-		self.enable_metadata_hash =
-			Some(MetadataExtraInfo { token_symbol: token_symbol.into(), decimals });
+	pub fn enable_metadata_hash_with(mut self, config: MetadataHashConfig) -> Self {
+		self.enable_metadata_hash = Some(config);
 
 		self
 	}
@@ -233,154 +994,2089 @@ impl WasmBuilder {
 		self
 	}
 
-	/// Build the WASM binary.
-	pub fn build(mut self) {
-		let target = crate::runtime_target();
-		if target == RuntimeTarget::Wasm {
-			if self.export_heap_base {
-				self.rust_flags.push("-Clink-arg=--export=__heap_base".into());
-			}
+	/// Disable the compaction and compression of the WASM binary.
+	///
+	/// Compacting the WASM binary takes some time on every build, which isn't required when only
+	/// the bloaty binary is used, e.g. while debugging. When the compaction is disabled,
+	/// `WASM_BINARY` contains the same binary as `WASM_BINARY_BLOATY`.
+	pub fn disable_compact(mut self) -> Self {
+		self.disable_compact = true;
+		self
+	}
 
-			if self.import_memory {
-				self.rust_flags.push("-C link-arg=--import-memory".into());
-			}
-		}
+	/// Optimize the WASM binary with `wasm-opt` at the given `level` while compacting it.
+	///
+	/// By default the binary is only compacted without running any optimization passes. The
+	/// optimizations run before the compression, so they reduce the size of `WASM_BINARY`. Like
+	/// the compaction, they are skipped for the `dev` profile or when the compaction is disabled.
+	pub fn optimize_with_wasm_opt(mut self, level: OptLevel) -> Self {
+		self.wasm_opt_level = Some(level);
+		self
+	}
 
-		let out_dir = PathBuf::from(env::var("OUT_DIR").expect("`OUT_DIR` is set by cargo!"));
-		let file_path =
-			out_dir.join(self.file_name.clone().unwrap_or_else(|| "wasm_binary.rs".into()));
+	/// Build the runtime with the given link time optimization.
+	///
+	/// This sets `lto` of the `release` and `production` profiles in the manifest of the
+	/// generated wasm project, which by default use `thin` and `fat` respectively. The profiles of
+	/// the workspace are not used for the wasm build, so a warning is printed if they set a
+	/// different `lto`.
+	pub fn lto(mut self, lto: Lto) -> Self {
+		self.lto = Some(lto);
+		self
+	}
 
-		if check_skip_build() {
-			// If we skip the build, we still want to make sure to be called when an env variable
-			// changes
-			generate_rerun_if_changed_instructions();
+	/// Build the runtime with the given number of codegen units.
+	///
+	/// This sets `codegen-units` of the `release` and `production` profiles in the manifest of
+	/// the generated wasm project, like [`Self::lto`]. The number needs to be at least 1 and
+	/// [`Self::deterministic`] builds require exactly 1 codegen unit.
+	pub fn codegen_units(mut self, units: u32) -> Self {
+		self.codegen_units = Some(units);
+		self
+	}
 
-			provide_dummy_wasm_binary_if_not_exist(&file_path);
+	/// Validate the WASM binary against the given `policy` after it was built.
+	///
+	/// The build fails if the binary uses instructions or sections that are not allowed by the
+	/// `policy`, e.g. nondeterministic floating point instructions. This catches issues at build
+	/// time that would otherwise only be found when the runtime is executed on chain.
+	pub fn validate(mut self, policy: ValidationPolicy) -> Self {
+		self.validation_policy = Some(policy);
+		self
+	}
 
-			return
-		}
+	/// Require the WASM binary to export the given functions.
+	///
+	/// The build fails with a list of the missing exports, e.g. when a runtime API was
+	/// accidentally excluded by a `#[cfg]`. Runtime API functions are exported as
+	/// `{Api}_{method}`, e.g. `Core_version` or `Metadata_metadata`.
+	pub fn require_exports(mut self, exports: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+		self.required_exports
+			.extend(exports.into_iter().map(|export| export.as_ref().to_owned()));
+		self
+	}
 
-		build_project(
-			target,
-			file_path,
-			self.project_cargo_toml,
-			self.rust_flags.into_iter().map(|f| format!("{} ", f)).collect(),
-			self.features_to_enable,
-			self.file_name,
-			!self.disable_runtime_version_section_check,
-			#[cfg(feature = "metadata-hash")]
-			self.enable_metadata_hash,
-		);
+	/// Only allow the WASM binary to import the given host functions.
+	///
+	/// The build fails with a list of all imported host functions that don't match one of the
+	/// `patterns`. A pattern either matches the name of a function exactly or, if it ends with
+	/// `*`, all names starting with the rest of the pattern, e.g. `ext_crypto_*`. This ensures
+	/// that the runtime doesn't call host functions the executor of the node doesn't provide.
+	///
+	/// Can be called multiple times to allow more host functions.
+	pub fn allowed_host_functions(
+		mut self,
+		patterns: impl IntoIterator<Item = impl AsRef<str>>,
+	) -> Self {
+		self.allowed_host_functions
+			.get_or_insert_with(Vec::new)
+			.extend(patterns.into_iter().map(|pattern| pattern.as_ref().to_owned()));
+		self
+	}
 
-		// As last step we need to generate our `rerun-if-changed` stuff. If a build fails, we don't
-		// want to spam the output!
-		generate_rerun_if_changed_instructions();
+	/// Set whether the text format of the WASM binary should be written next to it.
+	///
+	/// The bloaty binary is printed to a `.wat` file with the same name, e.g. `runtime.wat` for
+	/// `runtime.wasm`. This makes it possible to diff the runtime between releases without
+	/// installing additional tools.
+	#[cfg(feature = "wasmprinter")]
+	pub fn emit_wat(mut self, emit: bool) -> Self {
+		self.emit_wat = emit;
+		self
 	}
-}
 
-/// Generate the name of the skip build environment variable for the current crate.
-fn generate_crate_skip_build_env_name() -> String {
-	format!(
-		"SKIP_{}_WASM_BUILD",
-		env::var("CARGO_PKG_NAME")
-			.expect("Package name is set")
-			.to_uppercase()
-			.replace('-', "_"),
-	)
-}
+	/// Set whether the DWARF debug info should be split off into a separate file.
+	///
+	/// The runtime is built with debug info and the compacted binary is written once more with
+	/// the DWARF sections to `<name>.debug.wasm` next to it, e.g. for profilers or to symbolize
+	/// backtraces. The DWARF sections are then stripped from the compacted binary, so the shipped
+	/// runtime doesn't change. Has no effect if the runtime isn't compacted.
+	///
+	/// `wasm-opt` only keeps the debug info up to date on a best effort basis, so it may be
+	/// inaccurate for some functions.
+	pub fn split_debug_info(mut self, split: bool) -> Self {
+		self.split_debug_info = split;
+		self
+	}
 
-/// Checks if the build of the WASM binary should be skipped.
-fn check_skip_build() -> bool {
-	env::var(crate::SKIP_BUILD_ENV).is_ok() ||
-		env::var(generate_crate_skip_build_env_name()).is_ok() ||
-		// If we are running in docs.rs, let's skip building.
-		// https://docs.rs/about/builds#detecting-docsrs
-		env::var("DOCS_RS").is_ok()
-}
+	/// Set whether the name section should be kept in the bloaty binary of release builds.
+	///
+	/// The name section maps function indices to function names, which makes profiles and traces
+	/// of the runtime readable. By default it is stripped by all profiles but the debug profile.
+	/// The compacted binary keeps it, unless it is removed with [`Self::strip_custom_sections`].
+	pub fn keep_name_section(mut self, keep: bool) -> Self {
+		self.keep_name_section = keep;
+		self
+	}
 
-/// Provide a dummy WASM binary if there doesn't exist one.
-fn provide_dummy_wasm_binary_if_not_exist(file_path: &Path) {
-	if !file_path.exists() {
-		crate::write_file_if_changed(
-			file_path,
-			"pub const WASM_BINARY: Option<&[u8]> = None;\
-			 pub const WASM_BINARY_BLOATY: Option<&[u8]> = None;",
-		);
+	/// Build the runtime deterministically, to get the same binary on every machine.
+	///
+	/// This pins everything that could make the build output differ between machines:
+	///
+	/// - The paths of the source files are remapped to `/build` for the workspace, `/cargo` for
+	///   `CARGO_HOME` and `/wbuild` for the project the runtime is built in.
+	/// - `SOURCE_DATE_EPOCH` is set to `0` for build scripts and proc macros that embed a
+	///   timestamp.
+	/// - The runtime is built with `--locked` and a single codegen unit.
+	///
+	/// The build fails if the toolchain isn't pinned to a version, like `1.81.0`, or to a dated
+	/// channel, like `nightly-2024-09-01`, using [`Self::toolchain`] or `WASM_BUILD_TOOLCHAIN`.
+	pub fn deterministic(mut self) -> Self {
+		self.deterministic = true;
+		self
 	}
-}
 
-/// Generate the `rerun-if-changed` instructions for cargo to make sure that the WASM binary is
-/// rebuilt when needed.
-fn generate_rerun_if_changed_instructions() {
-	// Make sure that the `build.rs` is called again if one of the following env variables changes.
-	println!("cargo:rerun-if-env-changed={}", crate::SKIP_BUILD_ENV);
-	println!("cargo:rerun-if-env-changed={}", crate::FORCE_WASM_BUILD_ENV);
-	println!("cargo:rerun-if-env-changed={}", generate_crate_skip_build_env_name());
-}
+	/// Set whether a `build_info` custom section should be added to the WASM binary.
+	///
+	/// The section contains a JSON object with the git commit of the workspace, the `rustc`
+	/// version, the profile, the enabled features and the unix timestamp of the build, e.g.:
+	///
+	/// ```json
+	/// {"features":["std"],"git_commit":"4f1c...","profile":"production","rustc_version":"rustc 1.81.0","timestamp":1727000000}
+	/// ```
+	///
+	/// This allows to verify the provenance of a runtime with standard WASM tooling. The section
+	/// is added to the bloaty binary and kept by the compacted binary.
+	pub fn embed_build_info(mut self, embed: bool) -> Self {
+		self.embed_build_info = embed;
+		self
+	}
 
-/// Build the currently built project as wasm binary.
-///
-/// The current project is determined by using the `CARGO_MANIFEST_DIR` environment variable.
-///
+	/// Set whether the `build_info` section should contain the timestamp of the build.
+	///
+	/// The timestamp makes the binary differ between builds, so it is always omitted for
+	/// [`Self::deterministic`] builds. See [`Self::embed_build_info`].
+	pub fn build_info_timestamp(mut self, timestamp: bool) -> Self {
+		self.build_info_timestamp = timestamp;
+		self
+	}
+
+	/// Sign the final WASM binary with the given `key`.
+	///
+	/// The detached signature of the binary that is provided as `WASM_BINARY`, usually the
+	/// compacted and compressed binary, is written next to it with `.sig` appended to its file
+	/// name. It is also provided as `WASM_BINARY_SIGNATURE: Option<[u8; 64]>` constant. Sr25519
+	/// signatures use the `substrate` signing context.
+	#[cfg(feature = "signing")]
+	pub fn sign_with(mut self, key: SigningKey) -> Self {
+		self.signing_key = Some(match key {
+			SigningKey::File { scheme, path } =>
+				SigningKey::File { scheme, path: get_manifest_dir().join(path) },
+			key => key,
+		});
+		self
+	}
+
+	/// Write a report of the code size of the bloaty WASM binary into `OUT_DIR`.
+	///
+	/// The report lists the size of every function and the size of every crate, as the sum of
+	/// its functions, in `<name>.size-report.txt` and `<name>.size-report.json`. This helps to
+	/// find out which crate increased the size of the runtime. The names of the functions are
+	/// taken from the name section, so use [`Self::keep_name_section`] for release builds.
+	pub fn emit_size_report(mut self) -> Self {
+		self.emit_size_report = true;
+		self
+	}
+
+	/// Set whether the build of the WASM binary should be profiled with `cargo --timings`.
+	///
+	/// The HTML report of cargo is copied from the target directory of the WASM project into
+	/// `OUT_DIR` as `cargo-timing-<name>.html`. The `WASM_BUILD_TIMINGS` environment variable
+	/// overrides this setting.
+	pub fn emit_timings(mut self, emit: bool) -> Self {
+		self.emit_timings = emit;
+		self
+	}
+
+	/// Set whether the `RUSTC_WRAPPER` of the outer build should also be used for the wasm build.
+	///
+	/// By default the wrapper, e.g. `sccache`, that cargo uses for the outer build is passed to
+	/// the cargo building the WASM binary together with all `SCCACHE_*` environment variables, so
+	/// the WASM build is cached as well. When the toolchain is selected with `rustup run`, the
+	/// wrapper is called with the `rustc` proxy of rustup, which resolves to the selected
+	/// toolchain. If disabled, no wrapper is used for the WASM build, not even one configured with
+	/// `build.rustc-wrapper` in the cargo configuration. The `WASM_BUILD_USE_RUSTC_WRAPPER`
+	/// environment variable overrides this setting, a policy for `RUSTC_WRAPPER` set with
+	/// [`Self::rustc_env`] overrides both.
+	pub fn use_rustc_wrapper(mut self, use_wrapper: bool) -> Self {
+		self.use_rustc_wrapper = use_wrapper;
+		self
+	}
+
+	/// Set how the environment variable `var` of the outer build is passed to the wasm build.
+	///
+	/// Without a policy, `RUSTC` is removed for toolchains managed by rustup and set to the
+	/// `rustc` of the toolchain otherwise, `RUSTC_WRAPPER` follows [`Self::use_rustc_wrapper`]
+	/// and `RUSTC_WORKSPACE_WRAPPER` is inherited. An explicit policy replaces this, e.g. to
+	/// keep a `clippy-driver` set as workspace wrapper out of the runtime build or to compile
+	/// the runtime with a custom `rustc`.
+	pub fn rustc_env(mut self, var: RustcEnvVar, policy: EnvPolicy) -> Self {
+		self.rustc_env.insert(var, policy);
+		self
+	}
+
+	/// Set whether the runtime binary built by cargo should be cached in the artifact cache.
+	///
+	/// The cache is located in `$XDG_CACHE_HOME/wasm-builder`, defaulting to
+	/// `~/.cache/wasm-builder`, and is keyed on a hash of the watched sources of the local packages
+	/// the runtime depends on, the `Cargo.lock`, the toolchain and the flags of the build. If a
+	/// binary with a matching key is cached, it is used instead of invoking cargo, while the
+	/// compaction and all other post-processing steps still run as usual. Builds with
+	/// [`Self::before_build`] hooks or a [`Self::pgo`] workload are never cached, as their output
+	/// is not covered by the key. The `WASM_BUILD_ARTIFACT_CACHE` environment variable overrides
+	/// this setting.
+	///
+	/// The least recently used binaries are removed when the cache grows beyond 4 GiB. The cache
+	/// directory can also be deleted at any time to clean it up.
+	pub fn artifact_cache(mut self, enable: bool) -> Self {
+		self.artifact_cache = enable;
+		self
+	}
+
+	/// Share the artifact cache with other machines using the given `backend`, e.g. an
+	/// [`HttpArtifactCache`](crate::HttpArtifactCache).
+	///
+	/// This enables the [`Self::artifact_cache`]. Binaries that are not in the local cache are
+	/// fetched from the `backend` and the binaries that are built are stored in it. If the
+	/// `backend` fails, a warning is printed and the runtime is built locally. The fetched
+	/// binaries are only checked to be runtime binaries, so the `backend` must be as trusted as
+	/// the build machine, see [`ArtifactCache`](crate::ArtifactCache).
+	pub fn artifact_cache_backend(mut self, backend: impl ArtifactCache + 'static) -> Self {
+		self.artifact_cache = true;
+		self.artifact_cache_backend = Some(Box::new(backend));
+		self
+	}
+
+	/// Set whether the cargo target directory should be shared with the other runtimes of the
+	/// workspace.
+	///
+	/// By default every runtime is built in its own WASM project with its own target directory,
+	/// so the dependencies like `sp-*` and `frame-*` are compiled again for every runtime. With a
+	/// shared target directory, all runtimes that opt in reuse the compiled dependencies, which
+	/// considerably speeds up the build of workspaces with multiple runtimes. Cargo locks the
+	/// target directory, so the runtimes are built one after another. The
+	/// `WASM_BUILD_SHARED_TARGET_DIR` environment variable overrides this setting.
+	pub fn shared_target_dir(mut self, share: bool) -> Self {
+		self.shared_target_dir = share;
+		self
+	}
+
+	/// Set whether the WASM binary should be compiled incrementally when building with the `dev`
+	/// profile.
+	///
+	/// This speeds up the rebuilds after small changes to the runtime in the local development
+	/// loop. Builds with the `release` and `production` profiles are never incremental, so they
+	/// stay reproducible. The `WASM_BUILD_INCREMENTAL` environment variable overrides this
+	/// setting.
+	pub fn incremental(mut self, incremental: bool) -> Self {
+		self.incremental = incremental;
+		self
+	}
+
+	/// Set whether the WASM binary should be built with `--locked`, or `--frozen` when
+	/// `CARGO_NET_OFFLINE` is set.
+	///
+	/// The project the runtime is built in uses a copy of the `Cargo.lock` of the workspace. With
+	/// this setting, the build fails if cargo would need to change it, instead of silently
+	/// resolving different versions of the dependencies for the runtime. A
+	/// [`Self::deterministic`] build is always locked. The `WASM_BUILD_LOCKED` environment
+	/// variable overrides this setting.
+	pub fn locked(mut self, locked: bool) -> Self {
+		self.locked = locked;
+		self
+	}
+
+	/// Set whether the runtime should be built from its own `Cargo.toml` instead of a generated
+	/// project that depends on it.
+	///
+	/// By default the runtime is built in a generated project in the target directory, which
+	/// sets the features, the crate type and the profiles of the build in its `Cargo.toml`. When
+	/// building in place, cargo is invoked with `--manifest-path` of the runtime and these
+	/// settings are passed on the command line instead, so the profiles of the workspace are used
+	/// with `panic = "abort"`. The build still uses its own target directory. This is only
+	/// supported for WASM targets.
+	pub fn build_in_place(mut self, in_place: bool) -> Self {
+		self.build_in_place = in_place;
+		self
+	}
+
+	/// Only type-check the runtime with `cargo check`, without building a binary.
+	///
+	/// The generated file then contains `None` constants, like when the build is skipped. This is
+	/// useful for CI jobs that only want to know whether the runtime compiles for its target, as
+	/// it saves the time of the code generation and the post-processing.
+	///
+	/// The `WASM_BUILD_CHECK_ONLY` environment variable overrides this setting.
+	pub fn check_only(mut self) -> Self {
+		self.check_only = true;
+		self
+	}
+
+	/// Don't rebuild the WASM binary when files matching one of the glob `patterns` change.
+	///
+	/// The runtime is rebuilt whenever a file of one of the packages it depends on changes. The
+	/// patterns are matched against the paths relative to the directory of every package, e.g.
+	/// `docs/**` or `src/**/*.md`. `**` matches any number of directories, while `*` and `?`
+	/// match within a file or directory name. The `tests`, `benches`, `examples` and `target`
+	/// directories of the packages are always excluded, as they can't affect the runtime.
+	pub fn exclude_from_rerun(
+		mut self,
+		patterns: impl IntoIterator<Item = impl AsRef<str>>,
+	) -> Self {
+		self.rerun_exclusions
+			.extend(patterns.into_iter().map(|pattern| pattern.as_ref().to_owned()));
+		self
+	}
+
+	/// Also rebuild the WASM binary when the environment variable `name` changes.
+	///
+	/// This is useful for variables that are read by the runtime, e.g. with `option_env!`.
+	pub fn track_env_var(mut self, name: impl Into<String>) -> Self {
+		let name = name.into();
+		self.untracked_env_vars.retain(|untracked| *untracked != name);
+		self.tracked_env_vars.push(name);
+		self
+	}
+
+	/// Don't rebuild the WASM binary when the environment variable `name` changes.
+	///
+	/// By default the binary is rebuilt when one of the environment variables of the wasm builder
+	/// changes, see [`Self::tracked_env_vars`]. Untracking a variable that doesn't matter for this
+	/// project, e.g. `WASM_TARGET_DIRECTORY` or `WASM_BUILD_VERBOSITY`, prevents needless reruns
+	/// of the `build.rs`. The variables that decide whether the binary is built at all, like
+	/// `SKIP_WASM_BUILD`, are always tracked.
+	pub fn untrack_env_var(mut self, name: impl Into<String>) -> Self {
+		let name = name.into();
+		self.tracked_env_vars.retain(|tracked| *tracked != name);
+		self.untracked_env_vars.push(name);
+		self
+	}
+
+	/// Returns the environment variables that rebuild the WASM binary when they change.
+	pub fn tracked_env_vars(&self) -> Vec<String> {
+		crate::TRACKED_ENV_VARS
+			.iter()
+			.map(|name| name.to_string())
+			.chain(self.tracked_env_vars.iter().cloned())
+			.filter(|name| !self.untracked_env_vars.contains(name))
+			.fold(Vec::new(), |mut tracked, name| {
+				if !tracked.contains(&name) {
+					tracked.push(name);
+				}
+				tracked
+			})
+	}
+
+	/// Set the maximum size in bytes of the runtime blob.
+	///
+	/// The limit replaces the default decompression bomb limit of the compression. The build
+	/// fails if the compact binary exceeds the limit before or after the compression, instead of
+	/// silently writing an uncompressed blob. Use it to ensure that the runtime stays below the
+	/// code size limit of the chain. Only applies when the binary is compacted.
+	pub fn blob_size_limit(mut self, bytes: usize) -> Self {
+		self.blob_size_limit = Some(bytes);
+		self
+	}
+
+	/// Remove the custom sections matching the given `patterns` from the compacted WASM binary.
+	///
+	/// A pattern either matches the name of a section exactly or, if it ends with `*`, all names
+	/// starting with the rest of the pattern, e.g. `.debug_*`. This is useful to remove sections
+	/// like `producers` that differ between machines. Sections that are required by the node,
+	/// like `runtime_version` and `runtime_apis`, should not be matched. The sections are only
+	/// removed when the binary is compacted.
+	pub fn strip_custom_sections(
+		mut self,
+		patterns: impl IntoIterator<Item = impl AsRef<str>>,
+	) -> Self {
+		self.strip_custom_sections
+			.extend(patterns.into_iter().map(|pattern| pattern.as_ref().to_owned()));
+		self
+	}
+
+	/// Build the runtime for the given `targets`.
+	///
+	/// By default the runtime is only built for the target selected by the
+	/// `SUBSTRATE_RUNTIME_TARGET` environment variable. Every target is built in its own directory
+	/// and the generated file contains the constants of all targets: `WASM_BINARY` and
+	/// `WASM_BINARY_BLOATY` for [`RuntimeTarget::Wasm`], `WASM64_BINARY` for
+	/// [`RuntimeTarget::Wasm64`] and `RISCV_BINARY` for [`RuntimeTarget::Riscv`]. If WASM64 or
+	/// RISC-V is the only target, its binary is also available as `WASM_BINARY` and
+	/// `WASM_BINARY_BLOATY`, the same as when it is selected by the environment.
+	pub fn targets(mut self, targets: &[RuntimeTarget]) -> Self {
+		self.targets = Some(targets.to_vec());
+		self
+	}
+
+	/// Build the runtime with the given `profile`.
+	///
+	/// By default the profile is inferred from the profile of the main build, with the exception
+	/// of a debug build which builds the runtime with the `release` profile. The given `profile`
+	/// takes precedence over this inferred profile, but the `WASM_BUILD_TYPE` environment variable
+	/// still overrides it.
+	pub fn profile(mut self, profile: Profile) -> Self {
+		self.profile = Some(profile);
+		self
+	}
+
+	/// Build the runtime with the given rustup `toolchain`, e.g. `1.77.0` or `nightly-2024-04-01`.
+	///
+	/// Pinning the toolchain ensures that the runtime is always built with the same compiler,
+	/// which is required for reproducible builds. The `toolchain` takes precedence over the
+	/// `WASM_BUILD_TOOLCHAIN` environment variable, which takes precedence over the toolchain
+	/// pinned by a `rust-toolchain.toml` of the runtime crate or its workspace. The build fails if
+	/// the `toolchain` is not installed, unless [`Self::install_missing_toolchain`] is used.
+	pub fn toolchain(mut self, toolchain: impl Into<String>) -> Self {
+		self.toolchain = Some(toolchain.into());
+		self
+	}
+
+	/// Build the runtime with each of the given rustup `toolchains` as well and report whether
+	/// the blobs match, e.g. `["1.77.0", "1.81.0"]`.
+	///
+	/// This quantifies how reproducible the runtime is across compilers before pinning the
+	/// [`Self::toolchain`] of a release. The binaries and the constants are still the ones of the
+	/// normal build. Each toolchain builds in its own `verify-<toolchain>` project and target
+	/// directory, so the normal build is not affected. Each blob is compared to the one of the
+	/// normal build, the compact one if it exists, and every mismatch is reported as a warning.
+	/// All results are written to `<name>_toolchain_verification.json` next to the generated file
+	/// with the constants. The toolchains are installed if they are missing and
+	/// [`Self::install_missing_toolchain`] is used.
+	///
+	/// The build fails if [`Self::embed_build_info`] is enabled, as the embedded version of
+	/// `rustc` makes the blobs always differ.
+	pub fn verify_with_toolchains(
+		mut self,
+		toolchains: impl IntoIterator<Item = impl Into<String>>,
+	) -> Self {
+		self.verification_toolchains.extend(toolchains.into_iter().map(Into::into));
+		self
+	}
+
+	/// Build the runtime with the `cargo` binary at `path`.
+	///
+	/// This bypasses the selection of the toolchain, i.e. [`Self::toolchain`],
+	/// `WASM_BUILD_TOOLCHAIN`, the `rust-toolchain.toml` and rustup are ignored. It is meant for
+	/// build environments where the toolchain is provisioned by an external system. Without
+	/// [`Self::with_rustc`], cargo uses the `rustc` of its own toolchain.
+	pub fn with_cargo(mut self, path: impl Into<PathBuf>) -> Self {
+		self.cargo = Some(path.into());
+		self
+	}
+
+	/// Build the runtime with the `rustc` binary at `path`.
+	///
+	/// Like [`Self::with_cargo`], this bypasses the selection of the toolchain. Without
+	/// [`Self::with_cargo`], the `cargo` of the main build is used.
+	pub fn with_rustc(mut self, path: impl Into<PathBuf>) -> Self {
+		self.rustc = Some(path.into());
+		self
+	}
+
+	/// Build the runtime using the custom target specification at `path`.
+	///
+	/// The path of the JSON file is passed as `--target` to cargo instead of the builtin target
+	/// triple, e.g. to experiment with a WASM target that enables different features. The
+	/// target specification must produce the same kind of binary as the selected
+	/// [`RuntimeTarget`], so only one target can be built. As there is no precompiled standard
+	/// library for custom targets, the standard library is always built from source.
+	///
+	/// A relative `path` is interpreted relative to the directory of the current crate.
+	pub fn custom_target_spec(mut self, path: impl Into<PathBuf>) -> Self {
+		self.custom_target_spec = Some(get_manifest_dir().join(path.into()));
+		self
+	}
+
+	/// Pass the cargo configuration file at `path` to the build of the runtime.
+	///
+	/// The file is passed with `--config` to all cargo commands that operate on the project the
+	/// runtime is built in, which lives in the target directory and thus, doesn't see the
+	/// `.cargo/config.toml` files of the workspace if the target directory is outside of it. This
+	/// makes it possible to use e.g. the source replacement of `cargo vendor` for fully offline
+	/// builds with `CARGO_NET_OFFLINE=true`. Relative paths in the file are interpreted relative
+	/// to its directory.
+	///
+	/// A relative `path` is interpreted relative to the directory of the current crate. The
+	/// `WASM_BUILD_CARGO_CONFIG` environment variable overrides this setting.
+	pub fn cargo_config(mut self, path: impl Into<PathBuf>) -> Self {
+		self.cargo_config = Some(get_manifest_dir().join(path.into()));
+		self
+	}
+
+	/// Use the given `linker` for the build of the runtime, e.g. `rust-lld`.
+	///
+	/// By default the linker configured for the target in the `.cargo/config.toml` files is used.
+	/// This breaks the build if the configuration of the workspace sets a linker that can't link
+	/// the runtime target, e.g. `mold`. The linker is passed as `target.<triple>.linker` with
+	/// `--config`, so it takes precedence over these files.
+	///
+	/// The `WASM_BUILD_LINKER` environment variable overrides this setting.
+	pub fn linker(mut self, linker: impl Into<String>) -> Self {
+		self.linker = Some(linker.into());
+		self
+	}
+
+	/// Use the given linker `flavor` for the build of the runtime, e.g. `wasm-lld`.
+	///
+	/// The flavor is passed with `-C linker-flavor` to `rustc`, which otherwise infers it from the
+	/// target and the name of the linker.
+	///
+	/// The `WASM_BUILD_LINKER_FLAVOR` environment variable overrides this setting.
+	pub fn linker_flavor(mut self, flavor: impl Into<String>) -> Self {
+		self.linker_flavor = Some(flavor.into());
+		self
+	}
+
+	/// Set whether the standard library crates should be built from source for this project.
+	///
+	/// By default the standard library is only built for WASM, to make sure it only uses the
+	/// WASM feature set supported by the executor. The `WASM_BUILD_STD` environment variable
+	/// still overrides this setting for all projects when it is set.
+	pub fn build_std(mut self, build_std: bool) -> Self {
+		self.build_std = Some(build_std);
+		self
+	}
+
+	/// Enable the given features of the standard library crates, e.g. `panic_immediate_abort`.
+	///
+	/// The features are passed as `-Z build-std-features=<features>` to cargo, or as
+	/// `--build-std-features` depending on [`Self::build_std_mode`], and therefore only take
+	/// effect when the standard library is built from source, see [`Self::build_std`]. With
+	/// `panic_immediate_abort` the panic formatting machinery is removed from the runtime, which
+	/// considerably reduces its size, but panics don't print a message anymore.
+	pub fn build_std_features(
+		mut self,
+		features: impl IntoIterator<Item = impl AsRef<str>>,
+	) -> Self {
+		self.build_std_features
+			.extend(features.into_iter().map(|feature| feature.as_ref().to_owned()));
+		self
+	}
+
+	/// Set how cargo is told to build the standard library from source, see [`Self::build_std`].
+	///
+	/// Building the standard library is still unstable, so it needs `-Z build-std` of a nightly
+	/// toolchain. By default, [`BuildStdMode::Detect`] uses the stable `--build-std` flag instead
+	/// as soon as the `cargo` of the toolchain provides it. The other modes force one of the
+	/// flags, e.g. for toolchains whose detection is wrong. The `WASM_BUILD_STD_MODE` environment
+	/// variable overrides this setting for all projects when it is set.
+	pub fn build_std_mode(mut self, mode: BuildStdMode) -> Self {
+		self.build_std_mode = mode;
+		self
+	}
+
+	/// Use the prebuilt sysroot at `path` instead of building the standard library from source.
+	///
+	/// Building the standard library makes every clean build recompile `core`, `alloc` and
+	/// `std`. Instead, the runtime is compiled with `--sysroot=<path>`, which needs to be a copy
+	/// of the sysroot of the toolchain whose `lib/rustlib/<target>/lib` contains the standard
+	/// library built for the runtime. As it has to match the build exactly, the sysroot needs to
+	/// contain the file `wasm-builder-sysroot.fingerprint` with the fingerprint of the `rustc`
+	/// version, the target, the profile, the features of the standard library and the
+	/// `target-cpu` and `target-feature` flags. The build fails with the expected fingerprint if
+	/// it doesn't match. The `WASM_BUILD_SYSROOT` environment variable overrides this setting for
+	/// all projects when it is set.
+	pub fn sysroot(mut self, path: impl Into<PathBuf>) -> Self {
+		self.sysroot = Some(path.into());
+		self
+	}
+
+	/// Build WASM runtimes for the `wasm32v1-none` target if the toolchain provides it.
+	///
+	/// In contrast to `wasm32-unknown-unknown`, the `wasm32v1-none` target only enables the WASM
+	/// MVP feature set and has no `std`, so its precompiled `core` and `alloc` can be used
+	/// without building them from source. Therefore the standard library is not built for this
+	/// target, unless requested with [`Self::build_std`] or `WASM_BUILD_STD`. If the toolchain
+	/// doesn't provide the target, `wasm32-unknown-unknown` is used as before.
+	///
+	/// The `WASM_BUILD_WASM32V1_NONE` environment variable overrides this setting for all
+	/// projects when it is set.
+	pub fn prefer_wasm32v1_none(mut self, prefer: bool) -> Self {
+		self.prefer_wasm32v1_none = prefer;
+		self
+	}
+
+	/// Set the preference between the installed toolchains when the toolchain isn't pinned with
+	/// [`Self::toolchain`], `WASM_BUILD_TOOLCHAIN` or a `rust-toolchain.toml`.
+	///
+	/// By default, [`ToolchainPolicy::StableFirst`] uses the toolchain of the main build or
+	/// otherwise the newest stable toolchain. Teams that rely on nightly-only features prefer
+	/// [`ToolchainPolicy::NightlyFirst`], while [`ToolchainPolicy::PinnedOnly`] makes sure the
+	/// runtime is never built with a toolchain that happens to be installed. The
+	/// `WASM_BUILD_TOOLCHAIN_POLICY` environment variable overrides this setting for all projects
+	/// when it is set.
+	pub fn toolchain_policy(mut self, policy: ToolchainPolicy) -> Self {
+		self.toolchain_policy = policy;
+		self
+	}
+
+	/// Install the toolchain pinned by [`Self::toolchain`] or `WASM_BUILD_TOOLCHAIN` using
+	/// `rustup` if it is not installed.
+	pub fn install_missing_toolchain(mut self) -> Self {
+		self.install_missing_toolchain = true;
+		self
+	}
+
+	/// Install the wasm target the runtime is built for, e.g. `wasm32-unknown-unknown`, with
+	/// `rustup target add` if the toolchain lacks it, instead of failing with instructions.
+	///
+	/// The `WASM_BUILD_AUTO_INSTALL_TARGET` environment variable overrides this setting for all
+	/// projects when it is set.
+	pub fn install_missing_target(mut self) -> Self {
+		self.install_missing_target = true;
+		self
+	}
+
+	/// Install the `rust-src` component with `rustup component add` if the standard library is
+	/// built from source and the toolchain lacks it, instead of failing with instructions.
+	///
+	/// The `WASM_BUILD_AUTO_INSTALL_RUST_SRC` environment variable overrides this setting for all
+	/// projects when it is set.
+	pub fn install_missing_rust_src(mut self) -> Self {
+		self.install_missing_rust_src = true;
+		self
+	}
+
+	/// Require the runtime to be built with at least the given `rustc` `version`, e.g. `1.77.0`.
+	///
+	/// The build fails with an error naming the found and the required version if the selected
+	/// toolchain is older. Nightly toolchains are compared by their version only, e.g.
+	/// `1.78.0-nightly` satisfies `1.77.0`.
+	pub fn require_rustc_at_least(mut self, version: impl Into<String>) -> Self {
+		self.min_rustc_version = Some(version.into());
+		self
+	}
+
+	/// Call the given `hook` before the runtime is built.
+	///
+	/// The `hook` is called once per target with the directory of the wasm project that is
+	/// generated by the builder to build the runtime. Files generated by the `hook` in this
+	/// directory don't pollute the source tree of the project. The runtime can include them by
+	/// using the `WASM_BUILD_PROJECT_DIR` environment variable, which is set for the runtime build:
+	///
+	/// ```ignore
+	/// #[cfg(substrate_runtime)]
+	/// include!(concat!(env!("WASM_BUILD_PROJECT_DIR"), "/generated.rs"));
+	/// ```
+	///
+	/// If the targets are built concurrently, see [`Self::max_parallel`], the `hook` may also be
+	/// called concurrently.
+	pub fn before_build(mut self, hook: impl Fn(&Path) + Send + Sync + 'static) -> Self {
+		self.before_build.push(Box::new(hook));
+		self
+	}
+
+	/// Call the given `hook` after the runtime was built.
+	///
+	/// The `hook` is called once per target with the [`BuildArtifacts`] of the build, e.g. to
+	/// sign or upload the runtime binaries. It is not called when the build is skipped. Like the
+	/// [`Self::before_build`] hooks, it may be called concurrently.
+	pub fn on_build_complete(
+		mut self,
+		hook: impl Fn(&BuildArtifacts) + Send + Sync + 'static,
+	) -> Self {
+		self.on_build_complete.push(Box::new(hook));
+		self
+	}
+
+	/// Set the maximum number of targets that are built concurrently, e.g. WASM and RISC-V.
+	///
+	/// By default the targets are built one after another. With `n > 1`, up to `n` cargo
+	/// invocations run concurrently and the jobs of the outer build are split between them. Their
+	/// output is interleaved, so every line is prefixed with the crate and the target it belongs
+	/// to. `n` needs to be at least 1.
+	///
+	/// In [`Self::try_build_all`], the largest `n` of the builders also limits how many of them
+	/// are built concurrently.
+	pub fn max_parallel(mut self, n: usize) -> Self {
+		self.max_parallel = n;
+		self
+	}
+
+	/// Build the runtime with profile-guided optimization.
+	///
+	/// If a [`PgoConfig::workload`] is set, the runtime is first built with
+	/// `-C profile-generate` and the workload is run with the instrumented binary. The workload
+	/// needs to write the merged profile to [`PgoConfig::profdata_path`], e.g. by executing
+	/// blocks with the runtime and merging the collected profiles with `llvm-profdata merge`.
+	/// The runtime is then built again with `-C profile-use` of this profile. Without a workload,
+	/// the existing profile is used directly.
+	///
+	/// A relative `profdata_path` is interpreted relative to the directory of the current crate.
+	pub fn pgo(mut self, config: PgoConfig) -> Self {
+		self.pgo = Some(PgoConfig {
+			profdata_path: get_manifest_dir().join(config.profdata_path),
+			..config
+		});
+		self
+	}
+
+	/// Use the prebuilt runtime binary at `path` instead of building one.
+	///
+	/// The build is skipped entirely and the generated file embeds the given binary, e.g. the
+	/// `*.compact.compressed.wasm` of a deterministic release build. A compressed binary is
+	/// decompressed for `WASM_BINARY_BLOATY`. If `blake2_256` is given, the build fails if the
+	/// binary has a different BLAKE2-256 hash. This only supports building a single target.
+	///
+	/// A relative `path` is interpreted relative to the directory of the current crate. The
+	/// `WASM_BUILD_PREBUILT` and `WASM_BUILD_PREBUILT_BLAKE2_256` environment variables override
+	/// this setting.
+	pub fn use_prebuilt(mut self, path: impl Into<PathBuf>, blake2_256: Option<[u8; 32]>) -> Self {
+		self.prebuilt = Some(Prebuilt::File(get_manifest_dir().join(path.into()), blake2_256));
+		self
+	}
+
+	/// Use the prebuilt runtime binary downloaded from `url` instead of building one.
+	///
+	/// This works like [`Self::use_prebuilt`], but the binary is downloaded with `curl` into
+	/// `OUT_DIR`, e.g. to embed the exact binary that was approved by governance. The build fails
+	/// if the downloaded binary doesn't have the `blake2_256` hash. The binary is only downloaded
+	/// again if the hash changes, and never when `CARGO_NET_OFFLINE` is set.
+	pub fn use_prebuilt_from_url(mut self, url: impl Into<String>, blake2_256: [u8; 32]) -> Self {
+		self.prebuilt = Some(Prebuilt::Url(url.into(), blake2_256));
+		self
+	}
+
+	/// Build the WASM binary.
+	///
+	/// Exits the process with an error message if the build fails. Use [`Self::try_build`] to
+	/// handle the error instead.
+	pub fn build(self) {
+		if let Err(error) = self.try_build() {
+			// We use println! + exit instead of a panic in order to have a cleaner output.
+			println!("{error}");
+			process::exit(1);
+		}
+	}
+
+	/// Build the WASM binary and return the error if the build fails.
+	///
+	/// In contrast to [`Self::build`], this function never exits the process. This is useful when
+	/// the builder is not called from a `build.rs`, but from another tool that wants to handle
+	/// the error itself.
+	pub fn try_build(mut self) -> Result<BuildOutput, WasmBuilderError> {
+		crate::hermetic::init()?;
+		let first_rerun_instruction = crate::watch::instruction_count();
+
+		let targets = match self.targets.as_deref() {
+			Some([]) =>
+				return Err(WasmBuilderError::InvalidConfiguration(
+					"At least one runtime target needs to be passed to `targets`.".into(),
+				)),
+			Some(targets) => targets.iter().fold(Vec::new(), |mut targets, target| {
+				if !targets.contains(target) {
+					targets.push(*target);
+				}
+				targets
+			}),
+			None => vec![crate::runtime_target()?],
+		};
+
+		if let Some(spec) = &self.custom_target_spec {
+			if targets.len() > 1 {
+				return Err(WasmBuilderError::InvalidConfiguration(
+					"A custom target specification can only be used when building a single target."
+						.into(),
+				))
+			}
+
+			if spec.extension().map_or(true, |ext| ext != "json") || !spec.exists() {
+				return Err(WasmBuilderError::InvalidConfiguration(format!(
+					"The custom target specification `{}` needs to be an existing JSON file.",
+					spec.display()
+				)))
+			}
+		}
+
+		if self.stack_size.map_or(false, |stack_size| stack_size % 16 != 0) {
+			return Err(WasmBuilderError::InvalidConfiguration(
+				"The stack size needs to be a multiple of 16 bytes.".into(),
+			))
+		}
+
+		if let Some(feature) = self
+			.target_features
+			.iter()
+			.find(|feature| feature.len() < 2 || !feature.starts_with(['+', '-']))
+		{
+			return Err(WasmBuilderError::InvalidConfiguration(format!(
+				"The target feature `{feature}` needs to be prefixed with `+` or `-`."
+			)))
+		}
+
+		if self.build_in_place && targets.contains(&RuntimeTarget::Riscv) {
+			return Err(WasmBuilderError::InvalidConfiguration(
+				"A RISC-V runtime can not be built in place.".into(),
+			))
+		}
+
+		if self.max_parallel == 0 {
+			return Err(WasmBuilderError::InvalidConfiguration(
+				"The maximum number of parallel builds needs to be at least 1.".into(),
+			))
+		}
+
+		match self.codegen_units {
+			Some(0) =>
+				return Err(WasmBuilderError::InvalidConfiguration(
+					"The number of codegen units needs to be at least 1.".into(),
+				)),
+			Some(units) if units != 1 && self.deterministic =>
+				return Err(WasmBuilderError::InvalidConfiguration(format!(
+					"A deterministic build requires 1 codegen unit, but {units} are configured."
+				))),
+			_ => {},
+		}
+
+		if let Some((initial, max)) = self.memory {
+			if initial % WASM_PAGE_SIZE != 0 || max % WASM_PAGE_SIZE != 0 {
+				return Err(WasmBuilderError::InvalidConfiguration(format!(
+					"The initial and the maximum memory size need to be multiples of the page size \
+					 of {WASM_PAGE_SIZE} bytes."
+				)))
+			}
+
+			if initial > max || max > MAX_WASM_MEMORY {
+				return Err(WasmBuilderError::InvalidConfiguration(format!(
+					"The initial memory size of {initial} bytes needs to be at most the maximum \
+					 memory size of {max} bytes, which needs to be at most {MAX_WASM_MEMORY} bytes."
+				)))
+			}
+		}
+
+		let constant_prefix = match self.constant_prefix.as_deref() {
+			Some(prefix) if !is_valid_identifier(prefix) =>
+				return Err(WasmBuilderError::InvalidConfiguration(format!(
+					"The constant prefix `{prefix}` is not a valid Rust identifier."
+				))),
+			Some(prefix) => format!("{prefix}_"),
+			None => String::new(),
+		};
+		let constant_options = ConstantOptions {
+			prefix: constant_prefix,
+			embed_binary: self.embed_binary,
+			blake2_256: self.generate_blake2_256,
+			runtime_version: self.generate_runtime_version,
+			#[cfg(feature = "signing")]
+			signature: self.signing_key.is_some(),
+			#[cfg(not(feature = "signing"))]
+			signature: false,
+		};
+
+		let file_path = self.file_path();
+		let wasm_binary_name = match &self.output_file {
+			Some(output_file) => Some(
+				output_file
+					.file_stem()
+					.and_then(|stem| stem.to_str())
+					.ok_or_else(|| {
+						WasmBuilderError::InvalidConfiguration(format!(
+							"The output file `{}` needs to have a valid UTF-8 file name.",
+							output_file.display()
+						))
+					})?
+					.to_owned(),
+			),
+			None => self.file_name.clone(),
+		};
+
+		if let Some(parent) = file_path.parent() {
+			fs::create_dir_all(parent).map_err(WasmBuilderError::io(parent))?;
+		}
+
+		if check_skip_build(&self.project_cargo_toml) {
+			// If we skip the build, we still want to make sure to be called when an env variable
+			// changes
+			generate_rerun_if_changed_instructions(&self.project_cargo_toml);
+
+			provide_dummy_wasm_binary_if_not_exist(&file_path, &targets, &constant_options)?;
+
+			return Ok(BuildOutput { file_path, binaries: Vec::new() })
+		}
+
+		if let Some(prebuilt) = prebuilt_binary(self.prebuilt.take())? {
+			let [target] = targets[..] else {
+				return Err(WasmBuilderError::InvalidConfiguration(
+					"A prebuilt binary can only be used when building a single target.".into(),
+				))
+			};
+
+			let (prebuilt, blake2_256) = match prebuilt {
+				Prebuilt::File(path, blake2_256) => (path, blake2_256),
+				Prebuilt::Url(url, blake2_256) => {
+					let path = file_path.with_extension("download.wasm");
+					download_prebuilt_binary(&url, blake2_256, &path)?;
+					(path, Some(blake2_256))
+				},
+			};
+
+			generate_rerun_if_changed_instructions(&self.project_cargo_toml);
+			rerun_if_changed(&prebuilt);
+
+			provide_prebuilt_wasm_binary(
+				&prebuilt,
+				blake2_256,
+				&file_path,
+				target,
+				&constant_options,
+			)?;
+
+			return Ok(BuildOutput { file_path, binaries: Vec::new() })
+		}
+
+		let config = crate::config::load(&self.project_cargo_toml)?;
+		// The toolchain of a hermetic build is given by its manifest, and without rustup the
+		// toolchain of the main build is used.
+		let pinned_toolchain = match crate::hermetic::get() {
+			None if crate::rustup_available()? =>
+				crate::config::pinned_toolchain(&self.project_cargo_toml)?,
+			_ => None,
+		};
+
+		let targets: Vec<(RuntimeTarget, String)> = targets
+			.into_iter()
+			.map(|target| {
+				// The flags of the builder are added last, so they take precedence.
+				let mut rust_flags = config.rustflags.clone();
+				rust_flags.extend(self.rust_flags.iter().cloned());
+				if target.is_wasm() {
+					if self.export_heap_base {
+						rust_flags.push("-Clink-arg=--export=__heap_base".into());
+					}
+
+					match self.import_memory {
+						Some(true) => rust_flags.push("-C link-arg=--import-memory".into()),
+						Some(false) => rust_flags.push("-C link-arg=--export-memory".into()),
+						None => {},
+					}
+
+					if let Some(stack_size) = self.stack_size {
+						rust_flags.push(format!("-C link-arg=-zstack-size={stack_size}"));
+					}
+
+					if let Some((initial, max)) = self.memory {
+						rust_flags.push(format!("-C link-arg=--initial-memory={initial}"));
+						rust_flags.push(format!("-C link-arg=--max-memory={max}"));
+					}
+
+					if self.split_debug_info {
+						rust_flags.push("-C debuginfo=2".into());
+					}
+
+					if !self.target_features.is_empty() {
+						rust_flags
+							.push(format!("-C target-feature={}", self.target_features.join(",")));
+					}
+				}
+
+				for arg in &self.link_args {
+					rust_flags
+						.push(format!("-C link-arg={}", crate::wasm_project::rustflag_word(arg)));
+				}
+
+				(target, rust_flags.into_iter().map(|f| format!("{} ", f)).collect())
+			})
+			.collect();
+
+		let project_cargo_toml = self.project_cargo_toml.clone();
+		let verification_toolchains = mem::take(&mut self.verification_toolchains);
+		let options =
+			self.build_options(config, pinned_toolchain, wasm_binary_name, targets.len())?;
+
+		if !verification_toolchains.is_empty() && options.embed_build_info {
+			return Err(WasmBuilderError::InvalidConfiguration(
+				"The runtime can't be verified with other toolchains while the `build_info` \
+				 section is embedded, as it contains the version of `rustc`."
+					.into(),
+			))
+		}
+
+		if options.check_only_enabled()? {
+			check_project(&targets, &project_cargo_toml, &options)?;
+			provide_dummy_wasm_binary(
+				&file_path,
+				&targets.iter().map(|(target, _)| *target).collect::<Vec<_>>(),
+				&constant_options,
+			)?;
+			generate_rerun_if_changed_instructions(&project_cargo_toml);
+
+			return Ok(BuildOutput { file_path, binaries: Vec::new() })
+		}
+
+		// Probing the toolchain and running cargo takes a while even if nothing changed, so skip
+		// the whole build if the fingerprint of the last one still matches.
+		let fingerprint = (env::var_os(crate::FORCE_WASM_BUILD_ENV).is_none() &&
+			crate::hermetic::get().is_none() &&
+			options.before_build.is_empty() &&
+			options.on_build_complete.is_empty() &&
+			options.pgo.is_none() &&
+			verification_toolchains.is_empty())
+		.then(|| {
+			let targets = targets
+				.iter()
+				.map(|(target, rustflags)| format!("{} {rustflags}", target.rustc_target()))
+				.collect::<Vec<_>>()
+				.join("\n");
+			let toolchain =
+				format!("{:?} {:?} {:?}", options.toolchain, options.cargo, options.rustc);
+			BuildFingerprint::new(
+				&file_path,
+				&[
+					project_cargo_toml.as_os_str().as_encoded_bytes(),
+					targets.as_bytes(),
+					toolchain.as_bytes(),
+				],
+				&options.tracked_env_vars,
+			)
+		});
+		let unchanged = fingerprint.as_ref().and_then(BuildFingerprint::unchanged).and_then(
+			|(reruns, binaries)| {
+				let binaries = binaries.as_array()?.iter().map(RuntimeBinary::from_json);
+				Some((reruns, binaries.collect::<Option<Vec<_>>>()?))
+			},
+		);
+		if let Some((reruns, binaries)) = unchanged {
+			if options.verbosity >= crate::Verbosity::Normal {
+				println!("Nothing changed since the last build of: {}", file_path.display());
+			}
+			reruns.iter().for_each(Rerun::replay);
+
+			return Ok(BuildOutput { file_path, binaries })
+		}
+		let build_start = SystemTime::now();
+
+		let binaries = build_project(
+			targets.clone(),
+			file_path.clone(),
+			&constant_options,
+			project_cargo_toml.clone(),
+			&options,
+		)?;
+
+		// The outputs of the build and the toolchain it used, which the fingerprint depends on.
+		let fingerprint_files = binaries
+			.iter()
+			.flat_map(|binary| {
+				let toolchain = match (&options.rustc, &options.cargo) {
+					(Some(binary), _) | (None, Some(binary)) => binary.clone(),
+					(None, None) => toolchain_binary(&binary.build_info.toolchain),
+				};
+				binary
+					.compact_path
+					.iter()
+					.cloned()
+					.chain([binary.bloaty_path.clone(), toolchain])
+			})
+			.chain([file_path.clone(), build_info_file_path(&file_path)])
+			.collect::<Vec<_>>();
+
+		if !verification_toolchains.is_empty() {
+			verify_with_toolchains(
+				&targets,
+				&file_path,
+				&project_cargo_toml,
+				options,
+				&binaries,
+				&verification_toolchains,
+			)?;
+		}
+
+		// As last step we need to generate our `rerun-if-changed` stuff. If a build fails, we don't
+		// want to spam the output!
+		generate_rerun_if_changed_instructions(&project_cargo_toml);
+
+		if let Some(fingerprint) = &fingerprint {
+			fingerprint.write(
+				&Rerun::since(first_rerun_instruction),
+				&fingerprint_files,
+				binaries.iter().map(RuntimeBinary::to_json).collect(),
+				build_start,
+			)?;
+		}
+
+		Ok(BuildOutput { file_path, binaries })
+	}
+
+	/// Returns the options of a build with the default settings, which are only changed by the
+	/// environment variables.
+	pub(crate) fn default_build_options() -> Result<BuildOptions, WasmBuilderError> {
+		Self::for_project(PathBuf::new()).build_options(FileConfig::default(), None, None, 1)
+	}
+
+	/// Returns the options of the build of the given number of `targets`, where the builder takes
+	/// precedence over the environment variables and the `config` of the runtime crate.
+	fn build_options(
+		self,
+		config: FileConfig,
+		pinned_toolchain: Option<String>,
+		blob_out_name_override: Option<String>,
+		targets: usize,
+	) -> Result<BuildOptions, WasmBuilderError> {
+		let tracked_env_vars = self.tracked_env_vars();
+		let options = BuildOptions {
+			features_to_enable: config
+				.features
+				.into_iter()
+				.chain(self.features_to_enable)
+				.collect(),
+			features_override: self.features_override,
+			features_to_disable: config
+				.disable_features
+				.into_iter()
+				.chain(self.features_to_disable)
+				.collect(),
+			disable_default_features: self.disable_default_features || !config.default_features,
+			blob_out_name_override,
+			check_for_runtime_version_section: !self.disable_runtime_version_section_check,
+			disable_compact: self.disable_compact || !config.compact,
+			rustflags_after_env: self.rust_flags_after_env.join(" "),
+			cargo_args: self.cargo_args,
+			profile: self.profile.or(config.profile),
+			custom_target_spec: self.custom_target_spec,
+			cargo_config: self.cargo_config,
+			linker: self.linker,
+			linker_flavor: self.linker_flavor,
+			build_std: self.build_std,
+			build_std_features: self.build_std_features,
+			sysroot: self.sysroot,
+			build_std_mode: self.build_std_mode,
+			disabled_target_features: self
+				.target_features
+				.iter()
+				.filter_map(|feature| feature.strip_prefix('-').map(Into::into))
+				.collect(),
+			prefer_wasm32v1_none: self.prefer_wasm32v1_none,
+			// The toolchain of the builder takes precedence over `WASM_BUILD_TOOLCHAIN`, while the
+			// environment variable takes precedence over the configuration file and the toolchain
+			// file.
+			toolchain: self.toolchain.or_else(|| {
+				config
+					.toolchain
+					.or(pinned_toolchain)
+					.filter(|_| env::var_os(crate::WASM_BUILD_TOOLCHAIN).is_none())
+			}),
+			cargo: self.cargo,
+			rustc: self.rustc,
+			toolchain_policy: self.toolchain_policy,
+			install_missing_toolchain: self.install_missing_toolchain,
+			install_missing_target: self.install_missing_target,
+			install_missing_rust_src: self.install_missing_rust_src,
+			min_rustc_version: self
+				.min_rustc_version
+				.map(|version| {
+					Version::parse_release(&version).ok_or_else(|| {
+						WasmBuilderError::InvalidConfiguration(format!(
+							"`{version}` passed to `require_rustc_at_least` is not a version like \
+							 `1.77.0`.",
+						))
+					})
+				})
+				.transpose()?,
+			wasm_opt_level: self.wasm_opt_level,
+			strip_custom_sections: self.strip_custom_sections,
+			split_debug_info: self.split_debug_info,
+			keep_name_section: self.keep_name_section,
+			lto: self.lto,
+			codegen_units: self.codegen_units,
+			deterministic: self.deterministic,
+			embed_build_info: self.embed_build_info,
+			build_info_timestamp: self.build_info_timestamp,
+			generate_digest: self.generate_digest,
+			emit_size_report: self.emit_size_report,
+			emit_timings: self.emit_timings,
+			use_rustc_wrapper: self.use_rustc_wrapper,
+			rustc_env: self.rustc_env,
+			artifact_cache: self.artifact_cache,
+			artifact_cache_backend: self.artifact_cache_backend,
+			shared_target_dir: self.shared_target_dir,
+			incremental: self.incremental,
+			locked: self.locked,
+			build_in_place: self.build_in_place,
+			check_only: self.check_only,
+			rerun_exclusions: self.rerun_exclusions,
+			tracked_env_vars,
+			verbosity: crate::verbosity()?,
+			#[cfg(feature = "signing")]
+			signing_key: self.signing_key,
+			blob_size_limit: self.blob_size_limit,
+			validation_policy: self.validation_policy,
+			required_exports: self.required_exports,
+			allowed_host_functions: self.allowed_host_functions,
+			import_memory: self.import_memory,
+			#[cfg(feature = "wasmprinter")]
+			emit_wat: self.emit_wat,
+			before_build: self.before_build,
+			on_build_complete: self.on_build_complete,
+			max_parallel: self.max_parallel.min(targets),
+			concurrent_builds: self.max_parallel.min(targets) * self.concurrent_builders,
+			pgo: self.pgo,
+			#[cfg(feature = "metadata-hash")]
+			enable_metadata_hash: self
+				.enable_metadata_hash
+				.map(MetadataHashConfig::validated)
+				.transpose()?,
+		};
+
+		if options.deterministic {
+			let toolchain =
+				options.toolchain.clone().or_else(|| env::var(crate::WASM_BUILD_TOOLCHAIN).ok());
+			match toolchain {
+				Some(toolchain) if crate::is_pinned_toolchain(&toolchain) => {},
+				toolchain =>
+					return Err(WasmBuilderError::InvalidConfiguration(format!(
+						"A deterministic build requires a pinned toolchain, e.g. `1.81.0` or \
+						 `nightly-2024-09-01`, but {}. Pin it with `WasmBuilder::toolchain` or \
+						 `WASM_BUILD_TOOLCHAIN`.",
+						toolchain.map_or_else(
+							|| "none is set".into(),
+							|toolchain| format!("`{toolchain}` is used")
+						),
+					))),
+			}
+		}
+
+		Ok(options)
+	}
+
+	/// Returns the path to the `Cargo.toml` of the project that is built.
+	pub(crate) fn project_cargo_toml(&self) -> &Path {
+		&self.project_cargo_toml
+	}
+
+	/// Returns the path of the generated file.
+	fn file_path(&self) -> PathBuf {
+		let out_dir = crate::out_dir();
+
+		match &self.output_file {
+			Some(output_file) => out_dir.join(output_file),
+			None => out_dir.join(self.file_name.as_deref().unwrap_or("wasm_binary.rs")),
+		}
+	}
+
+	/// Build multiple WASM binaries from the same `build.rs`.
+	///
+	/// This is useful for nodes that embed multiple runtimes, e.g. for different networks. Each
+	/// builder generates its own file in `OUT_DIR`. Builders that did not set a file name using
+	/// [`Self::set_file_name`] or [`Self::output_file`] generate `<project_name>_wasm_binary.rs`,
+	/// where `project_name` is the name of the project with `-` replaced by `_`. Every generated
+	/// file needs to be included separately, for example into a module per runtime:
+	///
+	/// ```ignore
+	/// mod mainnet {
+	///     include!(concat!(env!("OUT_DIR"), "/mainnet_runtime_wasm_binary.rs"));
+	/// }
+	/// ```
+	///
+	/// Exits the build with an error if two builders would generate the same file or if one of the
+	/// builds fails. Use [`Self::try_build_all`] to handle the error instead.
+	pub fn build_all(builders: impl IntoIterator<Item = WasmBuilder>) {
+		if let Err(error) = Self::try_build_all(builders) {
+			// We use println! + exit instead of a panic in order to have a cleaner output.
+			println!("{error}");
+			process::exit(1);
+		}
+	}
+
+	/// Build multiple WASM binaries from the same `build.rs` and return the error if one of the
+	/// builds fails.
+	///
+	/// See [`Self::build_all`] for more information. Returns the outputs of all builds in the
+	/// order of the given `builders`.
+	///
+	/// The builders are built concurrently if one of them allows it with [`Self::max_parallel`].
+	/// Builders of the same project share its wasm project, so they are still built one after
+	/// another. The jobs of the outer build are split between all concurrent cargo invocations.
+	pub fn try_build_all(
+		builders: impl IntoIterator<Item = WasmBuilder>,
+	) -> Result<Vec<BuildOutput>, WasmBuilderError> {
+		let builders = builders
+			.into_iter()
+			.map(|mut builder| {
+				if builder.file_name.is_none() && builder.output_file.is_none() {
+					let project_name =
+						crate::wasm_project::get_crate_name(&builder.project_cargo_toml);
+					builder.file_name =
+						Some(format!("{}_wasm_binary.rs", project_name.replace('-', "_")));
+				}
+				builder
+			})
+			.collect::<Vec<_>>();
+
+		crate::hermetic::init()?;
+		let mut file_paths = HashSet::new();
+		for builder in &builders {
+			let file_path = builder.file_path();
+			if !file_paths.insert(file_path.clone()) {
+				return Err(WasmBuilderError::InvalidConfiguration(format!(
+					"Multiple wasm builders generate `{}`. \
+					 Use `set_file_name` to give every builder a distinct file name.",
+					file_path.display(),
+				)))
+			}
+		}
+
+		let max_parallel = builders.iter().map(|builder| builder.max_parallel).max().unwrap_or(1);
+		let count = builders.len();
+		let mut groups: Vec<Vec<(usize, WasmBuilder)>> = Vec::new();
+		for (index, builder) in builders.into_iter().enumerate() {
+			match groups
+				.iter_mut()
+				.find(|group| group[0].1.project_cargo_toml == builder.project_cargo_toml)
+			{
+				Some(group) => group.push((index, builder)),
+				None => groups.push(vec![(index, builder)]),
+			}
+		}
+
+		let parallel = max_parallel.min(groups.len());
+		if parallel <= 1 {
+			let mut builders = groups.into_iter().flatten().collect::<Vec<_>>();
+			builders.sort_by_key(|(index, _)| *index);
+			return builders.into_iter().map(|(_, builder)| builder.try_build()).collect()
+		}
+
+		// Every thread builds the next group of builders that isn't built yet, until all are
+		// built.
+		let groups = Mutex::new(groups.into_iter());
+		let results = Mutex::new((0..count).map(|_| None).collect::<Vec<_>>());
+		thread::scope(|scope| {
+			for _ in 0..parallel {
+				scope.spawn(|| loop {
+					let next =
+						groups.lock().expect("No thread panics while holding the lock; qed").next();
+					let Some(group) = next else { break };
+					for (index, mut builder) in group {
+						builder.concurrent_builders = parallel;
+						let result = builder.try_build();
+						results.lock().expect("No thread panics while holding the lock; qed")
+							[index] = Some(result);
+					}
+				});
+			}
+		});
+
+		results
+			.into_inner()
+			.expect("No thread panics while holding the lock; qed")
+			.into_iter()
+			.map(|result| result.expect("Every builder was built; qed"))
+			.collect()
+	}
+}
+
+/// Generate the name of the skip build environment variable for the given crate.
+fn generate_crate_skip_build_env_name(crate_name: &str) -> String {
+	format!("SKIP_{}_WASM_BUILD", crate_name.to_uppercase().replace('-', "_"))
+}
+
+/// Returns the skip build environment variables for the current crate and the project being built.
+///
+/// Both are the same when the current crate is built as wasm binary.
+fn crate_skip_build_env_names(project_cargo_toml: &Path) -> Vec<String> {
+	let project_crate = crate::wasm_project::get_crate_name(project_cargo_toml);
+	// Outside of a `build.rs`, e.g. in a hermetic build, only the project being built is known.
+	let current_crate = env::var("CARGO_PKG_NAME").unwrap_or_else(|_| project_crate.clone());
+
+	let mut names = vec![generate_crate_skip_build_env_name(&current_crate)];
+	if project_crate != current_crate {
+		names.push(generate_crate_skip_build_env_name(&project_crate));
+	}
+	names
+}
+
+/// Checks if the build of the WASM binary should be skipped.
+fn check_skip_build(project_cargo_toml: &Path) -> bool {
+	env::var(crate::SKIP_BUILD_ENV).is_ok() ||
+		crate_skip_build_env_names(project_cargo_toml).iter().any(|n| env::var(n).is_ok()) ||
+		// If we are running in docs.rs, let's skip building.
+		// https://docs.rs/about/builds#detecting-docsrs
+		env::var("DOCS_RS").is_ok()
+}
+
+/// A prebuilt binary that is used instead of building one.
+enum Prebuilt {
+	/// The binary at the given path, with its expected BLAKE2-256 hash.
+	File(PathBuf, Option<[u8; 32]>),
+	/// The binary at the given URL, with its expected BLAKE2-256 hash.
+	Url(String, [u8; 32]),
+}
+
+/// Returns the prebuilt binary that should be used instead of building one.
+///
+/// The `WASM_BUILD_PREBUILT` environment variable takes precedence over the `configured` binary. A
+/// relative path in it is interpreted relative to the directory of the current crate, like for
+/// [`WasmBuilder::use_prebuilt`], as the path is included from `OUT_DIR`.
+fn prebuilt_binary(configured: Option<Prebuilt>) -> Result<Option<Prebuilt>, WasmBuilderError> {
+	let Some(path) = env::var_os(crate::WASM_BUILD_PREBUILT) else { return Ok(configured) };
+
+	let blake2_256 = match env::var(crate::WASM_BUILD_PREBUILT_BLAKE2_256) {
+		Ok(hash) =>
+			Some(crate::from_hex(hash.trim()).and_then(|hash| hash.try_into().ok()).ok_or_else(
+				|| WasmBuilderError::InvalidEnvironmentVariable {
+					name: crate::WASM_BUILD_PREBUILT_BLAKE2_256,
+					value: hash,
+					expected: "a hex encoded 32 byte hash".into(),
+				},
+			)?),
+		Err(_) => None,
+	};
+
+	Ok(Some(Prebuilt::File(get_manifest_dir().join(path), blake2_256)))
+}
+
+/// Download the prebuilt binary at `url` to `dest`.
+///
+/// Nothing is downloaded if `dest` already has the `blake2_256` hash.
+fn download_prebuilt_binary(
+	url: &str,
+	blake2_256: [u8; 32],
+	dest: &Path,
+) -> Result<(), WasmBuilderError> {
+	if fs::read(dest).map_or(false, |blob| sp_crypto_hashing::blake2_256(&blob) == blake2_256) {
+		return Ok(())
+	}
+
+	crate::wasm_project::require_network(&format!("downloading the prebuilt binary `{url}`"))?;
+
+	let output = process::Command::new("curl")
+		.args(["--silent", "--show-error", "--location", "--fail", "--output"])
+		.arg(dest)
+		.arg(url)
+		.output()
+		.map_err(|error| {
+			WasmBuilderError::MissingPrerequisites(format!(
+				"Failed to run `curl` to download the prebuilt binary: {error}\nInstall `curl` with \
+				 the package manager of the system, or download the binary and pass its path."
+			))
+		})?;
+	if !output.status.success() {
+		return Err(WasmBuilderError::InvalidConfiguration(format!(
+			"Failed to download the prebuilt binary `{url}`: {}",
+			String::from_utf8_lossy(&output.stderr).trim()
+		)))
+	}
+
+	Ok(())
+}
+
+/// Generate the file at `file_path` for the prebuilt binary at `prebuilt`.
+///
+/// Fails if the binary doesn't have the `expected_blake2_256` hash.
+fn provide_prebuilt_wasm_binary(
+	prebuilt: &Path,
+	expected_blake2_256: Option<[u8; 32]>,
+	file_path: &Path,
+	target: RuntimeTarget,
+	constant_options: &ConstantOptions,
+) -> Result<(), WasmBuilderError> {
+	let blob = fs::read(prebuilt).map_err(WasmBuilderError::io(prebuilt))?;
+	let blake2_256 = sp_crypto_hashing::blake2_256(&blob);
+	if let Some(expected) = expected_blake2_256.filter(|expected| *expected != blake2_256) {
+		return Err(WasmBuilderError::InvalidRuntimeBlob(format!(
+			"The prebuilt binary `{}` has the BLAKE2-256 hash {}, but {} is expected.",
+			prebuilt.display(),
+			crate::to_hex(&blake2_256),
+			crate::to_hex(&expected),
+		)))
+	}
+
+	let decompressed =
+		sp_maybe_compressed_blob::decompress(&blob, sp_maybe_compressed_blob::CODE_BLOB_BOMB_LIMIT)
+			.map_err(|error| {
+				WasmBuilderError::InvalidRuntimeBlob(format!(
+					"Failed to decompress the prebuilt binary `{}`: {error}",
+					prebuilt.display()
+				))
+			})?;
+	// The bloaty binary of a compressed binary is its decompressed version.
+	let bloaty_path = match decompressed {
+		std::borrow::Cow::Owned(decompressed) => {
+			let bloaty_path = file_path.with_extension("prebuilt.wasm");
+			fs::write(&bloaty_path, decompressed).map_err(WasmBuilderError::io(&bloaty_path))?;
+			Some(bloaty_path)
+		},
+		std::borrow::Cow::Borrowed(_) => None,
+	};
+
+	let runtime_version = match constant_options.runtime_version && target.is_wasm() {
+		true => crate::runtime_version::read_runtime_version(
+			bloaty_path.as_deref().unwrap_or(prebuilt),
+		)?,
+		false => None,
+	};
+
+	let escape = |path: &Path| path.display().to_string().escape_default().to_string();
+	let binary_path = escape(prebuilt);
+	let bloaty_path = bloaty_path.as_deref().map(escape);
+	let binary = BuiltBinary {
+		binary_path: &binary_path,
+		bloaty_path: bloaty_path.as_deref().unwrap_or(&binary_path),
+		compressed_path: bloaty_path.is_some().then_some(&*binary_path),
+		blake2_256,
+		runtime_version: runtime_version.as_ref(),
+		signature: None,
+		toolchain: None,
+		prebuilt: true,
+	};
+
+	crate::write_file_if_changed(
+		file_path,
+		generate_binary_constants(target, true, constant_options, Some(&binary)),
+	)
+}
+
+/// Returns if `name` is a valid Rust identifier.
+fn is_valid_identifier(name: &str) -> bool {
+	let mut chars = name.chars();
+	chars.next().map_or(false, |c| c.is_ascii_alphabetic() || c == '_') &&
+		chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The options for generating the constants of the generated file.
+struct ConstantOptions {
+	/// The prefix of all constant names.
+	prefix: String,
+	/// Should the bytes of the binaries be embedded?
+	embed_binary: bool,
+	/// Should the BLAKE2-256 hashes of the binaries be generated?
+	blake2_256: bool,
+	/// Should the constants of the runtime version be generated?
+	runtime_version: bool,
+	/// Should the constant of the signature be generated?
+	signature: bool,
+}
+
+/// A built binary for which the constants are generated.
+struct BuiltBinary<'a> {
+	/// The escaped path to the final binary.
+	binary_path: &'a str,
+	/// The escaped path to the bloaty binary.
+	bloaty_path: &'a str,
+	/// The escaped path to the compressed binary, if the binary was compressed.
+	compressed_path: Option<&'a str>,
+	/// The BLAKE2-256 hash of the final binary.
+	blake2_256: [u8; 32],
+	/// The version of the runtime.
+	runtime_version: Option<&'a RuntimeVersionInfo>,
+	/// The signature of the final binary.
+	signature: Option<&'a [u8; 64]>,
+	/// The version of `rustc` and the toolchain the binary was built with, unknown for a prebuilt
+	/// binary.
+	toolchain: Option<(&'a str, &'a str)>,
+	/// Is this a prebuilt binary, see [`WasmBuilder::use_prebuilt`]?
+	prebuilt: bool,
+}
+
+/// Provide a dummy WASM binary if there doesn't exist one.
+fn provide_dummy_wasm_binary_if_not_exist(
+	file_path: &Path,
+	targets: &[RuntimeTarget],
+	constant_options: &ConstantOptions,
+) -> Result<(), WasmBuilderError> {
+	if !file_path.exists() {
+		provide_dummy_wasm_binary(file_path, targets, constant_options)?;
+	}
+
+	Ok(())
+}
+
+/// Provide a dummy WASM binary, i.e. write `None` constants for all `targets`.
+fn provide_dummy_wasm_binary(
+	file_path: &Path,
+	targets: &[RuntimeTarget],
+	constant_options: &ConstantOptions,
+) -> Result<(), WasmBuilderError> {
+	crate::write_file_if_changed(
+		file_path,
+		targets
+			.iter()
+			.map(|target| {
+				generate_binary_constants(*target, targets.len() == 1, constant_options, None)
+			})
+			.collect::<String>(),
+	)
+}
+
+/// Type-check the project for all `targets`, without building a binary.
+fn check_project(
+	targets: &[(RuntimeTarget, String)],
+	project_cargo_toml: &Path,
+	options: &BuildOptions,
+) -> Result<(), WasmBuilderError> {
+	for (target, default_rustflags) in targets {
+		let cargo_cmd = crate::prerequisites::check(*target, options)?;
+		crate::wasm_project::create_and_check(
+			*target,
+			project_cargo_toml,
+			default_rustflags,
+			cargo_cmd,
+			options,
+		)?;
+	}
+
+	Ok(())
+}
+
+/// Generate the constants of the generated file for the binaries of the given `target`.
+///
+/// `binary` is the built binary or `None` if the build was skipped. Besides the bytes of the
+/// binaries, their paths are also provided as `*_PATH` constants.
+fn generate_binary_constants(
+	target: RuntimeTarget,
+	is_only_target: bool,
+	constant_options: &ConstantOptions,
+	binary: Option<&BuiltBinary>,
+) -> String {
+	let (bytes, bloaty_bytes, path, bloaty_path, blake2_256) = match binary {
+		Some(binary) => (
+			format!(r#"Some(include_bytes!("{}"))"#, binary.binary_path),
+			format!(r#"Some(include_bytes!("{}"))"#, binary.bloaty_path),
+			format!(r#"Some("{}")"#, binary.binary_path),
+			format!(r#"Some("{}")"#, binary.bloaty_path),
+			format!("Some({:?})", binary.blake2_256),
+		),
+		None => ("None".into(), "None".into(), "None".into(), "None".into(), "None".into()),
+	};
+	let prefix = &constant_options.prefix;
+
+	let mut constants = String::new();
+	if target == RuntimeTarget::Wasm || is_only_target {
+		if target == RuntimeTarget::Wasm &&
+			constant_options.embed_binary &&
+			binary.map_or(false, |binary| {
+				!binary.prebuilt && binary.binary_path == binary.bloaty_path
+			}) {
+			constants.push_str(
+				"// The binary was not compacted, because the compaction was disabled or the \
+				 runtime was built\n// with the `dev` profile. So, `WASM_BINARY` is the same as \
+				 `WASM_BINARY_BLOATY`.\n",
+			);
+		}
+		let compressed_path = binary.and_then(|binary| binary.compressed_path);
+		if constant_options.embed_binary {
+			constants
+				.push_str(&format!("pub const {prefix}WASM_BINARY: Option<&[u8]> = {bytes};\n"));
+			constants.push_str(&format!(
+				"pub const {prefix}WASM_BINARY_BLOATY: Option<&[u8]> = {bloaty_bytes};\n"
+			));
+			constants.push_str(&format!(
+				"pub const {prefix}WASM_BINARY_COMPRESSED: Option<&[u8]> = {};\n",
+				compressed_path
+					.map_or_else(|| "None".into(), |p| format!(r#"Some(include_bytes!("{p}"))"#)),
+			));
+		}
+		constants
+			.push_str(&format!("pub const {prefix}WASM_BINARY_PATH: Option<&str> = {path};\n"));
+		constants.push_str(&format!(
+			"pub const {prefix}WASM_BINARY_BLOATY_PATH: Option<&str> = {bloaty_path};\n"
+		));
+		constants.push_str(&format!(
+			"pub const {prefix}WASM_BINARY_COMPRESSED_PATH: Option<&str> = {};\n",
+			compressed_path.map_or_else(|| "None".into(), |p| format!(r#"Some("{p}")"#)),
+		));
+		if constant_options.blake2_256 {
+			constants.push_str(&format!(
+				"pub const {prefix}WASM_BINARY_BLAKE2_256: Option<[u8; 32]> = {blake2_256};\n"
+			));
+		}
+		if constant_options.runtime_version {
+			let version = binary.and_then(|binary| binary.runtime_version);
+			let (spec_name, spec_version, impl_version) = match version {
+				Some(version) => (
+					format!("Some({:?})", version.spec_name),
+					format!("Some({})", version.spec_version),
+					format!("Some({})", version.impl_version),
+				),
+				None => ("None".into(), "None".into(), "None".into()),
+			};
+			constants
+				.push_str(&format!("pub const {prefix}SPEC_NAME: Option<&str> = {spec_name};\n"));
+			constants.push_str(&format!(
+				"pub const {prefix}SPEC_VERSION: Option<u32> = {spec_version};\n"
+			));
+			constants.push_str(&format!(
+				"pub const {prefix}IMPL_VERSION: Option<u32> = {impl_version};\n"
+			));
+		}
+		let (rustc_version, toolchain) = match binary.and_then(|binary| binary.toolchain) {
+			Some((rustc_version, toolchain)) =>
+				(format!("Some({rustc_version:?})"), format!("Some({toolchain:?})")),
+			None => ("None".into(), "None".into()),
+		};
+		constants.push_str(&format!(
+			"pub const {prefix}WASM_BUILD_RUSTC_VERSION: Option<&str> = {rustc_version};\n"
+		));
+		constants.push_str(&format!(
+			"pub const {prefix}WASM_BUILD_TOOLCHAIN: Option<&str> = {toolchain};\n"
+		));
+		if target == RuntimeTarget::Wasm && constant_options.signature {
+			let signature = binary.and_then(|binary| binary.signature);
+			constants.push_str(&format!(
+				"pub const {prefix}WASM_BINARY_SIGNATURE: Option<[u8; 64]> = {};\n",
+				signature.map_or_else(|| "None".into(), |s| format!("Some({s:?})")),
+			));
+		}
+	}
+	if target != RuntimeTarget::Wasm {
+		let name = if target == RuntimeTarget::Riscv { "RISCV" } else { "WASM64" };
+		if constant_options.embed_binary {
+			constants
+				.push_str(&format!("pub const {prefix}{name}_BINARY: Option<&[u8]> = {bytes};\n"));
+		}
+		constants
+			.push_str(&format!("pub const {prefix}{name}_BINARY_PATH: Option<&str> = {path};\n"));
+		if constant_options.blake2_256 {
+			constants.push_str(&format!(
+				"pub const {prefix}{name}_BINARY_BLAKE2_256: Option<[u8; 32]> = {blake2_256};\n"
+			));
+		}
+	}
+	constants
+}
+
+/// Generate the `rerun-if-changed` instructions for cargo to make sure that the WASM binary is
+/// rebuilt when needed.
+fn generate_rerun_if_changed_instructions(project_cargo_toml: &Path) {
+	// A hermetic build is rerun by the build system that declared its inputs.
+	if crate::hermetic::get().is_some() {
+		return
+	}
+
+	// Make sure that the `build.rs` is called again if one of the following env variables changes.
+	rerun_if_env_changed(crate::SKIP_BUILD_ENV);
+	rerun_if_env_changed(crate::FORCE_WASM_BUILD_ENV);
+	rerun_if_env_changed(crate::WASM_BUILD_PREBUILT);
+	rerun_if_env_changed(crate::WASM_BUILD_PREBUILT_BLAKE2_256);
+	for name in crate_skip_build_env_names(project_cargo_toml) {
+		rerun_if_env_changed(&name);
+	}
+}
+
+/// Build the currently built project as wasm binary.
+///
+/// The current project is determined by using the `CARGO_MANIFEST_DIR` environment variable.
+///
+/// `targets` - The targets to build for together with the default `RUSTFLAGS` that will always be
+/// set for the build of the respective target.
+///
 /// `file_name` - The name + path of the file being generated. The file contains the
 /// constant `WASM_BINARY`, which contains the built wasm binary.
 ///
-/// `project_cargo_toml` - The path to the `Cargo.toml` of the project that should be built.
-///
-/// `default_rustflags` - Default `RUSTFLAGS` that will always be set for the build.
+/// `constant_options` - The options for the constants in the generated file.
 ///
-/// `features_to_enable` - Features that should be enabled for the project.
+/// `project_cargo_toml` - The path to the `Cargo.toml` of the project that should be built.
 ///
-/// `wasm_binary_name` - The optional wasm binary name that is extended with
-/// `.compact.compressed.wasm`. If `None`, the project name will be used.
+/// `options` - The options for building the project.
 ///
-/// `check_for_runtime_version_section` - Should the wasm binary be checked for the
-/// `runtime_version` section?
+/// Returns the binaries that were built.
 fn build_project(
-	target: RuntimeTarget,
+	targets: Vec<(RuntimeTarget, String)>,
 	file_name: PathBuf,
+	constant_options: &ConstantOptions,
 	project_cargo_toml: PathBuf,
-	default_rustflags: String,
-	features_to_enable: Vec<String>,
-	wasm_binary_name: Option<String>,
-	check_for_runtime_version_section: bool,
-	#[cfg(feature = "metadata-hash")] enable_metadata_hash: Option<MetadataExtraInfo>,
-) {
-	let cargo_cmd = match crate::prerequisites::check(target) {
-		Ok(cmd) => cmd,
-		Err(err_msg) => {
-			eprintln!("{}", err_msg);
-			process::exit(1);
-		},
-	};
+	options: &BuildOptions,
+) -> Result<Vec<RuntimeBinary>, WasmBuilderError> {
+	let is_only_target = targets.len() == 1;
+	let mut constants = String::new();
+	let mut binaries = Vec::new();
 
-	let (wasm_binary, bloaty) = crate::wasm_project::create_and_compile(
-		target,
-		&project_cargo_toml,
-		&default_rustflags,
-		cargo_cmd,
-		features_to_enable,
-		wasm_binary_name,
-		check_for_runtime_version_section,
-		#[cfg(feature = "metadata-hash")]
-		enable_metadata_hash,
-	);
+	let compile = |(target, default_rustflags): &(RuntimeTarget, String)| {
+		let cargo_cmd = crate::prerequisites::check(*target, options)?;
+
+		crate::wasm_project::create_and_compile(
+			*target,
+			&project_cargo_toml,
+			default_rustflags,
+			cargo_cmd,
+			options,
+		)
+	};
+	let results = if options.max_parallel > 1 {
+		// Every thread builds the next target that isn't built yet, until all are built.
+		let next = AtomicUsize::new(0);
+		let results = Mutex::new(targets.iter().map(|_| None).collect::<Vec<_>>());
+		thread::scope(|scope| {
+			for _ in 0..options.max_parallel {
+				scope.spawn(|| loop {
+					let index = next.fetch_add(1, Ordering::Relaxed);
+					let Some(target) = targets.get(index) else { break };
+					let result = compile(target);
+					results.lock().expect("No thread panics while holding the lock; qed")[index] =
+						Some(result);
+				});
+			}
+		});
 
-	let (wasm_binary, wasm_binary_bloaty) = if let Some(wasm_binary) = wasm_binary {
-		(wasm_binary.wasm_binary_path_escaped(), bloaty.bloaty_path_escaped())
+		results
+			.into_inner()
+			.expect("No thread panics while holding the lock; qed")
+			.into_iter()
+			.map(|result| result.expect("Every target was built; qed"))
+			.collect::<Result<Vec<_>, _>>()?
 	} else {
-		(bloaty.bloaty_path_escaped(), bloaty.bloaty_path_escaped())
+		targets.iter().map(compile).collect::<Result<Vec<_>, _>>()?
 	};
 
-	crate::write_file_if_changed(
-		file_name,
-		format!(
-			r#"
-				pub const WASM_BINARY: Option<&[u8]> = Some(include_bytes!("{wasm_binary}"));
-				pub const WASM_BINARY_BLOATY: Option<&[u8]> = Some(include_bytes!("{wasm_binary_bloaty}"));
-			"#,
-			wasm_binary = wasm_binary,
-			wasm_binary_bloaty = wasm_binary_bloaty,
-		),
+	for (&(target, _), (wasm_binary, bloaty, build_info)) in targets.iter().zip(results) {
+		let (wasm_binary_escaped, wasm_binary_bloaty) = if let Some(wasm_binary) = &wasm_binary {
+			(wasm_binary.wasm_binary_path_escaped(), bloaty.bloaty_path_escaped())
+		} else {
+			(bloaty.bloaty_path_escaped(), bloaty.bloaty_path_escaped())
+		};
+		let compressed_escaped = wasm_binary
+			.as_ref()
+			.filter(|binary| binary.is_compressed())
+			.map(|binary| binary.wasm_binary_path_escaped());
+		let built_binary = BuiltBinary {
+			binary_path: &wasm_binary_escaped,
+			bloaty_path: &wasm_binary_bloaty,
+			compressed_path: compressed_escaped.as_deref(),
+			blake2_256: build_info.compact_blake2_256.unwrap_or(build_info.bloaty_blake2_256),
+			runtime_version: build_info.runtime_version.as_ref(),
+			signature: build_info.signature.as_ref(),
+			toolchain: Some((build_info.rustc_version.trim(), &build_info.toolchain)),
+			prebuilt: false,
+		};
+
+		constants.push_str(&generate_binary_constants(
+			target,
+			is_only_target,
+			constant_options,
+			Some(&built_binary),
+		));
+
+		binaries.push(RuntimeBinary {
+			target,
+			compact_path: wasm_binary.map(|b| b.wasm_binary_path().to_path_buf()),
+			bloaty_path: bloaty.bloaty_path().to_path_buf(),
+			build_info,
+		});
+	}
+
+	crate::write_file_if_changed(&file_name, constants)?;
+
+	let build_info = serde_json::Value::Array(
+		binaries.iter().map(|binary| binary.build_info.to_json()).collect(),
 	);
+	crate::write_file_if_changed(
+		build_info_file_path(&file_name),
+		serde_json::to_string_pretty(&build_info).expect("Build info is valid json; qed"),
+	)?;
+
+	Ok(binaries)
+}
+
+/// Build the runtime for the `targets` with each of the `toolchains` and compare the blobs with the
+/// `binaries` of the normal build, see [`WasmBuilder::verify_with_toolchains`].
+///
+/// The results are written next to the generated file at `file_name`.
+fn verify_with_toolchains(
+	targets: &[(RuntimeTarget, String)],
+	file_name: &Path,
+	project_cargo_toml: &Path,
+	mut options: BuildOptions,
+	binaries: &[RuntimeBinary],
+	toolchains: &[String],
+) -> Result<(), WasmBuilderError> {
+	let blob_hash = |info: &BuildInfo| info.compact_blake2_256.unwrap_or(info.bloaty_blake2_256);
+
+	let mut results = Vec::new();
+	for ((target, default_rustflags), binary) in targets.iter().zip(binaries) {
+		let blob_name =
+			binary.bloaty_path.file_stem().and_then(|s| s.to_str()).unwrap_or("runtime");
+		let expected = blob_hash(&binary.build_info);
+
+		for toolchain in toolchains {
+			options.toolchain = Some(toolchain.clone());
+			let cargo_cmd = crate::prerequisites::check(*target, &options)?;
+			let (blob_path, rustc_version) = crate::wasm_project::compile_for_verification(
+				*target,
+				project_cargo_toml,
+				default_rustflags,
+				cargo_cmd,
+				&options,
+				&format!("verify-{toolchain}"),
+			)?;
+
+			let actual = crate::wasm_project::blake2_256_of_file(&blob_path)?;
+			if actual != expected {
+				build_helper::warning!(
+					"The runtime `{blob_name}` built with `{toolchain}` ({}) differs from the one \
+					 built with `{}`: {} != {}",
+					rustc_version.trim(),
+					binary.build_info.toolchain,
+					crate::to_hex(&actual),
+					crate::to_hex(&expected),
+				);
+			} else if options.verbosity >= crate::Verbosity::Normal {
+				println!("The runtime `{blob_name}` built with `{toolchain}` is identical.");
+			}
+
+			results.push(serde_json::json!({
+				"target": target.rustc_target(),
+				"toolchain": toolchain,
+				"rustc_version": rustc_version.trim(),
+				"blake2_256": crate::to_hex(&actual),
+				"expected_blake2_256": crate::to_hex(&expected),
+				"matches": actual == expected,
+			}));
+		}
+	}
+
+	let stem = file_name.file_stem().and_then(|s| s.to_str()).unwrap_or("wasm_binary");
+	let stem = stem.strip_suffix("_binary").unwrap_or(stem);
+	crate::write_file_if_changed(
+		file_name.with_file_name(format!("{stem}_toolchain_verification.json")),
+		serde_json::to_string_pretty(&serde_json::Value::Array(results))
+			.expect("The verification results are valid JSON; qed"),
+	)
+}
+
+/// Returns the path of the build info file that belongs to the generated file at `file_name`.
+///
+/// The `_binary` suffix of the file stem is replaced by `_build_info`, e.g. `wasm_binary.rs`
+/// becomes `wasm_build_info.json`.
+fn build_info_file_path(file_name: &Path) -> PathBuf {
+	let stem = file_name.file_stem().and_then(|s| s.to_str()).unwrap_or("wasm_binary");
+	let stem = stem.strip_suffix("_binary").unwrap_or(stem);
+
+	file_name.with_file_name(format!("{stem}_build_info.json"))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn runtime_binary_json_roundtrip() {
+		let binary = RuntimeBinary {
+			target: RuntimeTarget::Wasm,
+			compact_path: Some("/out/runtime.compact.compressed.wasm".into()),
+			bloaty_path: "/out/runtime.wasm".into(),
+			build_info: BuildInfo {
+				target: RuntimeTarget::Wasm,
+				rustc_version: "rustc 1.81.0".into(),
+				toolchain: "stable".into(),
+				profile: Profile::Production,
+				features: vec!["std".into()],
+				rustflags: "-C debuginfo=0".into(),
+				bloaty_blake2_256: [1; 32],
+				compact_blake2_256: Some([2; 32]),
+				runtime_version: Some(RuntimeVersionInfo {
+					spec_name: "node".into(),
+					impl_name: "node".into(),
+					authoring_version: 1,
+					spec_version: 100,
+					impl_version: 2,
+					transaction_version: None,
+				}),
+				signature: None,
+			},
+		};
+
+		let decoded = RuntimeBinary::from_json(&binary.to_json()).unwrap();
+		assert_eq!(decoded.to_json(), binary.to_json());
+		assert_eq!(decoded.build_info.runtime_version, binary.build_info.runtime_version);
+
+		let mut json = binary.to_json();
+		json["build_info"]["bloaty_blake2_256"] = "0x01".into();
+		assert!(RuntimeBinary::from_json(&json).is_none());
+	}
+
+	fn constant_options(prefix: &str, embed_binary: bool) -> ConstantOptions {
+		ConstantOptions {
+			prefix: prefix.into(),
+			embed_binary,
+			blake2_256: true,
+			runtime_version: true,
+			signature: false,
+		}
+	}
+
+	#[test]
+	fn generate_binary_constants_without_binary() {
+		let constants =
+			generate_binary_constants(RuntimeTarget::Wasm, true, &constant_options("", true), None);
+
+		for constant in [
+			"WASM_BINARY: Option<&[u8]> = None;",
+			"WASM_BINARY_BLOATY: Option<&[u8]> = None;",
+			"WASM_BINARY_COMPRESSED: Option<&[u8]> = None;",
+			"WASM_BINARY_PATH: Option<&str> = None;",
+			"WASM_BINARY_BLAKE2_256: Option<[u8; 32]> = None;",
+			"SPEC_NAME: Option<&str> = None;",
+			"SPEC_VERSION: Option<u32> = None;",
+			"WASM_BUILD_TOOLCHAIN: Option<&str> = None;",
+		] {
+			assert!(constants.contains(&format!("pub const {constant}\n")), "{constants}");
+		}
+		assert!(!constants.contains("SIGNATURE"), "{constants}");
+		assert!(!constants.contains("//"), "{constants}");
+
+		let constants = generate_binary_constants(
+			RuntimeTarget::Riscv,
+			false,
+			&constant_options("KITCHENSINK_", false),
+			None,
+		);
+		assert_eq!(
+			constants,
+			"pub const KITCHENSINK_RISCV_BINARY_PATH: Option<&str> = None;\n\
+			 pub const KITCHENSINK_RISCV_BINARY_BLAKE2_256: Option<[u8; 32]> = None;\n",
+		);
+	}
+
+	#[test]
+	fn generate_binary_constants_with_binary() {
+		let runtime_version = RuntimeVersionInfo {
+			spec_name: "node".into(),
+			impl_name: "node".into(),
+			authoring_version: 1,
+			spec_version: 268,
+			impl_version: 2,
+			transaction_version: None,
+		};
+		let binary = BuiltBinary {
+			binary_path: "/runtime.wasm",
+			bloaty_path: "/runtime.wasm",
+			compressed_path: None,
+			blake2_256: [0; 32],
+			runtime_version: Some(&runtime_version),
+			signature: None,
+			toolchain: Some(("rustc 1.81.0", "stable")),
+			prebuilt: false,
+		};
+
+		let constants = generate_binary_constants(
+			RuntimeTarget::Wasm,
+			false,
+			&constant_options("NODE_", true),
+			Some(&binary),
+		);
+		for constant in [
+			r#"NODE_WASM_BINARY: Option<&[u8]> = Some(include_bytes!("/runtime.wasm"));"#,
+			r#"NODE_WASM_BINARY_BLOATY_PATH: Option<&str> = Some("/runtime.wasm");"#,
+			"NODE_WASM_BINARY_COMPRESSED_PATH: Option<&str> = None;",
+			r#"NODE_SPEC_NAME: Option<&str> = Some("node");"#,
+			"NODE_IMPL_VERSION: Option<u32> = Some(2);",
+			r#"NODE_WASM_BUILD_TOOLCHAIN: Option<&str> = Some("stable");"#,
+		] {
+			assert!(constants.contains(&format!("pub const {constant}\n")), "{constants}");
+		}
+		// The binary was not compacted, which is pointed out in a comment.
+		assert!(constants.starts_with("// The binary was not compacted"), "{constants}");
+	}
+
+	#[cfg(feature = "metadata-hash")]
+	#[test]
+	fn metadata_hash_config_validation() {
+		let config = MetadataHashConfig::new("DOT", 10);
+		assert!(config.clone().validated().is_ok());
+
+		for version in [14, 16, MetadataHashConfig::UNSTABLE_METADATA_VERSION] {
+			let error = config.clone().metadata_version(version).validated().unwrap_err();
+			assert!(error.to_string().contains("only from version 15"), "{error}");
+		}
+		assert!(MetadataHashConfig::new(" ", 10).validated().is_err());
+	}
 }
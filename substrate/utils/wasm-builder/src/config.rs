@@ -0,0 +1,337 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The configuration of a runtime crate in `wasm-builder.toml` or in the
+//! `[package.metadata.wasm-builder]` section of its `Cargo.toml`, and the toolchain pinned by its
+//! `rust-toolchain.toml`.
+
+use crate::{wasm_project::Profile, watch::rerun_if_changed, WasmBuilderError};
+
+use std::{fs, path::Path};
+use toml::{value::Table, Value};
+
+/// The name of the configuration file next to the `Cargo.toml` of the runtime crate.
+const CONFIG_FILE_NAME: &str = "wasm-builder.toml";
+
+/// The names of the files `rustup` reads the toolchain of a directory from, in the order of their
+/// precedence.
+const TOOLCHAIN_FILE_NAMES: &[&str] = &["rust-toolchain", "rust-toolchain.toml"];
+
+/// The configuration of a runtime crate.
+///
+/// Every option corresponds to a method of the [`WasmBuilder`](crate::WasmBuilder), which takes
+/// precedence over the configuration.
+pub(crate) struct FileConfig {
+	/// See [`WasmBuilder::profile`](crate::WasmBuilder::profile).
+	pub profile: Option<Profile>,
+	/// See [`WasmBuilder::toolchain`](crate::WasmBuilder::toolchain).
+	pub toolchain: Option<String>,
+	/// See [`WasmBuilder::append_to_rust_flags`](crate::WasmBuilder::append_to_rust_flags).
+	pub rustflags: Vec<String>,
+	/// See [`WasmBuilder::enable_feature`](crate::WasmBuilder::enable_feature).
+	pub features: Vec<String>,
+	/// See [`WasmBuilder::disable_feature`](crate::WasmBuilder::disable_feature).
+	pub disable_features: Vec<String>,
+	/// `false` is the same as
+	/// [`WasmBuilder::disable_default_features`](crate::WasmBuilder::disable_default_features).
+	pub default_features: bool,
+	/// `false` is the same as
+	/// [`WasmBuilder::disable_compact`](crate::WasmBuilder::disable_compact).
+	pub compact: bool,
+}
+
+/// Load the configuration of the runtime crate with the given `Cargo.toml`.
+///
+/// The `wasm-builder.toml` next to the `Cargo.toml` takes precedence over the
+/// `[package.metadata.wasm-builder]` section. Returns the default configuration if neither exists.
+pub(crate) fn load(project_cargo_toml: &Path) -> Result<FileConfig, WasmBuilderError> {
+	let config_file = project_cargo_toml.with_file_name(CONFIG_FILE_NAME);
+
+	let (table, source) = if config_file.exists() {
+		rerun_if_changed(&config_file);
+		(parse_toml(&config_file)?, config_file)
+	} else {
+		let section = parse_toml(project_cargo_toml)?
+			.remove("package")
+			.and_then(|package| package.try_into::<Table>().ok())
+			.and_then(|mut package| package.remove("metadata"))
+			.and_then(|metadata| metadata.try_into::<Table>().ok())
+			.and_then(|mut metadata| metadata.remove("wasm-builder"));
+
+		match section {
+			Some(Value::Table(table)) => (table, project_cargo_toml.to_path_buf()),
+			Some(_) =>
+				return Err(invalid(project_cargo_toml, "wasm-builder", "needs to be a table")),
+			None => return Ok(FileConfig::default()),
+		}
+	};
+
+	let mut config = FileConfig::default();
+	for (key, value) in table {
+		match key.as_str() {
+			"profile" => {
+				let name = as_str(&source, &key, value)?;
+				config.profile = Some(Profile::from_name(&name).ok_or_else(|| {
+					invalid(&source, &key, "needs to be `debug`, `release` or `production`")
+				})?);
+			},
+			"toolchain" => config.toolchain = Some(as_str(&source, &key, value)?),
+			"rustflags" => config.rustflags = as_str_list(&source, &key, value)?,
+			"features" => config.features = as_str_list(&source, &key, value)?,
+			"disable-features" => config.disable_features = as_str_list(&source, &key, value)?,
+			"default-features" => config.default_features = as_bool(&source, &key, value)?,
+			"compact" => config.compact = as_bool(&source, &key, value)?,
+			_ => return Err(invalid(&source, &key, "is not a known option")),
+		}
+	}
+
+	Ok(config)
+}
+
+/// Returns the toolchain pinned by the `rust-toolchain.toml` or `rust-toolchain` file of the
+/// runtime crate with the given `Cargo.toml`.
+///
+/// Like `rustup`, the file is searched in the directory of the crate and then in its parent
+/// directories, so a toolchain file of the workspace applies as well. Toolchain files that don't
+/// name a `channel`, e.g. because they point to a custom toolchain with `path`, are ignored.
+pub(crate) fn pinned_toolchain(
+	project_cargo_toml: &Path,
+) -> Result<Option<String>, WasmBuilderError> {
+	let Some(toolchain_file) = project_cargo_toml.ancestors().skip(1).find_map(|dir| {
+		TOOLCHAIN_FILE_NAMES
+			.iter()
+			.map(|name| dir.join(name))
+			.find(|path| path.is_file())
+	}) else {
+		return Ok(None)
+	};
+	rerun_if_changed(&toolchain_file);
+
+	// The legacy format of `rust-toolchain` only contains the name of the toolchain.
+	let content =
+		fs::read_to_string(&toolchain_file).map_err(WasmBuilderError::io(&toolchain_file))?;
+	if !content.contains(['=', '[']) {
+		return Ok(Some(content.trim().to_owned()).filter(|toolchain| !toolchain.is_empty()))
+	}
+
+	Ok(parse_toml(&toolchain_file)?
+		.get("toolchain")
+		.and_then(|toolchain| toolchain.get("channel"))
+		.and_then(Value::as_str)
+		.map(ToOwned::to_owned))
+}
+
+impl Default for FileConfig {
+	fn default() -> Self {
+		Self {
+			profile: None,
+			toolchain: None,
+			rustflags: Vec::new(),
+			features: Vec::new(),
+			disable_features: Vec::new(),
+			default_features: true,
+			compact: true,
+		}
+	}
+}
+
+fn parse_toml(path: &Path) -> Result<Table, WasmBuilderError> {
+	let content = fs::read_to_string(path).map_err(WasmBuilderError::io(path))?;
+	toml::from_str(&content).map_err(|e| {
+		WasmBuilderError::InvalidConfiguration(format!("Failed to parse `{}`: {e}", path.display()))
+	})
+}
+
+fn invalid(source: &Path, key: &str, reason: &str) -> WasmBuilderError {
+	WasmBuilderError::InvalidConfiguration(format!(
+		"The option `{key}` in `{}` {reason}.",
+		source.display()
+	))
+}
+
+fn as_str(source: &Path, key: &str, value: Value) -> Result<String, WasmBuilderError> {
+	match value {
+		Value::String(value) => Ok(value),
+		_ => Err(invalid(source, key, "needs to be a string")),
+	}
+}
+
+fn as_bool(source: &Path, key: &str, value: Value) -> Result<bool, WasmBuilderError> {
+	value.as_bool().ok_or_else(|| invalid(source, key, "needs to be a boolean"))
+}
+
+fn as_str_list(source: &Path, key: &str, value: Value) -> Result<Vec<String>, WasmBuilderError> {
+	let Value::Array(values) = value else {
+		return Err(invalid(source, key, "needs to be a list of strings"))
+	};
+
+	values
+		.into_iter()
+		.map(|value| match value {
+			Value::String(value) => Ok(value),
+			_ => Err(invalid(source, key, "needs to be a list of strings")),
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::path::PathBuf;
+
+	/// Create a crate with the given `Cargo.toml` and `wasm-builder.toml` in a temporary directory.
+	fn project(cargo_toml: &str, config: Option<&str>) -> (tempfile::TempDir, PathBuf) {
+		let dir = tempfile::tempdir().unwrap();
+		let cargo_toml_path = dir.path().join("Cargo.toml");
+		fs::write(&cargo_toml_path, cargo_toml).unwrap();
+		if let Some(config) = config {
+			fs::write(dir.path().join(CONFIG_FILE_NAME), config).unwrap();
+		}
+		(dir, cargo_toml_path)
+	}
+
+	fn load_error(config: &str) -> String {
+		let (_dir, cargo_toml) = project("[package]\nname = \"runtime\"\n", Some(config));
+		match load(&cargo_toml) {
+			Ok(_) => panic!("`{config}` is not a valid configuration"),
+			Err(error) => error.to_string(),
+		}
+	}
+
+	#[test]
+	fn load_reads_the_metadata_section() {
+		let (_dir, cargo_toml) = project(
+			"[package]\nname = \"runtime\"\n\n[package.metadata.wasm-builder]\n\
+			 profile = \"production\"\ntoolchain = \"stable\"\nrustflags = [\"-C debuginfo=0\"]\n\
+			 features = [\"a\"]\ndisable-features = [\"b\"]\ndefault-features = false\n\
+			 compact = false\n",
+			None,
+		);
+
+		let config = load(&cargo_toml).unwrap();
+		assert_eq!(config.profile, Some(Profile::Production));
+		assert_eq!(config.toolchain.as_deref(), Some("stable"));
+		assert_eq!(config.rustflags, ["-C debuginfo=0"]);
+		assert_eq!(config.features, ["a"]);
+		assert_eq!(config.disable_features, ["b"]);
+		assert!(!config.default_features);
+		assert!(!config.compact);
+	}
+
+	#[test]
+	fn load_prefers_the_config_file() {
+		let (_dir, cargo_toml) = project(
+			"[package]\nname = \"runtime\"\n\n[package.metadata.wasm-builder]\n\
+			 profile = \"production\"\nfeatures = [\"a\"]\n",
+			Some("profile = \"debug\"\n"),
+		);
+
+		let config = load(&cargo_toml).unwrap();
+		assert_eq!(config.profile, Some(Profile::Debug));
+		// The section is not merged into the configuration file.
+		assert!(config.features.is_empty());
+	}
+
+	#[test]
+	fn load_defaults_without_configuration() {
+		let (_dir, cargo_toml) = project("[package]\nname = \"runtime\"\n", None);
+
+		let config = load(&cargo_toml).unwrap();
+		assert_eq!(config.profile, None);
+		assert!(config.default_features && config.compact);
+	}
+
+	#[test]
+	fn load_rejects_invalid_options() {
+		assert!(load_error("unknown = 1").contains("`unknown` in"));
+		assert!(load_error("unknown = 1").contains("is not a known option"));
+		assert!(load_error("toolchain = 1").contains("`toolchain` in"));
+		assert!(load_error("toolchain = 1").contains("needs to be a string"));
+		assert!(load_error("compact = \"no\"").contains("needs to be a boolean"));
+		assert!(load_error("features = \"a\"").contains("needs to be a list of strings"));
+		assert!(load_error("features = [\"a\", 1]").contains("needs to be a list of strings"));
+		assert!(load_error("profile = \"fast\"")
+			.contains("needs to be `debug`, `release` or `production`"));
+		assert!(load_error("profile = ").contains("Failed to parse"));
+
+		let (_dir, cargo_toml) =
+			project("[package]\nname = \"runtime\"\nmetadata = { wasm-builder = 1 }\n", None);
+		assert!(load(&cargo_toml).is_err_and(|e| e.to_string().contains("needs to be a table")));
+	}
+
+	/// Returns the toolchain pinned for a crate in a workspace with the given toolchain files.
+	fn pinned(files: &[(&str, &str)]) -> Option<String> {
+		let dir = tempfile::tempdir().unwrap();
+		let cargo_toml = dir.path().join("runtime").join("Cargo.toml");
+		fs::create_dir_all(cargo_toml.parent().unwrap()).unwrap();
+		fs::write(&cargo_toml, "[package]\nname = \"runtime\"\n").unwrap();
+		for (name, content) in files {
+			fs::write(dir.path().join(name), content).unwrap();
+		}
+
+		pinned_toolchain(&cargo_toml).unwrap()
+	}
+
+	#[test]
+	fn pinned_toolchain_reads_the_legacy_format() {
+		assert_eq!(
+			pinned(&[("rust-toolchain", "nightly-2024-06-01\n")]).as_deref(),
+			Some("nightly-2024-06-01")
+		);
+		assert_eq!(pinned(&[("rust-toolchain", "\n")]), None);
+	}
+
+	#[test]
+	fn pinned_toolchain_reads_the_channel() {
+		assert_eq!(
+			pinned(&[(
+				"rust-toolchain.toml",
+				"[toolchain]\nchannel = \"1.81.0\"\ntargets = [\"wasm32-unknown-unknown\"]\n"
+			)])
+			.as_deref(),
+			Some("1.81.0"),
+		);
+		// `rust-toolchain` takes precedence, like for `rustup`.
+		assert_eq!(
+			pinned(&[
+				("rust-toolchain", "[toolchain]\nchannel = \"stable\"\n"),
+				("rust-toolchain.toml", "[toolchain]\nchannel = \"1.81.0\"\n"),
+			])
+			.as_deref(),
+			Some("stable"),
+		);
+	}
+
+	#[test]
+	fn pinned_toolchain_ignores_path_toolchains() {
+		assert_eq!(pinned(&[("rust-toolchain.toml", "[toolchain]\npath = \"/opt/rust\"\n")]), None);
+	}
+
+	#[test]
+	fn pinned_toolchain_prefers_the_closest_file() {
+		let dir = tempfile::tempdir().unwrap();
+		let cargo_toml = dir.path().join("runtime").join("Cargo.toml");
+		fs::create_dir_all(cargo_toml.parent().unwrap()).unwrap();
+		fs::write(&cargo_toml, "[package]\nname = \"runtime\"\n").unwrap();
+		fs::write(dir.path().join("rust-toolchain.toml"), "[toolchain]\nchannel = \"stable\"\n")
+			.unwrap();
+		assert_eq!(pinned_toolchain(&cargo_toml).unwrap().as_deref(), Some("stable"));
+
+		fs::write(dir.path().join("runtime").join("rust-toolchain"), "nightly\n").unwrap();
+		assert_eq!(pinned_toolchain(&cargo_toml).unwrap().as_deref(), Some("nightly"));
+	}
+}
@@ -0,0 +1,91 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The dependency files that describe the inputs and outputs of a runtime build.
+
+use crate::{write_file_if_changed, RuntimeTarget, WasmBuilderError};
+
+use std::{
+	env,
+	path::{Path, PathBuf},
+};
+
+/// Write the dependency files of the build of the blob `blob_name` for `target`.
+///
+/// The Makefile style `wasm-build-deps-<blob_name>-<triple>.d` lists the source files the
+/// `outputs` depend on, `wasm-build-deps-<blob_name>-<triple>.json` also contains the values of
+/// the environment variables that influenced the build. Both are written into `OUT_DIR`.
+pub(crate) fn write_depfiles(
+	target: RuntimeTarget,
+	blob_name: &str,
+	outputs: &[&Path],
+	sources: &[PathBuf],
+	env_vars: &[String],
+) -> Result<(), WasmBuilderError> {
+	let out_dir = build_helper::out_dir();
+	let file_name = format!("wasm-build-deps-{blob_name}-{}", target.rustc_target());
+
+	let mut depfile = outputs.iter().map(|p| escape_make_path(p)).collect::<Vec<_>>().join(" ");
+	depfile.push(':');
+	for source in sources {
+		depfile.push_str(" \\\n  ");
+		depfile.push_str(&escape_make_path(source));
+	}
+	depfile.push('\n');
+	write_file_if_changed(out_dir.join(format!("{file_name}.d")), depfile)?;
+
+	let json = serde_json::json!({
+		"outputs": outputs.iter().map(|p| p.display().to_string()).collect::<Vec<_>>(),
+		"sources": sources.iter().map(|p| p.display().to_string()).collect::<Vec<_>>(),
+		"env": env_vars
+			.iter()
+			.map(|name| (name.clone(), env::var(name).ok().into()))
+			.collect::<serde_json::Map<_, _>>(),
+	});
+	write_file_if_changed(
+		out_dir.join(format!("{file_name}.json")),
+		serde_json::to_string_pretty(&json).expect("The dependencies are valid JSON; qed"),
+	)
+}
+
+/// Escape the `path` to be used as target or prerequisite in a Makefile.
+fn escape_make_path(path: &Path) -> String {
+	let mut escaped = String::new();
+	for c in path.to_string_lossy().chars() {
+		match c {
+			' ' | '#' => {
+				escaped.push('\\');
+				escaped.push(c);
+			},
+			'$' => escaped.push_str("$$"),
+			c => escaped.push(c),
+		}
+	}
+	escaped
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn escape_make_path_works() {
+		assert_eq!(escape_make_path(Path::new("/src/lib.rs")), "/src/lib.rs");
+		assert_eq!(escape_make_path(Path::new("/my src/a#b.rs")), "/my\\ src/a\\#b.rs");
+		assert_eq!(escape_make_path(Path::new("/src/$x.rs")), "/src/$$x.rs");
+	}
+}
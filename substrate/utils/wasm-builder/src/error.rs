@@ -0,0 +1,108 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::RuntimeTarget;
+use std::{fmt, io, path::PathBuf};
+
+/// An error that occurred while building a runtime binary.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum WasmBuilderError {
+	/// An environment variable is set to an invalid value.
+	InvalidEnvironmentVariable {
+		/// The name of the environment variable.
+		name: &'static str,
+		/// The invalid value.
+		value: String,
+		/// A description of the expected values.
+		expected: String,
+	},
+	/// The [`WasmBuilder`](crate::WasmBuilder) was configured in an invalid way.
+	InvalidConfiguration(String),
+	/// The prerequisites for building the runtime are not installed.
+	MissingPrerequisites(String),
+	/// Running `cargo metadata` failed.
+	CargoMetadata(String),
+	/// The package of the project could not be found in the cargo metadata.
+	PackageNotFound(String),
+	/// The target directory could not be found, starting from the given `OUT_DIR`.
+	TargetDirectoryNotFound(PathBuf),
+	/// Compiling the runtime for the given target failed.
+	///
+	/// The error messages of the compiler were already printed to the output.
+	CompilationFailed(RuntimeTarget),
+	/// Post-processing the compiled runtime failed, e.g. linking or compacting it.
+	PostProcessing(String),
+	/// The compiled runtime is not valid.
+	InvalidRuntimeBlob(String),
+	/// Generating the metadata hash failed.
+	#[cfg(feature = "metadata-hash")]
+	MetadataHash(String),
+	/// The workload of the profile-guided optimization failed.
+	PgoWorkload(String),
+	/// An I/O operation on the given path failed.
+	Io {
+		/// The path the operation was executed on.
+		path: PathBuf,
+		/// The underlying error.
+		error: io::Error,
+	},
+}
+
+impl WasmBuilderError {
+	/// Create a [`Self::Io`] error for the given `path`.
+	pub(crate) fn io(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> Self {
+		let path = path.into();
+		move |error| Self::Io { path, error }
+	}
+}
+
+impl fmt::Display for WasmBuilderError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::InvalidEnvironmentVariable { name, value, expected } => write!(
+				f,
+				"The `{name}` environment variable has an invalid value `{value}`; \
+				 expected {expected}.",
+			),
+			Self::InvalidConfiguration(msg) |
+			Self::MissingPrerequisites(msg) |
+			Self::PostProcessing(msg) |
+			Self::PackageNotFound(msg) |
+			Self::InvalidRuntimeBlob(msg) => write!(f, "{msg}"),
+			Self::CargoMetadata(msg) => write!(f, "Running `cargo metadata` failed: {msg}"),
+			Self::TargetDirectoryNotFound(out_dir) =>
+				write!(f, "Could not find target dir in: {}", out_dir.display()),
+			Self::CompilationFailed(target) =>
+				write!(f, "Failed to compile the runtime for `{}`.", target.rustc_target()),
+			#[cfg(feature = "metadata-hash")]
+			Self::MetadataHash(msg) => write!(f, "Failed to generate the metadata hash: {msg}"),
+			Self::PgoWorkload(msg) =>
+				write!(f, "The workload of the profile-guided optimization failed: {msg}"),
+			Self::Io { path, error } => write!(f, "I/O error at `{}`: {error}", path.display()),
+		}
+	}
+}
+
+impl std::error::Error for WasmBuilderError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Self::Io { error, .. } => Some(error),
+			_ => None,
+		}
+	}
+}
@@ -0,0 +1,249 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fetching of runtime projects that are not part of the current workspace.
+//!
+//! The fetching itself is done by cargo: we create a small project that depends on the requested
+//! package and let `cargo metadata` download it.
+
+use crate::{wasm_project::create_metadata_command, write_file_if_changed, WasmBuilderError};
+
+use std::{
+	fs,
+	path::{Path, PathBuf},
+};
+
+/// The source of a runtime project that should be fetched.
+pub(crate) enum ExternalProject<'a> {
+	/// The `package` at the given `rev` of the git repository at `url`.
+	Git { url: &'a str, rev: &'a str, package: &'a str },
+	/// The `version` of the package `name` published on crates.io.
+	CratesIo { name: &'a str, version: &'a str },
+}
+
+impl ExternalProject<'_> {
+	fn package_name(&self) -> &str {
+		match self {
+			Self::Git { package, .. } => package,
+			Self::CratesIo { name, .. } => name,
+		}
+	}
+
+	/// The name of the directory in `OUT_DIR` that is used for this project.
+	fn dir_name(&self) -> String {
+		match self {
+			Self::Git { package, rev, .. } => format!("{package}-{rev}"),
+			Self::CratesIo { name, version } => format!("{name}-{version}"),
+		}
+	}
+
+	fn dependency(&self) -> String {
+		match self {
+			Self::Git { url, rev, package } =>
+				format!("{package} = {{ git = {url:?}, rev = {rev:?}, default-features = false }}"),
+			Self::CratesIo { name, version } =>
+				format!("{name} = {{ version = \"={version}\", default-features = false }}"),
+		}
+	}
+}
+
+/// Fetch the given `project` into `out_dir`.
+///
+/// Returns the path to the `Cargo.toml` of the fetched package.
+pub(crate) fn fetch(
+	project: &ExternalProject,
+	out_dir: &Path,
+) -> Result<PathBuf, WasmBuilderError> {
+	let project_dir = out_dir.join("external-projects").join(project.dir_name());
+	let fetch_dir = project_dir.join("fetch");
+	fs::create_dir_all(&fetch_dir).map_err(WasmBuilderError::io(&fetch_dir))?;
+
+	let fetch_manifest = fetch_dir.join("Cargo.toml");
+	write_file_if_changed(
+		&fetch_manifest,
+		format!(
+			r#"
+[package]
+name = "wasm-builder-fetch"
+version = "0.0.0"
+edition = "2021"
+
+[lib]
+path = "lib.rs"
+
+[dependencies]
+{}
+
+[workspace]
+"#,
+			project.dependency(),
+		),
+	)?;
+	write_file_if_changed(fetch_dir.join("lib.rs"), "")?;
+
+	let metadata = create_metadata_command(&fetch_manifest)
+		.exec()
+		.map_err(|e| WasmBuilderError::CargoMetadata(e.to_string()))?;
+
+	let package = metadata
+		.packages
+		.iter()
+		.find(|p| p.name == project.package_name() && p.source.is_some())
+		.ok_or_else(|| {
+			WasmBuilderError::PackageNotFound(format!(
+				"Failed to find package `{}` after fetching it.",
+				project.package_name(),
+			))
+		})?;
+
+	match project {
+		// Git checkouts are used in place, as the package may depend on other packages of its
+		// workspace.
+		ExternalProject::Git { .. } => Ok(package.manifest_path.clone().into()),
+		// Packages from crates.io are self-contained, so we unpack them into our own directory
+		// instead of touching the registry sources of cargo.
+		ExternalProject::CratesIo { .. } => {
+			let source_dir = package
+				.manifest_path
+				.parent()
+				.expect("The manifest path always points to a file in a directory; qed");
+			unpack(source_dir.as_std_path(), &project_dir.join("source"))
+		},
+	}
+}
+
+/// Unpack the package in `source_dir` into `unpacked_dir`, unless it is already unpacked.
+///
+/// The package is unpacked into a temporary directory next to `unpacked_dir` that is renamed once
+/// it is complete, so an interrupted or concurrent unpack never leaves a partial package behind.
+///
+/// Returns the path to the `Cargo.toml` of the unpacked package.
+fn unpack(source_dir: &Path, unpacked_dir: &Path) -> Result<PathBuf, WasmBuilderError> {
+	let manifest = unpacked_dir.join("Cargo.toml");
+
+	// Published versions are immutable, so we only need to unpack them once.
+	if manifest.exists() {
+		return Ok(manifest)
+	}
+
+	let parent = unpacked_dir.parent().expect("The unpacked directory has a parent; qed");
+	let tmp_dir = tempfile::Builder::new()
+		.prefix(".unpack-")
+		.tempdir_in(parent)
+		.map_err(WasmBuilderError::io(parent))?;
+	copy_dir(source_dir, tmp_dir.path())?;
+
+	// The unpacked package lives below the target directory of the current workspace, so it needs
+	// to be its own workspace root to not be treated as a member of it.
+	let tmp_manifest = tmp_dir.path().join("Cargo.toml");
+	let content = fs::read_to_string(&tmp_manifest).map_err(WasmBuilderError::io(&tmp_manifest))?;
+	fs::write(&tmp_manifest, format!("{content}\n[workspace]\n"))
+		.map_err(WasmBuilderError::io(&tmp_manifest))?;
+
+	// A partial package left behind by an older version of the wasm builder is replaced.
+	if unpacked_dir.exists() {
+		fs::remove_dir_all(unpacked_dir).map_err(WasmBuilderError::io(unpacked_dir))?;
+	}
+	if let Err(error) = fs::rename(tmp_dir.path(), unpacked_dir) {
+		// Another build unpacked the package in the meantime.
+		if !manifest.exists() {
+			return Err(WasmBuilderError::Io { path: unpacked_dir.to_path_buf(), error })
+		}
+	}
+
+	Ok(manifest)
+}
+
+/// Recursively copy the directory `src` to `dst`.
+fn copy_dir(src: &Path, dst: &Path) -> Result<(), WasmBuilderError> {
+	fs::create_dir_all(dst).map_err(WasmBuilderError::io(dst))?;
+
+	for entry in fs::read_dir(src).map_err(WasmBuilderError::io(src))? {
+		let entry = entry.map_err(WasmBuilderError::io(src))?;
+		let src_path = entry.path();
+		let dst_path = dst.join(entry.file_name());
+
+		if src_path.is_dir() {
+			copy_dir(&src_path, &dst_path)?;
+		} else {
+			fs::copy(&src_path, &dst_path).map_err(WasmBuilderError::io(&dst_path))?;
+		}
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const GIT: ExternalProject = ExternalProject::Git {
+		url: "https://github.com/paritytech/polkadot-sdk",
+		rev: "4f1c",
+		package: "kitchensink-runtime",
+	};
+	const CRATES_IO: ExternalProject =
+		ExternalProject::CratesIo { name: "asset-hub-runtime", version: "1.2.3" };
+
+	#[test]
+	fn dir_name_works() {
+		assert_eq!(GIT.dir_name(), "kitchensink-runtime-4f1c");
+		assert_eq!(CRATES_IO.dir_name(), "asset-hub-runtime-1.2.3");
+	}
+
+	#[test]
+	fn dependency_works() {
+		assert_eq!(
+			GIT.dependency(),
+			"kitchensink-runtime = { git = \"https://github.com/paritytech/polkadot-sdk\", \
+			 rev = \"4f1c\", default-features = false }",
+		);
+		assert_eq!(
+			CRATES_IO.dependency(),
+			"asset-hub-runtime = { version = \"=1.2.3\", default-features = false }",
+		);
+	}
+
+	#[test]
+	fn unpack_works() {
+		let dir = tempfile::tempdir().unwrap();
+		let source_dir = dir.path().join("registry");
+		fs::create_dir_all(source_dir.join("src")).unwrap();
+		fs::write(source_dir.join("Cargo.toml"), "[package]\nname = \"runtime\"\n").unwrap();
+		fs::write(source_dir.join("src/lib.rs"), "fn a() {}").unwrap();
+
+		// A partial package without a manifest is replaced.
+		let unpacked_dir = dir.path().join("project").join("source");
+		fs::create_dir_all(unpacked_dir.join("src")).unwrap();
+		fs::write(unpacked_dir.join("src/partial.rs"), "").unwrap();
+
+		let manifest = unpack(&source_dir, &unpacked_dir).unwrap();
+		assert_eq!(manifest, unpacked_dir.join("Cargo.toml"));
+		assert_eq!(
+			fs::read_to_string(&manifest).unwrap(),
+			"[package]\nname = \"runtime\"\n\n[workspace]\n",
+		);
+		assert_eq!(fs::read_to_string(unpacked_dir.join("src/lib.rs")).unwrap(), "fn a() {}");
+		assert!(!unpacked_dir.join("src/partial.rs").exists());
+		assert_eq!(fs::read_dir(dir.path().join("project")).unwrap().count(), 1);
+
+		// An unpacked package is kept as it is.
+		fs::write(source_dir.join("src/lib.rs"), "fn b() {}").unwrap();
+		unpack(&source_dir, &unpacked_dir).unwrap();
+		assert_eq!(fs::read_to_string(unpacked_dir.join("src/lib.rs")).unwrap(), "fn a() {}");
+	}
+}
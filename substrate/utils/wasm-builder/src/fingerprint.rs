@@ -0,0 +1,389 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The fingerprints of the builds of a runtime, to not run cargo when nothing changed.
+//!
+//! A fingerprint consists of the inputs of the build, the values of the tracked environment
+//! variables and the modification times of the source files and of the outputs. It is cheap to
+//! check, as the sources are not read.
+
+use crate::{to_hex, watch::Rerun, write_file_if_changed, RuntimeTarget, WasmBuilderError};
+
+use sp_crypto_hashing::blake2_256;
+use std::{
+	collections::BTreeSet,
+	env,
+	ffi::OsStr,
+	fs,
+	path::{Path, PathBuf},
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The prefixes of the environment variables that are part of the fingerprint besides the tracked
+/// ones.
+const ENV_PREFIXES: &[&str] = &["CARGO_FEATURE_", "CARGO_NET_OFFLINE", "RUSTC", "RUSTUP_"];
+
+/// The prefixes of the environment variables that are part of the [`BuildFingerprint`] besides the
+/// [`ENV_PREFIXES`], as they configure the builder or the cargo of the outer build.
+const BUILD_ENV_PREFIXES: &[&str] =
+	&["WASM_", "SKIP_", "CARGO_CFG_", "CARGO_BUILD_", "CARGO_PROFILE_"];
+
+/// The environment variables that are part of the [`BuildFingerprint`] besides the ones matching
+/// the prefixes.
+const BUILD_ENV_VARS: &[&str] = &[
+	"CARGO",
+	"CARGO_HOME",
+	"CARGO_TARGET_DIR",
+	"CARGO_ENCODED_RUSTFLAGS",
+	"PROFILE",
+	"OPT_LEVEL",
+	"DEBUG",
+	"TARGET",
+	"HOST",
+	"HOME",
+	"XDG_CACHE_HOME",
+];
+
+/// The fingerprint of the cargo build of a runtime blob.
+pub(crate) struct Fingerprint {
+	/// The file in `OUT_DIR` the fingerprint of the last build is recorded in.
+	path: PathBuf,
+	/// The hash of the inputs and the environment variables of the build.
+	inputs: String,
+}
+
+impl Fingerprint {
+	/// Create the fingerprint of the build of the blob `blob_name` for `target`.
+	///
+	/// The `inputs` are everything besides the sources that changes the blob, e.g. the manifest
+	/// and the flags of the build.
+	pub(crate) fn new(
+		target: RuntimeTarget,
+		blob_name: &str,
+		inputs: &[&[u8]],
+		tracked_env_vars: &[String],
+	) -> Self {
+		Self {
+			path: build_helper::out_dir()
+				.join(format!("wasm-build-fingerprint-{blob_name}-{}.json", target.rustc_target())),
+			inputs: hash_inputs(inputs, |name| {
+				ENV_PREFIXES.iter().any(|p| name.starts_with(p)) ||
+					tracked_env_vars.iter().any(|tracked| tracked == name)
+			}),
+		}
+	}
+
+	/// Returns the blob of the last build if it was built with the same inputs and neither the
+	/// blob nor any of the source files changed since.
+	pub(crate) fn unchanged_blob(&self) -> Option<PathBuf> {
+		let recorded = fs::read_to_string(&self.path).ok()?;
+		let recorded = serde_json::from_str::<serde_json::Value>(&recorded).ok()?;
+		if recorded["inputs"].as_str()? != self.inputs {
+			return None
+		}
+
+		let blob = PathBuf::from(recorded["blob"]["path"].as_str()?);
+		if modified(&blob)? != recorded["blob"]["modified"].as_str()? {
+			return None
+		}
+
+		files_unchanged(&recorded["files"])?.then_some(blob)
+	}
+
+	/// Record the fingerprint of the build of `blob` from the source `files`.
+	///
+	/// Nothing is recorded if one of the files changed after the build started at `build_start`,
+	/// as the change may not be part of the blob.
+	pub(crate) fn write(
+		&self,
+		blob: &Path,
+		files: &[PathBuf],
+		build_start: SystemTime,
+	) -> Result<(), WasmBuilderError> {
+		let Some(recorded_files) = record_files(files, build_start) else { return Ok(()) };
+
+		let json = serde_json::json!({
+			"inputs": self.inputs,
+			"blob": { "path": blob.display().to_string(), "modified": modified(blob) },
+			"files": recorded_files,
+		});
+		write_file_if_changed(
+			&self.path,
+			serde_json::to_string_pretty(&json).expect("The fingerprint is valid JSON; qed"),
+		)
+	}
+}
+
+/// The fingerprint of a whole build of a [`crate::WasmBuilder`], to skip it when nothing changed
+/// before the toolchain is even probed.
+///
+/// Besides the `inputs` and the environment, it covers the modification time of the build script,
+/// which changes with the configuration of the builder, and of the files the `rerun-if-changed`
+/// instructions of the last build point to. Those instructions are replayed when the build is
+/// skipped, so cargo keeps watching the same files.
+pub(crate) struct BuildFingerprint {
+	/// The file in `OUT_DIR` the fingerprint of the last build is recorded in.
+	path: PathBuf,
+	/// The hash of the inputs and the environment variables of the build.
+	inputs: String,
+}
+
+impl BuildFingerprint {
+	/// Create the fingerprint of the build that generates the file at `file_path`.
+	///
+	/// The `inputs` are everything besides the files and the environment that changes the build,
+	/// e.g. the targets and the name of the toolchain.
+	pub(crate) fn new(file_path: &Path, inputs: &[&[u8]], tracked_env_vars: &[String]) -> Self {
+		let build_script = env::current_exe().ok().and_then(|exe| modified(&exe));
+		let file_name = file_path.file_name().unwrap_or_default().to_string_lossy();
+
+		Self {
+			path: file_path.with_file_name(format!("wasm-build-fingerprint-{file_name}.json")),
+			inputs: hash_inputs(
+				&[inputs, &[build_script.unwrap_or_default().as_bytes()]].concat(),
+				|name| {
+					ENV_PREFIXES.iter().chain(BUILD_ENV_PREFIXES).any(|p| name.starts_with(p)) ||
+						BUILD_ENV_VARS.contains(&name) ||
+						tracked_env_vars.iter().any(|tracked| tracked == name)
+				},
+			),
+		}
+	}
+
+	/// Returns the `rerun-if-*` instructions and the binaries of the last build if it was built
+	/// with the same inputs and none of its files changed since.
+	pub(crate) fn unchanged(&self) -> Option<(Vec<Rerun>, serde_json::Value)> {
+		let recorded = fs::read_to_string(&self.path).ok()?;
+		let mut recorded = serde_json::from_str::<serde_json::Value>(&recorded).ok()?;
+		if recorded["inputs"].as_str()? != self.inputs || !files_unchanged(&recorded["files"])? {
+			return None
+		}
+
+		let paths = recorded["rerun_if_changed"]
+			.as_array()?
+			.iter()
+			.map(|path| path.as_str().map(|path| Rerun::IfChanged(PathBuf::from(path))));
+		let env_vars = recorded["rerun_if_env_changed"]
+			.as_array()?
+			.iter()
+			.map(|name| name.as_str().map(|name| Rerun::IfEnvChanged(name.into())));
+		let reruns = paths.chain(env_vars).collect::<Option<Vec<_>>>()?;
+
+		Some((reruns, recorded["binaries"].take()))
+	}
+
+	/// Record the fingerprint of the build that passed the `reruns` instructions and depends on
+	/// the `files` besides them, e.g. its outputs, together with the `binaries` it built.
+	///
+	/// Nothing is recorded if one of the files changed after the build started at `build_start`,
+	/// as the change may not be part of the build.
+	pub(crate) fn write(
+		&self,
+		reruns: &BTreeSet<Rerun>,
+		files: &[PathBuf],
+		binaries: serde_json::Value,
+		build_start: SystemTime,
+	) -> Result<(), WasmBuilderError> {
+		let (mut paths, mut env_vars) = (Vec::new(), Vec::new());
+		for rerun in reruns {
+			match rerun {
+				Rerun::IfChanged(path) => paths.push(path.clone()),
+				Rerun::IfEnvChanged(name) => env_vars.push(name.clone()),
+			}
+		}
+		let Some(recorded_files) = record_files(&[&paths[..], files].concat(), build_start) else {
+			return Ok(())
+		};
+
+		let json = serde_json::json!({
+			"inputs": self.inputs,
+			"files": recorded_files,
+			"rerun_if_changed": paths,
+			"rerun_if_env_changed": env_vars,
+			"binaries": binaries,
+		});
+		write_file_if_changed(
+			&self.path,
+			serde_json::to_string_pretty(&json).expect("The fingerprint is valid JSON; qed"),
+		)
+	}
+}
+
+/// Returns the `rustc` of the `toolchain` a runtime was built with, to notice when it is updated.
+///
+/// The `toolchain` is the name of a rustup toolchain or the path to `cargo` otherwise.
+pub(crate) fn toolchain_binary(toolchain: &str) -> PathBuf {
+	let cargo = Path::new(toolchain);
+	if cargo.is_absolute() {
+		return cargo.to_path_buf()
+	}
+
+	let rustup_home = env::var_os("RUSTUP_HOME").map(PathBuf::from).unwrap_or_else(|| {
+		env::var_os("HOME").map(PathBuf::from).unwrap_or_default().join(".rustup")
+	});
+	crate::executable_path(
+		&rustup_home.join("toolchains").join(toolchain).join("bin").join("rustc"),
+	)
+}
+
+/// Returns the hash of the `inputs` and of the environment variables whose name is `included`.
+fn hash_inputs(inputs: &[&[u8]], included: impl Fn(&str) -> bool) -> String {
+	let mut env = env::vars_os()
+		.filter(|(name, _)| name.to_str().map_or(false, &included))
+		.map(|(name, value)| {
+			let value = normalized_value(&name, value.as_encoded_bytes());
+			(name, value)
+		})
+		.collect::<Vec<_>>();
+	env.sort();
+
+	let mut preimage = Vec::new();
+	for input in inputs {
+		preimage.extend_from_slice(&blake2_256(input));
+	}
+	for (name, value) in env {
+		preimage.extend_from_slice(&blake2_256(name.as_encoded_bytes()));
+		preimage.extend_from_slice(&blake2_256(&value));
+	}
+
+	to_hex(&blake2_256(&preimage))
+}
+
+/// Returns the `files` with their modification times to record them in a fingerprint, or `None`
+/// if one of them changed after `build_start`.
+///
+/// Files that don't exist are recorded without a modification time, so creating them changes the
+/// fingerprint.
+fn record_files(files: &[PathBuf], build_start: SystemTime) -> Option<Vec<serde_json::Value>> {
+	files
+		.iter()
+		.map(|file| {
+			if let Ok(file_modified) = fs::metadata(file).and_then(|m| m.modified()) {
+				if file_modified >= build_start {
+					return None
+				}
+			}
+
+			Some(serde_json::json!({
+				"path": file.display().to_string(),
+				"modified": modified(file),
+			}))
+		})
+		.collect()
+}
+
+/// Returns whether none of the recorded `files` changed, `None` if they are malformed.
+fn files_unchanged(files: &serde_json::Value) -> Option<bool> {
+	Some(files.as_array()?.iter().all(|file| {
+		file["path"].as_str().and_then(|path| modified(Path::new(path))).as_deref() ==
+			file["modified"].as_str()
+	}))
+}
+
+/// Returns the `value` of the environment variable `name` in the form that matters for the build.
+///
+/// The flags in `WASM_BUILD_RUSTFLAGS` and `WASM_BUILD_CARGO_ARGS` are split into words, so
+/// changing only the whitespace between them doesn't change the fingerprint.
+fn normalized_value(name: &OsStr, value: &[u8]) -> Vec<u8> {
+	let words = match String::from_utf8_lossy(value) {
+		value if name == crate::WASM_BUILD_RUSTFLAGS_ENV =>
+			Some(value.split_whitespace().map(Into::into).collect()),
+		value if name == crate::WASM_BUILD_CARGO_ARGS => crate::split_shell_words(&value),
+		_ => None,
+	};
+
+	match words {
+		Some(words) => words.join("\0").into_bytes(),
+		None => value.to_vec(),
+	}
+}
+
+/// Returns the modification time of the file at `path` with nanosecond precision.
+fn modified(path: &Path) -> Option<String> {
+	let modified = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+	let since_epoch = modified.duration_since(UNIX_EPOCH).ok()?;
+	Some(format!("{}.{:09}", since_epoch.as_secs(), since_epoch.subsec_nanos()))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn normalized_value_works() {
+		let rustflags = OsStr::new(crate::WASM_BUILD_RUSTFLAGS_ENV);
+		assert_eq!(
+			normalized_value(rustflags, b" -C  debuginfo=0\t"),
+			normalized_value(rustflags, b"-C debuginfo=0"),
+		);
+
+		let cargo_args = OsStr::new(crate::WASM_BUILD_CARGO_ARGS);
+		assert_eq!(
+			normalized_value(cargo_args, b"--locked  '--timings=html'"),
+			normalized_value(cargo_args, b"--locked --timings=html"),
+		);
+		assert_ne!(
+			normalized_value(cargo_args, b"'--features=a b'"),
+			normalized_value(cargo_args, b"--features=a b"),
+		);
+
+		assert_eq!(normalized_value(OsStr::new("WASM_BUILD_TYPE"), b" release"), b" release");
+	}
+
+	#[test]
+	fn build_fingerprint_works() {
+		let dir = tempfile::tempdir().unwrap();
+		let file_path = dir.path().join("wasm_binary.rs");
+		let source = dir.path().join("lib.rs");
+		let missing = dir.path().join("missing.rs");
+		for file in [&file_path, &source] {
+			fs::write(file, "").unwrap();
+			filetime::set_file_mtime(file, filetime::FileTime::from_unix_time(1, 0)).unwrap();
+		}
+
+		let fingerprint = BuildFingerprint::new(&file_path, &[b"input"], &[]);
+		assert!(fingerprint.unchanged().is_none());
+
+		let reruns = BTreeSet::from([
+			Rerun::IfChanged(source.clone()),
+			Rerun::IfChanged(missing.clone()),
+			Rerun::IfEnvChanged("SKIP_WASM_BUILD".into()),
+		]);
+		let write = |build_start| {
+			fingerprint.write(&reruns, &[file_path.clone()], serde_json::json!([1]), build_start)
+		};
+		write(SystemTime::now()).unwrap();
+		let (replayed, binaries) = fingerprint.unchanged().unwrap();
+		assert_eq!(replayed, reruns.iter().cloned().collect::<Vec<_>>());
+		assert_eq!(binaries, serde_json::json!([1]));
+
+		assert!(BuildFingerprint::new(&file_path, &[b"other"], &[]).unchanged().is_none());
+
+		fs::write(&missing, "").unwrap();
+		assert!(fingerprint.unchanged().is_none());
+		fs::remove_file(&missing).unwrap();
+		assert!(fingerprint.unchanged().is_some());
+
+		fs::write(&source, "fn changed() {}").unwrap();
+		assert!(fingerprint.unchanged().is_none());
+
+		// A file that changed during the build may not be part of it.
+		fs::remove_file(&fingerprint.path).unwrap();
+		write(UNIX_EPOCH).unwrap();
+		assert!(fingerprint.unchanged().is_none());
+	}
+}
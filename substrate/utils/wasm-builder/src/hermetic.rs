@@ -0,0 +1,107 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The hermetic mode for build systems like Bazel or Buck2.
+//!
+//! In this mode all inputs are given explicitly by the manifest file at `WASM_BUILD_HERMETIC`,
+//! instead of being discovered from the environment of the `build.rs`.
+
+use crate::WasmBuilderError;
+
+use std::{
+	env, fs,
+	path::{Path, PathBuf},
+	sync::OnceLock,
+};
+
+/// The manifest of a hermetic build, if `WASM_BUILD_HERMETIC` is set.
+static HERMETIC: OnceLock<Option<Hermetic>> = OnceLock::new();
+
+/// The explicit inputs and outputs of a hermetic build.
+#[derive(Debug)]
+pub(crate) struct Hermetic {
+	/// The root of the workspace of the runtime, which contains the `Cargo.lock`.
+	pub source_root: PathBuf,
+	/// The `cargo` of the toolchain the runtime is built with.
+	pub cargo: PathBuf,
+	/// The directory all outputs are written to, it replaces `OUT_DIR`.
+	pub out_dir: PathBuf,
+}
+
+impl Hermetic {
+	/// Returns the directory the runtime projects and their target directories are put into.
+	pub(crate) fn wasm_workspace_root(&self) -> PathBuf {
+		// The profile of the runtime is derived from this path like for a `build.rs`.
+		self.out_dir.join("target").join("release")
+	}
+}
+
+/// Load the manifest of the hermetic build, if `WASM_BUILD_HERMETIC` is set.
+///
+/// Does nothing if the manifest is already loaded.
+pub(crate) fn init() -> Result<(), WasmBuilderError> {
+	if HERMETIC.get().is_none() {
+		let hermetic = match env::var_os(crate::WASM_BUILD_HERMETIC) {
+			Some(path) => Some(load(Path::new(&path))?),
+			None => None,
+		};
+		let _ = HERMETIC.set(hermetic);
+	}
+
+	Ok(())
+}
+
+/// Returns the manifest of the hermetic build, if `WASM_BUILD_HERMETIC` is set.
+pub(crate) fn get() -> Option<&'static Hermetic> {
+	HERMETIC.get().and_then(Option::as_ref)
+}
+
+/// Load the manifest at `path`.
+///
+/// The manifest is a JSON object with the paths `source_root`, `cargo` and `out_dir`. Relative
+/// paths are interpreted relative to the directory of the manifest.
+fn load(path: &Path) -> Result<Hermetic, WasmBuilderError> {
+	let content = fs::read_to_string(path).map_err(WasmBuilderError::io(path))?;
+	let manifest = serde_json::from_str::<serde_json::Value>(&content).map_err(|error| {
+		WasmBuilderError::InvalidConfiguration(format!(
+			"The hermetic build manifest `{}` is not valid JSON: {error}",
+			path.display()
+		))
+	})?;
+
+	let base = path.parent().unwrap_or(Path::new(""));
+	let path_of = |key: &str| {
+		manifest[key]
+			.as_str()
+			.map(|value| crate::simplified_path(base.join(value)))
+			.ok_or_else(|| {
+				WasmBuilderError::InvalidConfiguration(format!(
+					"The hermetic build manifest `{}` needs to contain the path `{key}`.",
+					path.display()
+				))
+			})
+	};
+
+	let hermetic = Hermetic {
+		source_root: path_of("source_root")?,
+		cargo: crate::executable_path(&path_of("cargo")?),
+		out_dir: path_of("out_dir")?,
+	};
+	fs::create_dir_all(&hermetic.out_dir).map_err(WasmBuilderError::io(&hermetic.out_dir))?;
+
+	Ok(hermetic)
+}
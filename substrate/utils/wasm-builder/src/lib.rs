@@ -47,7 +47,30 @@
 //!
 //! This will include the generated Wasm binary as two constants `WASM_BINARY` and
 //! `WASM_BINARY_BLOATY`. The former is a compact Wasm binary and the latter is the Wasm binary as
-//! being generated by the compiler. Both variables have `Option<&'static [u8]>` as type.
+//! being generated by the compiler. Both variables have `Option<&'static [u8]>` as type. The
+//! paths of the binaries on disk are available as `WASM_BINARY_PATH` and `WASM_BINARY_BLOATY_PATH`
+//! with type `Option<&'static str>`, e.g. for tools that want to load the files directly.
+//! `WASM_BUILD_RUSTC_VERSION` and `WASM_BUILD_TOOLCHAIN` name the compiler and the toolchain the
+//! binary was built with, so a node can log exactly which compiler produced its runtime.
+//!
+//! The compact binary is also compressed using the framing of `sp-maybe-compressed-blob`, as it is
+//! stored on chain. If the compression succeeded, `WASM_BINARY` is the compressed binary and it is
+//! also available as `WASM_BINARY_COMPRESSED` (`WASM_BINARY_COMPRESSED_PATH` for its path), which
+//! is `None` otherwise.
+//!
+//! By default the runtime is compiled for the [`RuntimeTarget`] selected by the
+//! `SUBSTRATE_RUNTIME_TARGET` environment variable. To build the runtime for WASM and RISC-V at
+//! once, use [`WasmBuilder::targets`]. The RISC-V binary is then available as `RISCV_BINARY`.
+//!
+//! The experimental [`RuntimeTarget::Wasm64`] builds the runtime for `wasm64-unknown-unknown`, the
+//! binary is then available as `WASM64_BINARY`. As there is no precompiled standard library for
+//! this target, the `rust-src` component is required. The binary is neither checked nor compacted,
+//! as the post-processing only supports 32 bit WASM.
+//!
+//! [`WasmBuilder::build`] exits the process when the build fails, which gives the cleanest output
+//! inside of a `build.rs`. Tools that drive the build themselves can use
+//! [`WasmBuilder::try_build`], which returns a [`WasmBuilderError`] instead and the
+//! [`BuildOutput`] with the paths of the built binaries on success.
 //!
 //! ### Feature
 //!
@@ -57,6 +80,9 @@
 //! feature. This `runtime-wasm` feature will be enabled by the wasm builder when it compiles the
 //! Wasm binary. If this feature is not present, it will not be enabled.
 //!
+//! The inferred set of features can be replaced with [`WasmBuilder::set_features`] and single
+//! features can be excluded from the wasm build with [`WasmBuilder::disable_feature`].
+//!
 //! ## Environment variables
 //!
 //! By using environment variables, you can configure which Wasm binaries are built and how:
@@ -64,32 +90,201 @@
 //! - `SKIP_WASM_BUILD` - Skips building any Wasm binary. This is useful when only native should be
 //!   recompiled. If this is the first run and there doesn't exist a Wasm binary, this will set both
 //!   variables to `None`.
+//! - `WASM_BUILD_PREBUILT` - The path to a prebuilt runtime binary, e.g. a
+//!   `*.compact.compressed.wasm` of a deterministic release build. The build is skipped and the
+//!   generated file embeds the given binary instead. If set, it overrides the binary passed to
+//!   `WasmBuilder::use_prebuilt` or `WasmBuilder::use_prebuilt_from_url` for all projects. A
+//!   relative path is interpreted relative to the directory of the crate that builds the runtime.
+//! - `WASM_BUILD_PREBUILT_BLAKE2_256` - The hex encoded BLAKE2-256 hash the binary of
+//!   `WASM_BUILD_PREBUILT` needs to have.
 //! - `WASM_BUILD_TYPE` - Sets the build type for building Wasm binaries. Supported values are
 //!   `release` or `debug`. By default the build type is equal to the build type used by the main
-//!   build.
+//!   build or the [`Profile`] set with [`WasmBuilder::profile`].
 //! - `FORCE_WASM_BUILD` - Can be set to force a Wasm build. On subsequent calls the value of the
 //!   variable needs to change. As wasm-builder instructs `cargo` to watch for file changes this
-//!   environment variable should only be required in certain circumstances.
+//!   environment variable should only be required in certain circumstances. While it is set, cargo
+//!   is always run for the wasm build, even if the fingerprint of the last build in `OUT_DIR` shows
+//!   that none of the sources changed.
 //! - `WASM_BUILD_RUSTFLAGS` - Extend `RUSTFLAGS` given to `cargo build` while building the wasm
-//!   binary.
-//! - `WASM_BUILD_NO_COLOR` - Disable color output of the wasm build.
+//!   binary. Flags of a single project can be set with [`WasmBuilder::append_rustflags`], which
+//!   take precedence over this variable.
+//! - `WASM_BUILD_CARGO_ARGS` - Extra arguments that are appended to the `cargo` command building
+//!   the wasm binary, e.g. `--locked --timings=html`. The arguments are split like a shell would
+//!   do, so quotes can be used for arguments that contain spaces. They are appended after the
+//!   arguments passed to `WasmBuilder::append_cargo_args`.
+//! - `WASM_BUILD_NO_COLOR` - Disable color output of the wasm build. The output of cargo is
+//!   forwarded while the wasm binary is built, with every line prefixed by the name of the crate.
 //! - `WASM_TARGET_DIRECTORY` - Will copy any build Wasm binary to the given directory. The path
 //!   needs to be absolute.
 //! - `WASM_BUILD_TOOLCHAIN` - The toolchain that should be used to build the Wasm binaries. The
-//!   format needs to be the same as used by cargo, e.g. `nightly-2020-02-20`.
+//!   format needs to be the same as used by cargo, e.g. `nightly-2020-02-20`. A toolchain pinned
+//!   with [`WasmBuilder::toolchain`] takes precedence over this variable. Otherwise, this variable
+//!   takes precedence over the toolchain pinned by the `rust-toolchain.toml` of the runtime crate
+//!   or its workspace, which is preferred over the toolchain of the main build.
+//! - `WASM_BUILD_TOOLCHAIN_POLICY` - The preference between the installed toolchains when no
+//!   toolchain is pinned, one of `stable-first` (default), `nightly-first`, `newest` or
+//!   `pinned-only`. If set, it overrides the setting of `WasmBuilder::toolchain_policy` for all
+//!   projects.
+//! - `WASM_BUILD_ASSUME_RUSTC_VERSION` - The version, e.g. `1.81.0` or `1.83.0-nightly`, that is
+//!   assumed for toolchains whose `--version` output can't be parsed, e.g. of unusual vendor
+//!   builds. Without it, such toolchains are treated as unusable.
 //! - `WASM_BUILD_WORKSPACE_HINT` - Hint the workspace that is being built. This is normally not
 //!   required as we walk up from the target directory until we find a `Cargo.toml`. If the target
 //!   directory is changed for the build, this environment variable can be used to point to the
 //!   actual workspace.
 //! - `WASM_BUILD_STD` - Sets whether the Rust's standard library crates will also be built. This is
 //!   necessary to make sure the standard library crates only use the exact WASM feature set that
-//!   our executor supports. Enabled by default.
+//!   our executor supports. Enabled by default for WASM. If set, it overrides the setting of
+//!   `WasmBuilder::build_std` for all projects.
+//! - `WASM_BUILD_STD_MODE` - How cargo is told to build the standard library, one of `detect`
+//!   (default), `unstable` for `-Z build-std` or `stable` for `--build-std`. If set, it overrides
+//!   the setting of `WasmBuilder::build_std_mode` for all projects.
+//! - `WASM_BUILD_ALLOW_RUSTC_BOOTSTRAP` - How `RUSTC_BOOTSTRAP` is handled for the toolchain of the
+//!   runtime, one of `clear` (default), `set` or `inherit`. By default a `RUSTC_BOOTSTRAP` of the
+//!   main build is removed, so a stable toolchain isn't mistaken for a nightly one. With `set`
+//!   every toolchain is allowed to use nightly features, while `inherit` keeps the value of the
+//!   main build. The standard library is still built with `RUSTC_BOOTSTRAP=1` if a stable toolchain
+//!   needs `-Z build-std`.
+//! - `WASM_BUILD_SYSROOT` - The path to a prebuilt sysroot that is used instead of building the
+//!   standard library from source, see `WasmBuilder::sysroot`. If set, it overrides the setting of
+//!   `WasmBuilder::sysroot` for all projects.
+//! - `WASM_BUILD_WASM32V1_NONE` - Sets whether WASM runtimes are built for the `wasm32v1-none`
+//!   target instead of `wasm32-unknown-unknown` if the toolchain provides it. If set, it overrides
+//!   the setting of `WasmBuilder::prefer_wasm32v1_none` for all projects.
+//! - `WASM_BUILD_USE_RUSTC_WRAPPER` - Sets whether the `RUSTC_WRAPPER` of the outer build, e.g.
+//!   `sccache`, is also used for the wasm build. Enabled by default. If set, it overrides the
+//!   setting of `WasmBuilder::use_rustc_wrapper` for all projects.
+//! - `WASM_BUILD_ARTIFACT_CACHE` - Sets whether the runtime binaries built by cargo are cached in
+//!   `$XDG_CACHE_HOME/wasm-builder` or `~/.cache/wasm-builder`. If set, it overrides the setting of
+//!   `WasmBuilder::artifact_cache` for all projects.
+//! - `WASM_BUILD_CACHE_TOKEN` - The token that is sent as bearer token to the server of an
+//!   `HttpArtifactCache`.
+//! - `WASM_BUILD_SHARED_TARGET_DIR` - Sets whether the runtimes share one cargo target directory,
+//!   so their dependencies are only compiled once. If set, it overrides the setting of
+//!   `WasmBuilder::shared_target_dir` for all projects.
+//! - `WASM_BUILD_INCREMENTAL` - Sets whether the wasm binary is compiled incrementally when it is
+//!   built with the `dev` profile. Release and production builds are never incremental. If set, it
+//!   overrides the setting of `WasmBuilder::incremental` for all projects.
+//! - `WASM_BUILD_CARGO_CONFIG` - The path to a cargo configuration file that is passed with
+//!   `--config` to the wasm build, e.g. with the source replacement of `cargo vendor`. If set, it
+//!   overrides the file passed to `WasmBuilder::cargo_config` for all projects.
+//! - `WASM_BUILD_LINKER` - The linker the wasm binary is linked with, e.g. `rust-lld`, instead of
+//!   the one configured for the target in the `.cargo/config.toml` files. If set, it overrides the
+//!   linker passed to `WasmBuilder::linker` for all projects.
+//! - `WASM_BUILD_LINKER_FLAVOR` - The linker flavor passed with `-C linker-flavor` to `rustc`, e.g.
+//!   `wasm-lld`. If set, it overrides the flavor passed to `WasmBuilder::linker_flavor` for all
+//!   projects.
+//! - `WASM_BUILD_LOCKED` - Sets whether the wasm binary is built with `--locked`, or `--frozen` if
+//!   `CARGO_NET_OFFLINE` is set, so the build fails if the copied `Cargo.lock` would change. If
+//!   set, it overrides the setting of `WasmBuilder::locked` for all projects.
+//! - `WASM_BUILD_CHECK_ONLY` - Sets whether the runtime is only type-checked with `cargo check`
+//!   instead of being built. The generated file then contains `None` constants, like with
+//!   `SKIP_WASM_BUILD`. If set, it overrides the setting of `WasmBuilder::check_only` for all
+//!   projects.
+//! - `WASM_BUILD_VERBOSITY` - Sets how much is printed about the wasm build, one of `quiet`,
+//!   `normal` (the default), `verbose` or `debug`. `quiet` only prints warnings and errors,
+//!   `verbose` also prints the environment variables set for cargo and the paths binaries are
+//!   copied to and `debug` also prints every path that is watched for changes to rebuild the wasm
+//!   binary.
+//! - `WASM_BUILD_TIMINGS` - Sets whether the build of the wasm binary is profiled with `cargo
+//!   --timings`. If set, it overrides the setting of `WasmBuilder::emit_timings` for all projects.
+//! - `WASM_BUILD_AUTO_INSTALL_TARGET` - Sets whether the wasm target is installed with `rustup
+//!   target add` if the toolchain lacks it, instead of failing with instructions. If set, it
+//!   overrides the setting of `WasmBuilder::install_missing_target` for all projects.
+//! - `WASM_BUILD_AUTO_INSTALL_RUST_SRC` - Sets whether the `rust-src` component is installed with
+//!   `rustup component add` if the standard library is built from source and the toolchain lacks
+//!   it. If set, it overrides the setting of `WasmBuilder::install_missing_rust_src` for all
+//!   projects.
+//! - `WASM_BUILD_NO_RUSTUP` - Sets whether rustup is never used, even if it is installed. This is
+//!   the case anyway if `rustup` is not found, see [Prerequisites](#prerequisites).
+//! - `WASM_BUILD_HERMETIC` - The path to the manifest of a hermetic build, see [Hermetic
+//!   builds](#hermetic-builds).
 //! - `CARGO_NET_OFFLINE` - If `true`, `--offline` will be passed to all processes launched to
-//!   prevent network access. Useful in offline environments.
+//!   prevent network access. Useful in offline environments. Rustup doesn't install missing
+//!   toolchains on demand, the HTTP artifact cache is skipped and installing a missing toolchain,
+//!   target or component or downloading a prebuilt binary fails right away with an error that names
+//!   what needed the network.
+//!
+//! Most of these variables rerun the `build.rs` when they change, see
+//! [`WasmBuilder::tracked_env_vars`]. Variables that don't matter for a project, e.g.
+//! `WASM_TARGET_DIRECTORY` on CI, can be untracked with [`WasmBuilder::untrack_env_var`], except
+//! for `SKIP_WASM_BUILD`, `FORCE_WASM_BUILD` and the `WASM_BUILD_PREBUILT*` variables. When the
+//! `build.rs` is rerun, cargo is only run for the wasm build if the value of a tracked variable
+//! changed. The values of `WASM_BUILD_RUSTFLAGS` and `WASM_BUILD_CARGO_ARGS` are compared word by
+//! word, so changing just the whitespace doesn't rebuild the wasm binary.
 //!
 //! Each project can be skipped individually by using the environment variable
 //! `SKIP_PROJECT_NAME_WASM_BUILD`. Where `PROJECT_NAME` needs to be replaced by the name of the
-//! cargo project, e.g. `argochain-runtime` will be `NODE_RUNTIME`.
+//! cargo project, e.g. `argochain-runtime` will be `ARGOCHAIN_RUNTIME`. This works for the crate
+//! that contains the `build.rs` and for the project being built, if they are different.
+//!
+//! ## Dependency files
+//!
+//! For every built binary, `OUT_DIR` contains the Makefile style dependency file
+//! `wasm-build-deps-<name>-<target>.d`, which lists the source files and the `Cargo.lock` the
+//! binaries depend on, and `wasm-build-deps-<name>-<target>.json`, which also contains the values
+//! of the environment variables that influenced the build. External build systems that wrap cargo
+//! can use them to find out when the embedded runtime is stale.
+//!
+//! ## Configuration file
+//!
+//! The build of a runtime crate can also be configured in a `wasm-builder.toml` next to its
+//! `Cargo.toml` or in the `[package.metadata.wasm-builder]` section of its `Cargo.toml`:
+//!
+//! ```toml
+//! profile = "production"
+//! toolchain = "1.81.0"
+//! rustflags = ["-C", "debuginfo=0"]
+//! features = ["on-chain-release-build"]
+//! disable-features = ["try-runtime"]
+//! default-features = true
+//! compact = true
+//! ```
+//!
+//! The methods of the [`WasmBuilder`] and the environment variables take precedence over the
+//! configuration file.
+//!
+//! ## Multiple projects
+//!
+//! A single `build.rs` can build multiple projects by passing one builder per project to
+//! [`WasmBuilder::build_all`]. Each project generates its own file in `OUT_DIR`, which by default
+//! is named after the project, e.g. `mainnet_runtime_wasm_binary.rs`. To include multiple of these
+//! files into the same module, use [`WasmBuilder::set_constant_prefix`] to give the constants of
+//! each file a distinct name, e.g. `MAINNET_WASM_BINARY`.
+//!
+//! ## Watch mode
+//!
+//! Going through the `build.rs` of the node crate for every change of the runtime is slow. To
+//! iterate on a runtime, [`watch`] builds it and rebuilds it whenever one of its sources changes,
+//! reusing the wasm project and its target directory between the builds. The `wasm-builder-watch`
+//! binary of this crate does this for the runtime at the given `Cargo.toml`:
+//!
+//! ```sh
+//! cargo run -p substrate-wasm-builder --bin wasm-builder-watch -- runtime/Cargo.toml
+//! ```
+//!
+//! The binaries are built into the same target directory as the release build of the node and the
+//! environment variables above configure the build as usual.
+//!
+//! ## Hermetic builds
+//!
+//! Build systems like Bazel or Buck2 declare all inputs and outputs of an action up front. For
+//! them, `WASM_BUILD_HERMETIC` can point to a JSON manifest that gives the inputs of the build
+//! explicitly:
+//!
+//! ```json
+//! {
+//!   "source_root": "path/to/workspace",
+//!   "cargo": "path/to/toolchain/bin/cargo",
+//!   "out_dir": "path/to/out"
+//! }
+//! ```
+//!
+//! Relative paths are relative to the directory of the manifest. The `Cargo.lock` is taken from
+//! `source_root`, cargo is run from the given toolchain without probing `rustup`, and the wasm
+//! project, its target directory and all generated files are put into `out_dir` instead of
+//! `OUT_DIR`. No `rerun-if-changed` instructions, fingerprints, dependency files or artifact cache
+//! entries are written, as the build system tracks the inputs itself.
 //!
 //! ## Prerequisites:
 //!
@@ -105,31 +300,95 @@
 //! installed as well. For example if installing the rust from 20.02.2020 using `rustup
 //! install nightly-2020-02-20`, the wasm target needs to be installed as well `rustup target add
 //! wasm32-unknown-unknown --toolchain nightly-2020-02-20`.
+//!
+//! Toolchains that are not managed by rustup, e.g. the ones of Nix or of a distribution, are
+//! supported as well. If `rustup` is not installed or `WASM_BUILD_NO_RUSTUP` is set, the `cargo`
+//! and `rustc` of the main build are used, i.e. the ones in `CARGO` and `RUSTC`, or otherwise the
+//! ones in `PATH`. They are checked to know the runtime target and to provide its standard library
+//! or the `rust-src` component, without ever running rustup, so neither `rustup toolchain list`
+//! nor `rustup run` pick a toolchain of a misconfigured rustup installation.
+//!
+//! [`check_prerequisites`] checks the prerequisites without building anything, so node CLIs and
+//! setup scripts can validate the environment up front.
+//!
+//! Windows is supported as a host as well. Paths may contain spaces, extended-length paths like
+//! `\\?\C:\runtime\Cargo.toml` are given to cargo in their normal form where possible and the
+//! paths given to [`WasmBuilder::with_cargo`] and [`WasmBuilder::with_rustc`] don't need the
+//! `.exe` suffix. Files checked out with `\r\n` line endings, e.g. the `Cargo.lock`, don't cause
+//! rebuilds.
 
 use std::{
+	cmp::Ordering,
 	collections::BTreeSet,
-	env, fs,
-	io::BufRead,
+	env,
+	ffi::OsStr,
+	fs,
 	path::{Path, PathBuf},
 	process::Command,
+	sync::OnceLock,
+	time::UNIX_EPOCH,
 };
 use version::Version;
 
+mod artifact_cache;
 mod builder;
+mod config;
+mod depfile;
+mod error;
+mod external_project;
+mod fingerprint;
+mod hermetic;
 #[cfg(feature = "metadata-hash")]
 mod metadata_hash;
 mod prerequisites;
+mod runtime_version;
+#[cfg(feature = "signing")]
+mod signing;
+mod size_report;
+mod validation;
 mod version;
 mod wasm_project;
+mod watch;
 
-pub use builder::{WasmBuilder, WasmBuilderSelectProject};
+pub use artifact_cache::{ArtifactCache, HttpArtifactCache};
+#[cfg(feature = "metadata-hash")]
+pub use builder::MetadataHashConfig;
+pub use builder::{
+	BuildArtifacts, BuildInfo, BuildOutput, RuntimeBinary, WasmBuilder, WasmBuilderSelectProject,
+};
+pub use error::WasmBuilderError;
+pub use prerequisites::{check_prerequisites, ToolchainReport};
+pub use runtime_version::RuntimeVersionInfo;
+#[cfg(feature = "signing")]
+pub use signing::{SignatureScheme, SigningKey};
+pub use validation::ValidationPolicy;
+pub use wasm_project::{EnvPolicy, Lto, OptLevel, PgoConfig, Profile, RustcEnvVar};
+pub use watch::{watch, DEFAULT_POLL_INTERVAL};
+
+/// The file in the target directory that caches the probed rustup toolchains.
+const RUSTUP_TOOLCHAINS_CACHE: &str = "wasm-builder-toolchains.json";
 
 /// Environment variable that tells us to skip building the wasm binary.
 const SKIP_BUILD_ENV: &str = "SKIP_WASM_BUILD";
 
+/// Environment variable with the path to a prebuilt binary that is used instead of building one.
+const WASM_BUILD_PREBUILT: &str = "WASM_BUILD_PREBUILT";
+
+/// Environment variable with the expected BLAKE2-256 hash of the binary of `WASM_BUILD_PREBUILT`.
+const WASM_BUILD_PREBUILT_BLAKE2_256: &str = "WASM_BUILD_PREBUILT_BLAKE2_256";
+
 /// Environment variable that tells us whether we should avoid network requests
 const OFFLINE: &str = "CARGO_NET_OFFLINE";
 
+/// Environment variable to set how `RUSTC_BOOTSTRAP` is handled for the toolchain of the runtime.
+const WASM_BUILD_ALLOW_RUSTC_BOOTSTRAP: &str = "WASM_BUILD_ALLOW_RUSTC_BOOTSTRAP";
+
+/// Environment variable that lets a stable compiler accept nightly features.
+const RUSTC_BOOTSTRAP: &str = "RUSTC_BOOTSTRAP";
+
+/// Environment variable that stops rustup from installing a missing toolchain on demand.
+const RUSTUP_AUTO_INSTALL: &str = "RUSTUP_AUTO_INSTALL";
+
 /// Environment variable to force a certain build type when building the wasm binary.
 /// Expects "debug", "release" or "production" as value.
 ///
@@ -141,6 +400,9 @@ const WASM_BUILD_TYPE_ENV: &str = "WASM_BUILD_TYPE";
 /// Environment variable to extend the `RUSTFLAGS` variable given to the wasm build.
 const WASM_BUILD_RUSTFLAGS_ENV: &str = "WASM_BUILD_RUSTFLAGS";
 
+/// Environment variable with extra arguments for the cargo command that builds the wasm binary.
+const WASM_BUILD_CARGO_ARGS: &str = "WASM_BUILD_CARGO_ARGS";
+
 /// Environment variable to set the target directory to copy the final wasm binary.
 ///
 /// The directory needs to be an absolute path.
@@ -152,6 +414,12 @@ const WASM_BUILD_NO_COLOR: &str = "WASM_BUILD_NO_COLOR";
 /// Environment variable to set the toolchain used to compile the wasm binary.
 const WASM_BUILD_TOOLCHAIN: &str = "WASM_BUILD_TOOLCHAIN";
 
+/// Environment variable to assume the version of toolchains whose version can't be parsed.
+const WASM_BUILD_ASSUME_RUSTC_VERSION: &str = "WASM_BUILD_ASSUME_RUSTC_VERSION";
+
+/// Environment variable to set the preference between the installed toolchains.
+const WASM_BUILD_TOOLCHAIN_POLICY: &str = "WASM_BUILD_TOOLCHAIN_POLICY";
+
 /// Environment variable that makes sure the WASM build is triggered.
 const FORCE_WASM_BUILD_ENV: &str = "FORCE_WASM_BUILD";
 
@@ -161,82 +429,608 @@ const WASM_BUILD_WORKSPACE_HINT: &str = "WASM_BUILD_WORKSPACE_HINT";
 /// Environment variable to set whether we'll build `core`/`std`.
 const WASM_BUILD_STD: &str = "WASM_BUILD_STD";
 
-/// The target to use for the runtime. Valid values are `wasm` (default) or `riscv`.
+/// Environment variable to set the prebuilt sysroot that replaces building the standard library.
+const WASM_BUILD_SYSROOT: &str = "WASM_BUILD_SYSROOT";
+
+/// Environment variable to set how cargo is told to build the standard library.
+const WASM_BUILD_STD_MODE: &str = "WASM_BUILD_STD_MODE";
+
+/// Environment variable to set whether WASM runtimes are built for `wasm32v1-none`.
+const WASM_BUILD_WASM32V1_NONE: &str = "WASM_BUILD_WASM32V1_NONE";
+
+/// Environment variable to set whether the `RUSTC_WRAPPER` is used for the wasm build.
+const WASM_BUILD_USE_RUSTC_WRAPPER: &str = "WASM_BUILD_USE_RUSTC_WRAPPER";
+
+/// Environment variable to set whether the runtime binaries are cached in the artifact cache.
+const WASM_BUILD_ARTIFACT_CACHE: &str = "WASM_BUILD_ARTIFACT_CACHE";
+
+/// Environment variable to set the token for the server of an `HttpArtifactCache`.
+const WASM_BUILD_CACHE_TOKEN: &str = "WASM_BUILD_CACHE_TOKEN";
+
+/// Environment variable to set whether the runtimes share one cargo target directory.
+const WASM_BUILD_SHARED_TARGET_DIR: &str = "WASM_BUILD_SHARED_TARGET_DIR";
+
+/// Environment variable to set whether the `dev` wasm build is incremental.
+const WASM_BUILD_INCREMENTAL: &str = "WASM_BUILD_INCREMENTAL";
+
+/// Environment variable with the path to a cargo configuration file for the wasm build.
+const WASM_BUILD_CARGO_CONFIG: &str = "WASM_BUILD_CARGO_CONFIG";
+
+/// Environment variable with the linker for the wasm build.
+const WASM_BUILD_LINKER: &str = "WASM_BUILD_LINKER";
+
+/// Environment variable with the linker flavor for the wasm build.
+const WASM_BUILD_LINKER_FLAVOR: &str = "WASM_BUILD_LINKER_FLAVOR";
+
+/// Environment variable to set whether the runtime is only type-checked.
+const WASM_BUILD_CHECK_ONLY: &str = "WASM_BUILD_CHECK_ONLY";
+
+/// Environment variable to set whether the wasm build fails instead of updating the `Cargo.lock`.
+const WASM_BUILD_LOCKED: &str = "WASM_BUILD_LOCKED";
+
+/// Environment variable to set how much is printed about the wasm build.
+const WASM_BUILD_VERBOSITY: &str = "WASM_BUILD_VERBOSITY";
+
+/// Environment variable to set whether the wasm build is profiled with `cargo --timings`.
+const WASM_BUILD_TIMINGS: &str = "WASM_BUILD_TIMINGS";
+
+/// Environment variable to set whether a missing wasm target is installed with `rustup`.
+const WASM_BUILD_AUTO_INSTALL_TARGET: &str = "WASM_BUILD_AUTO_INSTALL_TARGET";
+
+/// Environment variable to set whether a missing `rust-src` component is installed with `rustup`.
+const WASM_BUILD_AUTO_INSTALL_RUST_SRC: &str = "WASM_BUILD_AUTO_INSTALL_RUST_SRC";
+
+/// Environment variable to set whether rustup is never used, even if it is installed.
+const WASM_BUILD_NO_RUSTUP: &str = "WASM_BUILD_NO_RUSTUP";
+
+/// Environment variable with the path to the manifest of a hermetic build.
+const WASM_BUILD_HERMETIC: &str = "WASM_BUILD_HERMETIC";
+
+/// Environment variable that is set for the runtime build and points to the wasm project
+/// directory.
+const WASM_BUILD_PROJECT_DIR: &str = "WASM_BUILD_PROJECT_DIR";
+
+/// The target to use for the runtime. Valid values are `wasm` (default), `wasm64` or `riscv`.
 const RUNTIME_TARGET: &str = "SUBSTRATE_RUNTIME_TARGET";
 
+/// The environment variables that rebuild the wasm binary when they change, unless they are
+/// untracked with [`WasmBuilder::untrack_env_var`].
+const TRACKED_ENV_VARS: &[&str] = &[
+	WASM_BUILD_TYPE_ENV,
+	WASM_BUILD_RUSTFLAGS_ENV,
+	WASM_BUILD_CARGO_ARGS,
+	WASM_TARGET_DIRECTORY,
+	WASM_BUILD_TOOLCHAIN,
+	WASM_BUILD_TOOLCHAIN_POLICY,
+	WASM_BUILD_ASSUME_RUSTC_VERSION,
+	WASM_BUILD_STD,
+	WASM_BUILD_STD_MODE,
+	WASM_BUILD_ALLOW_RUSTC_BOOTSTRAP,
+	WASM_BUILD_SYSROOT,
+	WASM_BUILD_WASM32V1_NONE,
+	WASM_BUILD_TIMINGS,
+	WASM_BUILD_USE_RUSTC_WRAPPER,
+	WASM_BUILD_ARTIFACT_CACHE,
+	WASM_BUILD_SHARED_TARGET_DIR,
+	WASM_BUILD_INCREMENTAL,
+	WASM_BUILD_LOCKED,
+	WASM_BUILD_CHECK_ONLY,
+	WASM_BUILD_CARGO_CONFIG,
+	WASM_BUILD_LINKER,
+	WASM_BUILD_LINKER_FLAVOR,
+	WASM_BUILD_VERBOSITY,
+	RUNTIME_TARGET,
+];
+
+/// Returns the directory the generated files are written to.
+///
+/// This is `OUT_DIR`, or the output directory of the manifest in a hermetic build.
+fn out_dir() -> PathBuf {
+	match hermetic::get() {
+		Some(hermetic) => hermetic.out_dir.clone(),
+		None => build_helper::out_dir(),
+	}
+}
+
 /// Write to the given `file` if the `content` is different.
-fn write_file_if_changed(file: impl AsRef<Path>, content: impl AsRef<str>) {
-	if fs::read_to_string(file.as_ref()).ok().as_deref() != Some(content.as_ref()) {
-		fs::write(file.as_ref(), content.as_ref())
-			.unwrap_or_else(|_| panic!("Writing `{}` can not fail!", file.as_ref().display()));
+fn write_file_if_changed(
+	file: impl AsRef<Path>,
+	content: impl AsRef<str>,
+) -> Result<(), WasmBuilderError> {
+	let existing = fs::read_to_string(file.as_ref()).ok();
+	if !existing.map_or(false, |existing| same_ignoring_line_endings(&existing, content.as_ref())) {
+		fs::write(file.as_ref(), content.as_ref()).map_err(WasmBuilderError::io(file.as_ref()))?;
+	}
+
+	Ok(())
+}
+
+/// Returns the `0x` prefixed hex encoding of the given `bytes`.
+fn to_hex(bytes: &[u8]) -> String {
+	bytes.iter().fold(String::from("0x"), |hex, b| hex + &format!("{b:02x}"))
+}
+
+/// Decode the given `hex` string, optionally prefixed with `0x`.
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+	let hex = hex.strip_prefix("0x").unwrap_or(hex);
+	let chunks = hex.as_bytes().chunks_exact(2);
+	if !chunks.remainder().is_empty() || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+		return None
+	}
+
+	chunks
+		.map(|chunk| u8::from_str_radix(std::str::from_utf8(chunk).ok()?, 16).ok())
+		.collect()
+}
+
+/// Returns whether `a` and `b` only differ in their line endings.
+///
+/// On Windows, git may check out files like the `Cargo.lock` with `\r\n` line endings, while cargo
+/// and the wasm builder write them with `\n`. Rewriting them for that alone would trigger a
+/// rebuild every time.
+fn same_ignoring_line_endings(a: &str, b: &str) -> bool {
+	a == b || a.replace("\r\n", "\n") == b.replace("\r\n", "\n")
+}
+
+/// Returns the `path` without the `\\?\` prefix of an extended-length path on Windows.
+///
+/// `fs::canonicalize` returns extended-length paths on Windows, which not every tool accepts, e.g.
+/// as `path` of a dependency or in `--remap-path-prefix`. Other paths are returned unchanged.
+pub(crate) fn simplified_path(path: PathBuf) -> PathBuf {
+	if !cfg!(windows) {
+		return path
+	}
+
+	match path.to_str().and_then(strip_verbatim_prefix) {
+		Some(simplified) => PathBuf::from(simplified),
+		None => path,
+	}
+}
+
+/// Returns the `path` without its `\\?\` prefix, if it is a disk or network path that is also
+/// valid without the prefix.
+fn strip_verbatim_prefix(path: &str) -> Option<String> {
+	let rest = path.strip_prefix(r"\\?\")?;
+	// Without the prefix, paths are limited to `MAX_PATH` and `/` is a separator.
+	if path.len() >= 260 || rest.contains('/') {
+		return None
+	}
+
+	if let Some(unc) = rest.strip_prefix(r"UNC\") {
+		return Some(format!(r"\\{unc}"))
+	}
+
+	let mut chars = rest.chars();
+	match (chars.next(), chars.next(), chars.next()) {
+		(Some(drive), Some(':'), Some('\\')) if drive.is_ascii_alphabetic() => Some(rest.into()),
+		_ => None,
+	}
+}
+
+/// Returns the path of the executable at `path`, with the `.exe` suffix on Windows if `path` was
+/// given without it.
+pub(crate) fn executable_path(path: &Path) -> PathBuf {
+	let mut exe = path.as_os_str().to_owned();
+	exe.push(env::consts::EXE_SUFFIX);
+	let exe = PathBuf::from(exe);
+
+	if !path.is_file() && exe.is_file() {
+		exe
+	} else {
+		path.to_path_buf()
 	}
 }
 
 /// Copy `src` to `dst` if the `dst` does not exist or is different.
-fn copy_file_if_changed(src: PathBuf, dst: PathBuf) {
+fn copy_file_if_changed(src: PathBuf, dst: PathBuf) -> Result<(), WasmBuilderError> {
 	let src_file = fs::read_to_string(&src).ok();
 	let dst_file = fs::read_to_string(&dst).ok();
 
-	if src_file != dst_file {
-		fs::copy(&src, &dst).unwrap_or_else(|_| {
-			panic!("Copying `{}` to `{}` can not fail; qed", src.display(), dst.display())
-		});
+	let unchanged = match (&src_file, &dst_file) {
+		(Some(src_file), Some(dst_file)) => same_ignoring_line_endings(src_file, dst_file),
+		_ => false,
+	};
+	if !unchanged {
+		fs::copy(&src, &dst).map_err(WasmBuilderError::io(dst))?;
 	}
+
+	Ok(())
 }
 
 /// Get a cargo command that should be used to invoke the compilation.
-fn get_cargo_command(target: RuntimeTarget) -> CargoCommand {
-	let env_cargo =
-		CargoCommand::new(&env::var("CARGO").expect("`CARGO` env variable is always set by cargo"));
-	let default_cargo = CargoCommand::new("cargo");
-	let wasm_toolchain = env::var(WASM_BUILD_TOOLCHAIN).ok();
+///
+/// The `toolchain` pinned by the builder takes precedence over the toolchain requested by
+/// `WASM_BUILD_TOOLCHAIN`. If the pinned toolchain is not installed, it is installed with `rustup`
+/// when `install_missing_toolchain` is `true` and otherwise an error is returned. Without a pinned
+/// toolchain, the toolchain preferred by the `policy` is selected.
+fn get_cargo_command(
+	target: RuntimeTarget,
+	toolchain: Option<&str>,
+	install_missing_toolchain: bool,
+	policy: ToolchainPolicy,
+) -> Result<CargoCommand, WasmBuilderError> {
+	// A hermetic build uses exactly the given toolchain.
+	if let Some(hermetic) = hermetic::get() {
+		return Ok(CargoCommand::new(&hermetic.cargo.to_string_lossy()))
+	}
+
+	let requested_toolchain =
+		toolchain.map(ToOwned::to_owned).or_else(|| env::var(WASM_BUILD_TOOLCHAIN).ok());
+	if requested_toolchain.is_none() && policy == ToolchainPolicy::PinnedOnly {
+		return Err(WasmBuilderError::MissingPrerequisites(format!(
+			"The toolchain policy `pinned-only` requires the toolchain of the runtime to be pinned, \
+			 but none is. Pin it with `WasmBuilder::toolchain`, `{WASM_BUILD_TOOLCHAIN}` or a \
+			 `rust-toolchain.toml`."
+		)))
+	}
+
+	// A toolchain that is not managed by rustup, e.g. of Nix or a distribution, is used as it is,
+	// without ever running rustup.
+	if !rustup_available()? {
+		if let Some(toolchain) = requested_toolchain {
+			return Err(WasmBuilderError::MissingPrerequisites(format!(
+				"The toolchain `{toolchain}` is requested, but it can't be selected without rustup. \
+				 Provide it as `cargo` and `rustc` in `PATH` instead."
+			)))
+		}
+
+		// The toolchain of the main build comes first, the one in `PATH` is only probed if it is a
+		// different one.
+		let env_binaries = (env::var("CARGO").ok(), env::var("RUSTC").ok());
+		let env_cargo = CargoCommand::new_standalone(
+			env_binaries.0.as_deref().unwrap_or("cargo"),
+			env_binaries.1.as_deref().unwrap_or("rustc"),
+		);
+		let mut candidates = vec![(format!("{} (`CARGO`)", env_cargo.name()), env_cargo)];
+		if env_binaries != (None, None) {
+			let default_cargo = CargoCommand::new_standalone("cargo", "rustc");
+			candidates.push((default_cargo.name(), default_cargo));
+		}
+
+		if let Some((_, cargo)) =
+			candidates.iter().find(|(_, cargo)| cargo.unsupported_reason(target).is_none())
+		{
+			return Ok(cargo.clone())
+		}
+		let candidates =
+			candidates.iter().map(|(name, cargo)| (name.clone(), cargo)).collect::<Vec<_>>();
+		return Err(prerequisites::no_compatible_toolchain(target, &candidates, true))
+	}
 
 	// First check if the user requested a specific toolchain
-	if let Some(cmd) =
-		wasm_toolchain.map(|t| CargoCommand::new_with_args("rustup", &["run", &t, "cargo"]))
+	if let Some(toolchain) = requested_toolchain {
+		return get_toolchain_command(target, &toolchain, install_missing_toolchain)
+	}
+
+	let env_cargo = CargoCommand::new(&env::var("CARGO").unwrap_or_else(|_| "cargo".into()));
+	let default_cargo = CargoCommand::new("cargo");
+
+	// The cargo of the main build is used if the policy doesn't ask for a search of a better one.
+	let prefers_main_build = |cmd: &CargoCommand| match policy {
+		ToolchainPolicy::StableFirst | ToolchainPolicy::PinnedOnly => true,
+		ToolchainPolicy::NightlyFirst => cmd.version().map_or(false, |v| v.is_nightly()),
+		ToolchainPolicy::Newest => false,
+	};
+	if let Some(cmd) = [&env_cargo, &default_cargo]
+		.into_iter()
+		.find(|cmd| cmd.supports_substrate_runtime_env(target) && prefers_main_build(cmd))
 	{
-		cmd
-	} else if env_cargo.supports_substrate_runtime_env(target) {
-		env_cargo
-	} else if default_cargo.supports_substrate_runtime_env(target) {
-		default_cargo
-	} else {
-		// If no command before provided us with a cargo that supports our Substrate wasm env, we
-		// try to search one with rustup. If that fails as well, we return the default cargo and let
-		// the perquisites check fail.
-		get_rustup_command(target).unwrap_or(default_cargo)
+		return Ok(cmd.clone())
+	}
+
+	// If no command before provided us with a cargo that supports our Substrate wasm env, we
+	// try to search one with rustup. If that fails as well, we report every probed toolchain.
+	let rustup_commands = get_rustup_commands();
+	let commands = [&env_cargo, &default_cargo].into_iter().chain(rustup_commands.iter().flatten());
+	if let Some(cmd) = preferred_supported_command(target, policy, commands) {
+		return Ok(cmd.clone())
 	}
+
+	let candidates = [(format!("{} (`CARGO`)", env_cargo.name()), &env_cargo)]
+		.into_iter()
+		.chain([(default_cargo.name(), &default_cargo)])
+		.chain(rustup_commands.iter().flatten().map(|cmd| (cmd.name(), cmd)))
+		.collect::<Vec<_>>();
+	Err(prerequisites::no_compatible_toolchain(target, &candidates, rustup_commands.is_none()))
 }
 
-/// Get the newest rustup command that supports compiling a runtime.
+/// Returns whether the toolchains are managed by rustup, i.e. `rustup` is installed and its use is
+/// not disabled with `WASM_BUILD_NO_RUSTUP`.
+pub(crate) fn rustup_available() -> Result<bool, WasmBuilderError> {
+	static RUSTUP_INSTALLED: OnceLock<bool> = OnceLock::new();
+
+	if get_bool_environment_variable(WASM_BUILD_NO_RUSTUP)? == Some(true) {
+		return Ok(false)
+	}
+
+	Ok(*RUSTUP_INSTALLED.get_or_init(|| {
+		tool_command("rustup")
+			.arg("--version")
+			.output()
+			.map_or(false, |o| o.status.success())
+	}))
+}
+
+/// Returns the command that runs the toolchain binary `program`, e.g. `rustup`, `cargo` or `rustc`.
+///
+/// These binaries are usually rustup proxies, which install a missing toolchain on demand. This is
+/// disabled for offline builds, so a missing toolchain fails right away instead of trying to reach
+/// the network.
 ///
-/// Stable versions are always favored over nightly versions even if the nightly versions are
-/// newer.
-fn get_rustup_command(target: RuntimeTarget) -> Option<CargoCommand> {
-	let output = Command::new("rustup").args(&["toolchain", "list"]).output().ok()?.stdout;
-	let lines = output.as_slice().lines();
+/// `RUSTC_BOOTSTRAP` is set or removed as requested by `WASM_BUILD_ALLOW_RUSTC_BOOTSTRAP`, see
+/// [`RustcBootstrap`].
+pub(crate) fn tool_command(program: impl AsRef<OsStr>) -> Command {
+	let mut cmd = Command::new(program);
+	if wasm_project::offline_build() {
+		cmd.env(RUSTUP_AUTO_INSTALL, "0");
+	}
+	match RustcBootstrap::from_env().unwrap_or_default() {
+		RustcBootstrap::Clear => cmd.env_remove(RUSTC_BOOTSTRAP),
+		RustcBootstrap::Set => cmd.env(RUSTC_BOOTSTRAP, "1"),
+		RustcBootstrap::Inherit => &mut cmd,
+	};
+	cmd
+}
 
-	let mut versions = Vec::new();
-	for line in lines.filter_map(|l| l.ok()) {
-		// Split by a space to get rid of e.g. " (default)" at the end.
-		let rustup_version = line.split(" ").next().unwrap();
-		let cmd = CargoCommand::new_with_args("rustup", &["run", &rustup_version, "cargo"]);
+/// How `RUSTC_BOOTSTRAP` is handled for the toolchain of the runtime, as set by
+/// `WASM_BUILD_ALLOW_RUSTC_BOOTSTRAP`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) enum RustcBootstrap {
+	/// `RUSTC_BOOTSTRAP` is removed, so only nightly toolchains can use nightly features.
+	#[default]
+	Clear,
+	/// `RUSTC_BOOTSTRAP=1` is set, so every toolchain can use nightly features.
+	Set,
+	/// The `RUSTC_BOOTSTRAP` of the main build is kept.
+	Inherit,
+}
+
+impl RustcBootstrap {
+	/// Returns the handling set by `WASM_BUILD_ALLOW_RUSTC_BOOTSTRAP`. Returns an error if the
+	/// value is invalid.
+	pub(crate) fn from_env() -> Result<Self, WasmBuilderError> {
+		let Some(value) = env::var_os(WASM_BUILD_ALLOW_RUSTC_BOOTSTRAP) else {
+			return Ok(Self::default())
+		};
+
+		match value.to_str() {
+			Some("clear") => Ok(Self::Clear),
+			Some("set") => Ok(Self::Set),
+			Some("inherit") => Ok(Self::Inherit),
+			_ => Err(WasmBuilderError::InvalidEnvironmentVariable {
+				name: WASM_BUILD_ALLOW_RUSTC_BOOTSTRAP,
+				value: value.to_string_lossy().into(),
+				expected: "one of `clear`, `set` or `inherit`".into(),
+			}),
+		}
+	}
+
+	/// Returns whether the toolchain of the runtime accepts nightly features because of
+	/// `RUSTC_BOOTSTRAP`, no matter whether it is a nightly.
+	pub(crate) fn enabled() -> bool {
+		match Self::from_env().unwrap_or_default() {
+			Self::Clear => false,
+			Self::Set => true,
+			Self::Inherit => env::var_os(RUSTC_BOOTSTRAP).is_some(),
+		}
+	}
+}
+
+/// Returns whether the rustup `toolchain` always refers to the same compiler.
+///
+/// This is the case for versions like `1.81.0` and dated channels like `nightly-2024-09-01`, but
+/// not for channels like `stable` that are updated over time.
+pub(crate) fn is_pinned_toolchain(toolchain: &str) -> bool {
+	let mut parts = toolchain.split('-');
+	match parts.next() {
+		Some(version) if version.starts_with(|c: char| c.is_ascii_digit()) => true,
+		Some("nightly" | "beta") => parts
+			.next()
+			.map_or(false, |year| year.len() == 4 && year.chars().all(|c| c.is_ascii_digit())),
+		_ => false,
+	}
+}
+
+/// Get the cargo command of the given rustup `toolchain`.
+///
+/// Installs the `toolchain` if it is missing and `install_missing` is `true`.
+fn get_toolchain_command(
+	target: RuntimeTarget,
+	toolchain: &str,
+	install_missing: bool,
+) -> Result<CargoCommand, WasmBuilderError> {
+	let cmd = CargoCommand::new_with_args("rustup", &["run", toolchain, "cargo"]);
+	if cmd.version().is_some() {
+		return Ok(cmd)
+	}
 
+	if !install_missing {
+		return Err(WasmBuilderError::MissingPrerequisites(format!(
+			"The toolchain `{toolchain}` that is required to build the runtime is not installed!\n\
+			 You can install it with `rustup toolchain install {toolchain}`.",
+		)))
+	}
+
+	wasm_project::require_network(&format!("installing the missing toolchain `{toolchain}`"))?;
+	println!("Installing the missing toolchain `{toolchain}` with rustup.");
+	let mut install_cmd = tool_command("rustup");
+	install_cmd.args(["toolchain", "install", toolchain, "--profile", "minimal"]);
+	match target {
+		RuntimeTarget::Wasm =>
+			install_cmd.args(["--target", target.rustc_target(), "--component", "rust-src"]),
+		// There is no precompiled standard library for `wasm64`.
+		RuntimeTarget::Wasm64 => install_cmd.args(["--component", "rust-src"]),
+		RuntimeTarget::Riscv => &mut install_cmd,
+	};
+
+	if !install_cmd.status().map_or(false, |s| s.success()) {
+		return Err(WasmBuilderError::MissingPrerequisites(format!(
+			"Failed to install the toolchain `{toolchain}` with rustup.",
+		)))
+	}
+
+	Ok(CargoCommand::new_with_args("rustup", &["run", toolchain, "cargo"]))
+}
+
+/// Get the cargo commands of all rustup toolchains, `None` if `rustup` is not installed.
+fn get_rustup_commands() -> Option<Vec<CargoCommand>> {
+	let output = tool_command("rustup").args(["toolchain", "list", "--verbose"]).output().ok()?;
+	let toolchain_list = String::from_utf8(output.stdout).ok()?;
+
+	Some(probe_rustup_toolchains(&toolchain_list))
+}
+
+/// Get the one of the `commands` that supports compiling a runtime and is preferred by the
+/// `policy`.
+///
+/// With [`ToolchainPolicy::StableFirst`], stable versions are always favored over nightly versions
+/// even if the nightly versions are newer.
+fn preferred_supported_command<'a>(
+	target: RuntimeTarget,
+	policy: ToolchainPolicy,
+	commands: impl IntoIterator<Item = &'a CargoCommand>,
+) -> Option<&'a CargoCommand> {
+	let mut versions = Vec::new();
+	for cmd in commands {
 		if !cmd.supports_substrate_runtime_env(target) {
 			continue
 		}
 
 		let Some(cargo_version) = cmd.version() else { continue };
 
-		versions.push((cargo_version, rustup_version.to_string()));
+		versions.push((cargo_version, cmd));
 	}
 
-	// Sort by the parsed version to get the latest version (greatest version) at the end of the
+	// Sort by the preference of the parsed versions to get the preferred version at the end of the
 	// vec.
-	versions.sort_by_key(|v| v.0);
-	let version = &versions.last()?.1;
+	versions.sort_by(|a, b| policy.compare(&a.0, &b.0));
+	versions.pop().map(|(_, cmd)| cmd)
+}
+
+/// Returns the cargo commands of all toolchains in the `toolchain_list`, the output of `rustup
+/// toolchain list --verbose`.
+///
+/// Probing the version and the targets of every toolchain takes a while, so the results are cached
+/// in [`RUSTUP_TOOLCHAINS_CACHE`] in the target directory. They are only probed again when a
+/// toolchain is added, removed or updated.
+fn probe_rustup_toolchains(toolchain_list: &str) -> Vec<CargoCommand> {
+	let toolchains = toolchain_list.lines().filter_map(parse_toolchain_line).collect::<Vec<_>>();
+
+	// An updated toolchain keeps its name and path, but its `cargo` binary changes.
+	let fingerprint = toolchains
+		.iter()
+		.map(|(name, path)| {
+			let modified = path
+				.and_then(|path| {
+					let cargo = format!("cargo{}", env::consts::EXE_SUFFIX);
+					fs::metadata(path.join("bin").join(cargo)).ok()
+				})
+				.and_then(|metadata| metadata.modified().ok())
+				.and_then(|modified| modified.duration_since(UNIX_EPOCH).ok());
+			format!("{name} {path:?} {modified:?}\n")
+		})
+		.collect::<String>();
+
+	// Outside of a build, e.g. in `check_prerequisites`, there is no target directory for the
+	// cache.
+	let in_build = env::var_os("OUT_DIR").is_some() || hermetic::get().is_some();
+	let cache_path = in_build
+		.then(wasm_project::get_wasm_workspace_root)
+		.and_then(Result::ok)
+		.map(|root| root.join(RUSTUP_TOOLCHAINS_CACHE));
+	if let Some(cached) = cache_path.as_deref().and_then(|path| load_toolchains(path, &fingerprint))
+	{
+		return cached
+	}
+
+	let commands = toolchains
+		.iter()
+		.map(|(name, _)| CargoCommand::new_with_args("rustup", &["run", name, "cargo"]))
+		.collect::<Vec<_>>();
+
+	if let Some(path) = cache_path {
+		let toolchains = commands
+			.iter()
+			.map(|cmd| {
+				serde_json::json!({
+					"args": cmd.args,
+					"version": cmd.version_output,
+					"targets": cmd.target_list,
+				})
+			})
+			.collect::<Vec<_>>();
+		let cache = serde_json::json!({ "fingerprint": fingerprint, "toolchains": toolchains });
+		// Write the cache atomically, as the build scripts of other runtimes may read it.
+		let tmp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+		let written =
+			fs::write(&tmp_path, cache.to_string()).and_then(|_| fs::rename(&tmp_path, &path));
+		if written.is_err() {
+			let _ = fs::remove_file(&tmp_path);
+		}
+	}
+
+	commands
+}
+
+/// Returns the toolchains cached at `path`, if they were probed with the same `fingerprint`.
+fn load_toolchains(path: &Path, fingerprint: &str) -> Option<Vec<CargoCommand>> {
+	let cache: serde_json::Value = serde_json::from_slice(&fs::read(path).ok()?).ok()?;
+	if cache["fingerprint"] != fingerprint {
+		return None
+	}
+
+	cache["toolchains"]
+		.as_array()?
+		.iter()
+		.map(|toolchain| {
+			let args = serde_json::from_value::<Vec<String>>(toolchain["args"].clone()).ok()?;
+			let version_output = toolchain["version"].as_str().map(ToOwned::to_owned);
+			let target_list = serde_json::from_value(toolchain["targets"].clone()).ok()?;
+			Some(CargoCommand {
+				program: "rustup".into(),
+				args,
+				version: version_output.as_deref().and_then(parse_version_output),
+				version_output,
+				target_list,
+				rustc: None,
+				riscv_probe: OnceLock::new(),
+				stable_build_std: OnceLock::new(),
+			})
+		})
+		.collect()
+}
+
+/// Extract the version from the `output` of `cargo --version` or `rustc --version`.
+///
+/// If the output can't be parsed, the version given by `WASM_BUILD_ASSUME_RUSTC_VERSION` is
+/// assumed.
+fn parse_version_output(output: &str) -> Option<Version> {
+	Version::extract(output).or_else(|| {
+		let value = env::var(WASM_BUILD_ASSUME_RUSTC_VERSION).ok()?;
+		let version = Version::extract(&format!("rustc {value}"));
+		if version.is_none() {
+			build_helper::warning!(
+				"`{WASM_BUILD_ASSUME_RUSTC_VERSION}` is set to `{value}`, which is not a version \
+				 like `1.81.0` or `1.83.0-nightly`."
+			);
+		}
+		version
+	})
+}
+
+/// Parse a line of `rustup toolchain list --verbose` into the name and the path of the toolchain.
+///
+/// The lines look like `stable-x86_64-unknown-linux-gnu (active, default) /path/to/toolchain`.
+fn parse_toolchain_line(line: &str) -> Option<(&str, Option<&Path>)> {
+	let line = line.trim();
+	let name = line.split_whitespace().next()?;
+
+	let mut rest = line[name.len()..].trim_start();
+	if rest.starts_with('(') {
+		rest = rest.split_once(')').map_or("", |(_, rest)| rest.trim_start());
+	}
 
-	Some(CargoCommand::new_with_args("rustup", &["run", &version, "cargo"]))
+	Some((name, Some(Path::new(rest)).filter(|path| !path.as_os_str().is_empty())))
 }
 
 /// Wraps a specific command which represents a cargo invocation.
@@ -245,51 +1039,118 @@ struct CargoCommand {
 	program: String,
 	args: Vec<String>,
 	version: Option<Version>,
+	/// The output of `cargo --version` the `version` was extracted from.
+	version_output: Option<String>,
 	target_list: Option<BTreeSet<String>>,
+	/// The `rustc` of a toolchain that is not managed by rustup. Otherwise cargo picks the `rustc`
+	/// of its toolchain.
+	rustc: Option<String>,
+	/// The result of compiling for the RISC-V target, which is only probed if the `target_list`
+	/// is unknown.
+	riscv_probe: OnceLock<Result<(), String>>,
+	/// Whether cargo supports the stable `--build-std` flag, probed on first use.
+	stable_build_std: OnceLock<bool>,
 }
 
 impl CargoCommand {
 	fn new(program: &str) -> Self {
-		let version = Self::extract_version(program, &[]);
-		let target_list = Self::extract_target_list(program, &[]);
-
-		CargoCommand { program: program.into(), args: Vec::new(), version, target_list }
+		Self::new_with_args(program, &[])
 	}
 
 	fn new_with_args(program: &str, args: &[&str]) -> Self {
-		let version = Self::extract_version(program, args);
+		let version_output = Self::extract_version_output(program, args);
 		let target_list = Self::extract_target_list(program, args);
 
 		CargoCommand {
 			program: program.into(),
 			args: args.iter().map(ToString::to_string).collect(),
-			version,
+			version: version_output.as_deref().and_then(parse_version_output),
+			version_output,
 			target_list,
+			rustc: None,
+			riscv_probe: OnceLock::new(),
+			stable_build_std: OnceLock::new(),
+		}
+	}
+
+	/// Create a command for a toolchain that is not managed by rustup, e.g. of Nix or a
+	/// distribution, from its `cargo` and `rustc` binaries.
+	///
+	/// Unlike for [`Self::new`], the targets are listed by `rustc` itself, which also works with
+	/// stable compilers.
+	fn new_standalone(cargo: &str, rustc: &str) -> Self {
+		let version_output = Self::extract_version_output(cargo, &[]);
+		let target_list = tool_command(rustc)
+			.args(["--print", "target-list"])
+			.output()
+			.ok()
+			.filter(|o| o.status.success())
+			.and_then(|o| String::from_utf8(o.stdout).ok())
+			.map(|list| list.lines().map(ToString::to_string).collect());
+
+		CargoCommand {
+			program: cargo.into(),
+			args: Vec::new(),
+			version: version_output.as_deref().and_then(parse_version_output),
+			version_output,
+			target_list,
+			rustc: Some(rustc.into()),
+			riscv_probe: OnceLock::new(),
+			stable_build_std: OnceLock::new(),
+		}
+	}
+
+	/// Create a command for the explicitly given `cargo` and `rustc` binaries.
+	///
+	/// Without `cargo`, the `cargo` of the main build is used. Without `rustc`, cargo uses the
+	/// `rustc` of its own toolchain.
+	fn with_paths(cargo: Option<&Path>, rustc: Option<&Path>) -> Self {
+		let cargo = cargo.map_or_else(
+			|| env::var("CARGO").unwrap_or_else(|_| "cargo".into()),
+			|cargo| executable_path(cargo).to_string_lossy().into_owned(),
+		);
+
+		match rustc {
+			Some(rustc) => Self::new_standalone(&cargo, &executable_path(rustc).to_string_lossy()),
+			None => Self::new(&cargo),
 		}
 	}
 
 	fn command(&self) -> Command {
-		let mut cmd = Command::new(&self.program);
+		let mut cmd = tool_command(&self.program);
 		cmd.args(&self.args);
+		match &self.rustc {
+			Some(rustc) => cmd.env("RUSTC", rustc),
+			// Make sure if we're called from within a `build.rs` the host toolchain won't override
+			// a rustup toolchain we've picked.
+			None => cmd.env_remove("RUSTC"),
+		};
 		cmd
 	}
 
-	fn extract_version(program: &str, args: &[&str]) -> Option<Version> {
-		let version = Command::new(program)
+	/// Returns the name of this command in diagnostics, e.g. the rustup toolchain it runs.
+	fn name(&self) -> String {
+		match self.args.as_slice() {
+			[run, toolchain, ..] if self.program == "rustup" && run == "run" =>
+				format!("{toolchain} (rustup)"),
+			_ => self.program.clone(),
+		}
+	}
+
+	fn extract_version_output(program: &str, args: &[&str]) -> Option<String> {
+		tool_command(program)
 			.args(args)
 			.arg("--version")
 			.output()
 			.ok()
-			.and_then(|o| String::from_utf8(o.stdout).ok())?;
-
-		Version::extract(&version)
+			.and_then(|o| String::from_utf8(o.stdout).ok())
 	}
 
 	fn extract_target_list(program: &str, args: &[&str]) -> Option<BTreeSet<String>> {
 		// This is technically an unstable option, but we don't care because we only need this
 		// to build RISC-V runtimes, and those currently require a specific nightly toolchain
 		// anyway, so it's totally fine for this to fail in other cases.
-		let list = Command::new(program)
+		let list = tool_command(program)
 			.args(args)
 			.args(&["rustc", "-Z", "unstable-options", "--print", "target-list"])
 			// Make sure if we're called from within a `build.rs` the host toolchain won't override
@@ -299,7 +1160,8 @@ impl CargoCommand {
 			.ok()
 			.and_then(|o| String::from_utf8(o.stdout).ok())?;
 
-		Some(list.trim().split("\n").map(ToString::to_string).collect())
+		// On Windows, the lines end with `\r\n`.
+		Some(list.trim().lines().map(ToString::to_string).collect())
 	}
 
 	/// Returns the version of this cargo command or `None` if it failed to extract the version.
@@ -307,29 +1169,106 @@ impl CargoCommand {
 		self.version
 	}
 
+	/// Returns whether cargo supports the stable `--build-std` flag, i.e. lists it in the help of
+	/// `cargo build`.
+	fn supports_stable_build_std(&self) -> bool {
+		*self.stable_build_std.get_or_init(|| {
+			self.command()
+				.args(["build", "--help"])
+				.output()
+				.ok()
+				.filter(|o| o.status.success())
+				.map_or(false, |o| String::from_utf8_lossy(&o.stdout).contains("--build-std"))
+		})
+	}
+
 	/// Returns whether this version of the toolchain supports nightly features.
 	fn supports_nightly_features(&self) -> bool {
-		self.version.map_or(false, |version| version.is_nightly) ||
-			env::var("RUSTC_BOOTSTRAP").is_ok()
+		self.version.map_or(false, |version| version.is_nightly) || RustcBootstrap::enabled()
 	}
 
 	/// Check if the supplied cargo command supports our runtime environment.
 	fn supports_substrate_runtime_env(&self, target: RuntimeTarget) -> bool {
 		match target {
-			RuntimeTarget::Wasm => self.supports_substrate_runtime_env_wasm(),
+			RuntimeTarget::Wasm | RuntimeTarget::Wasm64 =>
+				self.supports_substrate_runtime_env_wasm(),
 			RuntimeTarget::Riscv => self.supports_substrate_runtime_env_riscv(),
 		}
 	}
 
+	/// Returns why this cargo command doesn't support our runtime environment for `target`, `None`
+	/// if it does.
+	///
+	/// For a toolchain that is not managed by rustup, it is also checked that `rustc` knows the
+	/// target and that either the standard library of the target or its sources are installed.
+	fn unsupported_reason(&self, target: RuntimeTarget) -> Option<String> {
+		if self.supports_substrate_runtime_env(target) {
+			let rustc = self.rustc.as_deref()?;
+			let triple = target.rustc_target();
+			if !self.supports_target(triple) {
+				return Some(format!("`{triple}` is not a known target"))
+			}
+
+			let sysroot = tool_command(rustc)
+				.args(["--print", "sysroot"])
+				.output()
+				.ok()
+				.and_then(|o| String::from_utf8(o.stdout).ok())?;
+			let rustlib = Path::new(sysroot.trim()).join("lib").join("rustlib");
+			if !rustlib.join(triple).exists() && !rustlib.join("src").join("rust").exists() {
+				return Some(format!(
+					"neither the standard library of `{triple}` nor `rust-src` is installed"
+				))
+			}
+
+			return None
+		}
+
+		Some(match target {
+			RuntimeTarget::Wasm | RuntimeTarget::Wasm64 => match self.version {
+				Some(version) => format!(
+					"stable {}.{}.{} is older than 1.68.0",
+					version.major, version.minor, version.patch
+				),
+				None => "`cargo --version` failed".into(),
+			},
+			RuntimeTarget::Riscv => match (&self.target_list, self.riscv_probe()) {
+				(Some(_), _) => "`riscv32ema-unknown-none-elf` is not a known target".into(),
+				(None, Err(error)) => format!(
+					"the target list could not be printed and compiling for \
+					 `riscv32ema-unknown-none-elf` failed: {error}"
+				),
+				(None, Ok(())) => "the target list could not be printed".into(),
+			},
+		})
+	}
+
+	/// Returns whether the toolchain of this cargo command provides the given target `triple`.
+	///
+	/// This is always `false` if the target list couldn't be extracted.
+	fn supports_target(&self, triple: &str) -> bool {
+		self.target_list.as_ref().map_or(false, |list| list.contains(triple))
+	}
+
 	/// Check if the supplied cargo command supports our RISC-V runtime environment.
 	fn supports_substrate_runtime_env_riscv(&self) -> bool {
-		let Some(target_list) = self.target_list.as_ref() else { return false };
+		// Stable toolchains can't print their target list, so try to compile for the target.
+		let Some(target_list) = self.target_list.as_ref() else {
+			return self.riscv_probe().is_ok()
+		};
 		// This is our custom target which currently doesn't exist on any upstream toolchain,
 		// so if it exists it's guaranteed to be our custom toolchain and have have everything
 		// we need, so any further version checks are unnecessary at this point.
 		target_list.contains("riscv32ema-unknown-none-elf")
 	}
 
+	/// Returns the result of compiling an empty crate for the RISC-V target, see
+	/// [`prerequisites::probe_target`].
+	fn riscv_probe(&self) -> &Result<(), String> {
+		self.riscv_probe
+			.get_or_init(|| prerequisites::probe_target(self, RuntimeTarget::Riscv))
+	}
+
 	/// Check if the supplied cargo command supports our Substrate wasm environment.
 	///
 	/// This means that either the cargo version is at minimum 1.68.0 or this is a nightly cargo.
@@ -337,10 +1276,10 @@ impl CargoCommand {
 	/// Assumes that cargo version matches the rustc version.
 	fn supports_substrate_runtime_env_wasm(&self) -> bool {
 		// `RUSTC_BOOTSTRAP` tells a stable compiler to behave like a nightly. So, when this env
-		// variable is set, we can assume that whatever rust compiler we have, it is a nightly
-		// compiler. For "more" information, see:
+		// variable is passed to the toolchain, we can assume that whatever rust compiler we have,
+		// it is a nightly compiler. For "more" information, see:
 		// https://github.com/rust-lang/rust/blob/fa0f7d0080d8e7e9eb20aa9cbf8013f96c81287f/src/libsyntax/feature_gate/check.rs#L891
-		if env::var("RUSTC_BOOTSTRAP").is_ok() {
+		if RustcBootstrap::enabled() {
 			return true
 		}
 
@@ -356,17 +1295,23 @@ impl CargoCommand {
 struct CargoCommandVersioned {
 	command: CargoCommand,
 	version: String,
+	toolchain: String,
 }
 
 impl CargoCommandVersioned {
-	fn new(command: CargoCommand, version: String) -> Self {
-		Self { command, version }
+	fn new(command: CargoCommand, version: String, toolchain: String) -> Self {
+		Self { command, version, toolchain }
 	}
 
 	/// Returns the `rustc` version.
 	fn rustc_version(&self) -> &str {
 		&self.version
 	}
+
+	/// Returns the toolchain, the name of the rustup toolchain or the path to `cargo` otherwise.
+	fn toolchain(&self) -> &str {
+		&self.toolchain
+	}
 }
 
 impl std::ops::Deref for CargoCommandVersioned {
@@ -382,34 +1327,249 @@ fn color_output_enabled() -> bool {
 	env::var(crate::WASM_BUILD_NO_COLOR).is_err()
 }
 
-/// Fetches a boolean environment variable. Will exit the process if the value is invalid.
-fn get_bool_environment_variable(name: &str) -> Option<bool> {
-	let value = env::var_os(name)?;
+/// How much is printed about the wasm build, see `WASM_BUILD_VERBOSITY`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+enum Verbosity {
+	/// Only warnings and errors are printed.
+	Quiet,
+	/// The build command and the progress of the post-processing are printed.
+	#[default]
+	Normal,
+	/// The environment of the build command and the copied files are printed as well.
+	Verbose,
+	/// The paths that are watched for changes are printed as well.
+	Debug,
+}
+
+/// The preference between the installed toolchains when the toolchain of the runtime isn't pinned,
+/// see [`WasmBuilder::toolchain_policy`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ToolchainPolicy {
+	/// The toolchain of the main build is used if it supports the runtime. Otherwise the newest
+	/// stable toolchain is preferred over any nightly toolchain.
+	#[default]
+	StableFirst,
+	/// A nightly toolchain is preferred, e.g. for the nightly-only features of `build-std`.
+	/// Without one, the toolchain is selected like for [`Self::StableFirst`].
+	NightlyFirst,
+	/// The toolchain with the newest compiler is used, no matter whether it is stable or nightly.
+	Newest,
+	/// The build fails unless the toolchain is pinned, e.g. by a `rust-toolchain.toml`, and that
+	/// toolchain is installed.
+	PinnedOnly,
+}
+
+impl ToolchainPolicy {
+	/// Returns the policy set by `WASM_BUILD_TOOLCHAIN_POLICY`. Returns an error if the value is
+	/// invalid.
+	fn from_env() -> Result<Option<Self>, WasmBuilderError> {
+		let Some(value) = env::var_os(WASM_BUILD_TOOLCHAIN_POLICY) else { return Ok(None) };
+
+		match value.to_str() {
+			Some("stable-first") => Ok(Some(Self::StableFirst)),
+			Some("nightly-first") => Ok(Some(Self::NightlyFirst)),
+			Some("newest") => Ok(Some(Self::Newest)),
+			Some("pinned-only") => Ok(Some(Self::PinnedOnly)),
+			_ => Err(WasmBuilderError::InvalidEnvironmentVariable {
+				name: WASM_BUILD_TOOLCHAIN_POLICY,
+				value: value.to_string_lossy().into(),
+				expected: "one of `stable-first`, `nightly-first`, `newest` or `pinned-only`"
+					.into(),
+			}),
+		}
+	}
+
+	/// Compares the toolchain versions `a` and `b`, the preferred one is the greater one.
+	fn compare(self, a: &Version, b: &Version) -> Ordering {
+		match self {
+			Self::StableFirst | Self::PinnedOnly => a.cmp(b),
+			Self::NightlyFirst => a.is_nightly().cmp(&b.is_nightly()).then_with(|| a.cmp(b)),
+			// A release is newer than the nightlies of its version.
+			Self::Newest => (a.major, a.minor, a.patch)
+				.cmp(&(b.major, b.minor, b.patch))
+				.then_with(|| a.is_stable().cmp(&b.is_stable()))
+				.then_with(|| (a.year, a.month, a.day).cmp(&(b.year, b.month, b.day))),
+		}
+	}
+}
+
+/// How cargo is told to build the standard library from source, see
+/// [`WasmBuilder::build_std_mode`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum BuildStdMode {
+	/// The stable `--build-std` flag is used if `cargo build --help` of the toolchain lists it,
+	/// otherwise the unstable `-Z build-std`.
+	#[default]
+	Detect,
+	/// The unstable `-Z build-std` flag is used, which needs a nightly toolchain or sets
+	/// `RUSTC_BOOTSTRAP`.
+	Unstable,
+	/// The stable `--build-std` flag is used.
+	Stable,
+}
+
+impl BuildStdMode {
+	/// Returns the mode set by `WASM_BUILD_STD_MODE`. Returns an error if the value is invalid.
+	fn from_env() -> Result<Option<Self>, WasmBuilderError> {
+		let Some(value) = env::var_os(WASM_BUILD_STD_MODE) else { return Ok(None) };
+
+		match value.to_str() {
+			Some("detect") => Ok(Some(Self::Detect)),
+			Some("unstable") => Ok(Some(Self::Unstable)),
+			Some("stable") => Ok(Some(Self::Stable)),
+			_ => Err(WasmBuilderError::InvalidEnvironmentVariable {
+				name: WASM_BUILD_STD_MODE,
+				value: value.to_string_lossy().into(),
+				expected: "one of `detect`, `unstable` or `stable`".into(),
+			}),
+		}
+	}
+}
+
+/// Returns the verbosity set by `WASM_BUILD_VERBOSITY`. Returns an error if the value is invalid.
+fn verbosity() -> Result<Verbosity, WasmBuilderError> {
+	let Some(value) = env::var_os(WASM_BUILD_VERBOSITY) else { return Ok(Verbosity::default()) };
+
+	match value.to_str() {
+		Some("quiet") => Ok(Verbosity::Quiet),
+		Some("normal") => Ok(Verbosity::Normal),
+		Some("verbose") => Ok(Verbosity::Verbose),
+		Some("debug") => Ok(Verbosity::Debug),
+		_ => Err(WasmBuilderError::InvalidEnvironmentVariable {
+			name: WASM_BUILD_VERBOSITY,
+			value: value.to_string_lossy().into(),
+			expected: "one of `quiet`, `normal`, `verbose` or `debug`".into(),
+		}),
+	}
+}
+
+/// Fetches a boolean environment variable. Returns an error if the value is invalid.
+fn get_bool_environment_variable(name: &'static str) -> Result<Option<bool>, WasmBuilderError> {
+	let Some(value) = env::var_os(name) else { return Ok(None) };
 
 	// We're comparing `OsString`s here so we can't use a `match`.
 	if value == "1" {
-		Some(true)
+		Ok(Some(true))
 	} else if value == "0" {
-		Some(false)
+		Ok(Some(false))
 	} else {
-		build_helper::warning!(
-			"the '{}' environment variable has an invalid value; it must be either '1' or '0'",
-			name
-		);
-		std::process::exit(1);
+		Err(WasmBuilderError::InvalidEnvironmentVariable {
+			name,
+			value: value.to_string_lossy().into(),
+			expected: "either `1` or `0`".into(),
+		})
 	}
 }
 
-/// Returns whether we need to also compile the standard library when compiling the runtime.
-fn build_std_required() -> bool {
-	let default = runtime_target() == RuntimeTarget::Wasm;
+/// Split the given `input` into words like a POSIX shell would do.
+///
+/// Supports single quotes, double quotes and backslash escapes. Returns `None` if a quote is not
+/// terminated.
+fn split_shell_words(input: &str) -> Option<Vec<String>> {
+	let mut words = Vec::new();
+	let mut word = None::<String>;
+	let mut chars = input.chars();
 
-	crate::get_bool_environment_variable(crate::WASM_BUILD_STD).unwrap_or(default)
+	while let Some(c) = chars.next() {
+		match c {
+			c if c.is_whitespace() => words.extend(word.take()),
+			'\'' => {
+				let word = word.get_or_insert_with(String::new);
+				loop {
+					match chars.next()? {
+						'\'' => break,
+						c => word.push(c),
+					}
+				}
+			},
+			'"' => {
+				let word = word.get_or_insert_with(String::new);
+				loop {
+					match chars.next()? {
+						'"' => break,
+						'\\' => match chars.next()? {
+							c @ ('"' | '\\' | '$' | '`') => word.push(c),
+							c => {
+								word.push('\\');
+								word.push(c);
+							},
+						},
+						c => word.push(c),
+					}
+				}
+			},
+			'\\' => {
+				let word = word.get_or_insert_with(String::new);
+				word.extend(chars.next());
+			},
+			c => word.get_or_insert_with(String::new).push(c),
+		}
+	}
+
+	words.extend(word);
+	Some(words)
 }
 
-#[derive(Copy, Clone, PartialEq, Eq)]
-enum RuntimeTarget {
+/// Returns whether the `/` separated `path` matches the glob `pattern`.
+///
+/// `**` matches any number of path components, including none, while `*` matches any characters
+/// and `?` a single character within one component.
+fn glob_matches(pattern: &str, path: &str) -> bool {
+	fn components_match(pattern: &[&str], path: &[&str]) -> bool {
+		match pattern.split_first() {
+			None => path.is_empty(),
+			Some((&"**", rest)) =>
+				(0..=path.len()).any(|skip| components_match(rest, &path[skip..])),
+			Some((component, rest)) => path.split_first().map_or(false, |(name, path)| {
+				let pattern = component.chars().collect::<Vec<_>>();
+				chars_match(&pattern, &name.chars().collect::<Vec<_>>()) &&
+					components_match(rest, path)
+			}),
+		}
+	}
+
+	fn chars_match(pattern: &[char], name: &[char]) -> bool {
+		match pattern.split_first() {
+			None => name.is_empty(),
+			Some(('*', rest)) => (0..=name.len()).any(|skip| chars_match(rest, &name[skip..])),
+			Some((c, rest)) => name
+				.split_first()
+				.map_or(false, |(n, name)| (*c == '?' || c == n) && chars_match(rest, name)),
+		}
+	}
+
+	let pattern = pattern.split('/').filter(|c| !c.is_empty()).collect::<Vec<_>>();
+	let path = path.split('/').filter(|c| !c.is_empty()).collect::<Vec<_>>();
+	components_match(&pattern, &path)
+}
+
+/// Returns whether we need to also compile the standard library when compiling the runtime for
+/// the given `target`.
+///
+/// `build_std` is the setting of the builder, which is overridden by `WASM_BUILD_STD`.
+fn build_std_required(
+	target: RuntimeTarget,
+	build_std: Option<bool>,
+) -> Result<bool, WasmBuilderError> {
+	let default = build_std.unwrap_or(target.is_wasm());
+
+	Ok(crate::get_bool_environment_variable(crate::WASM_BUILD_STD)?.unwrap_or(default))
+}
+
+/// The target triple that [`RuntimeTarget::Wasm`] runtimes can be built for instead of
+/// `wasm32-unknown-unknown`, see [`WasmBuilder::prefer_wasm32v1_none`].
+const WASM32V1_NONE_TARGET: &str = "wasm32v1-none";
+
+/// The target a runtime is compiled for.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RuntimeTarget {
+	/// The runtime is compiled to WASM.
 	Wasm,
+	/// The runtime is compiled to 64 bit WASM using the memory64 proposal.
+	///
+	/// This is experimental and only meant for executors that support memory64.
+	Wasm64,
+	/// The runtime is compiled to RISC-V, for execution by PolkaVM.
 	Riscv,
 }
 
@@ -417,6 +1577,7 @@ impl RuntimeTarget {
 	fn rustc_target(self) -> &'static str {
 		match self {
 			RuntimeTarget::Wasm => "wasm32-unknown-unknown",
+			RuntimeTarget::Wasm64 => "wasm64-unknown-unknown",
 			RuntimeTarget::Riscv => "riscv32ema-unknown-none-elf",
 		}
 	}
@@ -426,24 +1587,144 @@ impl RuntimeTarget {
 		// the targets we won't trigger unnecessary rebuilds.
 		match self {
 			RuntimeTarget::Wasm => "wbuild",
+			RuntimeTarget::Wasm64 => "wbuild64",
 			RuntimeTarget::Riscv => "rbuild",
 		}
 	}
+
+	/// Returns whether the runtime is compiled to WASM, either 32 or 64 bit.
+	fn is_wasm(self) -> bool {
+		matches!(self, RuntimeTarget::Wasm | RuntimeTarget::Wasm64)
+	}
 }
 
-fn runtime_target() -> RuntimeTarget {
+fn runtime_target() -> Result<RuntimeTarget, WasmBuilderError> {
 	let Some(value) = env::var_os(RUNTIME_TARGET) else {
-		return RuntimeTarget::Wasm;
+		return Ok(RuntimeTarget::Wasm);
 	};
 
 	if value == "wasm" {
-		RuntimeTarget::Wasm
+		Ok(RuntimeTarget::Wasm)
+	} else if value == "wasm64" {
+		Ok(RuntimeTarget::Wasm64)
 	} else if value == "riscv" {
-		RuntimeTarget::Riscv
+		Ok(RuntimeTarget::Riscv)
 	} else {
-		build_helper::warning!(
-			"the '{RUNTIME_TARGET}' environment variable has an invalid value; it must be either 'wasm' or 'riscv'"
+		Err(WasmBuilderError::InvalidEnvironmentVariable {
+			name: RUNTIME_TARGET,
+			value: value.to_string_lossy().into(),
+			expected: "either `wasm`, `wasm64` or `riscv`".into(),
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn split_shell_words_works() {
+		assert_eq!(split_shell_words("").unwrap(), Vec::<String>::new());
+		assert_eq!(
+			split_shell_words("  --timings=html   --locked ").unwrap(),
+			vec!["--timings=html", "--locked"],
+		);
+		assert_eq!(
+			split_shell_words(r#"--config 'build.jobs = 4' -Z"threads=8" a\ b"#).unwrap(),
+			vec!["--config", "build.jobs = 4", "-Zthreads=8", "a b"],
+		);
+		assert_eq!(split_shell_words(r#""\"x\" \y" ''"#).unwrap(), vec![r#""x" \y"#, ""]);
+		assert!(split_shell_words("--config 'build.jobs = 4").is_none());
+		assert!(split_shell_words(r#""\""#).is_none());
+	}
+
+	#[test]
+	fn from_hex_works() {
+		assert_eq!(from_hex("0x00ff10"), Some(vec![0x00, 0xff, 0x10]));
+		assert_eq!(from_hex("ABcd"), Some(vec![0xab, 0xcd]));
+		assert_eq!(from_hex(""), Some(vec![]));
+		assert_eq!(from_hex(&to_hex(&[1, 2, 3])), Some(vec![1, 2, 3]));
+		assert!(from_hex("0x0").is_none());
+		assert!(from_hex("0xzz").is_none());
+		assert!(from_hex("+1").is_none());
+	}
+
+	#[test]
+	fn same_ignoring_line_endings_works() {
+		assert!(same_ignoring_line_endings("[a]\r\nb = 1\r\n", "[a]\nb = 1\n"));
+		assert!(same_ignoring_line_endings("", ""));
+		assert!(!same_ignoring_line_endings("[a]\r\nb = 1\r\n", "[a]\nb = 2\n"));
+		assert!(!same_ignoring_line_endings("a\r", "a"));
+	}
+
+	#[test]
+	fn strip_verbatim_prefix_works() {
+		assert_eq!(
+			strip_verbatim_prefix(r"\\?\C:\Users\A B\runtime"),
+			Some(r"C:\Users\A B\runtime".into()),
+		);
+		assert_eq!(
+			strip_verbatim_prefix(r"\\?\UNC\server\share\runtime"),
+			Some(r"\\server\share\runtime".into()),
+		);
+		assert_eq!(strip_verbatim_prefix(r"C:\Users\runtime"), None);
+		assert_eq!(strip_verbatim_prefix(r"\\?\Volume{1234}\runtime"), None);
+		assert_eq!(strip_verbatim_prefix(r"\\?\C:\a/b"), None);
+		assert_eq!(strip_verbatim_prefix(&format!(r"\\?\C:\{}", "a".repeat(300))), None);
+	}
+
+	#[test]
+	fn toolchain_policy_compare_works() {
+		let stable = Version::extract("cargo 1.81.0 (2dbb1af80 2024-08-20)").unwrap();
+		let nightly = Version::extract("cargo 1.83.0-nightly (ad074abe3 2024-10-04)").unwrap();
+		let older_nightly =
+			Version::extract("cargo 1.81.0-nightly (ad074abe3 2024-06-04)").unwrap();
+
+		let preferred = |policy: ToolchainPolicy| {
+			let mut versions = vec![stable, nightly, older_nightly];
+			versions.sort_by(|a, b| policy.compare(a, b));
+			versions.pop().unwrap()
+		};
+		assert_eq!(preferred(ToolchainPolicy::StableFirst), stable);
+		assert_eq!(preferred(ToolchainPolicy::NightlyFirst), nightly);
+		assert_eq!(preferred(ToolchainPolicy::Newest), nightly);
+		assert_eq!(ToolchainPolicy::Newest.compare(&stable, &older_nightly), Ordering::Greater);
+	}
+
+	#[test]
+	fn parse_toolchain_line_works() {
+		assert_eq!(
+			parse_toolchain_line("stable-x86_64-unknown-linux-gnu (active, default) /r/stable"),
+			Some(("stable-x86_64-unknown-linux-gnu", Some(Path::new("/r/stable")))),
+		);
+		assert_eq!(
+			parse_toolchain_line("1.81.0-x86_64-pc-windows-msvc C:\\Users\\A B\\1.81.0"),
+			Some(("1.81.0-x86_64-pc-windows-msvc", Some(Path::new("C:\\Users\\A B\\1.81.0")))),
 		);
-		std::process::exit(1);
+		assert_eq!(parse_toolchain_line("nightly (default)"), Some(("nightly", None)));
+		assert_eq!(parse_toolchain_line("  "), None);
+	}
+
+	#[test]
+	fn glob_matches_works() {
+		assert!(glob_matches("tests/**", "tests"));
+		assert!(glob_matches("tests/**", "tests/integration/mod.rs"));
+		assert!(!glob_matches("tests/**", "src/tests/mod.rs"));
+		assert!(glob_matches("**/fixtures", "src/a/fixtures"));
+		assert!(glob_matches("src/*.rs", "src/lib.rs"));
+		assert!(!glob_matches("src/*.rs", "src/a/lib.rs"));
+		assert!(glob_matches("src/?ib.rs", "src/lib.rs"));
+		assert!(!glob_matches("src/?ib.rs", "src/ib.rs"));
+	}
+
+	#[test]
+	fn is_pinned_toolchain_works() {
+		assert!(is_pinned_toolchain("1.81.0"));
+		assert!(is_pinned_toolchain("1.81.0-x86_64-unknown-linux-gnu"));
+		assert!(is_pinned_toolchain("nightly-2024-09-01"));
+		assert!(is_pinned_toolchain("beta-2024-09-01-aarch64-apple-darwin"));
+		assert!(!is_pinned_toolchain("stable"));
+		assert!(!is_pinned_toolchain("nightly"));
+		assert!(!is_pinned_toolchain("nightly-x86_64-unknown-linux-gnu"));
 	}
 }
@@ -15,7 +15,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::builder::MetadataExtraInfo;
+use crate::{builder::MetadataHashConfig, WasmBuilderError};
 use codec::{Decode, Encode};
 use frame_metadata::{RuntimeMetadata, RuntimeMetadataPrefixed};
 use merkleized_metadata::{generate_metadata_digest, ExtraInfo};
@@ -45,10 +45,13 @@ type HostFunctions = (
 /// [RFC78](https://polkadot-fellows.github.io/RFCs/approved/0078-merkleized-metadata.html).
 ///
 /// Returns the metadata hash.
-pub fn generate_metadata_hash(wasm: &Path, extra_info: MetadataExtraInfo) -> [u8; 32] {
+pub fn generate_metadata_hash(
+	wasm: &Path,
+	config: MetadataHashConfig,
+) -> Result<[u8; 32], WasmBuilderError> {
 	sp_tracing::try_init_simple();
 
-	let wasm = std::fs::read(wasm).expect("Wasm file was just created and should be readable.");
+	let wasm = std::fs::read(wasm).map_err(WasmBuilderError::io(wasm))?;
 
 	let executor = WasmExecutor::<HostFunctions>::builder()
 		.with_allow_missing_host_functions(true)
@@ -61,58 +64,132 @@ pub fn generate_metadata_hash(wasm: &Path, extra_info: MetadataExtraInfo) -> [u8
 		hash: vec![1, 2, 3],
 	};
 
-	let metadata = executor
+	let metadata = metadata_at_version(&executor, &runtime_code, config.metadata_version)?
+		.ok_or_else(|| {
+			WasmBuilderError::MetadataHash(format!(
+				"Metadata {} support is required, the runtime supports {}.",
+				version_name(config.metadata_version),
+				supported_metadata_versions(&executor, &runtime_code),
+			))
+		})?;
+
+	let runtime_version = executor
 		.call(
 			&mut sp_io::TestExternalities::default().ext(),
 			&runtime_code,
-			"Metadata_metadata_at_version",
-			&15u32.encode(),
+			"Core_version",
+			&[],
 			CallContext::Offchain,
 		)
 		.0
-		.expect("`Metadata::metadata_at_version` should exist.");
+		.map_err(|e| WasmBuilderError::MetadataHash(format!("`Core_version` should exist: {e}")))?;
+	let runtime_version =
+		sp_version::RuntimeVersion::decode(&mut &runtime_version[..]).map_err(|e| {
+			WasmBuilderError::MetadataHash(format!("Invalid `RuntimeVersion` encoding: {e}"))
+		})?;
+
+	let base58_prefix = match config.base58_prefix {
+		Some(base58_prefix) => base58_prefix,
+		None if config.metadata_version == 15 => extract_ss58_prefix(&metadata)?,
+		// The constants are read from the V15 metadata, which runtimes keep providing next to
+		// the newer versions.
+		None => {
+			let metadata = metadata_at_version(&executor, &runtime_code, 15)?.ok_or_else(|| {
+				WasmBuilderError::MetadataHash(
+					"Metadata V15 support is required to read the SS58 prefix, configure the \
+					 `base58_prefix` of the metadata hash instead."
+						.into(),
+				)
+			})?;
+			extract_ss58_prefix(&metadata)?
+		},
+	};
+	let extra_info = ExtraInfo {
+		spec_version: runtime_version.spec_version,
+		spec_name: config.spec_name.unwrap_or_else(|| runtime_version.spec_name.into()),
+		base58_prefix,
+		decimals: config.decimals,
+		token_symbol: config.token_symbol,
+	};
 
-	let metadata = Option::<Vec<u8>>::decode(&mut &metadata[..])
-		.ok()
-		.flatten()
-		.expect("Metadata V15 support is required.");
+	Ok(generate_metadata_digest(&metadata, extra_info)
+		.map_err(|e| {
+			WasmBuilderError::MetadataHash(format!(
+				"Failed to generate the metadata digest from metadata {}: {e:?}",
+				version_name(config.metadata_version),
+			))
+		})?
+		.hash())
+}
+
+/// Fetch the metadata at `version` from the runtime.
+///
+/// Returns `None` if the runtime doesn't support the `version`.
+fn metadata_at_version(
+	executor: &WasmExecutor<HostFunctions>,
+	runtime_code: &RuntimeCode,
+	version: u32,
+) -> Result<Option<RuntimeMetadata>, WasmBuilderError> {
+	let metadata = executor
+		.call(
+			&mut sp_io::TestExternalities::default().ext(),
+			runtime_code,
+			"Metadata_metadata_at_version",
+			&version.encode(),
+			CallContext::Offchain,
+		)
+		.0
+		.map_err(|e| {
+			WasmBuilderError::MetadataHash(format!(
+				"`Metadata::metadata_at_version` should exist: {e}"
+			))
+		})?;
+
+	let Some(metadata) = Option::<Vec<u8>>::decode(&mut &metadata[..]).ok().flatten() else {
+		return Ok(None)
+	};
 
-	let metadata = RuntimeMetadataPrefixed::decode(&mut &metadata[..])
-		.expect("Invalid encoded metadata?")
-		.1;
+	RuntimeMetadataPrefixed::decode(&mut &metadata[..])
+		.map(|metadata| Some(metadata.1))
+		.map_err(|e| WasmBuilderError::MetadataHash(format!("Invalid encoded metadata: {e}")))
+}
 
-	let runtime_version = executor
+/// Returns the metadata versions supported by the runtime, for the error messages.
+fn supported_metadata_versions(
+	executor: &WasmExecutor<HostFunctions>,
+	runtime_code: &RuntimeCode,
+) -> String {
+	let versions = executor
 		.call(
 			&mut sp_io::TestExternalities::default().ext(),
-			&runtime_code,
-			"Core_version",
+			runtime_code,
+			"Metadata_metadata_versions",
 			&[],
 			CallContext::Offchain,
 		)
 		.0
-		.expect("`Core_version` should exist.");
-	let runtime_version = sp_version::RuntimeVersion::decode(&mut &runtime_version[..])
-		.expect("Invalid `RuntimeVersion` encoding");
-
-	let base58_prefix = extract_ss58_prefix(&metadata);
+		.ok()
+		.and_then(|versions| Vec::<u32>::decode(&mut &versions[..]).ok());
 
-	let extra_info = ExtraInfo {
-		spec_version: runtime_version.spec_version,
-		spec_name: runtime_version.spec_name.into(),
-		base58_prefix,
-		decimals: extra_info.decimals,
-		token_symbol: extra_info.token_symbol,
-	};
+	match versions {
+		Some(versions) => versions.into_iter().map(version_name).collect::<Vec<_>>().join(", "),
+		None => "an unknown set of versions".into(),
+	}
+}
 
-	generate_metadata_digest(&metadata, extra_info)
-		.expect("Failed to generate the metadata digest")
-		.hash()
+/// Returns the name of the metadata `version`, like `V15` or `unstable`.
+fn version_name(version: u32) -> String {
+	if version == MetadataHashConfig::UNSTABLE_METADATA_VERSION {
+		"unstable".into()
+	} else {
+		format!("V{version}")
+	}
 }
 
 /// Extract the `SS58` from the constants in the given `metadata`.
-fn extract_ss58_prefix(metadata: &RuntimeMetadata) -> u16 {
+fn extract_ss58_prefix(metadata: &RuntimeMetadata) -> Result<u16, WasmBuilderError> {
 	let RuntimeMetadata::V15(ref metadata) = metadata else {
-		panic!("Metadata version 15 required")
+		return Err(WasmBuilderError::MetadataHash("Metadata version 15 required".into()))
 	};
 
 	let system = metadata
@@ -121,12 +198,12 @@ fn extract_ss58_prefix(metadata: &RuntimeMetadata) -> u16 {
 		.find(|p| p.name == "System")
 		.expect("Each FRAME runtime has the `System` pallet; qed");
 
-	system
+	Ok(system
 		.constants
 		.iter()
 		.find_map(|c| {
 			(c.name == "SS58Prefix")
 				.then(|| u16::decode(&mut &c.value[..]).expect("SS58 is an `u16`; qed"))
 		})
-		.expect("`SS58PREFIX` exists in the `System` constants; qed")
+		.expect("`SS58PREFIX` exists in the `System` constants; qed"))
 }
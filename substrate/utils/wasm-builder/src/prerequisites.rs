@@ -15,17 +15,23 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::{write_file_if_changed, CargoCommand, CargoCommandVersioned, RuntimeTarget};
+use crate::{
+	version::Version, wasm_project::BuildOptions, write_file_if_changed, CargoCommand,
+	CargoCommandVersioned, RuntimeTarget, RustcBootstrap, WasmBuilderError,
+};
 
 use console::style;
 use std::{
-	fs,
+	env, fs,
 	path::{Path, PathBuf},
 	process::Command,
 };
 
 use tempfile::tempdir;
 
+/// The error cargo reports when the `wasm32-unknown-unknown` target is not installed.
+const TARGET_NOT_INSTALLED: &str = "the `wasm32-unknown-unknown` target may not be installed";
+
 /// Colorizes an error message, if color output is enabled.
 fn colorize_error_message(message: &str) -> String {
 	if super::color_output_enabled() {
@@ -44,37 +50,350 @@ fn colorize_aux_message(message: &str) -> String {
 	}
 }
 
+/// The toolchain a runtime is built with, as found by [`check_prerequisites`].
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct ToolchainReport {
+	/// The target the runtime is built for.
+	pub target: RuntimeTarget,
+	/// The toolchain that was selected, e.g. the name of a rustup toolchain or the path to
+	/// `cargo`.
+	pub toolchain: String,
+	/// The output of `rustc --version` of the toolchain.
+	pub rustc_version: String,
+	/// The target triple the runtime is compiled for, e.g. `wasm32v1-none`.
+	pub target_triple: &'static str,
+	/// Whether the standard library is built from source, which requires `rust-src`.
+	pub build_std: bool,
+	/// The `rust-lld` of the toolchain, which links the runtime unless another linker is set.
+	pub rust_lld: Option<PathBuf>,
+}
+
+/// Check that the prerequisites for building a runtime for `target` are installed.
+///
+/// The toolchain is selected, and the target and the `rust-src` component are checked, like for a
+/// [`WasmBuilder`](crate::WasmBuilder) with the default settings, i.e. only the environment
+/// variables change them. As this doesn't need to run in a `build.rs`, node CLIs and setup scripts
+/// can use it to validate the environment up front. The error explains how to install what is
+/// missing.
+pub fn check_prerequisites(target: RuntimeTarget) -> Result<ToolchainReport, WasmBuilderError> {
+	crate::hermetic::init()?;
+	let options = crate::WasmBuilder::default_build_options()?;
+	let cargo_command = check(target, &options)?;
+
+	Ok(ToolchainReport {
+		target,
+		toolchain: cargo_command.name(),
+		rustc_version: cargo_command.rustc_version().trim().into(),
+		target_triple: options.target_triple(target, &cargo_command)?,
+		build_std: options.build_std_required(target, &cargo_command)?,
+		rust_lld: DummyCrate::new(&cargo_command, target)?.rust_lld(),
+	})
+}
+
 /// Checks that all prerequisites are installed.
 ///
+/// The toolchain pinned by the `options` is installed if it is missing and
+/// `install_missing_toolchain` is set, a missing wasm target or `rust-src` component of the
+/// toolchain if `install_missing_target` or `install_missing_rust_src` is set.
+///
+/// The `rustc` of the toolchain needs to be at least the `min_rustc_version` of the `options`.
+///
 /// Returns the versioned cargo command on success.
-pub(crate) fn check(target: RuntimeTarget) -> Result<CargoCommandVersioned, String> {
-	let cargo_command = crate::get_cargo_command(target);
+pub(crate) fn check(
+	target: RuntimeTarget,
+	options: &BuildOptions,
+) -> Result<CargoCommandVersioned, WasmBuilderError> {
+	let rustc_bootstrap = RustcBootstrap::from_env()?;
+	let cargo_command = check_toolchain(target, options)?;
+	check_rustc_bootstrap(&cargo_command, rustc_bootstrap);
+	check_version_skew(&cargo_command, options.deterministic)?;
+	check_auxiliary_tools(&cargo_command, target, options)?;
+	if let Some(required) = &options.min_rustc_version {
+		check_rustc_version(&cargo_command, required)?;
+	}
+
+	Ok(cargo_command)
+}
+
+/// Checks that the binaries the enabled options of the build run are installed.
+///
+/// Missing binaries are reported with a hint how to install them, instead of failing when they
+/// are used. Splitting off the debug info, the size report, `wasm-opt` and the linking of RISC-V
+/// runtimes don't need any binaries, as they are part of this crate.
+fn check_auxiliary_tools(
+	cargo_command: &CargoCommandVersioned,
+	target: RuntimeTarget,
+	options: &BuildOptions,
+) -> Result<(), WasmBuilderError> {
+	if let Some(backend) = &options.artifact_cache_backend {
+		if options.artifact_cache_enabled()? {
+			if let Err(error) = backend.check() {
+				build_helper::warning!("The artifact cache backend can't be used: {error}");
+			}
+		}
+	}
+
+	// The workload merges the profiles, usually with the `llvm-profdata` of the toolchain, as its
+	// format depends on the LLVM version of `rustc`.
+	if options.pgo.as_ref().map_or(false, |pgo| pgo.workload.is_some()) {
+		let dummy_crate = DummyCrate::new(cargo_command, target)?;
+		if dummy_crate.toolchain_binary("llvm-profdata").is_none() {
+			let hint = match dummy_crate.get_toolchain() {
+				Some(toolchain) => format!(
+					"Install it with `rustup component add llvm-tools --toolchain {toolchain}`."
+				),
+				None => format!(
+					"Install the `llvm-tools` of the toolchain of `{}`.",
+					cargo_command.name()
+				),
+			};
+			build_helper::warning!(
+				"The `llvm-profdata` of the toolchain {} that merges the profiles of the PGO \
+				 workload is not installed. {hint}",
+				dummy_crate.toolchain_name(),
+			);
+		}
+	}
+
+	Ok(())
+}
+
+/// Warns if `RUSTC_BOOTSTRAP` changes whether the stable toolchain of the `cargo_command` is
+/// treated as a nightly one, depending on how it is handled as set by `rustc_bootstrap`.
+fn check_rustc_bootstrap(cargo_command: &CargoCommandVersioned, rustc_bootstrap: RustcBootstrap) {
+	if cargo_command.version().map_or(true, |version| version.is_nightly) {
+		return
+	}
+
+	let outer = env::var_os(crate::RUSTC_BOOTSTRAP).is_some();
+	let toolchain = cargo_command.toolchain();
+	match rustc_bootstrap {
+		RustcBootstrap::Clear if outer => build_helper::warning!(
+			"`RUSTC_BOOTSTRAP` is removed for the build of the runtime, so the stable toolchain \
+			 `{toolchain}` can't use nightly features. Set `{}=inherit` to keep it.",
+			crate::WASM_BUILD_ALLOW_RUSTC_BOOTSTRAP,
+		),
+		RustcBootstrap::Set | RustcBootstrap::Inherit if RustcBootstrap::enabled() =>
+			build_helper::warning!(
+				"The stable toolchain `{toolchain}` is treated as a nightly one, because \
+				 `RUSTC_BOOTSTRAP` is set for the build of the runtime."
+			),
+		_ => {},
+	}
+}
+
+/// Checks that `cargo` and `rustc` of the `cargo_command` have the same version.
+///
+/// They can diverge when `RUSTC` or a wrapper script points to another compiler, which leads to
+/// confusing failures of the build. This is a warning, unless the build is `deterministic`.
+fn check_version_skew(
+	cargo_command: &CargoCommandVersioned,
+	deterministic: bool,
+) -> Result<(), WasmBuilderError> {
+	let (Some(cargo), Some(rustc)) =
+		(cargo_command.version(), crate::parse_version_output(cargo_command.rustc_version()))
+	else {
+		return Ok(())
+	};
+
+	// The dates are the ones of the commits of cargo and rustc, which differ for the same release.
+	let release = |v: Version| (v.major, v.minor, v.patch, v.is_nightly);
+	if release(cargo) == release(rustc) {
+		return Ok(())
+	}
+
+	let message = format!(
+		"The versions of cargo and rustc differ: `{}` is used with `{}`. \
+		 Make sure `RUSTC` and any rustc wrapper belong to the toolchain of cargo.",
+		cargo_command.version_output.as_deref().unwrap_or_default().trim(),
+		cargo_command.rustc_version().trim(),
+	);
+	if deterministic {
+		return Err(WasmBuilderError::MissingPrerequisites(colorize_error_message(&format!(
+			"Cannot compile the runtime deterministically: {message}"
+		))))
+	}
+
+	build_helper::warning!("{message}");
+	Ok(())
+}
+
+/// Checks that the `rustc` of the `cargo_command` is at least the `required` version.
+fn check_rustc_version(
+	cargo_command: &CargoCommandVersioned,
+	required: &Version,
+) -> Result<(), WasmBuilderError> {
+	let found = cargo_command.rustc_version().trim();
+	if crate::parse_version_output(found)
+		.map_or(false, |version| version.is_release_at_least(required))
+	{
+		return Ok(())
+	}
+
+	let hint = if crate::rustup_available()? {
+		"Select a newer toolchain, e.g. with `WASM_BUILD_TOOLCHAIN`."
+	} else {
+		"Provide a newer `cargo` and `rustc`, e.g. in `PATH`."
+	};
+	Err(WasmBuilderError::MissingPrerequisites(colorize_error_message(&format!(
+		"Cannot compile the runtime: it requires rustc {}.{}.{} or newer, but found `{found}`!\n\
+		 {hint}",
+		required.major, required.minor, required.patch,
+	))))
+}
+
+/// Checks the toolchain and the targets and components the runtime is built with.
+fn check_toolchain(
+	target: RuntimeTarget,
+	options: &BuildOptions,
+) -> Result<CargoCommandVersioned, WasmBuilderError> {
+	// Explicitly given binaries bypass the selection of the toolchain, except in a hermetic build.
+	let cargo_command = if options.explicit_toolchain() && crate::hermetic::get().is_none() {
+		CargoCommand::with_paths(options.cargo.as_deref(), options.rustc.as_deref())
+	} else {
+		crate::get_cargo_command(
+			target,
+			options.toolchain.as_deref(),
+			options.install_missing_toolchain,
+			options.toolchain_policy()?,
+		)?
+	};
+	if !cargo_command.supports_substrate_runtime_env(target) {
+		return Err(no_compatible_toolchain(
+			target,
+			&[(cargo_command.name(), &cargo_command)],
+			false,
+		))
+	}
+
 	match target {
 		RuntimeTarget::Wasm => {
-			if !cargo_command.supports_substrate_runtime_env(target) {
-				return Err(colorize_error_message(
-					"Cannot compile a WASM runtime: no compatible Rust compiler found!\n\
-					 Install at least Rust 1.68.0 or a recent nightly version.",
-				));
+			// The standard library of a prebuilt sysroot doesn't need the sources.
+			let build_std_required =
+				options.build_std_required(target, &cargo_command)? && options.sysroot().is_none();
+			let install_missing_target = options.install_missing_target_enabled()?;
+			let install_missing_rust_src = options.install_missing_rust_src_enabled()?;
+			if options.uses_wasm32v1_none(target, &cargo_command)? {
+				return check_wasm32v1_none_installed(
+					cargo_command,
+					build_std_required,
+					install_missing_target,
+					install_missing_rust_src,
+				)
 			}
 
-			check_wasm_toolchain_installed(cargo_command)
+			check_wasm_toolchain_installed(
+				cargo_command,
+				build_std_required,
+				install_missing_target,
+				install_missing_rust_src,
+			)
 		},
-		RuntimeTarget::Riscv => {
-			if !cargo_command.supports_substrate_runtime_env(target) {
-				return Err(colorize_error_message(
-					"Cannot compile a RISC-V runtime: no compatible Rust compiler found!\n\
-					 Install a toolchain from here and try again: https://github.com/paritytech/rustc-rv32e-toolchain/",
-				));
+		RuntimeTarget::Wasm64 => {
+			// There is no precompiled standard library for `wasm64-unknown-unknown`.
+			let dummy_crate = DummyCrate::new(&cargo_command, target)?;
+			if options.sysroot().is_none() {
+				check_rust_src_installed(
+					&dummy_crate,
+					"a WASM64 runtime",
+					options.install_missing_rust_src_enabled()?,
+				)?;
 			}
 
-			let dummy_crate = DummyCrate::new(&cargo_command, target);
 			let version = dummy_crate.get_rustc_version();
-			Ok(CargoCommandVersioned::new(cargo_command, version))
+			let toolchain = dummy_crate.toolchain();
+			Ok(CargoCommandVersioned::new(cargo_command, version, toolchain))
+		},
+		RuntimeTarget::Riscv => {
+			let dummy_crate = DummyCrate::new(&cargo_command, target)?;
+			let version = dummy_crate.get_rustc_version();
+			let toolchain = dummy_crate.toolchain();
+			Ok(CargoCommandVersioned::new(cargo_command, version, toolchain))
 		},
 	}
 }
 
+/// Returns the error that none of the probed `candidates` can compile a runtime for `target`.
+///
+/// The error contains a table of the candidates with their version, the number of targets they
+/// know and the reason they were rejected. `rustup_missing` notes that no rustup toolchains could
+/// be probed.
+pub(crate) fn no_compatible_toolchain(
+	target: RuntimeTarget,
+	candidates: &[(String, &CargoCommand)],
+	rustup_missing: bool,
+) -> WasmBuilderError {
+	let (runtime, hint) = match target {
+		RuntimeTarget::Wasm =>
+			("a WASM runtime", "Install at least Rust 1.68.0 or a recent nightly version."),
+		RuntimeTarget::Wasm64 =>
+			("a WASM64 runtime", "Install at least Rust 1.68.0 or a recent nightly version."),
+		RuntimeTarget::Riscv => (
+			"a RISC-V runtime",
+			"Install a toolchain from here and try again: https://github.com/paritytech/rustc-rv32e-toolchain/",
+		),
+	};
+
+	let header = ["toolchain", "version", "target list", "rejected because"].map(String::from);
+	let rows = candidates
+		.iter()
+		.map(|(name, cmd)| {
+			[
+				name.clone(),
+				cmd.version_output.as_deref().map_or("unknown".into(), |v| v.trim().into()),
+				cmd.target_list
+					.as_ref()
+					.map_or("unknown".into(), |list| format!("{} targets", list.len())),
+				cmd.unsupported_reason(target).unwrap_or_default(),
+			]
+		})
+		.collect::<Vec<_>>();
+
+	let widths = (0..header.len())
+		.map(|i| rows.iter().chain([&header]).map(|row| row[i].len()).max().unwrap_or(0))
+		.collect::<Vec<_>>();
+	let mut table = String::new();
+	for row in [&header].into_iter().chain(&rows) {
+		let cells = row
+			.iter()
+			.zip(&widths)
+			.map(|(cell, width)| format!("{cell:<width$}"))
+			.collect::<Vec<_>>();
+		table.push_str("  ");
+		table.push_str(cells.join("  ").trim_end());
+		table.push('\n');
+	}
+	if rustup_missing {
+		table.push_str("  `rustup` is not available, so no rustup toolchains were probed.\n");
+	}
+
+	WasmBuilderError::MissingPrerequisites(format!(
+		"{}\n{hint}\n\nThe following toolchains were probed:\n{table}",
+		colorize_error_message(&format!(
+			"Cannot compile {runtime}: no compatible Rust compiler found!"
+		)),
+	))
+}
+
+/// Compiles an empty `no_std` crate for `target` with the `cargo_command`.
+///
+/// This probes the support of the target by toolchains that can't list their targets, e.g. stable
+/// ones, as `--print target-list` needs `-Z unstable-options`. Returns the first error of the
+/// compiler if the crate can't be compiled.
+pub(crate) fn probe_target(
+	cargo_command: &CargoCommand,
+	target: RuntimeTarget,
+) -> Result<(), String> {
+	let dummy_crate = DummyCrate::new(cargo_command, target).map_err(|error| error.to_string())?;
+	dummy_crate.try_check_no_std().map_err(|error| {
+		error
+			.as_deref()
+			.and_then(|stderr| stderr.lines().find(|line| line.starts_with("error")))
+			.map_or_else(|| "`cargo check` could not be run".into(), |line| line.trim().into())
+	})
+}
+
 struct DummyCrate<'a> {
 	cargo_command: &'a CargoCommand,
 	temp: tempfile::TempDir,
@@ -84,7 +403,10 @@ struct DummyCrate<'a> {
 
 impl<'a> DummyCrate<'a> {
 	/// Creates a minimal dummy crate.
-	fn new(cargo_command: &'a CargoCommand, target: RuntimeTarget) -> Self {
+	fn new(
+		cargo_command: &'a CargoCommand,
+		target: RuntimeTarget,
+	) -> Result<Self, WasmBuilderError> {
 		let temp = tempdir().expect("Creating temp dir does not fail; qed");
 		let project_dir = temp.path();
 		fs::create_dir_all(project_dir.join("src")).expect("Creating src dir does not fail; qed");
@@ -100,10 +422,10 @@ impl<'a> DummyCrate<'a> {
 
 				[workspace]
 			"#,
-		);
+		)?;
 
-		write_file_if_changed(project_dir.join("src/main.rs"), "fn main() {}");
-		DummyCrate { cargo_command, temp, manifest_path, target }
+		write_file_if_changed(project_dir.join("src/main.rs"), "fn main() {}")?;
+		Ok(DummyCrate { cargo_command, temp, manifest_path, target })
 	}
 
 	fn prepare_command(&self, subcommand: &str) -> Command {
@@ -123,13 +445,14 @@ impl<'a> DummyCrate<'a> {
 		let target_dir = self.temp.path().join("target").display().to_string();
 		cmd.env("CARGO_TARGET_DIR", &target_dir);
 
+		if crate::wasm_project::offline_build() {
+			cmd.arg("--offline");
+		}
+
 		// Make sure the host's flags aren't used here, e.g. if an alternative linker is specified
 		// in the RUSTFLAGS then the check we do here will break unless we clear these.
 		cmd.env_remove("CARGO_ENCODED_RUSTFLAGS");
 		cmd.env_remove("RUSTFLAGS");
-		// Make sure if we're called from within a `build.rs` the host toolchain won't override a
-		// rustup toolchain we've picked.
-		cmd.env_remove("RUSTC");
 		cmd
 	}
 
@@ -149,7 +472,31 @@ impl<'a> DummyCrate<'a> {
 		sysroot_cmd.output().ok().and_then(|o| String::from_utf8(o.stdout).ok())
 	}
 
+	/// Returns the `rust-lld` that ships with the toolchain in the directory of its host target.
+	fn rust_lld(&self) -> Option<PathBuf> {
+		self.toolchain_binary("rust-lld")
+	}
+
+	/// Returns the binary `name` that ships with the toolchain in the directory of its host
+	/// target, e.g. `rust-lld` or the `llvm-profdata` of the `llvm-tools` component.
+	fn toolchain_binary(&self, name: &str) -> Option<PathBuf> {
+		let sysroot = self.get_sysroot()?;
+		let rustlib = Path::new(sysroot.trim()).join("lib").join("rustlib");
+		fs::read_dir(rustlib)
+			.ok()?
+			.filter_map(|entry| entry.ok())
+			.map(|entry| crate::executable_path(&entry.path().join("bin").join(name)))
+			.find(|path| path.is_file())
+	}
+
+	/// Returns the name of the rustup toolchain, `None` if the toolchains are not managed by
+	/// rustup.
 	fn get_toolchain(&self) -> Option<String> {
+		// Without rustup, the sysroot is not named after a toolchain, e.g. it is `/usr`.
+		if !crate::rustup_available().unwrap_or(false) {
+			return None
+		}
+
 		let sysroot = self.get_sysroot()?;
 		Path::new(sysroot.trim())
 			.file_name()
@@ -157,6 +504,36 @@ impl<'a> DummyCrate<'a> {
 			.map(|s| s.to_string())
 	}
 
+	/// Returns the toolchain, the name of the rustup toolchain or the path to `cargo` otherwise.
+	fn toolchain(&self) -> String {
+		self.get_toolchain().unwrap_or_else(|| self.cargo_command.program.clone())
+	}
+
+	/// Returns the name of the toolchain in diagnostics, the `cargo` if it isn't a rustup
+	/// toolchain.
+	fn toolchain_name(&self) -> String {
+		self.get_toolchain()
+			.unwrap_or_else(|| format!("`{}`", self.cargo_command.name()))
+	}
+
+	/// Returns how the missing rustup `kind` `name`, e.g. the `target` `wasm32v1-none`, can be
+	/// installed, or installed automatically with `auto_install_env`.
+	///
+	/// Without rustup, only the toolchain that is actually used is mentioned.
+	fn install_hint(&self, kind: &str, name: &str, auto_install_env: &str) -> String {
+		match self.get_toolchain() {
+			Some(toolchain) => format!(
+				"You can install it with `rustup {kind} add {name} --toolchain {toolchain}`, or set \
+				 `{auto_install_env}=1` to install it automatically."
+			),
+			None => format!(
+				"Install it for the toolchain of `{}`, whose sysroot is `{}`.",
+				self.cargo_command.name(),
+				self.get_sysroot().as_deref().map_or("unknown", str::trim),
+			),
+		}
+	}
+
 	fn try_build(&self) -> Result<(), Option<String>> {
 		let Ok(result) = self.prepare_command("build").output() else { return Err(None) };
 		if !result.status.success() {
@@ -164,52 +541,191 @@ impl<'a> DummyCrate<'a> {
 		}
 		Ok(())
 	}
+
+	/// Type-checks an empty `no_std` library, which only needs `core` for the target.
+	fn try_check_no_std(&self) -> Result<(), Option<String>> {
+		write_file_if_changed(self.temp.path().join("src/lib.rs"), "#![no_std]")
+			.map_err(|_| None)?;
+		let Ok(result) = self.prepare_command("check").arg("--lib").output() else {
+			return Err(None)
+		};
+		if !result.status.success() {
+			return Err(Some(String::from_utf8_lossy(&result.stderr).into()));
+		}
+		Ok(())
+	}
+}
+
+/// Installs the rustup `target` for the `toolchain`.
+fn install_target(toolchain: &str, target: &str) -> Result<(), WasmBuilderError> {
+	rustup_add("target", target, toolchain)
+}
+
+/// Installs the rustup `component` for the `toolchain`.
+fn install_component(toolchain: &str, component: &str) -> Result<(), WasmBuilderError> {
+	rustup_add("component", component, toolchain)
 }
 
+/// Runs `rustup <kind> add <name>` for the `toolchain`.
+fn rustup_add(kind: &str, name: &str, toolchain: &str) -> Result<(), WasmBuilderError> {
+	crate::wasm_project::require_network(&format!(
+		"installing the missing {kind} `{name}` for the toolchain `{toolchain}`"
+	))?;
+	println!("Installing the missing {kind} `{name}` for the toolchain `{toolchain}` with rustup.");
+	let status = crate::tool_command("rustup")
+		.args([kind, "add", name, "--toolchain", toolchain])
+		.status();
+	if !status.map_or(false, |s| s.success()) {
+		return Err(WasmBuilderError::MissingPrerequisites(colorize_error_message(&format!(
+			"Failed to install the {kind} `{name}` for the toolchain `{toolchain}` with rustup.",
+		))))
+	}
+
+	Ok(())
+}
+
+/// Checks that the `rust-src` component is installed, which is needed to build the standard
+/// library from source for the `runtime`.
+///
+/// The component is installed if it is missing and `install_missing_rust_src` is set.
+fn check_rust_src_installed(
+	dummy_crate: &DummyCrate,
+	runtime: &str,
+	install_missing_rust_src: bool,
+) -> Result<(), WasmBuilderError> {
+	let is_installed = || {
+		dummy_crate.get_sysroot().map_or(false, |sysroot| {
+			Path::new(sysroot.trim()).join("lib/rustlib/src/rust").exists()
+		})
+	};
+	if is_installed() {
+		return Ok(())
+	}
+
+	let toolchain = dummy_crate.get_toolchain();
+	if let (true, Some(toolchain)) = (install_missing_rust_src, &toolchain) {
+		install_component(toolchain, "rust-src")?;
+		if is_installed() {
+			return Ok(())
+		}
+	}
+
+	Err(WasmBuilderError::MissingPrerequisites(colorize_error_message(&format!(
+		"Cannot compile {runtime}: the `rust-src` component is not installed!\n\
+		 The standard library is built from source, which needs its sources. {}",
+		dummy_crate.install_hint("component", "rust-src", crate::WASM_BUILD_AUTO_INSTALL_RUST_SRC),
+	))))
+}
+
+/// Checks that either the `wasm32v1-none` target or, if the standard library is built from
+/// source, the `rust-src` component is installed.
+///
+/// The target is installed if it is missing and `install_missing_target` is set, the component
+/// if `install_missing_rust_src` is set.
+fn check_wasm32v1_none_installed(
+	cargo_command: CargoCommand,
+	build_std_required: bool,
+	install_missing_target: bool,
+	install_missing_rust_src: bool,
+) -> Result<CargoCommandVersioned, WasmBuilderError> {
+	let dummy_crate = DummyCrate::new(&cargo_command, RuntimeTarget::Wasm)?;
+	if build_std_required {
+		check_rust_src_installed(
+			&dummy_crate,
+			"the WASM runtime for `wasm32v1-none`",
+			install_missing_rust_src,
+		)?;
+	} else {
+		let is_installed = || {
+			dummy_crate.get_sysroot().map_or(false, |sysroot| {
+				Path::new(sysroot.trim()).join("lib/rustlib/wasm32v1-none").exists()
+			})
+		};
+		let mut installed = is_installed();
+		if !installed && install_missing_target {
+			if let Some(toolchain) = dummy_crate.get_toolchain() {
+				install_target(&toolchain, "wasm32v1-none")?;
+				installed = is_installed();
+			}
+		}
+		if !installed {
+			return Err(WasmBuilderError::MissingPrerequisites(colorize_error_message(&format!(
+				"Cannot compile the WASM runtime for `wasm32v1-none`: the target is not installed!\n\
+				 {}",
+				dummy_crate.install_hint(
+					"target",
+					"wasm32v1-none",
+					crate::WASM_BUILD_AUTO_INSTALL_TARGET
+				),
+			))))
+		}
+	}
+
+	let version = dummy_crate.get_rustc_version();
+	let toolchain = dummy_crate.toolchain();
+	Ok(CargoCommandVersioned::new(cargo_command, version, toolchain))
+}
+
+/// Checks that the `wasm32-unknown-unknown` target and, if the standard library is built from
+/// source, the `rust-src` component are installed.
+///
+/// The target is installed if it is missing and `install_missing_target` is set, the component
+/// if `install_missing_rust_src` is set.
 fn check_wasm_toolchain_installed(
 	cargo_command: CargoCommand,
-) -> Result<CargoCommandVersioned, String> {
-	let dummy_crate = DummyCrate::new(&cargo_command, RuntimeTarget::Wasm);
+	build_std_required: bool,
+	install_missing_target: bool,
+	install_missing_rust_src: bool,
+) -> Result<CargoCommandVersioned, WasmBuilderError> {
+	let dummy_crate = DummyCrate::new(&cargo_command, RuntimeTarget::Wasm)?;
 
-	if let Err(error) = dummy_crate.try_build() {
-		let toolchain = dummy_crate.get_toolchain().unwrap_or("<unknown>".to_string());
+	let mut build_result = dummy_crate.try_build();
+	if let Err(Some(error)) = &build_result {
+		if install_missing_target && error.contains(TARGET_NOT_INSTALLED) {
+			if let Some(toolchain) = dummy_crate.get_toolchain() {
+				install_target(&toolchain, "wasm32-unknown-unknown")?;
+				build_result = dummy_crate.try_build();
+			}
+		}
+	}
+
+	if let Err(error) = build_result {
+		let toolchain = dummy_crate.toolchain_name();
 		let basic_error_message = colorize_error_message(
 			&format!("Rust WASM target for toolchain {toolchain} is not properly installed; please install it!")
 		);
-		return match error {
-			None => Err(basic_error_message),
-			Some(error) if error.contains("the `wasm32-unknown-unknown` target may not be installed") => {
-				Err(colorize_error_message(&format!("Cannot compile the WASM runtime: the `wasm32-unknown-unknown` target is not installed!\n\
-				                         You can install it with `rustup target add wasm32-unknown-unknown --toolchain {toolchain}` if you're using `rustup`.")))
+		let error = match error {
+			None => basic_error_message,
+			Some(error) if error.contains(TARGET_NOT_INSTALLED) => {
+				colorize_error_message(&format!("Cannot compile the WASM runtime: the `wasm32-unknown-unknown` target is not installed!\n{}",
+				                         dummy_crate.install_hint("target", "wasm32-unknown-unknown", crate::WASM_BUILD_AUTO_INSTALL_TARGET)))
 			},
 			// Apparently this can happen when we're running on a non Tier 1 platform.
-			Some(ref error) if error.contains("linker `rust-lld` not found") =>
-				Err(colorize_error_message("Cannot compile the WASM runtime: `rust-lld` not found!")),
-			Some(error) => Err(format!(
+			Some(ref error) if error.contains("linker `rust-lld` not found") => format!(
+				"{}\nThe toolchain {toolchain} doesn't ship `rust-lld` in the `lib/rustlib/<host>/bin` \
+				 directory of its sysroot `{}`. Install a toolchain that provides it, or link the \
+				 runtime with another linker, see `WasmBuilder::linker`.",
+				colorize_error_message("Cannot compile the WASM runtime: `rust-lld` not found!"),
+				dummy_crate.get_sysroot().as_deref().map_or("unknown", str::trim),
+			),
+			Some(error) => format!(
 				"{}\n\n{}\n{}\n{}{}\n",
 				basic_error_message,
 				colorize_aux_message("Further error information:"),
 				colorize_aux_message(&"-".repeat(60)),
 				error,
 				colorize_aux_message(&"-".repeat(60)),
-			))
-		}
+			),
+		};
+		return Err(WasmBuilderError::MissingPrerequisites(error))
 	}
 
-	let version = dummy_crate.get_rustc_version();
-	if crate::build_std_required() {
-		if let Some(sysroot) = dummy_crate.get_sysroot() {
-			let src_path =
-				Path::new(sysroot.trim()).join("lib").join("rustlib").join("src").join("rust");
-			if !src_path.exists() {
-				let toolchain = dummy_crate.get_toolchain().unwrap_or("<toolchain>".to_string());
-				return Err(colorize_error_message(
-					&format!("Cannot compile the WASM runtime: no standard library sources found at {}!\n\
-					 You can install them with `rustup component add rust-src --toolchain {toolchain}` if you're using `rustup`.", src_path.display()),
-				))
-			}
-		}
+	if build_std_required {
+		check_rust_src_installed(&dummy_crate, "the WASM runtime", install_missing_rust_src)?;
 	}
 
-	Ok(CargoCommandVersioned::new(cargo_command, version))
+	let version = dummy_crate.get_rustc_version();
+	let toolchain = dummy_crate.toolchain();
+
+	Ok(CargoCommandVersioned::new(cargo_command, version, toolchain))
 }
@@ -0,0 +1,195 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Extraction of the runtime version from the `runtime_version` section of a WASM binary.
+
+use crate::WasmBuilderError;
+
+use parity_wasm::elements::{deserialize_buffer, Module};
+use std::{fs, path::Path};
+
+/// The version of a runtime, as found in the `runtime_version` section of its WASM binary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeVersionInfo {
+	/// The name of the runtime specification.
+	pub spec_name: String,
+	/// The name of the implementation of the specification.
+	pub impl_name: String,
+	/// The version of the authorship interface.
+	pub authoring_version: u32,
+	/// The version of the runtime specification.
+	pub spec_version: u32,
+	/// The version of the implementation of the specification.
+	pub impl_version: u32,
+	/// The version of the transaction format, if the runtime provides it.
+	pub transaction_version: Option<u32>,
+}
+
+impl RuntimeVersionInfo {
+	/// Returns the JSON representation of this runtime version.
+	pub(crate) fn to_json(&self) -> serde_json::Value {
+		serde_json::json!({
+			"spec_name": self.spec_name,
+			"impl_name": self.impl_name,
+			"authoring_version": self.authoring_version,
+			"spec_version": self.spec_version,
+			"impl_version": self.impl_version,
+			"transaction_version": self.transaction_version,
+		})
+	}
+
+	/// Returns the runtime version from its JSON representation, see [`Self::to_json`].
+	pub(crate) fn from_json(json: &serde_json::Value) -> Option<Self> {
+		let version = |name: &str| json[name].as_u64()?.try_into().ok();
+		Some(Self {
+			spec_name: json["spec_name"].as_str()?.into(),
+			impl_name: json["impl_name"].as_str()?.into(),
+			authoring_version: version("authoring_version")?,
+			spec_version: version("spec_version")?,
+			impl_version: version("impl_version")?,
+			transaction_version: match json["transaction_version"] {
+				serde_json::Value::Null => None,
+				_ => Some(version("transaction_version")?),
+			},
+		})
+	}
+
+	/// Decode the SCALE encoded content of the `runtime_version` section.
+	fn decode(mut input: &[u8]) -> Option<Self> {
+		let spec_name = decode_string(&mut input)?;
+		let impl_name = decode_string(&mut input)?;
+		let authoring_version = decode_u32(&mut input)?;
+		let spec_version = decode_u32(&mut input)?;
+		let impl_version = decode_u32(&mut input)?;
+
+		// Skip the runtime apis, every entry consists of an 8 byte id and a `u32` version.
+		let apis = decode_compact_len(&mut input)?;
+		take(&mut input, apis.checked_mul(12)?)?;
+
+		// Older runtimes don't provide the transaction version.
+		let transaction_version = decode_u32(&mut input);
+
+		Some(Self {
+			spec_name,
+			impl_name,
+			authoring_version,
+			spec_version,
+			impl_version,
+			transaction_version,
+		})
+	}
+}
+
+/// Read the runtime version from the `runtime_version` section of the WASM binary at `blob_path`.
+///
+/// Returns `None` if the binary has no such section.
+pub(crate) fn read_runtime_version(
+	blob_path: &Path,
+) -> Result<Option<RuntimeVersionInfo>, WasmBuilderError> {
+	let blob = fs::read(blob_path).map_err(WasmBuilderError::io(blob_path))?;
+
+	let module: Module = deserialize_buffer(&blob).map_err(|e| {
+		WasmBuilderError::InvalidRuntimeBlob(format!(
+			"Failed to deserialize `{}`: {e:?}",
+			blob_path.display()
+		))
+	})?;
+
+	let Some(section) = module.custom_sections().find(|cs| cs.name() == "runtime_version") else {
+		return Ok(None)
+	};
+
+	RuntimeVersionInfo::decode(section.payload()).map(Some).ok_or_else(|| {
+		WasmBuilderError::InvalidRuntimeBlob(format!(
+			"Failed to decode the `runtime_version` section of `{}`.",
+			blob_path.display()
+		))
+	})
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+	if input.len() < len {
+		return None
+	}
+
+	let (data, rest) = input.split_at(len);
+	*input = rest;
+	Some(data)
+}
+
+fn decode_u32(input: &mut &[u8]) -> Option<u32> {
+	take(input, 4).map(|data| u32::from_le_bytes(data.try_into().expect("Takes 4 bytes; qed")))
+}
+
+/// Decode a SCALE compact encoded length.
+fn decode_compact_len(input: &mut &[u8]) -> Option<usize> {
+	let first = *take(input, 1)?.first()?;
+
+	let len = match first & 0b11 {
+		0b00 => u32::from(first >> 2),
+		0b01 => u32::from(u16::from_le_bytes([first, *take(input, 1)?.first()?]) >> 2),
+		0b10 => {
+			let rest = take(input, 3)?;
+			u32::from_le_bytes([first, rest[0], rest[1], rest[2]]) >> 2
+		},
+		// Lengths that don't fit into a `u32` are not supported.
+		_ if first >> 2 == 0 => decode_u32(input)?,
+		_ => return None,
+	};
+
+	usize::try_from(len).ok()
+}
+
+fn decode_string(input: &mut &[u8]) -> Option<String> {
+	let len = decode_compact_len(input)?;
+	String::from_utf8(take(input, len)?.to_vec()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn decode_runtime_version_works() {
+		let mut encoded = vec![4 << 2];
+		encoded.extend(b"node");
+		encoded.push(9 << 2);
+		encoded.extend(b"node-impl");
+		encoded.extend(1u32.to_le_bytes());
+		encoded.extend(268u32.to_le_bytes());
+		encoded.extend(2u32.to_le_bytes());
+		encoded.push(1 << 2);
+		encoded.extend([1; 8]);
+		encoded.extend(4u32.to_le_bytes());
+
+		let mut expected = RuntimeVersionInfo {
+			spec_name: "node".into(),
+			impl_name: "node-impl".into(),
+			authoring_version: 1,
+			spec_version: 268,
+			impl_version: 2,
+			transaction_version: None,
+		};
+		assert_eq!(RuntimeVersionInfo::decode(&encoded), Some(expected.clone()));
+
+		encoded.extend(7u32.to_le_bytes());
+		expected.transaction_version = Some(7);
+		assert_eq!(RuntimeVersionInfo::decode(&encoded), Some(expected));
+
+		assert_eq!(RuntimeVersionInfo::decode(&encoded[..10]), None);
+	}
+}
@@ -0,0 +1,111 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Signing of the built WASM binary.
+
+use crate::WasmBuilderError;
+
+use std::{
+	fs,
+	path::{Path, PathBuf},
+};
+
+/// The signing context of sr25519 signatures, the same as used by Substrate.
+const SR25519_SIGNING_CTX: &[u8] = b"substrate";
+
+/// The signature scheme of a [`SigningKey`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SignatureScheme {
+	/// Ed25519 signatures.
+	Ed25519,
+	/// Schnorr signatures on Ristretto25519, as used by Substrate.
+	Sr25519,
+}
+
+/// The key the WASM binary is signed with, see
+/// [`WasmBuilder::sign_with`](crate::WasmBuilder::sign_with).
+///
+/// Doesn't implement `Debug` to not accidentally print the secret seed.
+#[derive(Clone)]
+pub enum SigningKey {
+	/// A key given by its 32 byte secret seed.
+	Seed {
+		/// The signature scheme of the key.
+		scheme: SignatureScheme,
+		/// The secret seed of the key.
+		seed: [u8; 32],
+	},
+	/// A key whose hex encoded 32 byte secret seed is stored in a file.
+	///
+	/// This is the format of the secret seed printed by `subkey`, optionally prefixed with `0x`.
+	File {
+		/// The signature scheme of the key.
+		scheme: SignatureScheme,
+		/// The path to the file, relative to the directory of the current crate.
+		path: PathBuf,
+	},
+}
+
+impl SigningKey {
+	/// Returns the signature scheme and the secret seed of this key.
+	fn load(&self) -> Result<(SignatureScheme, [u8; 32]), WasmBuilderError> {
+		match self {
+			Self::Seed { scheme, seed } => Ok((*scheme, *seed)),
+			Self::File { scheme, path } => {
+				let content = fs::read_to_string(path).map_err(WasmBuilderError::io(path))?;
+				let seed = decode_seed(content.trim()).ok_or_else(|| {
+					WasmBuilderError::InvalidConfiguration(format!(
+						"The signing key in `{}` needs to be a hex encoded 32 byte seed.",
+						path.display()
+					))
+				})?;
+
+				Ok((*scheme, seed))
+			},
+		}
+	}
+}
+
+/// Sign the WASM binary at `blob_path` with the given `key`.
+///
+/// The detached signature is written next to the binary, with `.sig` appended to its file name.
+pub(crate) fn sign(blob_path: &Path, key: &SigningKey) -> Result<[u8; 64], WasmBuilderError> {
+	let (scheme, seed) = key.load()?;
+	let blob = fs::read(blob_path).map_err(WasmBuilderError::io(blob_path))?;
+
+	let signature = match scheme {
+		SignatureScheme::Ed25519 =>
+			ed25519_dalek::Signer::sign(&ed25519_dalek::SigningKey::from_bytes(&seed), &blob)
+				.to_bytes(),
+		SignatureScheme::Sr25519 => schnorrkel::MiniSecretKey::from_bytes(&seed)
+			.expect("The seed has the length of a mini secret key; qed")
+			.expand_to_keypair(schnorrkel::ExpansionMode::Ed25519)
+			.sign_simple(SR25519_SIGNING_CTX, &blob)
+			.to_bytes(),
+	};
+
+	let mut signature_path = blob_path.as_os_str().to_owned();
+	signature_path.push(".sig");
+	fs::write(&signature_path, signature).map_err(WasmBuilderError::io(&signature_path))?;
+
+	Ok(signature)
+}
+
+/// Decode a hex encoded 32 byte seed, optionally prefixed with `0x`.
+fn decode_seed(hex: &str) -> Option<[u8; 32]> {
+	crate::from_hex(hex)?.try_into().ok()
+}
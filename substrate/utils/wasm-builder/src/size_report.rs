@@ -0,0 +1,187 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The code size report of the WASM binary.
+
+use crate::{validation::parse_error, write_file_if_changed, WasmBuilderError};
+
+use std::{
+	collections::{BTreeMap, HashMap},
+	fmt::Write,
+	fs,
+	path::Path,
+};
+use wasmparser::{CustomSectionReader, Name, NameSectionReader, Parser, Payload, TypeRef};
+
+/// The number of the biggest functions that are listed in the text report.
+const TEXT_REPORT_FUNCTIONS: usize = 100;
+
+/// The size of a function in the code section.
+struct FunctionSize {
+	/// The name of the function, from the name section if available.
+	name: String,
+	/// The crate the function belongs to, derived from the `name`.
+	krate: String,
+	/// The size of the function body in bytes.
+	size: usize,
+}
+
+/// Write the code size report of the WASM binary at `blob_path` into `out_dir`.
+///
+/// The report is written as `<blob_name>.size-report.txt` and as `<blob_name>.size-report.json`.
+/// Functions are only attributed to crates if the binary contains the name section.
+pub(crate) fn write_size_report(
+	blob_path: &Path,
+	blob_name: &str,
+	out_dir: &Path,
+) -> Result<(), WasmBuilderError> {
+	let blob = fs::read(blob_path).map_err(WasmBuilderError::io(blob_path))?;
+
+	let mut imported_functions = 0;
+	let mut body_sizes = Vec::new();
+	let mut names = HashMap::new();
+	for payload in Parser::new(0).parse_all(&blob) {
+		match parse_error(blob_path, payload)? {
+			Payload::ImportSection(reader) =>
+				for import in reader {
+					if matches!(parse_error(blob_path, import)?.ty, TypeRef::Func(_)) {
+						imported_functions += 1;
+					}
+				},
+			Payload::CodeSectionEntry(body) => body_sizes.push(body.range().len()),
+			Payload::CustomSection(section) if section.name() == "name" =>
+				names = function_names(blob_path, &section)?,
+			_ => {},
+		}
+	}
+
+	let mut functions = body_sizes
+		.into_iter()
+		.enumerate()
+		.map(|(index, size)| {
+			let index = imported_functions + index as u32;
+			let name = names.remove(&index).unwrap_or_else(|| format!("func[{index}]"));
+			FunctionSize { krate: crate_of(&name).to_owned(), name, size }
+		})
+		.collect::<Vec<_>>();
+	functions.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.name.cmp(&b.name)));
+
+	let mut crates = BTreeMap::<&str, usize>::new();
+	functions.iter().for_each(|f| *crates.entry(&f.krate).or_default() += f.size);
+	let mut crates = crates.into_iter().collect::<Vec<_>>();
+	crates.sort_by_key(|(_, size)| std::cmp::Reverse(*size));
+
+	let code_size = functions.iter().map(|f| f.size).sum::<usize>();
+
+	let mut text = format!(
+		"Size report of `{}`\n\nTotal: {} bytes, code: {code_size} bytes\n\nCrates:\n",
+		blob_path.display(),
+		blob.len()
+	);
+	for (krate, size) in &crates {
+		let _ = writeln!(text, "{size:>10} {:>6.2}% {krate}", percent(*size, code_size));
+	}
+	let _ = writeln!(text, "\nThe {TEXT_REPORT_FUNCTIONS} biggest functions:");
+	for function in functions.iter().take(TEXT_REPORT_FUNCTIONS) {
+		let _ = writeln!(
+			text,
+			"{:>10} {:>6.2}% {}",
+			function.size,
+			percent(function.size, code_size),
+			function.name
+		);
+	}
+
+	let json = serde_json::json!({
+		"total_size": blob.len(),
+		"code_size": code_size,
+		"crates": crates
+			.iter()
+			.map(|(krate, size)| serde_json::json!({ "name": krate, "size": size }))
+			.collect::<Vec<_>>(),
+		"functions": functions
+			.iter()
+			.map(|f| serde_json::json!({ "name": f.name, "crate": f.krate, "size": f.size }))
+			.collect::<Vec<_>>(),
+	});
+
+	write_file_if_changed(out_dir.join(format!("{blob_name}.size-report.txt")), text)?;
+	write_file_if_changed(
+		out_dir.join(format!("{blob_name}.size-report.json")),
+		serde_json::to_string_pretty(&json).expect("The size report is valid JSON; qed"),
+	)
+}
+
+/// Returns the names of the functions by their index from the name `section` of the WASM binary at
+/// `blob_path`.
+pub(crate) fn function_names(
+	blob_path: &Path,
+	section: &CustomSectionReader,
+) -> Result<HashMap<u32, String>, WasmBuilderError> {
+	let mut names = HashMap::new();
+	for name in NameSectionReader::new(section.data(), section.data_offset()) {
+		if let Name::Function(map) = parse_error(blob_path, name)? {
+			for naming in map {
+				let naming = parse_error(blob_path, naming)?;
+				names.insert(naming.index, naming.name.to_owned());
+			}
+		}
+	}
+	Ok(names)
+}
+
+/// Returns the crate of the demangled function `name`, e.g. `sp_io` for `sp_io::storage::get`.
+///
+/// For trait implementations like `<pallet_balances::Call<T> as core::fmt::Debug>::fmt` this is
+/// the crate of the type.
+pub(crate) fn crate_of(name: &str) -> &str {
+	let path = name.trim_start_matches(|c| c == '<' || c == '&').trim_start_matches("mut ");
+	match path.split_once("::") {
+		Some((krate, _))
+			if !krate.is_empty() && !krate.contains(|c: char| c == ' ' || c == '<') =>
+			krate,
+		_ => "<unknown>",
+	}
+}
+
+fn percent(size: usize, total: usize) -> f64 {
+	if total == 0 {
+		0.0
+	} else {
+		size as f64 * 100.0 / total as f64
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn crate_of_works() {
+		assert_eq!(crate_of("sp_io::storage::get::h5f2b1a"), "sp_io");
+		assert_eq!(
+			crate_of("<pallet_balances::Call<T> as core::fmt::Debug>::fmt"),
+			"pallet_balances"
+		);
+		assert_eq!(
+			crate_of("<&mut alloc::vec::Vec<u8> as parity_scale_codec::Output>::write"),
+			"alloc"
+		);
+		assert_eq!(crate_of("<T as core::clone::Clone>::clone"), "<unknown>");
+		assert_eq!(crate_of("func[42]"), "<unknown>");
+	}
+}
@@ -0,0 +1,471 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Validation of the built WASM binary.
+
+use crate::{wasm_project::section_name_matches, WasmBuilderError};
+
+use std::{
+	collections::{BTreeMap, HashMap, HashSet},
+	fs,
+	path::Path,
+};
+use wasmparser::{
+	ExternalKind, FuncValidatorAllocations, Parser, Payload, TypeRef, ValidPayload, Validator,
+	WasmFeatures,
+};
+
+/// The crates of the standard library, which are precompiled unless it is built from source.
+const STD_CRATES: &[&str] = &["core", "alloc", "std", "compiler_builtins"];
+
+/// The policy the WASM binary is validated against, see
+/// [`WasmBuilder::validate`](crate::WasmBuilder::validate).
+///
+/// By default floating point instructions, SIMD and threads are rejected, while all custom
+/// sections are allowed.
+#[derive(Clone, Debug)]
+pub struct ValidationPolicy {
+	/// Reject floating point instructions, as they are not deterministic across machines.
+	pub deny_floats: bool,
+	/// Reject instructions of the SIMD proposals.
+	pub deny_simd: bool,
+	/// Reject instructions and shared memories of the threads proposal.
+	pub deny_threads: bool,
+	/// The custom sections that are allowed, all others are rejected.
+	///
+	/// The entries follow the patterns of
+	/// [`WasmBuilder::strip_custom_sections`](crate::WasmBuilder::strip_custom_sections). If
+	/// `None`, all custom sections are allowed.
+	pub allowed_custom_sections: Option<Vec<String>>,
+}
+
+impl Default for ValidationPolicy {
+	fn default() -> Self {
+		Self {
+			deny_floats: true,
+			deny_simd: true,
+			deny_threads: true,
+			allowed_custom_sections: None,
+		}
+	}
+}
+
+/// Validate the WASM binary at `blob_path` against the given `policy`.
+pub(crate) fn validate(
+	blob_path: &Path,
+	policy: &ValidationPolicy,
+) -> Result<(), WasmBuilderError> {
+	let blob = fs::read(blob_path).map_err(WasmBuilderError::io(blob_path))?;
+
+	let features = WasmFeatures {
+		floats: !policy.deny_floats,
+		simd: !policy.deny_simd,
+		relaxed_simd: !policy.deny_simd,
+		threads: !policy.deny_threads,
+		..Default::default()
+	};
+	Validator::new_with_features(features).validate_all(&blob).map_err(|e| {
+		WasmBuilderError::InvalidRuntimeBlob(format!(
+			"The runtime `{}` violates the validation policy: {e}",
+			blob_path.display()
+		))
+	})?;
+
+	let Some(allowed) = &policy.allowed_custom_sections else { return Ok(()) };
+	for payload in Parser::new(0).parse_all(&blob) {
+		if let Payload::CustomSection(section) = parse_error(blob_path, payload)? {
+			if !section_name_matches(section.name(), allowed) {
+				return Err(WasmBuilderError::InvalidRuntimeBlob(format!(
+					"The runtime `{}` contains the custom section `{}`, which is not allowed by \
+					 the validation policy.",
+					blob_path.display(),
+					section.name()
+				)))
+			}
+		}
+	}
+
+	Ok(())
+}
+
+/// Ensure that the WASM binary at `blob_path` exports all `required` functions.
+pub(crate) fn check_required_exports(
+	blob_path: &Path,
+	required: &[String],
+) -> Result<(), WasmBuilderError> {
+	let blob = fs::read(blob_path).map_err(WasmBuilderError::io(blob_path))?;
+
+	let mut exports = HashSet::new();
+	for payload in Parser::new(0).parse_all(&blob) {
+		if let Payload::ExportSection(reader) = parse_error(blob_path, payload)? {
+			for export in reader {
+				exports.insert(parse_error(blob_path, export)?.name);
+			}
+		}
+	}
+
+	let missing = required
+		.iter()
+		.filter(|name| !exports.contains(name.as_str()))
+		.collect::<Vec<_>>();
+	if !missing.is_empty() {
+		return Err(WasmBuilderError::InvalidRuntimeBlob(format!(
+			"The runtime `{}` doesn't export the required functions: {}",
+			blob_path.display(),
+			missing.iter().map(|name| format!("`{name}`")).collect::<Vec<_>>().join(", "),
+		)))
+	}
+
+	Ok(())
+}
+
+/// Ensure that the WASM binary at `blob_path` only imports host functions matching the `allowed`
+/// patterns.
+///
+/// The patterns follow the same rules as for [`section_name_matches`].
+pub(crate) fn check_host_functions(
+	blob_path: &Path,
+	allowed: &[String],
+) -> Result<(), WasmBuilderError> {
+	let blob = fs::read(blob_path).map_err(WasmBuilderError::io(blob_path))?;
+
+	let mut disallowed = Vec::new();
+	for payload in Parser::new(0).parse_all(&blob) {
+		if let Payload::ImportSection(reader) = parse_error(blob_path, payload)? {
+			for import in reader {
+				let import = parse_error(blob_path, import)?;
+				if matches!(import.ty, TypeRef::Func(_)) &&
+					!section_name_matches(import.name, allowed)
+				{
+					disallowed.push(format!("`{}::{}`", import.module, import.name));
+				}
+			}
+		}
+	}
+
+	if !disallowed.is_empty() {
+		return Err(WasmBuilderError::InvalidRuntimeBlob(format!(
+			"The runtime `{}` imports host functions that are not allowed: {}",
+			blob_path.display(),
+			disallowed.join(", "),
+		)))
+	}
+
+	Ok(())
+}
+
+/// Ensure that the WASM binary at `blob_path` imports its memory if `import` is `true` and that
+/// it exports its memory otherwise.
+pub(crate) fn check_memory(blob_path: &Path, import: bool) -> Result<(), WasmBuilderError> {
+	let blob = fs::read(blob_path).map_err(WasmBuilderError::io(blob_path))?;
+
+	let mut imported = false;
+	let mut exported = false;
+	for payload in Parser::new(0).parse_all(&blob) {
+		match parse_error(blob_path, payload)? {
+			Payload::ImportSection(reader) =>
+				for import in reader {
+					imported |= matches!(parse_error(blob_path, import)?.ty, TypeRef::Memory(_));
+				},
+			Payload::ExportSection(reader) =>
+				for export in reader {
+					exported |=
+						matches!(parse_error(blob_path, export)?.kind, ExternalKind::Memory);
+				},
+			_ => {},
+		}
+	}
+
+	match (import, imported, exported) {
+		(true, true, _) | (false, _, true) => Ok(()),
+		(true, false, _) => Err(WasmBuilderError::InvalidRuntimeBlob(format!(
+			"The runtime `{}` doesn't import its memory, although `--import-memory` was passed \
+			 to the linker.",
+			blob_path.display()
+		))),
+		(false, _, false) => Err(WasmBuilderError::InvalidRuntimeBlob(format!(
+			"The runtime `{}` doesn't export its memory, although `--export-memory` was passed \
+			 to the linker.",
+			blob_path.display()
+		))),
+	}
+}
+
+/// Ensure that the WASM binary at `blob_path` doesn't use any of the `disabled` target features.
+///
+/// The features are named like the LLVM target features, e.g. `sign-ext`. Features that have no
+/// equivalent WASM proposal known to `wasmparser` are ignored.
+///
+/// Every function is checked, so the error lists all crates that use a disabled feature, as far as
+/// the binary contains the name section. `std_from_source` tells whether the standard library was
+/// built with the flags of the runtime, otherwise the error points to building it from source if
+/// one of its crates uses a disabled feature.
+pub(crate) fn check_target_features(
+	blob_path: &Path,
+	disabled: &[String],
+	std_from_source: bool,
+) -> Result<(), WasmBuilderError> {
+	let mut features = WasmFeatures::default();
+	for feature in disabled {
+		match feature.as_str() {
+			"sign-ext" => features.sign_extension = false,
+			"bulk-memory" => features.bulk_memory = false,
+			"multivalue" => features.multi_value = false,
+			"mutable-globals" => features.mutable_global = false,
+			"nontrapping-fptoint" => features.saturating_float_to_int = false,
+			"reference-types" => features.reference_types = false,
+			"simd128" => features.simd = false,
+			"relaxed-simd" => features.relaxed_simd = false,
+			"atomics" => features.threads = false,
+			"tail-call" => features.tail_call = false,
+			"extended-const" => features.extended_const = false,
+			"multimemory" => features.multi_memory = false,
+			"exception-handling" => features.exceptions = false,
+			_ => {},
+		}
+	}
+
+	let blob = fs::read(blob_path).map_err(WasmBuilderError::io(blob_path))?;
+	let mut validator = Validator::new_with_features(features);
+	let mut violations = Vec::new();
+	let mut names = HashMap::new();
+	for payload in Parser::new(0).parse_all(&blob) {
+		let payload = parse_error(blob_path, payload)?;
+		if let Payload::CustomSection(section) = &payload {
+			if section.name() == "name" {
+				names = crate::size_report::function_names(blob_path, section)?;
+			}
+		}
+
+		// Only the functions are checked one by one, a disabled feature outside of them, e.g. in
+		// the types or globals, is reported right away.
+		let payload = validator.payload(&payload).map_err(|e| {
+			WasmBuilderError::InvalidRuntimeBlob(format!(
+				"The runtime `{}` uses a disabled target feature: {e}",
+				blob_path.display()
+			))
+		})?;
+		if let ValidPayload::Func(func, body) = payload {
+			let mut func = func.into_validator(FuncValidatorAllocations::default());
+			if let Err(e) = func.validate(&body) {
+				violations.push((func.index(), e));
+			}
+		}
+	}
+
+	if violations.is_empty() {
+		return Ok(())
+	}
+
+	// The first violating function and the number of violating functions per crate.
+	let mut crates = BTreeMap::<String, (String, wasmparser::BinaryReaderError, usize)>::new();
+	for (index, error) in violations {
+		let name = names.remove(&index).unwrap_or_else(|| format!("func[{index}]"));
+		let krate = crate::size_report::crate_of(&name).to_owned();
+		crates.entry(krate).or_insert((name, error, 0)).2 += 1;
+	}
+
+	let mut message =
+		format!("The runtime `{}` uses disabled target features in:", blob_path.display());
+	for (krate, (name, error, count)) in &crates {
+		message.push_str(&format!(
+			"\n  `{krate}`: {count} function(s), e.g. `{name}`: {}",
+			error.message()
+		));
+	}
+	if !std_from_source && crates.keys().any(|krate| STD_CRATES.contains(&krate.as_str())) {
+		message.push_str(
+			"\nThe precompiled standard library ignores the disabled target features, build it \
+			 from source with `WasmBuilder::build_std` to remove them.",
+		);
+	}
+
+	Err(WasmBuilderError::InvalidRuntimeBlob(message))
+}
+
+/// Map a parsing error of the WASM binary at `blob_path` to a [`WasmBuilderError`].
+pub(crate) fn parse_error<T>(
+	blob_path: &Path,
+	result: wasmparser::Result<T>,
+) -> Result<T, WasmBuilderError> {
+	result.map_err(|e| {
+		WasmBuilderError::InvalidRuntimeBlob(format!(
+			"Failed to parse `{}`: {e}",
+			blob_path.display()
+		))
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::path::PathBuf;
+	use tempfile::TempDir;
+
+	/// The types `() -> ()` and `(i32) -> i32`.
+	const TYPES: &[u8] = &[2, 0x60, 0, 0, 0x60, 1, 0x7f, 1, 0x7f];
+
+	/// Returns the encoding of the name `name`.
+	fn name(name: &str) -> Vec<u8> {
+		[name.len() as u8].into_iter().chain(name.bytes()).collect()
+	}
+
+	/// Write the module with the given sections as `(id, content)` into `dir`.
+	fn module(dir: &TempDir, sections: &[(u8, Vec<u8>)]) -> PathBuf {
+		let mut module = b"\0asm\x01\0\0\0".to_vec();
+		for (id, content) in sections {
+			assert!(content.len() < 0x80, "The section size is encoded in a single byte");
+			module.push(*id);
+			module.push(content.len() as u8);
+			module.extend(content);
+		}
+
+		let path = dir.path().join("runtime.wasm");
+		fs::write(&path, module).unwrap();
+		path
+	}
+
+	/// Returns the code section with the given function bodies, which have no locals.
+	fn code(bodies: &[&[u8]]) -> Vec<u8> {
+		let mut code = vec![bodies.len() as u8];
+		for body in bodies {
+			code.push(body.len() as u8 + 2);
+			code.push(0);
+			code.extend(*body);
+			code.push(0x0b);
+		}
+		code
+	}
+
+	/// Returns the name section with the given function names.
+	fn function_names(names: &[&str]) -> Vec<u8> {
+		let mut map = vec![names.len() as u8];
+		for (index, function) in names.iter().enumerate() {
+			map.push(index as u8);
+			map.extend(name(function));
+		}
+		[name("name"), vec![1, map.len() as u8], map].concat()
+	}
+
+	fn error_message<T: std::fmt::Debug>(result: Result<T, WasmBuilderError>) -> String {
+		result.unwrap_err().to_string()
+	}
+
+	#[test]
+	fn validate_rejects_floats() {
+		let dir = tempfile::tempdir().unwrap();
+		let policy = ValidationPolicy::default();
+		// The function `(x, x) -> x` with `local.get 0`, `local.get 1` and `x.add`.
+		let add = |value_type, add| {
+			module(
+				&dir,
+				&[
+					(1, vec![1, 0x60, 2, value_type, value_type, 1, value_type]),
+					(3, vec![1, 0]),
+					(10, code(&[&[0x20, 0, 0x20, 1, add]])),
+				],
+			)
+		};
+
+		validate(&add(0x7f, 0x6a), &policy).unwrap();
+
+		let blob = add(0x7d, 0x92);
+		assert!(error_message(validate(&blob, &policy)).contains("violates the validation policy"));
+		validate(&blob, &ValidationPolicy { deny_floats: false, ..policy }).unwrap();
+	}
+
+	#[test]
+	fn check_required_exports_reports_missing_exports() {
+		let dir = tempfile::tempdir().unwrap();
+		let exports = [vec![1], name("Core_version"), vec![0, 0]].concat();
+		let blob =
+			module(&dir, &[(1, TYPES.to_vec()), (3, vec![1, 0]), (7, exports), (10, code(&[&[]]))]);
+
+		check_required_exports(&blob, &["Core_version".into()]).unwrap();
+		let error = error_message(check_required_exports(
+			&blob,
+			&["Core_version".into(), "Metadata_metadata".into()],
+		));
+		assert!(error.ends_with("required functions: `Metadata_metadata`"), "{error}");
+	}
+
+	#[test]
+	fn check_host_functions_reports_disallowed_imports() {
+		let dir = tempfile::tempdir().unwrap();
+		let imports = [
+			vec![2],
+			name("env"),
+			name("ext_storage_get"),
+			vec![0, 0],
+			name("env"),
+			name("ext_foo"),
+		]
+		.concat();
+		let blob = module(&dir, &[(1, TYPES.to_vec()), (2, [imports, vec![0, 0]].concat())]);
+
+		check_host_functions(&blob, &["ext_*".into()]).unwrap();
+		let error = error_message(check_host_functions(&blob, &["ext_storage_*".into()]));
+		assert!(error.ends_with("not allowed: `env::ext_foo`"), "{error}");
+	}
+
+	#[test]
+	fn check_memory_works() {
+		let dir = tempfile::tempdir().unwrap();
+
+		let imports = [vec![1], name("env"), name("memory"), vec![2, 0, 1]].concat();
+		let blob = module(&dir, &[(2, imports)]);
+		check_memory(&blob, true).unwrap();
+		assert!(error_message(check_memory(&blob, false)).contains("doesn't export its memory"));
+
+		let exports = [vec![1], name("memory"), vec![2, 0]].concat();
+		let blob = module(&dir, &[(5, vec![1, 0, 1]), (7, exports)]);
+		check_memory(&blob, false).unwrap();
+		assert!(error_message(check_memory(&blob, true)).contains("doesn't import its memory"));
+	}
+
+	#[test]
+	fn check_target_features_reports_crates() {
+		let dir = tempfile::tempdir().unwrap();
+		// local.get 0, i32.extend8_s
+		let sign_ext: &[u8] = &[0x20, 0, 0xc0];
+		let blob = |names: &[&str]| {
+			module(
+				&dir,
+				&[
+					(1, TYPES.to_vec()),
+					(3, vec![2, 1, 1]),
+					(10, code(&[&[0x20, 0], sign_ext])),
+					(0, function_names(names)),
+				],
+			)
+		};
+
+		let disabled = ["sign-ext".to_owned()];
+		check_target_features(&blob(&["a::f", "b::g"]), &[], false).unwrap();
+
+		let error =
+			error_message(check_target_features(&blob(&["a::f", "b::g"]), &disabled, false));
+		assert!(error.contains("`b`: 1 function(s), e.g. `b::g`"), "{error}");
+		assert!(!error.contains("`a`"), "{error}");
+		assert!(!error.contains("build it from source"), "{error}");
+
+		let error =
+			error_message(check_target_features(&blob(&["a::f", "core::g"]), &disabled, false));
+		assert!(error.contains("`core`: 1 function(s)"), "{error}");
+		assert!(error.contains("build it from source"), "{error}");
+	}
+}
@@ -40,50 +40,83 @@ impl Version {
 		self.is_nightly
 	}
 
-	/// Extract from the given `version` string.
+	/// Parse a release `version` like `1.77.0` or `1.77`, where the patch version defaults to `0`.
+	pub fn parse_release(version: &str) -> Option<Self> {
+		let mut parts = version.trim().split('.').map(str::parse::<u32>);
+		let major = parts.next()?.ok()?;
+		let minor = parts.next()?.ok()?;
+		let patch = parts.next().transpose().ok()?.unwrap_or(0);
+		if parts.next().is_some() {
+			return None
+		}
+
+		Some(Version { major, minor, patch, is_nightly: false, year: None, month: None, day: None })
+	}
+
+	/// Returns whether the release of `self` is at least the one of `other`.
+	///
+	/// Unlike the ordering, this ignores whether the versions are nightly versions and their dates.
+	pub fn is_release_at_least(&self, other: &Self) -> bool {
+		(self.major, self.minor, self.patch) >= (other.major, other.minor, other.patch)
+	}
+
+	/// Extract from the given `version` string, the output of `cargo --version` or `rustc
+	/// --version`.
+	///
+	/// Besides the official builds, e.g. `rustc 1.73.0 (cc66ad468 2023-10-03)`, this understands
+	/// the builds of vendors that add further parentheses like `(Fedora 1.76.0-1.fc39)`, builds
+	/// with only a commit hash or without any of them, and locally built compilers of the `dev`
+	/// channel, which support nightly features like the `nightly` channel.
 	pub fn extract(version: &str) -> Option<Self> {
-		let mut is_nightly = false;
-		let version_parts = version
-			.trim()
-			.split(" ")
-			.nth(1)?
-			.split(".")
-			.filter_map(|v| {
-				if let Some(rest) = v.strip_suffix("-nightly") {
-					is_nightly = true;
-					rest.parse().ok()
-				} else {
-					v.parse().ok()
-				}
-			})
-			.collect::<Vec<u32>>();
-
-		if version_parts.len() != 3 {
+		let word = version.split_whitespace().nth(1)?;
+		let (release, channel) = match word.split_once('-') {
+			Some((release, channel)) => (release, Some(channel)),
+			None => (word, None),
+		};
+
+		let mut parts = release.split('.').map(str::parse::<u32>);
+		let major = parts.next()?.ok()?;
+		let minor = parts.next()?.ok()?;
+		let patch = parts.next()?.ok()?;
+		if parts.next().is_some() {
 			return None
 		}
 
-		let date_parts = version
-			.split(" ")
-			.nth(3)
-			.map(|date| {
-				date.split("-")
-					.filter_map(|v| v.trim().strip_suffix(")").unwrap_or(v).parse().ok())
-					.collect::<Vec<u32>>()
-			})
-			.unwrap_or_default();
+		// The channel may be followed by a number, e.g. `beta.3`.
+		let is_nightly = matches!(
+			channel.and_then(|channel| channel.split('.').next()),
+			Some("nightly" | "dev")
+		);
+
+		// The commit info in the first parentheses is the hash and the date, either may be missing.
+		let date = version
+			.split_once('(')
+			.and_then(|(_, rest)| rest.split_once(')'))
+			.and_then(|(commit_info, _)| commit_info.split_whitespace().find_map(parse_date));
 
 		Some(Version {
-			major: version_parts[0],
-			minor: version_parts[1],
-			patch: version_parts[2],
+			major,
+			minor,
+			patch,
 			is_nightly,
-			year: date_parts.get(0).copied(),
-			month: date_parts.get(1).copied(),
-			day: date_parts.get(2).copied(),
+			year: date.map(|date| date.0),
+			month: date.map(|date| date.1),
+			day: date.map(|date| date.2),
 		})
 	}
 }
 
+/// Parse a `date` like `2023-10-03` into the year, month and day.
+fn parse_date(date: &str) -> Option<(u32, u32, u32)> {
+	let mut parts = date.split('-');
+	let (year, month, day) = (parts.next()?, parts.next()?, parts.next()?);
+	if parts.next().is_some() || year.len() != 4 {
+		return None
+	}
+
+	Some((year.parse().ok()?, month.parse().ok()?, day.parse().ok()?))
+}
+
 /// Ordering is done in the following way:
 ///
 /// 1. `stable` > `nightly`
@@ -213,6 +246,55 @@ mod tests {
 		);
 	}
 
+	#[test]
+	fn parse_release_works() {
+		let version_1_77_0 = Version::parse_release("1.77.0").unwrap();
+		assert_eq!(Version::parse_release("1.77"), Some(version_1_77_0));
+		assert_eq!(Version::parse_release("1.77.0.1"), None);
+		assert_eq!(Version::parse_release("1.77.x"), None);
+		assert_eq!(Version::parse_release("nightly"), None);
+
+		let nightly = Version::extract("rustc 1.78.0-nightly (d65d197ad 2024-02-01)").unwrap();
+		assert!(nightly.is_release_at_least(&version_1_77_0));
+		let version_1_76_0 = Version::extract("rustc 1.76.0 (d65d197ad 2024-02-04)").unwrap();
+		assert!(!version_1_76_0.is_release_at_least(&version_1_77_0));
+	}
+
+	#[test]
+	fn parse_vendor_and_local_builds() {
+		let fedora = Version::extract("cargo 1.76.0 (c84b36747 2024-01-18) (Fedora 1.76.0-1.fc39)");
+		assert_eq!(
+			fedora,
+			Some(Version {
+				major: 1,
+				minor: 76,
+				patch: 0,
+				is_nightly: false,
+				year: Some(2024),
+				month: Some(1),
+				day: Some(18),
+			}),
+		);
+
+		let homebrew = Version::extract("rustc 1.72.1 (d5c2e9c34 2023-09-13) (Homebrew)").unwrap();
+		assert_eq!((homebrew.minor, homebrew.patch, homebrew.day), (72, 1, Some(13)));
+
+		let local = Version::extract("rustc 1.80.0-dev").unwrap();
+		assert!(local.is_nightly());
+		assert_eq!(local.year, None);
+
+		let hash_only = Version::extract("rustc 1.81.0-nightly (3f5fd8dd4)").unwrap();
+		assert!(hash_only.is_nightly());
+		assert_eq!(hash_only.year, None);
+
+		let beta = Version::extract("rustc 1.70.0-beta.3 (b5b4e1ed3 2023-05-09)").unwrap();
+		assert!(beta.is_stable());
+		assert_eq!((beta.minor, beta.patch), (70, 0));
+
+		assert_eq!(Version::extract("rustc 1.70"), None);
+		assert_eq!(Version::extract("rustc version unknown"), None);
+	}
+
 	#[test]
 	fn parse_rustc_version() {
 		let version = Version::extract("rustc 1.73.0 (cc66ad468 2023-10-03)").unwrap();
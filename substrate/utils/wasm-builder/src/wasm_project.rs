@@ -16,26 +16,45 @@
 // limitations under the License.
 
 #[cfg(feature = "metadata-hash")]
-use crate::builder::MetadataExtraInfo;
-use crate::{write_file_if_changed, CargoCommandVersioned, RuntimeTarget, OFFLINE};
+use crate::builder::MetadataHashConfig;
+use crate::{
+	builder::{BuildArtifacts, BuildInfo},
+	fingerprint::Fingerprint,
+	version::Version,
+	watch::{rerun_if_changed, rerun_if_env_changed},
+	write_file_if_changed, ArtifactCache, BuildStdMode, CargoCommand, CargoCommandVersioned,
+	RuntimeTarget, ToolchainPolicy, ValidationPolicy, Verbosity, WasmBuilderError, OFFLINE,
+};
 
-use build_helper::rerun_if_changed;
 use cargo_metadata::{DependencyKind, Metadata, MetadataCommand};
 use console::style;
-use parity_wasm::elements::{deserialize_buffer, Module};
+use parity_wasm::elements::{deserialize_buffer, serialize_to_file, Module, Section};
 use std::{
 	borrow::ToOwned,
-	collections::HashSet,
-	env, fs,
-	hash::{Hash, Hasher},
-	ops::Deref,
+	collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+	env,
+	ffi::OsString,
+	fs,
+	io::{self, BufRead, BufReader, Read},
 	path::{Path, PathBuf},
-	process,
+	process::{Command, Stdio},
+	thread,
+	time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use strum::{EnumIter, IntoEnumIterator};
 use toml::value::Table;
 use walkdir::WalkDir;
 
+/// The file in a prebuilt sysroot that contains its fingerprint, see
+/// [`WasmBuilder::sysroot`](crate::WasmBuilder::sysroot).
+const SYSROOT_FINGERPRINT: &str = "wasm-builder-sysroot.fingerprint";
+
+/// Stands for a space within a path in the `RUSTFLAGS` of the runtime, see [`rustflag_path`].
+const RUSTFLAGS_SPACE: char = '\u{1f}';
+
+/// The paths within a package that are never watched, as they can't affect the runtime.
+const DEFAULT_RERUN_EXCLUSIONS: &[&str] = &["tests/**", "benches/**", "examples/**", "target/**"];
+
 /// Colorize an info message.
 ///
 /// Returns the colorized message.
@@ -47,6 +66,15 @@ fn colorize_info_message(message: &str) -> String {
 	}
 }
 
+/// Colorize the prefix of the lines of the cargo output.
+fn colorize_output_prefix(prefix: &str) -> String {
+	if super::color_output_enabled() {
+		style(prefix).cyan().to_string()
+	} else {
+		prefix.into()
+	}
+}
+
 /// Holds the path to the bloaty WASM binary.
 pub struct WasmBinaryBloaty(PathBuf);
 
@@ -75,9 +103,14 @@ impl WasmBinary {
 	pub fn wasm_binary_path_escaped(&self) -> String {
 		self.0.display().to_string().escape_default().to_string()
 	}
+
+	/// Returns whether the wasm binary is compressed.
+	pub fn is_compressed(&self) -> bool {
+		self.0.to_str().map_or(false, |path| path.ends_with(".compact.compressed.wasm"))
+	}
 }
 
-fn crate_metadata(cargo_manifest: &Path) -> Metadata {
+fn crate_metadata(cargo_manifest: &Path) -> Result<Metadata, WasmBuilderError> {
 	let mut cargo_lock = cargo_manifest.to_path_buf();
 	cargo_lock.set_file_name("Cargo.lock");
 
@@ -85,18 +118,24 @@ fn crate_metadata(cargo_manifest: &Path) -> Metadata {
 
 	// If we can find a `Cargo.lock`, we assume that this is the workspace root and there exists a
 	// `Cargo.toml` that we can use for getting the metadata.
-	let cargo_manifest = if let Some(mut cargo_lock) = find_cargo_lock(cargo_manifest) {
+	let workspace_manifest = if let Some(mut cargo_lock) = find_cargo_lock(cargo_manifest) {
 		cargo_lock.set_file_name("Cargo.toml");
 		cargo_lock
 	} else {
 		cargo_manifest.to_path_buf()
 	};
 
-	let crate_metadata_command = create_metadata_command(cargo_manifest);
-
-	let crate_metadata = crate_metadata_command
+	let mut crate_metadata = create_metadata_command(&workspace_manifest)
 		.exec()
-		.expect("`cargo metadata` can not fail on project `Cargo.toml`; qed");
+		.map_err(|e| WasmBuilderError::CargoMetadata(e.to_string()))?;
+
+	// Projects that are not part of this workspace, e.g. fetched from git or crates.io, are
+	// queried directly.
+	if !crate_metadata.packages.iter().any(|p| p.manifest_path == cargo_manifest) {
+		crate_metadata = create_metadata_command(cargo_manifest)
+			.exec()
+			.map_err(|e| WasmBuilderError::CargoMetadata(e.to_string()))?;
+	}
 	// If the `Cargo.lock` didn't exist, we need to remove it after
 	// calling `cargo metadata`. This is required to ensure that we don't change
 	// the build directory outside of the `target` folder. Commands like
@@ -105,183 +144,1252 @@ fn crate_metadata(cargo_manifest: &Path) -> Metadata {
 		let _ = fs::remove_file(&cargo_lock);
 	}
 
-	crate_metadata
+	Ok(crate_metadata)
 }
 
-/// Creates the WASM project, compiles the WASM binary and compacts the WASM binary.
-///
-/// # Returns
+/// The options of a project build, as configured by the [`WasmBuilder`](crate::WasmBuilder).
+pub(crate) struct BuildOptions {
+	/// Features that should be enabled for the project.
+	pub features_to_enable: Vec<String>,
+	/// Features that replace the features inferred from the native build.
+	pub features_override: Option<Vec<String>>,
+	/// Features that should never be enabled for the project.
+	pub features_to_disable: Vec<String>,
+	/// Should the features of the `default` feature not be forwarded?
+	pub disable_default_features: bool,
+	/// The name of the runtime binaries. If `None`, the project name will be used.
+	pub blob_out_name_override: Option<String>,
+	/// Should the wasm binary be checked for the `runtime_version` section?
+	pub check_for_runtime_version_section: bool,
+	/// Should the compaction and compression of the wasm binary be skipped?
+	pub disable_compact: bool,
+	/// `RUSTFLAGS` that are appended after the flags of `WASM_BUILD_RUSTFLAGS`.
+	pub rustflags_after_env: String,
+	/// Arguments that are appended to the cargo command building the runtime.
+	pub cargo_args: Vec<String>,
+	/// The profile to build the runtime with, if not overridden by `WASM_BUILD_TYPE`.
+	pub profile: Option<Profile>,
+	/// The path to a custom target specification that replaces the builtin target.
+	pub custom_target_spec: Option<PathBuf>,
+	/// The cargo configuration file, if not overridden by `WASM_BUILD_CARGO_CONFIG`.
+	pub cargo_config: Option<PathBuf>,
+	/// The linker, if not overridden by `WASM_BUILD_LINKER`.
+	pub linker: Option<String>,
+	/// The linker flavor, if not overridden by `WASM_BUILD_LINKER_FLAVOR`.
+	pub linker_flavor: Option<String>,
+	/// Should the standard library be built, if not overridden by `WASM_BUILD_STD`?
+	pub build_std: Option<bool>,
+	/// The features of the standard library crates when building them from source.
+	pub build_std_features: Vec<String>,
+	/// The prebuilt sysroot that replaces building the standard library, if not overridden by
+	/// `WASM_BUILD_SYSROOT`.
+	pub sysroot: Option<PathBuf>,
+	/// How cargo is told to build the standard library, if not overridden by
+	/// `WASM_BUILD_STD_MODE`.
+	pub build_std_mode: BuildStdMode,
+	/// The WASM target features the binary is not allowed to use.
+	pub disabled_target_features: Vec<String>,
+	/// Should WASM runtimes be built for `wasm32v1-none` if the toolchain provides it, if not
+	/// overridden by `WASM_BUILD_WASM32V1_NONE`?
+	pub prefer_wasm32v1_none: bool,
+	/// The toolchain to build the runtime with, takes precedence over `WASM_BUILD_TOOLCHAIN`.
+	pub toolchain: Option<String>,
+	/// The `cargo` binary to build with, bypasses the selection of the toolchain.
+	pub cargo: Option<PathBuf>,
+	/// The `rustc` binary to build with, bypasses the selection of the toolchain.
+	pub rustc: Option<PathBuf>,
+	/// The preference between the installed toolchains, if not overridden by
+	/// `WASM_BUILD_TOOLCHAIN_POLICY`.
+	pub toolchain_policy: ToolchainPolicy,
+	/// Should the `toolchain` be installed if it is missing?
+	pub install_missing_toolchain: bool,
+	/// Should a missing wasm target of the toolchain be installed?
+	pub install_missing_target: bool,
+	/// Should a missing `rust-src` component of the toolchain be installed?
+	pub install_missing_rust_src: bool,
+	/// The oldest version of `rustc` the runtime may be built with.
+	pub min_rustc_version: Option<Version>,
+	/// The level `wasm-opt` optimizes the runtime with while compacting it.
+	pub wasm_opt_level: Option<OptLevel>,
+	/// Patterns of the custom sections that are removed from the compacted runtime.
+	pub strip_custom_sections: Vec<String>,
+	/// Should the DWARF debug info be written to a separate file?
+	pub split_debug_info: bool,
+	/// Should the name section be kept in release builds?
+	pub keep_name_section: bool,
+	/// The link time optimization of the `release` and `production` profiles.
+	pub lto: Option<Lto>,
+	/// The number of codegen units of the `release` and `production` profiles.
+	pub codegen_units: Option<u32>,
+	/// Should everything that could make the build output differ between machines be pinned?
+	pub deterministic: bool,
+	/// Should the `build_info` section be added to the wasm binary?
+	pub embed_build_info: bool,
+	/// Should the `build_info` section contain the time of the build?
+	pub build_info_timestamp: bool,
+	/// Should the `digest.json` of the build be written?
+	pub generate_digest: bool,
+	/// Should the code size report of the wasm binary be written?
+	pub emit_size_report: bool,
+	/// Should the wasm build be profiled with `cargo --timings`, if not overridden by
+	/// `WASM_BUILD_TIMINGS`?
+	pub emit_timings: bool,
+	/// Should the `RUSTC_WRAPPER` of the outer build be used, if not overridden by
+	/// `WASM_BUILD_USE_RUSTC_WRAPPER`?
+	pub use_rustc_wrapper: bool,
+	/// How the `rustc` environment variables of the outer build are passed to the wasm build.
+	pub rustc_env: BTreeMap<RustcEnvVar, EnvPolicy>,
+	/// Should the runtime binary built by cargo be cached, if not overridden by
+	/// `WASM_BUILD_ARTIFACT_CACHE`?
+	pub artifact_cache: bool,
+	/// The shared backend of the artifact cache.
+	pub artifact_cache_backend: Option<Box<dyn ArtifactCache>>,
+	/// Should the target directory be shared with the other runtimes, if not overridden by
+	/// `WASM_BUILD_SHARED_TARGET_DIR`?
+	pub shared_target_dir: bool,
+	/// Should `dev` builds be incremental, if not overridden by `WASM_BUILD_INCREMENTAL`?
+	pub incremental: bool,
+	/// Should the build be locked, if not overridden by `WASM_BUILD_LOCKED`?
+	pub locked: bool,
+	/// Should the runtime be built from its own manifest instead of a generated project?
+	pub build_in_place: bool,
+	/// Should the runtime only be type-checked, if not overridden by `WASM_BUILD_CHECK_ONLY`?
+	pub check_only: bool,
+	/// Glob patterns of the paths within the packages that are not watched for changes.
+	pub rerun_exclusions: Vec<String>,
+	/// The environment variables that rebuild the runtime when they change.
+	pub tracked_env_vars: Vec<String>,
+	/// How much is printed about the build.
+	pub verbosity: Verbosity,
+	/// The key to sign the wasm binary with.
+	#[cfg(feature = "signing")]
+	pub signing_key: Option<crate::SigningKey>,
+	/// The maximum size of the runtime blob, before and after the compression.
+	pub blob_size_limit: Option<usize>,
+	/// The policy the bloaty wasm binary is validated against.
+	pub validation_policy: Option<ValidationPolicy>,
+	/// The functions the wasm binary needs to export.
+	pub required_exports: Vec<String>,
+	/// Patterns of the host functions the wasm binary is allowed to import.
+	pub allowed_host_functions: Option<Vec<String>>,
+	/// Should the wasm binary import its memory or, if `false`, export it?
+	pub import_memory: Option<bool>,
+	/// Should the text format of the wasm binary be written next to it?
+	#[cfg(feature = "wasmprinter")]
+	pub emit_wat: bool,
+	/// Hooks that are called in the wasm project directory before the runtime is built.
+	pub before_build: Vec<Box<dyn Fn(&Path) + Send + Sync>>,
+	/// Hooks that are called after the runtime was built.
+	pub on_build_complete: Vec<Box<dyn Fn(&BuildArtifacts) + Send + Sync>>,
+	/// The maximum number of targets that are built concurrently.
+	pub max_parallel: usize,
+	/// The maximum number of cargo invocations that run concurrently, also counting the ones of
+	/// the other builders of `WasmBuilder::try_build_all`.
+	pub concurrent_builds: usize,
+	/// The configuration of the profile-guided optimization.
+	pub pgo: Option<PgoConfig>,
+	/// Whether to generate the metadata hash.
+	#[cfg(feature = "metadata-hash")]
+	pub enable_metadata_hash: Option<MetadataHashConfig>,
+}
+
+impl BuildOptions {
+	/// Returns whether the standard library needs to be compiled for the given `target`.
+	pub fn build_std_required(
+		&self,
+		target: RuntimeTarget,
+		cargo_cmd: &CargoCommand,
+	) -> Result<bool, WasmBuilderError> {
+		// The precompiled standard library of `wasm32v1-none` already only uses the MVP feature
+		// set, so it only needs to be built if explicitly requested.
+		let build_std = match self.uses_wasm32v1_none(target, cargo_cmd)? {
+			true => Some(self.build_std.unwrap_or(false)),
+			false => self.build_std,
+		};
+
+		// For custom targets there is no precompiled standard library at all.
+		Ok(self.custom_target_spec.is_some() || crate::build_std_required(target, build_std)?)
+	}
+
+	/// Returns whether the runtime for the given `target` is built for `wasm32v1-none` instead of
+	/// `wasm32-unknown-unknown`.
+	///
+	/// This is only the case if it is enabled, no custom target specification is used and the
+	/// toolchain of `cargo_cmd` provides the target.
+	pub fn uses_wasm32v1_none(
+		&self,
+		target: RuntimeTarget,
+		cargo_cmd: &CargoCommand,
+	) -> Result<bool, WasmBuilderError> {
+		let enabled = crate::get_bool_environment_variable(crate::WASM_BUILD_WASM32V1_NONE)?
+			.unwrap_or(self.prefer_wasm32v1_none);
+
+		Ok(enabled &&
+			target == RuntimeTarget::Wasm &&
+			self.custom_target_spec.is_none() &&
+			cargo_cmd.supports_target(crate::WASM32V1_NONE_TARGET))
+	}
+
+	/// Returns whether the build should be profiled with `cargo --timings`.
+	fn timings_enabled(&self) -> Result<bool, WasmBuilderError> {
+		Ok(crate::get_bool_environment_variable(crate::WASM_BUILD_TIMINGS)?
+			.unwrap_or(self.emit_timings))
+	}
+
+	/// Returns the target directory of cargo for building the runtime `project`.
+	///
+	/// This is the `target` directory of the project or, if shared, the `target` directory in the
+	/// build subdirectory that contains the projects of all runtimes.
+	fn cargo_target_dir(&self, project: &Path) -> Result<PathBuf, WasmBuilderError> {
+		let shared = crate::get_bool_environment_variable(crate::WASM_BUILD_SHARED_TARGET_DIR)?
+			.unwrap_or(self.shared_target_dir);
+
+		Ok(match project.parent() {
+			Some(runtime_workspace) if shared => runtime_workspace.join("target"),
+			_ => project.join("target"),
+		})
+	}
+
+	/// Returns whether the build with the given `profile` is incremental.
+	fn incremental_enabled(&self, profile: &Profile) -> Result<bool, WasmBuilderError> {
+		// Incremental builds are only used for the `dev` profile, to not risk the determinism of
+		// the builds that are deployed.
+		Ok(*profile == Profile::Debug &&
+			crate::get_bool_environment_variable(crate::WASM_BUILD_INCREMENTAL)?
+				.unwrap_or(self.incremental))
+	}
+
+	/// Returns the cargo configuration file that is passed to the cargo commands.
+	fn cargo_config(&self) -> Result<Option<PathBuf>, WasmBuilderError> {
+		let Some(path) = env::var_os(crate::WASM_BUILD_CARGO_CONFIG)
+			.map(PathBuf::from)
+			.or_else(|| self.cargo_config.clone())
+		else {
+			return Ok(None)
+		};
+
+		if !path.is_file() {
+			return Err(WasmBuilderError::InvalidConfiguration(format!(
+				"The cargo configuration file `{}` does not exist.",
+				path.display()
+			)))
+		}
+
+		Ok(Some(path))
+	}
+
+	/// Returns the linker the runtime is linked with, if it is not inferred by cargo.
+	fn linker(&self) -> Option<String> {
+		env::var(crate::WASM_BUILD_LINKER)
+			.ok()
+			.filter(|linker| !linker.is_empty())
+			.or_else(|| self.linker.clone())
+	}
+
+	/// Returns the linker flavor the runtime is linked with, if it is not inferred by `rustc`.
+	fn linker_flavor(&self) -> Option<String> {
+		env::var(crate::WASM_BUILD_LINKER_FLAVOR)
+			.ok()
+			.filter(|flavor| !flavor.is_empty())
+			.or_else(|| self.linker_flavor.clone())
+	}
+
+	/// Returns whether the standard library is built with the stable `--build-std` flag of cargo
+	/// instead of `-Z build-std`.
+	fn stable_build_std(&self, cargo_cmd: &CargoCommand) -> Result<bool, WasmBuilderError> {
+		Ok(match BuildStdMode::from_env()?.unwrap_or(self.build_std_mode) {
+			BuildStdMode::Detect => cargo_cmd.supports_stable_build_std(),
+			BuildStdMode::Unstable => false,
+			BuildStdMode::Stable => true,
+		})
+	}
+
+	/// Returns the prebuilt sysroot that replaces building the standard library.
+	pub(crate) fn sysroot(&self) -> Option<PathBuf> {
+		env::var_os(crate::WASM_BUILD_SYSROOT)
+			.filter(|path| !path.is_empty())
+			.map(PathBuf::from)
+			.or_else(|| self.sysroot.clone())
+	}
+
+	/// Returns whether the runtime should only be type-checked.
+	pub(crate) fn check_only_enabled(&self) -> Result<bool, WasmBuilderError> {
+		Ok(crate::get_bool_environment_variable(crate::WASM_BUILD_CHECK_ONLY)?
+			.unwrap_or(self.check_only))
+	}
+
+	/// Returns whether the `cargo` or `rustc` binary to build with is given explicitly.
+	pub(crate) fn explicit_toolchain(&self) -> bool {
+		self.cargo.is_some() || self.rustc.is_some()
+	}
+
+	/// Returns whether a missing wasm target of the toolchain is installed with `rustup`.
+	pub(crate) fn install_missing_target_enabled(&self) -> Result<bool, WasmBuilderError> {
+		// A hermetic build only uses the toolchain of its manifest as it is, like an explicitly
+		// given toolchain.
+		Ok(crate::hermetic::get().is_none() &&
+			!self.explicit_toolchain() &&
+			crate::rustup_available()? &&
+			crate::get_bool_environment_variable(crate::WASM_BUILD_AUTO_INSTALL_TARGET)?
+				.unwrap_or(self.install_missing_target))
+	}
+
+	/// Returns whether a missing `rust-src` component of the toolchain is installed with `rustup`.
+	pub(crate) fn install_missing_rust_src_enabled(&self) -> Result<bool, WasmBuilderError> {
+		// A hermetic build only uses the toolchain of its manifest as it is, like an explicitly
+		// given toolchain.
+		Ok(crate::hermetic::get().is_none() &&
+			!self.explicit_toolchain() &&
+			crate::rustup_available()? &&
+			crate::get_bool_environment_variable(crate::WASM_BUILD_AUTO_INSTALL_RUST_SRC)?
+				.unwrap_or(self.install_missing_rust_src))
+	}
+
+	/// Returns whether cargo should fail instead of updating the `Cargo.lock`.
+	fn locked_enabled(&self) -> Result<bool, WasmBuilderError> {
+		// A deterministic build must not pick different versions of the dependencies.
+		Ok(self.deterministic ||
+			crate::get_bool_environment_variable(crate::WASM_BUILD_LOCKED)?
+				.unwrap_or(self.locked))
+	}
+
+	/// Returns whether the runtime binary built by cargo is cached in the artifact cache.
+	pub(crate) fn artifact_cache_enabled(&self) -> Result<bool, WasmBuilderError> {
+		// A hermetic build only writes the outputs declared in its manifest.
+		Ok(crate::hermetic::get().is_none() &&
+			crate::get_bool_environment_variable(crate::WASM_BUILD_ARTIFACT_CACHE)?
+				.unwrap_or(self.artifact_cache))
+	}
+
+	/// Returns the preference between the installed toolchains.
+	pub(crate) fn toolchain_policy(&self) -> Result<ToolchainPolicy, WasmBuilderError> {
+		Ok(ToolchainPolicy::from_env()?.unwrap_or(self.toolchain_policy))
+	}
+
+	/// Returns whether the `RUSTC_WRAPPER` of the outer build is used for the wasm build.
+	fn rustc_wrapper_enabled(&self) -> Result<bool, WasmBuilderError> {
+		Ok(crate::get_bool_environment_variable(crate::WASM_BUILD_USE_RUSTC_WRAPPER)?
+			.unwrap_or(self.use_rustc_wrapper))
+	}
+
+	/// Returns how the `rustc` environment variable `var` of the outer build is passed to the wasm
+	/// build, `None` if it is left as set up for the toolchain.
+	fn rustc_env_policy(&self, var: RustcEnvVar) -> Result<Option<EnvPolicy>, WasmBuilderError> {
+		if let Some(policy) = self.rustc_env.get(&var) {
+			return Ok(Some(policy.clone()))
+		}
+
+		Ok(match var {
+			// `RUSTC` is set for toolchains that are not managed by rustup and removed otherwise.
+			RustcEnvVar::Rustc => None,
+			RustcEnvVar::RustcWrapper if self.rustc_wrapper_enabled()? => Some(EnvPolicy::Inherit),
+			RustcEnvVar::RustcWrapper => Some(EnvPolicy::Clear),
+			RustcEnvVar::RustcWorkspaceWrapper => None,
+		})
+	}
+
+	/// Returns the target triple the runtime for the given `target` is built for.
+	pub(crate) fn target_triple(
+		&self,
+		target: RuntimeTarget,
+		cargo_cmd: &CargoCommand,
+	) -> Result<&'static str, WasmBuilderError> {
+		Ok(match self.uses_wasm32v1_none(target, cargo_cmd)? {
+			true => crate::WASM32V1_NONE_TARGET,
+			false => target.rustc_target(),
+		})
+	}
+
+	/// Returns the value of `--target` for building the runtime for the given `target`.
+	fn target_arg(
+		&self,
+		target: RuntimeTarget,
+		cargo_cmd: &CargoCommand,
+	) -> Result<String, WasmBuilderError> {
+		match &self.custom_target_spec {
+			Some(spec) => Ok(spec.display().to_string()),
+			None => self.target_triple(target, cargo_cmd).map(Into::into),
+		}
+	}
+
+	/// Returns the name of the directory cargo puts the artifacts of the given `target` into.
+	///
+	/// For a custom target specification this is the file stem of the specification.
+	fn target_dir_name(
+		&self,
+		target: RuntimeTarget,
+		cargo_cmd: &CargoCommand,
+	) -> Result<String, WasmBuilderError> {
+		match self.custom_target_spec.as_ref().and_then(|spec| spec.file_stem()) {
+			Some(stem) => Ok(stem.to_string_lossy().into()),
+			None => self.target_triple(target, cargo_cmd).map(Into::into),
+		}
+	}
+
+	/// Returns the name of the directory the runtime project for the given `target` is put into.
+	fn build_subdirectory(&self, target: RuntimeTarget) -> String {
+		match self.custom_target_spec.as_ref().and_then(|spec| spec.file_stem()) {
+			// Keep the projects of the custom targets separate from the builtin ones.
+			Some(stem) => format!("{}-{}", target.build_subdirectory(), stem.to_string_lossy()),
+			None => target.build_subdirectory().into(),
+		}
+	}
+}
+
+/// Creates the WASM project and type-checks the runtime with `cargo check`.
 ///
-/// The path to the compact runtime binary and the bloaty runtime binary.
-pub(crate) fn create_and_compile(
+/// No code is generated, so there is no binary to return.
+pub(crate) fn create_and_check(
 	target: RuntimeTarget,
 	orig_project_cargo_toml: &Path,
 	default_rustflags: &str,
 	cargo_cmd: CargoCommandVersioned,
-	features_to_enable: Vec<String>,
-	blob_out_name_override: Option<String>,
-	check_for_runtime_version_section: bool,
-	#[cfg(feature = "metadata-hash")] enable_metadata_hash: Option<MetadataExtraInfo>,
-) -> (Option<WasmBinary>, WasmBinaryBloaty) {
-	let runtime_workspace_root = get_wasm_workspace_root();
-	let runtime_workspace = runtime_workspace_root.join(target.build_subdirectory());
-
-	let crate_metadata = crate_metadata(orig_project_cargo_toml);
+	options: &BuildOptions,
+) -> Result<(), WasmBuilderError> {
+	let runtime_workspace = get_wasm_workspace_root()?.join(options.build_subdirectory(target));
+	let crate_metadata = crate_metadata(orig_project_cargo_toml)?;
 
-	let project = create_project(
+	let (project, enabled_features) = create_project(
 		target,
 		orig_project_cargo_toml,
 		&runtime_workspace,
 		&crate_metadata,
 		crate_metadata.workspace_root.as_ref(),
-		features_to_enable,
+		options,
+	)?;
+
+	options.before_build.iter().for_each(|hook| hook(&project));
+
+	let build_config =
+		BuildConfiguration::detect(&options.build_subdirectory(target), &project, options.profile)?;
+	let manifest = RuntimeManifest::new(
+		&project,
+		orig_project_cargo_toml,
+		build_config.blob_build_profile,
+		&enabled_features,
+		options,
 	);
-	let wasm_project_cargo_toml = project.join("Cargo.toml");
+	let rustflags = runtime_rustflags(target, default_rustflags, options);
 
-	let build_config = BuildConfiguration::detect(target, &project);
+	let mut check_cmd = runtime_cargo_command(
+		"check",
+		target,
+		&build_config.blob_build_profile,
+		&project,
+		&manifest,
+		&rustflags,
+		options,
+		&cargo_cmd,
+	)?;
+	run_cargo_command(&mut check_cmd, target, &manifest.path, options, &cargo_cmd)?;
 
-	#[cfg(feature = "metadata-hash")]
-	let raw_blob_path = match enable_metadata_hash {
-		Some(extra_info) => {
-			// When the metadata hash is enabled we need to build the runtime twice.
-			let raw_blob_path = build_bloaty_blob(
-				target,
-				&build_config.blob_build_profile,
-				&project,
-				default_rustflags,
-				cargo_cmd.clone(),
-				None,
-			);
+	generate_rerun_if_changed_instructions(
+		orig_project_cargo_toml,
+		&manifest.path,
+		// Custom target specifications are not supported by `--filter-platform`.
+		options.custom_target_spec.is_none().then(|| target.rustc_target()),
+		options.cargo_config()?.as_deref(),
+		&options.rerun_exclusions,
+		&options.tracked_env_vars,
+		options.verbosity,
+		None,
+		None,
+	)?;
+	options.custom_target_spec.iter().for_each(rerun_if_changed);
+	options.cargo_config()?.iter().for_each(rerun_if_changed);
 
-			let hash = crate::metadata_hash::generate_metadata_hash(&raw_blob_path, extra_info);
+	Ok(())
+}
 
-			build_bloaty_blob(
-				target,
-				&build_config.blob_build_profile,
+/// Creates the WASM project in the `subdirectory` of the build subdirectory, then compiles and
+/// compacts the runtime like [`create_and_compile`] to verify that it can be reproduced.
+///
+/// The project and its target directory are separate from the ones of the normal build. Only the
+/// steps that change the blob are run, so nothing is validated, copied to the
+/// `WASM_TARGET_DIRECTORY`, cached, signed or recorded, and no hooks are called.
+///
+/// Returns the path to the final runtime binary and the version of `rustc` it was built with.
+pub(crate) fn compile_for_verification(
+	target: RuntimeTarget,
+	orig_project_cargo_toml: &Path,
+	default_rustflags: &str,
+	cargo_cmd: CargoCommandVersioned,
+	options: &BuildOptions,
+	subdirectory: &str,
+) -> Result<(PathBuf, String), WasmBuilderError> {
+	let rustc_version = cargo_cmd.rustc_version().to_owned();
+	let runtime_workspace = get_wasm_workspace_root()?
+		.join(options.build_subdirectory(target))
+		.join(subdirectory);
+	let crate_metadata = crate_metadata(orig_project_cargo_toml)?;
+
+	let (project, enabled_features) = create_project(
+		target,
+		orig_project_cargo_toml,
+		&runtime_workspace,
+		&crate_metadata,
+		crate_metadata.workspace_root.as_ref(),
+		options,
+	)?;
+
+	let build_config =
+		BuildConfiguration::detect(&options.build_subdirectory(target), &project, options.profile)?;
+	let manifest = RuntimeManifest::new(
+		&project,
+		orig_project_cargo_toml,
+		build_config.blob_build_profile,
+		&enabled_features,
+		options,
+	);
+	let mut rustflags = runtime_rustflags(target, default_rustflags, options);
+	if options.deterministic {
+		rustflags.push_str(&remap_path_prefix_rustflags(
+			crate_metadata.workspace_root.as_ref(),
+			&runtime_workspace,
+		));
+	}
+	let blob_name = options
+		.blob_out_name_override
+		.clone()
+		.unwrap_or_else(|| get_blob_name(target, &manifest.path));
+
+	let raw_blob_path = build_runtime_blob(
+		target,
+		&build_config.blob_build_profile,
+		&project,
+		&manifest,
+		&mut rustflags,
+		options,
+		cargo_cmd,
+	)?;
+
+	let out_path = match target {
+		RuntimeTarget::Wasm | RuntimeTarget::Wasm64 => project.join(format!("{blob_name}.wasm")),
+		RuntimeTarget::Riscv => project.join(format!("{blob_name}.polkavm")),
+	};
+	fs::copy(&raw_blob_path, &out_path).map_err(WasmBuilderError::io(&out_path))?;
+
+	let final_blob_path = match target {
+		RuntimeTarget::Wasm => {
+			let (final_blob_binary, _) = maybe_compact_and_compress_wasm(
 				&project,
-				default_rustflags,
-				cargo_cmd,
-				Some(hash),
-			)
+				WasmBinaryBloaty(out_path.clone()),
+				&blob_name,
+				options,
+				&build_config,
+			)?;
+			final_blob_binary.map_or(out_path, |binary| binary.wasm_binary_path().to_path_buf())
 		},
-		None => build_bloaty_blob(
-			target,
-			&build_config.blob_build_profile,
-			&project,
-			default_rustflags,
-			cargo_cmd,
-			None,
-		),
+		RuntimeTarget::Wasm64 | RuntimeTarget::Riscv => out_path,
 	};
 
-	// If the feature is not enabled, we only need to do it once.
-	#[cfg(not(feature = "metadata-hash"))]
-	let raw_blob_path = {
-		build_bloaty_blob(
+	Ok((final_blob_path, rustc_version))
+}
+
+/// Creates the WASM project, compiles the WASM binary and compacts the WASM binary.
+///
+/// # Returns
+///
+/// The path to the compact runtime binary, the bloaty runtime binary and the information about
+/// how they were built.
+pub(crate) fn create_and_compile(
+	target: RuntimeTarget,
+	orig_project_cargo_toml: &Path,
+	default_rustflags: &str,
+	cargo_cmd: CargoCommandVersioned,
+	options: &BuildOptions,
+) -> Result<(Option<WasmBinary>, WasmBinaryBloaty, BuildInfo), WasmBuilderError> {
+	let mut phases = PhaseTimings::default();
+	let start = Instant::now();
+
+	let rustc_version = cargo_cmd.rustc_version().to_owned();
+	let toolchain = cargo_cmd.toolchain().to_owned();
+	let std_from_source = options.build_std_required(target, &cargo_cmd)?;
+	let runtime_workspace_root = get_wasm_workspace_root()?;
+	let runtime_workspace = runtime_workspace_root.join(options.build_subdirectory(target));
+
+	let crate_metadata = crate_metadata(orig_project_cargo_toml)?;
+
+	let (project, enabled_features) = create_project(
+		target,
+		orig_project_cargo_toml,
+		&runtime_workspace,
+		&crate_metadata,
+		crate_metadata.workspace_root.as_ref(),
+		options,
+	)?;
+
+	options.before_build.iter().for_each(|hook| hook(&project));
+	phases.project_generation = start.elapsed();
+
+	let start = Instant::now();
+	let build_config =
+		BuildConfiguration::detect(&options.build_subdirectory(target), &project, options.profile)?;
+	let manifest = RuntimeManifest::new(
+		&project,
+		orig_project_cargo_toml,
+		build_config.blob_build_profile,
+		&enabled_features,
+		options,
+	);
+	let mut rustflags = runtime_rustflags(target, default_rustflags, options);
+	if options.deterministic {
+		rustflags.push_str(&remap_path_prefix_rustflags(
+			crate_metadata.workspace_root.as_ref(),
+			&runtime_workspace,
+		));
+	}
+
+	let blob_name = options
+		.blob_out_name_override
+		.clone()
+		.unwrap_or_else(|| get_blob_name(target, &manifest.path));
+
+	// Running cargo takes a while even if nothing changed, so skip it if the fingerprint of the
+	// last build still matches.
+	let fingerprint = (env::var_os(crate::FORCE_WASM_BUILD_ENV).is_none() &&
+		crate::hermetic::get().is_none() &&
+		options.before_build.is_empty() &&
+		options.pgo.is_none())
+	.then(|| {
+		cargo_build_inputs(
 			target,
+			&manifest,
 			&build_config.blob_build_profile,
-			&project,
-			default_rustflags,
-			cargo_cmd,
+			&rustflags,
+			options,
+			&cargo_cmd,
 		)
+		.map(|inputs| {
+			Fingerprint::new(
+				target,
+				&blob_name,
+				&inputs.iter().map(Vec::as_slice).collect::<Vec<_>>(),
+				&options.tracked_env_vars,
+			)
+		})
+	})
+	.transpose()?;
+	let build_start = SystemTime::now();
+
+	let cache_backend = options.artifact_cache_backend.as_deref();
+	let raw_blob_path = match fingerprint.as_ref().and_then(Fingerprint::unchanged_blob) {
+		Some(unchanged_blob_path) => {
+			if options.verbosity >= Verbosity::Normal {
+				println!(
+					"{} {}",
+					colorize_info_message("Nothing changed since the last build of:"),
+					unchanged_blob_path.display()
+				);
+			}
+			unchanged_blob_path
+		},
+		None => {
+			// Only hash the sources when the fingerprint doesn't match.
+			let cache_key = artifact_cache_key(
+				target,
+				orig_project_cargo_toml,
+				&project,
+				crate_metadata.workspace_root.as_ref(),
+				&runtime_workspace,
+				&manifest,
+				&build_config.blob_build_profile,
+				&rustflags,
+				options,
+				&cargo_cmd,
+			)?;
+			match cache_key
+				.as_deref()
+				.and_then(|key| crate::artifact_cache::lookup(key, target, cache_backend))
+			{
+				Some(cached_blob_path) => {
+					if options.verbosity >= Verbosity::Normal {
+						println!(
+							"{} {}",
+							colorize_info_message("Using the cached runtime binary:"),
+							cached_blob_path.display()
+						);
+					}
+					cached_blob_path
+				},
+				None => {
+					let raw_blob_path = build_runtime_blob(
+						target,
+						&build_config.blob_build_profile,
+						&project,
+						&manifest,
+						&mut rustflags,
+						options,
+						cargo_cmd,
+					)?;
+					if let Some(key) = &cache_key {
+						crate::artifact_cache::store(key, &raw_blob_path, cache_backend);
+					}
+					raw_blob_path
+				},
+			}
+		},
 	};
+	phases.compilation = start.elapsed();
 
-	let blob_name =
-		blob_out_name_override.unwrap_or_else(|| get_blob_name(target, &wasm_project_cargo_toml));
+	let start = Instant::now();
+	let out_path = match target {
+		RuntimeTarget::Wasm | RuntimeTarget::Wasm64 => project.join(format!("{blob_name}.wasm")),
+		RuntimeTarget::Riscv => project.join(format!("{blob_name}.polkavm")),
+	};
+	fs::copy(&raw_blob_path, &out_path).map_err(WasmBuilderError::io(&out_path))?;
+	phases.copying = start.elapsed();
 
+	let start = Instant::now();
 	let (final_blob_binary, bloaty_blob_binary) = match target {
 		RuntimeTarget::Wasm => {
-			let out_path = project.join(format!("{blob_name}.wasm"));
-			fs::copy(raw_blob_path, &out_path).expect("copying the runtime blob should never fail");
+			if let Some(policy) = &options.validation_policy {
+				crate::validation::validate(&out_path, policy)?;
+			}
+			if !options.required_exports.is_empty() {
+				crate::validation::check_required_exports(&out_path, &options.required_exports)?;
+			}
+			if let Some(allowed) = &options.allowed_host_functions {
+				crate::validation::check_host_functions(&out_path, allowed)?;
+			}
+			if let Some(import) = options.import_memory {
+				crate::validation::check_memory(&out_path, import)?;
+			}
+			if !options.disabled_target_features.is_empty() {
+				crate::validation::check_target_features(
+					&out_path,
+					&options.disabled_target_features,
+					std_from_source,
+				)?;
+			}
+			if options.emit_size_report {
+				crate::size_report::write_size_report(&out_path, &blob_name, &crate::out_dir())?;
+			}
+			if options.embed_build_info {
+				add_build_info_section(
+					&out_path,
+					crate_metadata.workspace_root.as_ref(),
+					&rustc_version,
+					build_config.blob_build_profile,
+					&enabled_features,
+					options.build_info_timestamp && !options.deterministic,
+				)?;
+			}
+			#[cfg(feature = "wasmprinter")]
+			if options.emit_wat {
+				write_wat(&out_path)?;
+			}
 
 			maybe_compact_and_compress_wasm(
-				&wasm_project_cargo_toml,
 				&project,
 				WasmBinaryBloaty(out_path),
 				&blob_name,
-				check_for_runtime_version_section,
+				options,
 				&build_config,
-			)
-		},
-		RuntimeTarget::Riscv => {
-			let out_path = project.join(format!("{blob_name}.polkavm"));
-			fs::copy(raw_blob_path, &out_path).expect("copying the runtime blob should never fail");
-			(None, WasmBinaryBloaty(out_path))
+			)?
 		},
+		// The post-processing only supports 32 bit WASM, so the binary is used as it is.
+		RuntimeTarget::Wasm64 | RuntimeTarget::Riscv => (None, WasmBinaryBloaty(out_path)),
 	};
+	phases.post_processing = start.elapsed();
 
-	generate_rerun_if_changed_instructions(
+	let start = Instant::now();
+	if let Some(binary) = final_blob_binary.as_ref() {
+		copy_blob_to_target_directory(&manifest.path, binary, options.verbosity)?;
+	}
+	phases.copying += start.elapsed();
+	write_phase_timings(&phases, target, &blob_name, options.verbosity)?;
+
+	let mut sources = generate_rerun_if_changed_instructions(
 		orig_project_cargo_toml,
-		&project,
-		&runtime_workspace,
+		&manifest.path,
+		// Custom target specifications are not supported by `--filter-platform`.
+		options.custom_target_spec.is_none().then(|| target.rustc_target()),
+		options.cargo_config()?.as_deref(),
+		&options.rerun_exclusions,
+		&options.tracked_env_vars,
+		options.verbosity,
 		final_blob_binary.as_ref(),
-		&bloaty_blob_binary,
+		Some(&bloaty_blob_binary),
+	)?;
+	options.custom_target_spec.iter().for_each(rerun_if_changed);
+	options.cargo_config()?.iter().for_each(rerun_if_changed);
+	sources.extend(options.custom_target_spec.iter().cloned().chain(options.cargo_config()?));
+
+	if let Some(fingerprint) = &fingerprint {
+		fingerprint.write(&raw_blob_path, &sources, build_start)?;
+	}
+
+	// A hermetic build is not rerun by cargo and the build system tracks the inputs itself.
+	if crate::hermetic::get().is_none() {
+		crate::depfile::write_depfiles(
+			target,
+			&blob_name,
+			&final_blob_binary
+				.iter()
+				.map(|binary| binary.wasm_binary_path())
+				.chain([bloaty_blob_binary.bloaty_path()])
+				.collect::<Vec<_>>(),
+			&sources,
+			&options.tracked_env_vars,
+		)?;
+
+		if let Err(err) = adjust_mtime(&bloaty_blob_binary, final_blob_binary.as_ref()) {
+			build_helper::warning!("Error while adjusting the mtime of the blob binaries: {}", err)
+		}
+	}
+
+	let artifacts = BuildArtifacts {
+		target,
+		bloaty_path: bloaty_blob_binary.bloaty_path().to_path_buf(),
+		compact_path: final_blob_binary.as_ref().map(|b| b.wasm_binary_path().to_path_buf()),
+		debug_path: (final_blob_binary.is_some() && options.split_debug_info)
+			.then(|| debug_info_path(&project, &blob_name)),
+		profile: build_config.blob_build_profile,
+		rustc_version: rustc_version.clone(),
+	};
+	options.on_build_complete.iter().for_each(|hook| hook(&artifacts));
+
+	let build_info = BuildInfo {
+		target,
+		rustc_version,
+		toolchain,
+		profile: build_config.blob_build_profile,
+		features: enabled_features,
+		rustflags: rustflags.replace(RUSTFLAGS_SPACE, " "),
+		bloaty_blake2_256: blake2_256_of_file(bloaty_blob_binary.bloaty_path())?,
+		compact_blake2_256: final_blob_binary
+			.as_ref()
+			.map(|binary| blake2_256_of_file(binary.wasm_binary_path()))
+			.transpose()?,
+		runtime_version: match target {
+			RuntimeTarget::Wasm =>
+				crate::runtime_version::read_runtime_version(bloaty_blob_binary.bloaty_path())?,
+			RuntimeTarget::Wasm64 | RuntimeTarget::Riscv => None,
+		},
+		#[cfg(feature = "signing")]
+		signature: match (&options.signing_key, target) {
+			(Some(key), RuntimeTarget::Wasm) => Some(crate::signing::sign(
+				final_blob_binary
+					.as_ref()
+					.map_or(bloaty_blob_binary.bloaty_path(), |binary| binary.wasm_binary_path()),
+				key,
+			)?),
+			_ => None,
+		},
+		#[cfg(not(feature = "signing"))]
+		signature: None,
+	};
+
+	if options.generate_digest && target == RuntimeTarget::Wasm {
+		write_digest(
+			&project,
+			&blob_name,
+			&get_crate_name(orig_project_cargo_toml),
+			&build_info,
+			final_blob_binary.as_ref(),
+			options,
+		)?;
+	}
+
+	Ok((final_blob_binary, bloaty_blob_binary, build_info))
+}
+
+/// How long the phases of building a runtime took.
+#[derive(Default)]
+struct PhaseTimings {
+	/// Generating the project the runtime is built in, including the `before_build` hooks.
+	project_generation: Duration,
+	/// Compiling the runtime with cargo or fetching it from the artifact cache.
+	compilation: Duration,
+	/// Validating, compacting and compressing the runtime.
+	post_processing: Duration,
+	/// Copying the runtime out of the target directory and to `WASM_TARGET_DIRECTORY`.
+	copying: Duration,
+}
+
+/// Print the `phases` of building the runtime and write them to
+/// `OUT_DIR/wasm-build-phases-<blob_name>-<target>.json`.
+fn write_phase_timings(
+	phases: &PhaseTimings,
+	target: RuntimeTarget,
+	blob_name: &str,
+	verbosity: Verbosity,
+) -> Result<(), WasmBuilderError> {
+	let phases = [
+		("project_generation", phases.project_generation),
+		("compilation", phases.compilation),
+		("post_processing", phases.post_processing),
+		("copying", phases.copying),
+	];
+
+	if verbosity >= Verbosity::Normal {
+		println!(
+			"{} {}",
+			colorize_info_message("Build phases:"),
+			phases
+				.iter()
+				.map(|(phase, duration)| format!("{} {duration:.2?}", phase.replace('_', " ")))
+				.collect::<Vec<_>>()
+				.join(", ")
+		);
+	}
+
+	let json = serde_json::Value::Object(
+		phases
+			.iter()
+			.map(|(phase, duration)| ((*phase).into(), duration.as_secs_f64().into()))
+			.collect(),
 	);
+	write_file_if_changed(
+		crate::out_dir()
+			.join(format!("wasm-build-phases-{blob_name}-{}.json", target.rustc_target())),
+		serde_json::to_string_pretty(&json).expect("The build phases are valid JSON; qed"),
+	)
+}
 
-	if let Err(err) = adjust_mtime(&bloaty_blob_binary, final_blob_binary.as_ref()) {
-		build_helper::warning!("Error while adjusting the mtime of the blob binaries: {}", err)
+/// The manifest cargo builds the runtime from.
+struct RuntimeManifest {
+	/// The path to the `Cargo.toml`.
+	path: PathBuf,
+	/// The path to the `Cargo.lock` used by the build.
+	cargo_lock: Option<PathBuf>,
+	/// The arguments cargo needs to build the runtime from this manifest.
+	cargo_args: Vec<String>,
+}
+
+impl RuntimeManifest {
+	/// Returns the manifest of the generated `project` or, if built in place, of the runtime
+	/// itself.
+	fn new(
+		project: &Path,
+		orig_project_cargo_toml: &Path,
+		profile: Profile,
+		enabled_features: &[String],
+		options: &BuildOptions,
+	) -> Self {
+		if !options.build_in_place {
+			return Self {
+				path: project.join("Cargo.toml"),
+				cargo_lock: Some(project.join("Cargo.lock")),
+				cargo_args: Vec::new(),
+			}
+		}
+
+		let mut cargo_args = vec![
+			"--lib".to_owned(),
+			"--crate-type=cdylib".to_owned(),
+			"--no-default-features".to_owned(),
+		];
+		if !enabled_features.is_empty() {
+			cargo_args.push(format!("--features={}", enabled_features.join(",")));
+		}
+
+		// Apply the settings of the profiles of the generated project to the workspace profile.
+		let name = profile.name();
+		let mut settings = vec![("panic", toml::Value::from("abort"))];
+		if profile == Profile::Production {
+			settings.push(("inherits", "release".into()));
+		}
+		if let Some(lto) = options.lto {
+			settings.push(("lto", lto.value()));
+		}
+		if let Some(units) = options.codegen_units.or(options.deterministic.then_some(1)) {
+			settings.push(("codegen-units", units.into()));
+		}
+		if options.keep_name_section {
+			settings.push(("strip", false.into()));
+		}
+		cargo_args.extend(
+			settings
+				.into_iter()
+				.map(|(key, value)| format!("--config=profile.{name}.{key}={value}")),
+		);
+
+		Self {
+			path: orig_project_cargo_toml.to_path_buf(),
+			cargo_lock: find_cargo_lock(orig_project_cargo_toml),
+			cargo_args,
+		}
 	}
+}
 
-	(final_blob_binary, bloaty_blob_binary)
+/// Returns the BLAKE2-256 hash of the file at `path`.
+pub(crate) fn blake2_256_of_file(path: &Path) -> Result<[u8; 32], WasmBuilderError> {
+	let data = fs::read(path).map_err(WasmBuilderError::io(path))?;
+	Ok(sp_crypto_hashing::blake2_256(&data))
 }
 
 fn maybe_compact_and_compress_wasm(
-	wasm_project_cargo_toml: &Path,
 	project: &Path,
 	bloaty_blob_binary: WasmBinaryBloaty,
 	blob_name: &str,
-	check_for_runtime_version_section: bool,
+	options: &BuildOptions,
 	build_config: &BuildConfiguration,
-) -> (Option<WasmBinary>, WasmBinaryBloaty) {
-	// Try to compact and compress the bloaty blob, if the *outer* profile wants it.
+) -> Result<(Option<WasmBinary>, WasmBinaryBloaty), WasmBuilderError> {
+	// Try to compact and compress the bloaty blob, if the *outer* profile wants it and it wasn't
+	// disabled.
 	//
 	// This is because, by default the inner profile will be set to `Release` even when the outer
 	// profile is `Debug`, because the blob built in `Debug` profile is too slow for normal
 	// development activities.
 	let (compact_blob_path, compact_compressed_blob_path) =
-		if build_config.outer_build_profile.wants_compact() {
-			let compact_blob_path = compact_wasm(&project, blob_name, &bloaty_blob_binary);
-			let compact_compressed_blob_path =
-				compact_blob_path.as_ref().and_then(|p| try_compress_blob(&p.0, blob_name));
-			(compact_blob_path, compact_compressed_blob_path)
+		if build_config.outer_build_profile.wants_compact() && !options.disable_compact {
+			let compact_blob_path = compact_wasm(
+				&project,
+				blob_name,
+				&bloaty_blob_binary,
+				options.wasm_opt_level,
+				options.split_debug_info,
+				options.verbosity,
+			)?;
+			if options.split_debug_info {
+				split_debug_info(
+					compact_blob_path.wasm_binary_path(),
+					&debug_info_path(project, blob_name),
+				)?;
+			}
+			if !options.strip_custom_sections.is_empty() {
+				strip_custom_sections(
+					compact_blob_path.wasm_binary_path(),
+					&options.strip_custom_sections,
+				)?;
+			}
+			let compact_compressed_blob_path = try_compress_blob(
+				&compact_blob_path.0,
+				blob_name,
+				options.blob_size_limit,
+				options.verbosity,
+			)?;
+			(Some(compact_blob_path), compact_compressed_blob_path)
 		} else {
 			(None, None)
 		};
 
-	if check_for_runtime_version_section {
-		ensure_runtime_version_wasm_section_exists(bloaty_blob_binary.bloaty_path());
+	if options.check_for_runtime_version_section {
+		ensure_runtime_version_wasm_section_exists(bloaty_blob_binary.bloaty_path())?;
 	}
 
 	let final_blob_binary = compact_compressed_blob_path.or(compact_blob_path);
 
-	final_blob_binary
-		.as_ref()
-		.map(|binary| copy_blob_to_target_directory(wasm_project_cargo_toml, binary));
+	Ok((final_blob_binary, bloaty_blob_binary))
+}
 
-	(final_blob_binary, bloaty_blob_binary)
+/// Returns the path of the file the DWARF debug info of the blob with the given name is split into.
+fn debug_info_path(project: &Path, blob_name: &str) -> PathBuf {
+	project.join(format!("{blob_name}.debug.wasm"))
 }
 
-/// Ensures that the `runtime_version` section exists in the given blob.
+/// Copies the blob at `blob_path` to `debug_path` and strips the DWARF sections from the blob.
 ///
-/// If the section can not be found, it will print an error and exit the builder.
-fn ensure_runtime_version_wasm_section_exists(blob_path: &Path) {
-	let blob = fs::read(blob_path).expect("`{blob_path}` was just written and should exist; qed");
-
-	let module: Module = match deserialize_buffer(&blob) {
-		Ok(m) => m,
-		Err(e) => {
-			println!("Failed to deserialize `{}`: {e:?}", blob_path.display());
-			process::exit(1);
-		},
+/// DWARF refers to code by its offset in the code section, so the copy stays valid for the
+/// stripped blob.
+fn split_debug_info(blob_path: &Path, debug_path: &Path) -> Result<(), WasmBuilderError> {
+	fs::copy(blob_path, debug_path).map_err(WasmBuilderError::io(debug_path))?;
+	strip_custom_sections(blob_path, &[".debug_*".into()])
+}
+
+/// Adds the `build_info` custom section, which describes how the blob was built, to the blob at
+/// `blob_path`.
+///
+/// The section contains a JSON object with the git commit of the `workspace_root`, the
+/// `rustc_version`, the `profile`, the enabled `features` and, if requested, the unix `timestamp`
+/// of the build.
+fn add_build_info_section(
+	blob_path: &Path,
+	workspace_root: &Path,
+	rustc_version: &str,
+	profile: Profile,
+	features: &[String],
+	timestamp: bool,
+) -> Result<(), WasmBuilderError> {
+	let git_commit = Command::new("git")
+		.args(["rev-parse", "HEAD"])
+		.current_dir(workspace_root)
+		.output()
+		.ok()
+		.filter(|output| output.status.success())
+		.map(|output| String::from_utf8_lossy(&output.stdout).trim().to_owned());
+
+	let mut build_info = serde_json::json!({
+		"git_commit": git_commit,
+		"rustc_version": rustc_version.trim(),
+		"profile": profile.name(),
+		"features": features,
+	});
+	if timestamp {
+		let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+		build_info["timestamp"] = now.into();
+	}
+
+	let blob = fs::read(blob_path).map_err(WasmBuilderError::io(blob_path))?;
+	let mut module: Module = deserialize_buffer(&blob).map_err(|e| {
+		WasmBuilderError::InvalidRuntimeBlob(format!(
+			"Failed to deserialize `{}`: {e:?}",
+			blob_path.display()
+		))
+	})?;
+
+	module.set_custom_section("build_info", build_info.to_string().into_bytes());
+
+	serialize_to_file(blob_path, module).map_err(|e| {
+		WasmBuilderError::PostProcessing(format!(
+			"Failed to write `{}` after adding the `build_info` section: {e:?}",
+			blob_path.display()
+		))
+	})
+}
+
+/// Write the `digest.json` of the build into the `project` directory.
+///
+/// The format follows the JSON summary of srtool. The proposal hash is the hash of the
+/// `System::set_code` call with the blob, assuming that `frame_system` is the first pallet.
+fn write_digest(
+	project: &Path,
+	blob_name: &str,
+	package: &str,
+	build_info: &BuildInfo,
+	final_blob_binary: Option<&WasmBinary>,
+	options: &BuildOptions,
+) -> Result<(), WasmBuilderError> {
+	use crate::to_hex;
+	use sp_crypto_hashing::blake2_256;
+
+	let runtime_digest = |path: &Path| -> Result<serde_json::Value, WasmBuilderError> {
+		let blob = fs::read(path).map_err(WasmBuilderError::io(path))?;
+
+		// The pallet and call index of `System::set_code`, followed by the encoded blob.
+		let mut call = vec![0, 2];
+		call.extend(encode_compact_len(blob.len()));
+		call.extend(&blob);
+
+		Ok(serde_json::json!({
+			"size": blob.len().to_string(),
+			"prop": to_hex(&blake2_256(&call)),
+			"blake2_256": to_hex(&blake2_256(&blob)),
+			"wasm": path.display().to_string(),
+		}))
 	};
 
+	let compact_path = final_blob_binary.map(|_| project.join(format!("{blob_name}.compact.wasm")));
+	let compressed_path = final_blob_binary
+		.filter(|binary| binary.is_compressed())
+		.map(|binary| binary.wasm_binary_path());
+
+	let digest = serde_json::json!({
+		"gen": format!("substrate-wasm-builder v{}", env!("CARGO_PKG_VERSION")),
+		"pkg": package,
+		"rustc": build_info.rustc_version.trim(),
+		"profile": build_info.profile.name(),
+		"features": build_info.features,
+		"rustflags": build_info.rustflags,
+		"cargo_args": options.cargo_args,
+		"core_version": build_info.runtime_version.as_ref().map(|version| format!(
+			"{}-{} ({}-{}.tx{}.au{})",
+			version.spec_name,
+			version.spec_version,
+			version.impl_name,
+			version.impl_version,
+			version.transaction_version.unwrap_or_default(),
+			version.authoring_version,
+		)),
+		"runtimes": {
+			"compact": compact_path.as_deref().map(runtime_digest).transpose()?,
+			"compressed": compressed_path.map(runtime_digest).transpose()?,
+		},
+	});
+
+	write_file_if_changed(
+		project.join("digest.json"),
+		serde_json::to_string_pretty(&digest).expect("The digest is valid JSON; qed"),
+	)
+}
+
+/// SCALE encode the given `len` as compact integer.
+fn encode_compact_len(len: usize) -> Vec<u8> {
+	match len {
+		0..=0x3f => vec![(len as u8) << 2],
+		0x40..=0x3fff => (((len as u16) << 2) | 0b01).to_le_bytes().to_vec(),
+		0x4000..=0x3fff_ffff => (((len as u32) << 2) | 0b10).to_le_bytes().to_vec(),
+		// Blobs don't exceed 4 GiB, so the length always fits into 4 bytes.
+		_ => [0b11].into_iter().chain((len as u32).to_le_bytes()).collect(),
+	}
+}
+
+/// Write the text format of the WASM binary at `blob_path` next to it, e.g. `runtime.wat` for
+/// `runtime.wasm`.
+#[cfg(feature = "wasmprinter")]
+fn write_wat(blob_path: &Path) -> Result<(), WasmBuilderError> {
+	let wat = wasmprinter::print_file(blob_path).map_err(|e| {
+		WasmBuilderError::PostProcessing(format!(
+			"Failed to print `{}` in the text format: {e}",
+			blob_path.display()
+		))
+	})?;
+
+	write_file_if_changed(blob_path.with_extension("wat"), wat)
+}
+
+/// Returns whether the section `name` matches one of the `patterns`.
+///
+/// A pattern either matches a name exactly or, if it ends with `*`, every name that starts with
+/// the rest of the pattern.
+pub(crate) fn section_name_matches(name: &str, patterns: &[String]) -> bool {
+	patterns.iter().any(|pattern| match pattern.strip_suffix('*') {
+		Some(prefix) => name.starts_with(prefix),
+		None => name == pattern,
+	})
+}
+
+/// Removes the custom sections whose names match one of the `patterns` from the given blob.
+fn strip_custom_sections(blob_path: &Path, patterns: &[String]) -> Result<(), WasmBuilderError> {
+	let blob = fs::read(blob_path).map_err(WasmBuilderError::io(blob_path))?;
+
+	let mut module: Module = deserialize_buffer(&blob).map_err(|e| {
+		WasmBuilderError::InvalidRuntimeBlob(format!(
+			"Failed to deserialize `{}`: {e:?}",
+			blob_path.display()
+		))
+	})?;
+
+	let matches = |name: &str| section_name_matches(name, patterns);
+	module.sections_mut().retain(|section| match section {
+		Section::Custom(section) => !matches(section.name()),
+		Section::Name(_) => !matches("name"),
+		Section::Reloc(section) => !matches(section.name()),
+		_ => true,
+	});
+
+	serialize_to_file(blob_path, module).map_err(|e| {
+		WasmBuilderError::PostProcessing(format!(
+			"Failed to write `{}` after stripping custom sections: {e:?}",
+			blob_path.display()
+		))
+	})
+}
+
+/// Ensures that the `runtime_version` section exists in the given blob.
+///
+/// Returns an error if the section can not be found.
+fn ensure_runtime_version_wasm_section_exists(blob_path: &Path) -> Result<(), WasmBuilderError> {
+	let blob = fs::read(blob_path).map_err(WasmBuilderError::io(blob_path))?;
+
+	let module: Module = deserialize_buffer(&blob).map_err(|e| {
+		WasmBuilderError::InvalidRuntimeBlob(format!(
+			"Failed to deserialize `{}`: {e:?}",
+			blob_path.display()
+		))
+	})?;
+
 	if !module.custom_sections().any(|cs| cs.name() == "runtime_version") {
-		println!(
+		return Err(WasmBuilderError::InvalidRuntimeBlob(
 			"Couldn't find the `runtime_version` section. \
-				  Please ensure that you are using the `sp_version::runtime_version` attribute macro!"
-		);
-		process::exit(1);
+			 Please ensure that you are using the `sp_version::runtime_version` attribute macro!"
+				.into(),
+		))
 	}
+
+	Ok(())
 }
 
 /// Adjust the mtime of the bloaty and compressed/compact wasm files.
@@ -314,6 +1422,8 @@ fn adjust_mtime(
 
 /// Find the `Cargo.lock` relative to the `OUT_DIR` environment variable.
 ///
+/// In a hermetic build, the `Cargo.lock` is taken from the source root of the manifest instead.
+///
 /// If the `Cargo.lock` cannot be found, we emit a warning and return `None`.
 fn find_cargo_lock(cargo_manifest: &Path) -> Option<PathBuf> {
 	fn find_impl(mut path: PathBuf) -> Option<PathBuf> {
@@ -328,6 +1438,18 @@ fn find_cargo_lock(cargo_manifest: &Path) -> Option<PathBuf> {
 		}
 	}
 
+	if let Some(hermetic) = crate::hermetic::get() {
+		let path = hermetic.source_root.join("Cargo.lock");
+		if !path.exists() {
+			build_helper::warning!(
+				"The source root `{}` of the hermetic build doesn't contain a `Cargo.lock`.",
+				hermetic.source_root.display(),
+			);
+			return None
+		}
+		return Some(path)
+	}
+
 	if let Ok(workspace) = env::var(crate::WASM_BUILD_WORKSPACE_HINT) {
 		let path = PathBuf::from(workspace);
 
@@ -357,7 +1479,7 @@ fn find_cargo_lock(cargo_manifest: &Path) -> Option<PathBuf> {
 }
 
 /// Extract the crate name from the given `Cargo.toml`.
-fn get_crate_name(cargo_manifest: &Path) -> String {
+pub(crate) fn get_crate_name(cargo_manifest: &Path) -> String {
 	let cargo_toml: Table = toml::from_str(
 		&fs::read_to_string(cargo_manifest).expect("File exists as checked before; qed"),
 	)
@@ -390,20 +1512,24 @@ fn get_lib_name(cargo_manifest: &Path) -> Option<String> {
 /// Returns the name for the blob binary.
 fn get_blob_name(target: RuntimeTarget, cargo_manifest: &Path) -> String {
 	match target {
-		RuntimeTarget::Wasm => get_lib_name(cargo_manifest)
-			.expect("The wasm project should have a `lib.name`; qed")
+		RuntimeTarget::Wasm | RuntimeTarget::Wasm64 => get_lib_name(cargo_manifest)
+			.unwrap_or_else(|| get_crate_name(cargo_manifest))
 			.replace('-', "_"),
 		RuntimeTarget::Riscv => get_crate_name(cargo_manifest),
 	}
 }
 
 /// Returns the root path of the wasm workspace.
-fn get_wasm_workspace_root() -> PathBuf {
+pub(crate) fn get_wasm_workspace_root() -> Result<PathBuf, WasmBuilderError> {
+	if let Some(hermetic) = crate::hermetic::get() {
+		return Ok(hermetic.wasm_workspace_root())
+	}
+
 	let mut out_dir = build_helper::out_dir();
 
 	loop {
 		match out_dir.parent() {
-			Some(parent) if out_dir.ends_with("build") => return parent.to_path_buf(),
+			Some(parent) if out_dir.ends_with("build") => return Ok(parent.to_path_buf()),
 			_ =>
 				if !out_dir.pop() {
 					break
@@ -411,7 +1537,7 @@ fn get_wasm_workspace_root() -> PathBuf {
 		}
 	}
 
-	panic!("Could not find target dir in: {}", build_helper::out_dir().display())
+	Err(WasmBuilderError::TargetDirectoryNotFound(build_helper::out_dir()))
 }
 
 fn create_project_cargo_toml(
@@ -421,7 +1547,8 @@ fn create_project_cargo_toml(
 	crate_name: &str,
 	crate_path: &Path,
 	enabled_features: impl Iterator<Item = String>,
-) {
+	options: &BuildOptions,
+) -> Result<(), WasmBuilderError> {
 	let mut workspace_toml: Table = toml::from_str(
 		&fs::read_to_string(workspace_root_path.join("Cargo.toml"))
 			.expect("Workspace root `Cargo.toml` exists; qed"),
@@ -434,12 +1561,44 @@ fn create_project_cargo_toml(
 	let mut release_profile = Table::new();
 	release_profile.insert("panic".into(), "abort".into());
 	release_profile.insert("lto".into(), "thin".into());
+	if options.keep_name_section {
+		// By default cargo strips the debug info in release builds, which includes the name
+		// section. The production profile inherits this setting.
+		release_profile.insert("strip".into(), false.into());
+	}
+	if options.deterministic {
+		// Splitting the crates into multiple codegen units can depend on the build machine.
+		release_profile.insert("codegen-units".into(), 1.into());
+	}
 
 	let mut production_profile = Table::new();
 	production_profile.insert("inherits".into(), "release".into());
 	production_profile.insert("lto".into(), "fat".into());
 	production_profile.insert("codegen-units".into(), 1.into());
 
+	let overrides = options
+		.lto
+		.map(|lto| ("lto", lto.value()))
+		.into_iter()
+		.chain(options.codegen_units.map(|units| ("codegen-units", units.into())));
+	for (key, value) in overrides {
+		for (name, profile) in
+			[("release", &mut release_profile), ("production", &mut production_profile)]
+		{
+			let workspace_value = workspace_toml
+				.get("profile")
+				.and_then(|profiles| profiles.get(name))
+				.and_then(|profile| profile.get(key));
+			if workspace_value.map_or(false, |workspace_value| *workspace_value != value) {
+				build_helper::warning!(
+					"`{key}` of `[profile.{name}]` in the workspace `Cargo.toml` is ignored by the \
+					 wasm build, which uses `{key} = {value}` as configured by the `WasmBuilder`."
+				);
+			}
+			profile.insert(key.into(), value.clone());
+		}
+	}
+
 	let mut dev_profile = Table::new();
 	dev_profile.insert("panic".into(), "abort".into());
 
@@ -481,7 +1640,7 @@ fn create_project_cargo_toml(
 
 	wasm_workspace_toml.insert("package".into(), package.into());
 
-	if target == RuntimeTarget::Wasm {
+	if target.is_wasm() {
 		let mut lib = Table::new();
 		lib.insert("name".into(), crate_name.replace("-", "_").into());
 		lib.insert("crate-type".into(), vec!["cdylib".to_string()].into());
@@ -517,7 +1676,7 @@ fn create_project_cargo_toml(
 	write_file_if_changed(
 		wasm_workspace.join("Cargo.toml"),
 		toml::to_string_pretty(&wasm_workspace_toml).expect("Wasm workspace toml is valid; qed"),
-	);
+	)
 }
 
 /// Find a package by the given `manifest_path` in the metadata. In case it can't be found by its
@@ -525,14 +1684,14 @@ fn create_project_cargo_toml(
 /// package's manifest path will be *generated* within a specific packaging directory, thus it won't
 /// be found by its original path anymore.
 ///
-/// Panics if the package could not be found.
+/// Returns an error if the package could not be found.
 fn find_package_by_manifest_path<'a>(
 	pkg_name: &str,
 	manifest_path: &Path,
 	crate_metadata: &'a cargo_metadata::Metadata,
-) -> &'a cargo_metadata::Package {
+) -> Result<&'a cargo_metadata::Package, WasmBuilderError> {
 	if let Some(pkg) = crate_metadata.packages.iter().find(|p| p.manifest_path == manifest_path) {
-		return pkg
+		return Ok(pkg)
 	}
 
 	let pkgs_by_name = crate_metadata
@@ -541,29 +1700,51 @@ fn find_package_by_manifest_path<'a>(
 		.filter(|p| p.name == pkg_name)
 		.collect::<Vec<_>>();
 
-	if let Some(pkg) = pkgs_by_name.first() {
-		if pkgs_by_name.len() > 1 {
-			panic!(
-				"Found multiple packages matching the name {pkg_name} ({manifest_path:?}): {:?}",
-				pkgs_by_name
-			);
-		} else {
-			return pkg
+	match pkgs_by_name[..] {
+		[pkg] => Ok(pkg),
+		[] => Err(WasmBuilderError::PackageNotFound(format!(
+			"Failed to find entry for package {pkg_name} ({manifest_path:?})."
+		))),
+		_ => Err(WasmBuilderError::PackageNotFound(format!(
+			"Found multiple packages matching the name {pkg_name} ({manifest_path:?}): {:?}",
+			pkgs_by_name
+		))),
+	}
+}
+
+/// Returns all features of the given `package` that are (transitively) enabled by its `default`
+/// feature.
+fn default_enabled_features(package: &cargo_metadata::Package) -> BTreeSet<String> {
+	let mut features = BTreeSet::new();
+	let mut to_visit = vec!["default"];
+
+	while let Some(feature) = to_visit.pop() {
+		for enabled in package.features.get(feature).into_iter().flatten() {
+			// Only local features are of interest, `dep:x` and `x/y` point to dependencies.
+			if package.features.contains_key(enabled) && features.insert(enabled.clone()) {
+				to_visit.push(enabled);
+			}
 		}
-	} else {
-		panic!("Failed to find entry for package {pkg_name} ({manifest_path:?}).");
 	}
+
+	features
 }
 
 /// Get a list of enabled features for the project.
+///
+/// If `disable_default_features` is `true`, all features that are enabled by the `default`
+/// feature are not returned.
 fn project_enabled_features(
 	pkg_name: &str,
 	cargo_manifest: &Path,
 	crate_metadata: &cargo_metadata::Metadata,
-) -> Vec<String> {
-	let package = find_package_by_manifest_path(pkg_name, cargo_manifest, crate_metadata);
+	disable_default_features: bool,
+) -> Result<Vec<String>, WasmBuilderError> {
+	let package = find_package_by_manifest_path(pkg_name, cargo_manifest, crate_metadata)?;
 
 	let std_enabled = package.features.get("std");
+	let default_features =
+		if disable_default_features { default_enabled_features(package) } else { BTreeSet::new() };
 
 	let mut enabled_features = package
 		.features
@@ -591,15 +1772,17 @@ fn project_enabled_features(
 			// We don't want to enable the `std`/`default` feature for the wasm build and
 			// we need to check if the feature is enabled by checking the env variable.
 			*f != "std" &&
-				*f != "default" && env::var(format!("CARGO_FEATURE_{}", feature_env))
-				.map(|v| v == "1")
-				.unwrap_or_default()
+				*f != "default" &&
+				!default_features.contains(*f) &&
+				env::var(format!("CARGO_FEATURE_{}", feature_env))
+					.map(|v| v == "1")
+					.unwrap_or_default()
 		})
 		.map(|d| d.0.clone())
 		.collect::<Vec<_>>();
 
 	enabled_features.sort();
-	enabled_features
+	Ok(enabled_features)
 }
 
 /// Returns if the project has the `runtime-wasm` feature
@@ -607,41 +1790,62 @@ fn has_runtime_wasm_feature_declared(
 	pkg_name: &str,
 	cargo_manifest: &Path,
 	crate_metadata: &cargo_metadata::Metadata,
-) -> bool {
-	let package = find_package_by_manifest_path(pkg_name, cargo_manifest, crate_metadata);
+) -> Result<bool, WasmBuilderError> {
+	let package = find_package_by_manifest_path(pkg_name, cargo_manifest, crate_metadata)?;
 
-	package.features.keys().any(|k| k == "runtime-wasm")
+	Ok(package.features.keys().any(|k| k == "runtime-wasm"))
 }
 
 /// Create the project used to build the wasm binary.
 ///
+/// The features of the project are either inferred from the native build or taken from
+/// `features_override` of the `options`. Afterwards `features_to_enable` are added and
+/// `features_to_disable` are removed. When inferring the features, `disable_default_features`
+/// excludes the features of the `default` feature.
+///
 /// # Returns
 ///
-/// The path to the created wasm project.
+/// The path to the created wasm project and the enabled features.
 fn create_project(
 	target: RuntimeTarget,
 	project_cargo_toml: &Path,
 	wasm_workspace: &Path,
 	crate_metadata: &Metadata,
 	workspace_root_path: &Path,
-	features_to_enable: Vec<String>,
-) -> PathBuf {
+	options: &BuildOptions,
+) -> Result<(PathBuf, Vec<String>), WasmBuilderError> {
 	let crate_name = get_crate_name(project_cargo_toml);
 	let crate_path = project_cargo_toml.parent().expect("Parent path exists; qed");
 	let wasm_project_folder = wasm_workspace.join(&crate_name);
 
 	fs::create_dir_all(wasm_project_folder.join("src"))
-		.expect("Wasm project dir create can not fail; qed");
-
-	let mut enabled_features =
-		project_enabled_features(&crate_name, project_cargo_toml, crate_metadata);
+		.map_err(WasmBuilderError::io(&wasm_project_folder))?;
+
+	let mut enabled_features = match &options.features_override {
+		Some(features) => features.clone(),
+		None => project_enabled_features(
+			&crate_name,
+			project_cargo_toml,
+			crate_metadata,
+			options.disable_default_features,
+		)?,
+	};
 
-	if has_runtime_wasm_feature_declared(&crate_name, project_cargo_toml, crate_metadata) {
+	if has_runtime_wasm_feature_declared(&crate_name, project_cargo_toml, crate_metadata)? {
 		enabled_features.push("runtime-wasm".into());
 	}
 
-	let mut enabled_features = enabled_features.into_iter().collect::<HashSet<_>>();
-	enabled_features.extend(features_to_enable.into_iter());
+	// Use a sorted set to always generate the same `Cargo.toml` for the same features.
+	let mut enabled_features = enabled_features.into_iter().collect::<BTreeSet<_>>();
+	enabled_features.extend(options.features_to_enable.iter().cloned());
+	options.features_to_disable.iter().for_each(|f| {
+		enabled_features.remove(f);
+	});
+
+	// When building in place, the project only holds the outputs of the build.
+	if options.build_in_place {
+		return Ok((wasm_project_folder, enabled_features.into_iter().collect()))
+	}
 
 	create_project_cargo_toml(
 		target,
@@ -649,35 +1853,43 @@ fn create_project(
 		workspace_root_path,
 		&crate_name,
 		crate_path,
-		enabled_features.into_iter(),
-	);
+		enabled_features.iter().cloned(),
+		options,
+	)?;
 
 	match target {
-		RuntimeTarget::Wasm => {
+		RuntimeTarget::Wasm | RuntimeTarget::Wasm64 => {
 			write_file_if_changed(
 				wasm_project_folder.join("src/lib.rs"),
 				"#![no_std] pub use wasm_project::*;",
-			);
+			)?;
 		},
 		RuntimeTarget::Riscv => {
 			write_file_if_changed(
 				wasm_project_folder.join("src/main.rs"),
 				"#![no_std] #![no_main] pub use wasm_project::*;",
-			);
+			)?;
 		},
 	}
 
 	if let Some(crate_lock_file) = find_cargo_lock(project_cargo_toml) {
 		// Use the `Cargo.lock` of the main project.
-		crate::copy_file_if_changed(crate_lock_file, wasm_project_folder.join("Cargo.lock"));
+		if options.verbosity >= Verbosity::Verbose {
+			println!(
+				"{} {}",
+				colorize_info_message("Using the `Cargo.lock` at:"),
+				crate_lock_file.display()
+			);
+		}
+		crate::copy_file_if_changed(crate_lock_file, wasm_project_folder.join("Cargo.lock"))?;
 	}
 
-	wasm_project_folder
+	Ok((wasm_project_folder, enabled_features.into_iter().collect()))
 }
 
-/// A rustc profile.
-#[derive(Clone, Debug, EnumIter)]
-enum Profile {
+/// A cargo profile to build the runtime with.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, EnumIter)]
+pub enum Profile {
 	/// The `--profile dev` profile.
 	Debug,
 	/// The `--profile release` profile.
@@ -688,7 +1900,7 @@ enum Profile {
 
 impl Profile {
 	/// The name of the profile as supplied to the cargo `--profile` cli option.
-	fn name(&self) -> &'static str {
+	pub(crate) fn name(&self) -> &'static str {
 		match self {
 			Self::Debug => "dev",
 			Self::Release => "release",
@@ -696,6 +1908,12 @@ impl Profile {
 		}
 	}
 
+	/// Returns the profile with the given name, which is either the name of the cargo profile or
+	/// of its sub directory within `target`, e.g. `debug`.
+	pub(crate) fn from_name(name: &str) -> Option<Self> {
+		Self::iter().find(|p| p.name() == name || p.directory() == name)
+	}
+
 	/// The sub directory within `target` where cargo places the build output.
 	///
 	/// # Note
@@ -715,6 +1933,113 @@ impl Profile {
 	}
 }
 
+/// An optimization level of `wasm-opt`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OptLevel {
+	/// `-O0`, no optimizations.
+	O0,
+	/// `-O1`, quick and useful optimizations.
+	O1,
+	/// `-O2`, most optimizations.
+	O2,
+	/// `-O3`, spend potentially a lot of time optimizing.
+	O3,
+	/// `-O4`, also flatten the IR, which can take a lot more time and memory.
+	O4,
+	/// `-Os`, focus on code size.
+	Os,
+	/// `-Oz`, focus even more on code size.
+	Oz,
+}
+
+impl OptLevel {
+	/// The `wasm-opt` options of this level.
+	fn options(self) -> wasm_opt::OptimizationOptions {
+		match self {
+			Self::O0 => wasm_opt::OptimizationOptions::new_opt_level_0(),
+			Self::O1 => wasm_opt::OptimizationOptions::new_opt_level_1(),
+			Self::O2 => wasm_opt::OptimizationOptions::new_opt_level_2(),
+			Self::O3 => wasm_opt::OptimizationOptions::new_opt_level_3(),
+			Self::O4 => wasm_opt::OptimizationOptions::new_opt_level_4(),
+			Self::Os => wasm_opt::OptimizationOptions::new_optimize_for_size(),
+			Self::Oz => wasm_opt::OptimizationOptions::new_optimize_for_size_aggressively(),
+		}
+	}
+}
+
+/// The link time optimization of a cargo profile.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Lto {
+	/// `lto = "off"`, no link time optimization at all.
+	Off,
+	/// `lto = "thin"`, a faster link time optimization.
+	Thin,
+	/// `lto = "fat"`, the link time optimization across the whole dependency graph.
+	Fat,
+}
+
+impl Lto {
+	/// The value of `lto` in the cargo profile.
+	fn value(self) -> toml::Value {
+		match self {
+			Self::Off => "off".into(),
+			Self::Thin => "thin".into(),
+			Self::Fat => "fat".into(),
+		}
+	}
+}
+
+/// An environment variable that selects how cargo runs `rustc`, see
+/// [`WasmBuilder::rustc_env`](crate::WasmBuilder::rustc_env).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RustcEnvVar {
+	/// `RUSTC`, the compiler cargo runs.
+	Rustc,
+	/// `RUSTC_WRAPPER`, the wrapper cargo runs the compiler with for all crates, e.g. `sccache`.
+	RustcWrapper,
+	/// `RUSTC_WORKSPACE_WRAPPER`, the wrapper cargo runs the compiler with for the members of the
+	/// workspace, e.g. `clippy-driver`.
+	RustcWorkspaceWrapper,
+}
+
+impl RustcEnvVar {
+	/// The name of the environment variable.
+	pub fn name(self) -> &'static str {
+		match self {
+			Self::Rustc => "RUSTC",
+			Self::RustcWrapper => "RUSTC_WRAPPER",
+			Self::RustcWorkspaceWrapper => "RUSTC_WORKSPACE_WRAPPER",
+		}
+	}
+}
+
+/// How a [`RustcEnvVar`] of the outer build is passed to the cargo building the runtime.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EnvPolicy {
+	/// The value of the outer build is passed on.
+	Inherit,
+	/// The variable is cleared.
+	///
+	/// `RUSTC` is removed, so the `rustc` of the toolchain cargo belongs to is used. The wrappers
+	/// are set to an empty value, which also overrides the ones of the cargo configuration.
+	Clear,
+	/// The variable is set to the given value.
+	Set(OsString),
+}
+
+/// The configuration of the profile-guided optimization, see
+/// [`WasmBuilder::pgo`](crate::WasmBuilder::pgo).
+#[derive(Default)]
+pub struct PgoConfig {
+	/// The path to the merged profile (`.profdata`) the optimized runtime is built with.
+	pub profdata_path: PathBuf,
+	/// The workload that is run with the instrumented runtime to write the profile.
+	///
+	/// It is called with the path to the instrumented bloaty binary. If `None`, no instrumented
+	/// runtime is built and the profile at `profdata_path` needs to exist already.
+	pub workload: Option<Box<dyn Fn(&Path) -> Result<(), String> + Send + Sync>>,
+}
+
 /// The build configuration for this build.
 #[derive(Debug)]
 struct BuildConfiguration {
@@ -733,7 +2058,10 @@ impl BuildConfiguration {
 	/// "production". It would only contain the builtin profile where the custom profile
 	/// inherits from. This is why we inspect the build path to learn which profile is used.
 	///
-	/// When not overridden by a env variable we always default to building wasm with the `Release`
+	/// The `profile` selected by the [`WasmBuilder`](crate::WasmBuilder) takes precedence over the
+	/// detected profile and is treated the same way as a profile set by an env variable.
+	///
+	/// When not overridden we always default to building wasm with the `Release`
 	/// profile even when the main build uses the debug build. This is because wasm built with the
 	/// `Debug` profile is too slow for normal development activities and almost never intended.
 	///
@@ -743,9 +2071,15 @@ impl BuildConfiguration {
 	/// # Note
 	///
 	/// Can be overridden by setting [`crate::WASM_BUILD_TYPE_ENV`].
-	fn detect(target: RuntimeTarget, wasm_project: &Path) -> Self {
+	fn detect(
+		build_subdirectory: &str,
+		wasm_project: &Path,
+		profile: Option<Profile>,
+	) -> Result<Self, WasmBuilderError> {
 		let (name, overridden) = if let Ok(name) = env::var(crate::WASM_BUILD_TYPE_ENV) {
 			(name, true)
+		} else if let Some(profile) = profile {
+			(profile.directory().to_string(), true)
 		} else {
 			// First go backwards to the beginning of the target directory.
 			// Then go forwards to find the build subdirectory.
@@ -759,7 +2093,7 @@ impl BuildConfiguration {
 				.collect::<Vec<_>>()
 				.iter()
 				.rev()
-				.take_while(|c| c.as_os_str() != target.build_subdirectory())
+				.take_while(|c| c.as_os_str() != build_subdirectory)
 				.last()
 				.expect("We put the runtime project within a `target/.../[rw]build` path; qed")
 				.as_os_str()
@@ -769,7 +2103,7 @@ impl BuildConfiguration {
 			(name, false)
 		};
 		let outer_build_profile = Profile::iter().find(|p| p.directory() == name);
-		let blob_build_profile = match (outer_build_profile.clone(), overridden) {
+		let blob_build_profile = match (outer_build_profile, overridden) {
 			// When not overridden by a env variable we default to using the `Release` profile
 			// for the wasm build even when the main build uses the debug build. This
 			// is because the `Debug` profile is too slow for normal development activities.
@@ -790,81 +2124,415 @@ impl BuildConfiguration {
 				profile
 			},
 			// Invalid profile specified.
-			(None, true) => {
-				// We use println! + exit instead of a panic in order to have a cleaner output.
-				println!(
-					"Unexpected profile name: `{}`. One of the following is expected: {:?}",
-					name,
-					Profile::iter().map(|p| p.directory()).collect::<Vec<_>>(),
-				);
-				process::exit(1);
-			},
+			(None, true) =>
+				return Err(WasmBuilderError::InvalidEnvironmentVariable {
+					name: crate::WASM_BUILD_TYPE_ENV,
+					value: name,
+					expected: format!(
+						"one of {:?}",
+						Profile::iter().map(|p| p.directory()).collect::<Vec<_>>()
+					),
+				}),
 		};
-		BuildConfiguration {
+		Ok(BuildConfiguration {
 			outer_build_profile: outer_build_profile.unwrap_or(Profile::Release),
 			blob_build_profile,
-		}
+		})
+	}
+}
+
+/// Check environment whether we should build without network
+pub(crate) fn offline_build() -> bool {
+	env::var(OFFLINE).map_or(false, |v| v == "true")
+}
+
+/// Returns an error for offline builds, as the network is required for `action`.
+pub(crate) fn require_network(action: &str) -> Result<(), WasmBuilderError> {
+	if offline_build() {
+		return Err(WasmBuilderError::MissingPrerequisites(format!(
+			"The network is required for {action}, but `{OFFLINE}` is set to `true`."
+		)))
+	}
+
+	Ok(())
+}
+
+/// Returns the `RUSTFLAGS` for building the runtime for the given `target`.
+///
+/// The `rustflags_after_env` of the `options` are appended after the flags of
+/// `WASM_BUILD_RUSTFLAGS`, so they take precedence over them.
+fn runtime_rustflags(
+	target: RuntimeTarget,
+	default_rustflags: &str,
+	options: &BuildOptions,
+) -> String {
+	let mut rustflags = String::new();
+	match target {
+		RuntimeTarget::Wasm => {
+			rustflags.push_str(
+				"-C target-cpu=mvp -C target-feature=-sign-ext -C link-arg=--export-table ",
+			);
+		},
+		RuntimeTarget::Wasm64 => {
+			rustflags.push_str("-C link-arg=--export-table ");
+		},
+		RuntimeTarget::Riscv => {
+			rustflags.push_str("-C target-feature=+lui-addi-fusion -C relocation-model=pie -C link-arg=--emit-relocs -C link-arg=--unique ");
+		},
+	}
+
+	rustflags.push_str(default_rustflags);
+	rustflags.push_str(" --cfg substrate_runtime ");
+	if let Some(flavor) = options.linker_flavor() {
+		rustflags.push_str(&format!("-C linker-flavor={flavor} "));
+	}
+	rustflags.push_str(&env::var(crate::WASM_BUILD_RUSTFLAGS_ENV).unwrap_or_default());
+	rustflags.push_str(" ");
+	rustflags.push_str(&options.rustflags_after_env);
+	rustflags
+}
+
+/// Returns the `path` as part of a flag in the `RUSTFLAGS` of the runtime, see [`rustflag_word`].
+fn rustflag_path(path: &Path) -> String {
+	rustflag_word(&path.display().to_string())
+}
+
+/// Returns the `word` as part of a flag in the `RUSTFLAGS` of the runtime.
+///
+/// The spaces of the word are replaced by [`RUSTFLAGS_SPACE`], so the flag stays one word when
+/// the flags are split at whitespace.
+pub(crate) fn rustflag_word(word: &str) -> String {
+	word.replace(' ', &RUSTFLAGS_SPACE.to_string())
+}
+
+/// Returns the `rustflags` in the format of `CARGO_ENCODED_RUSTFLAGS`, i.e. separated by
+/// `\x1f`, which keeps the spaces in the paths of the flags.
+fn encode_rustflags(rustflags: &str) -> String {
+	rustflags
+		.split_whitespace()
+		.map(|flag| flag.replace(RUSTFLAGS_SPACE, " "))
+		.collect::<Vec<_>>()
+		.join("\x1f")
+}
+
+/// Returns the `RUSTFLAGS` that remap the machine specific paths of the source files that end up
+/// in the runtime, e.g. in panic messages.
+///
+/// When multiple prefixes match, the last one is used. So the more specific `runtime_workspace`,
+/// which usually is inside of the `workspace_root`, comes last.
+fn remap_path_prefix_rustflags(workspace_root: &Path, runtime_workspace: &Path) -> String {
+	let mut rustflags = String::new();
+	for (from, to) in path_remaps(workspace_root, runtime_workspace) {
+		rustflags.push_str(&format!(" --remap-path-prefix={}={to}", rustflag_path(&from)));
+	}
+	rustflags
+}
+
+/// Returns the machine specific path prefixes of the deterministic builds and what they are
+/// remapped to, in the order they are passed to `rustc`.
+fn path_remaps(workspace_root: &Path, runtime_workspace: &Path) -> Vec<(PathBuf, &'static str)> {
+	let cargo_home = env::var_os("CARGO_HOME")
+		.map(PathBuf::from)
+		.or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cargo")));
+
+	cargo_home
+		.into_iter()
+		.map(|path| (path, "/cargo"))
+		.chain([(workspace_root.into(), "/build"), (runtime_workspace.into(), "/wbuild")])
+		.collect()
+}
+
+/// Build the runtime blob, including all the builds required for the profile-guided optimization
+/// and the metadata hash.
+///
+/// The flags for using the profile of the profile-guided optimization are added to `rustflags`.
+///
+/// Returns the path to the generated bloaty runtime blob.
+fn build_runtime_blob(
+	target: RuntimeTarget,
+	blob_build_profile: &Profile,
+	project: &Path,
+	manifest: &RuntimeManifest,
+	rustflags: &mut String,
+	options: &BuildOptions,
+	cargo_cmd: CargoCommandVersioned,
+) -> Result<PathBuf, WasmBuilderError> {
+	if let Some(pgo) = &options.pgo {
+		if let Some(workload) = &pgo.workload {
+			let instrumented_blob_path = build_bloaty_blob(
+				target,
+				blob_build_profile,
+				project,
+				manifest,
+				&format!(
+					"{rustflags} -C profile-generate={} ",
+					rustflag_path(&project.join("pgo"))
+				),
+				options,
+				cargo_cmd.clone(),
+				#[cfg(feature = "metadata-hash")]
+				None,
+			)?;
+
+			// The optimized build overwrites the instrumented binary, so keep a copy of it.
+			let mut instrumented_path = instrumented_blob_path.clone();
+			instrumented_path.set_extension("instrumented");
+			fs::copy(&instrumented_blob_path, &instrumented_path)
+				.map_err(WasmBuilderError::io(&instrumented_path))?;
+
+			workload(&instrumented_path).map_err(WasmBuilderError::PgoWorkload)?;
+		}
+
+		if !pgo.profdata_path.exists() {
+			return Err(WasmBuilderError::InvalidConfiguration(format!(
+				"The profile `{}` of the profile-guided optimization doesn't exist.",
+				pgo.profdata_path.display()
+			)))
+		}
+		rerun_if_changed(&pgo.profdata_path);
+		rustflags.push_str(&format!("-C profile-use={} ", rustflag_path(&pgo.profdata_path)));
+	}
+
+	#[cfg(feature = "metadata-hash")]
+	let raw_blob_path = match options.enable_metadata_hash.clone() {
+		Some(config) => {
+			// When the metadata hash is enabled we need to build the runtime twice.
+			let raw_blob_path = build_bloaty_blob(
+				target,
+				blob_build_profile,
+				project,
+				manifest,
+				rustflags,
+				options,
+				cargo_cmd.clone(),
+				None,
+			)?;
+
+			let hash = crate::metadata_hash::generate_metadata_hash(&raw_blob_path, config)?;
+
+			build_bloaty_blob(
+				target,
+				blob_build_profile,
+				project,
+				manifest,
+				rustflags,
+				options,
+				cargo_cmd,
+				Some(hash),
+			)?
+		},
+		None => build_bloaty_blob(
+			target,
+			blob_build_profile,
+			project,
+			manifest,
+			rustflags,
+			options,
+			cargo_cmd,
+			None,
+		)?,
+	};
+
+	// If the feature is not enabled, we only need to do it once.
+	#[cfg(not(feature = "metadata-hash"))]
+	let raw_blob_path = build_bloaty_blob(
+		target,
+		blob_build_profile,
+		project,
+		manifest,
+		rustflags,
+		options,
+		cargo_cmd,
+	)?;
+
+	Ok(raw_blob_path)
+}
+
+/// Returns the key of the runtime binary in the artifact cache or `None` if it is not used.
+///
+/// The key covers the sources of the local packages the runtime depends on, as far as they are
+/// watched for changes, the manifest and the `Cargo.lock` of the wasm project, the toolchain and
+/// the flags of the build. The packages from registries and git are covered by the `Cargo.lock`.
+/// Builds that depend on the output of hooks, i.e. with `before_build` hooks or a profile-guided
+/// optimization workload, are not cached.
+fn artifact_cache_key(
+	target: RuntimeTarget,
+	orig_project_cargo_toml: &Path,
+	project: &Path,
+	workspace_root: &Path,
+	runtime_workspace: &Path,
+	manifest: &RuntimeManifest,
+	blob_build_profile: &Profile,
+	rustflags: &str,
+	options: &BuildOptions,
+	cargo_cmd: &CargoCommandVersioned,
+) -> Result<Option<String>, WasmBuilderError> {
+	if !options.artifact_cache_enabled()? ||
+		!options.before_build.is_empty() ||
+		options.pgo.is_some()
+	{
+		return Ok(None)
 	}
+
+	let inputs =
+		cargo_build_inputs(target, manifest, blob_build_profile, rustflags, options, cargo_cmd)?;
+
+	// Paths are part of the key, as they end up in the binary, e.g. in panic messages. Unless they
+	// are remapped, then only the remapped paths end up in the binary.
+	let remaps = if options.deterministic {
+		path_remaps(workspace_root, runtime_workspace)
+	} else {
+		Vec::new()
+	};
+	let mut sources = dependency_sources(
+		orig_project_cargo_toml,
+		&manifest.path,
+		// Custom target specifications are not supported by `--filter-platform`.
+		options.custom_target_spec.is_none().then(|| target.rustc_target()),
+		options.cargo_config()?.as_deref(),
+		&options.rerun_exclusions,
+		|package| package.source.is_none(),
+	)?;
+	sources.push(project.join(match target {
+		RuntimeTarget::Wasm | RuntimeTarget::Wasm64 => "src/lib.rs",
+		RuntimeTarget::Riscv => "src/main.rs",
+	}));
+
+	crate::artifact_cache::cache_key(
+		&inputs.iter().map(Vec::as_slice).collect::<Vec<_>>(),
+		sources,
+		&remaps,
+	)
+	.map(Some)
 }
 
-/// Check environment whether we should build without network
-fn offline_build() -> bool {
-	env::var(OFFLINE).map_or(false, |v| v == "true")
+/// Returns the inputs of the cargo build of the runtime blob besides its sources, i.e. the
+/// manifest, the `Cargo.lock`, the version of `rustc` and the flags of the build.
+fn cargo_build_inputs(
+	target: RuntimeTarget,
+	manifest: &RuntimeManifest,
+	blob_build_profile: &Profile,
+	rustflags: &str,
+	options: &BuildOptions,
+	cargo_cmd: &CargoCommandVersioned,
+) -> Result<[Vec<u8>; 4], WasmBuilderError> {
+	let manifest_content = fs::read(&manifest.path).unwrap_or_default();
+	let cargo_lock = manifest
+		.cargo_lock
+		.as_ref()
+		.and_then(|path| fs::read(path).ok())
+		.unwrap_or_default();
+	#[cfg(feature = "metadata-hash")]
+	let metadata_hash = options.enable_metadata_hash.as_ref().map(|config| {
+		format!(
+			"{:?} {} {:?} {:?} {}",
+			config.token_symbol,
+			config.decimals,
+			config.base58_prefix,
+			config.spec_name,
+			config.metadata_version
+		)
+	});
+	#[cfg(not(feature = "metadata-hash"))]
+	let metadata_hash = None::<String>;
+	let flags = format!(
+		"{} {} {rustflags} {:?} {:?} {:?} {} {:?} {:?} {} {metadata_hash:?} {:?}",
+		options.target_arg(target, cargo_cmd)?,
+		blob_build_profile.name(),
+		manifest.cargo_args,
+		options.cargo_args,
+		env::var(crate::WASM_BUILD_CARGO_ARGS).ok(),
+		options.build_std_required(target, cargo_cmd)?,
+		options.build_std_features,
+		options.sysroot(),
+		options.deterministic,
+		options.linker(),
+	);
+
+	Ok([manifest_content, cargo_lock, cargo_cmd.rustc_version().into(), flags.into()])
 }
 
-/// Build the project and create the bloaty runtime blob.
+/// Run the given command and print every line of its output with the given `prefix` as soon as
+/// it is written.
 ///
-/// Returns the path to the generated bloaty runtime blob.
-fn build_bloaty_blob(
+/// This shows the progress of long builds and keeps the output of concurrent builds apart. The
+/// lines are forwarded unchanged, so the colors of the output are kept. Returns whether the
+/// command succeeded, or the error if it could not be run.
+fn run_with_prefixed_output(cmd: &mut Command, prefix: &str) -> io::Result<bool> {
+	let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+	let stdout = child.stdout.take().expect("`stdout` is piped; qed");
+	let stderr = child.stderr.take().expect("`stderr` is piped; qed");
+
+	// Read the lines as bytes, as an invalid UTF-8 line would otherwise stop the forwarding and
+	// block the command once the pipe is full.
+	fn lines(output: impl Read) -> impl Iterator<Item = String> {
+		BufReader::new(output)
+			.split(b'\n')
+			.map_while(Result::ok)
+			.map(|line| String::from_utf8_lossy(&line).trim_end_matches('\r').to_owned())
+	}
+
+	let prefix = colorize_output_prefix(prefix);
+	thread::scope(|scope| {
+		scope.spawn(|| lines(stdout).for_each(|line| println!("{prefix}{line}")));
+		lines(stderr).for_each(|line| eprintln!("{prefix}{line}"));
+	});
+
+	Ok(child.wait()?.success())
+}
+
+/// Returns the cargo command that runs `subcommand`, e.g. `rustc` or `check`, for the runtime.
+///
+/// The `cargo_args` of the `options` and of `WASM_BUILD_CARGO_ARGS` are appended to the cargo
+/// command.
+fn runtime_cargo_command(
+	subcommand: &str,
 	target: RuntimeTarget,
 	blob_build_profile: &Profile,
 	project: &Path,
-	default_rustflags: &str,
-	cargo_cmd: CargoCommandVersioned,
-	#[cfg(feature = "metadata-hash")] metadata_hash: Option<[u8; 32]>,
-) -> PathBuf {
-	let manifest_path = project.join("Cargo.toml");
+	manifest: &RuntimeManifest,
+	rustflags: &str,
+	options: &BuildOptions,
+	cargo_cmd: &CargoCommandVersioned,
+) -> Result<Command, WasmBuilderError> {
+	let cargo_target_dir = options.cargo_target_dir(project)?;
 	let mut build_cmd = cargo_cmd.command();
 
-	let mut rustflags = String::new();
-	match target {
-		RuntimeTarget::Wasm => {
-			rustflags.push_str(
-				"-C target-cpu=mvp -C target-feature=-sign-ext -C link-arg=--export-table ",
-			);
-		},
-		RuntimeTarget::Riscv => {
-			rustflags.push_str("-C target-feature=+lui-addi-fusion -C relocation-model=pie -C link-arg=--emit-relocs -C link-arg=--unique ");
-		},
-	}
-
-	rustflags.push_str(default_rustflags);
-	rustflags.push_str(" --cfg substrate_runtime ");
-	rustflags.push_str(&env::var(crate::WASM_BUILD_RUSTFLAGS_ENV).unwrap_or_default());
+	let prebuilt_sysroot = match options.build_std_required(target, cargo_cmd)? {
+		true => prebuilt_sysroot(target, blob_build_profile, rustflags, options, cargo_cmd)?,
+		false => None,
+	};
+	let rustflags = match &prebuilt_sysroot {
+		Some(sysroot) => format!("{rustflags} --sysroot={}", rustflag_path(sysroot)),
+		None => rustflags.to_owned(),
+	};
 
 	build_cmd
-		.arg("rustc")
-		.arg(format!("--target={}", target.rustc_target()))
-		.arg(format!("--manifest-path={}", manifest_path.display()))
-		.env("RUSTFLAGS", rustflags)
+		.arg(subcommand)
+		.arg(format!("--target={}", options.target_arg(target, cargo_cmd)?))
+		.arg(format!("--manifest-path={}", manifest.path.display()))
+		// Only `cargo rustc` can change the crate type, which doesn't matter for the other
+		// subcommands.
+		.args(
+			manifest
+				.cargo_args
+				.iter()
+				.filter(|arg| subcommand == "rustc" || !arg.starts_with("--crate-type")),
+		)
+		.env("CARGO_ENCODED_RUSTFLAGS", encode_rustflags(&rustflags))
 		// Manually set the `CARGO_TARGET_DIR` to prevent a cargo deadlock (cargo locks a target dir
 		// exclusive). The runner project is created in `CARGO_TARGET_DIR` and executing it will
 		// create a sub target directory inside of `CARGO_TARGET_DIR`.
-		.env("CARGO_TARGET_DIR", &project.join("target").display().to_string())
-		// As we are being called inside a build-script, this env variable is set. However, we set
-		// our own `RUSTFLAGS` and thus, we need to remove this. Otherwise cargo favors this
-		// env variable.
-		.env_remove("CARGO_ENCODED_RUSTFLAGS")
-		// Make sure if we're called from within a `build.rs` the host toolchain won't override a
-		// rustup toolchain we've picked.
-		.env_remove("RUSTC")
+		// When the target directory is shared between the runtimes, cargo's lock of the directory
+		// makes sure that only one of them is built at a time.
+		.env("CARGO_TARGET_DIR", &cargo_target_dir.display().to_string())
+		// Our flags are passed encoded, so the paths in them may contain spaces. Cargo favors
+		// them over any `RUSTFLAGS` of the outer build, which are removed nevertheless.
+		.env_remove("RUSTFLAGS")
 		// We don't want to call ourselves recursively
-		.env(crate::SKIP_BUILD_ENV, "");
-
-	#[cfg(feature = "metadata-hash")]
-	if let Some(hash) = metadata_hash {
-		build_cmd.env("RUNTIME_METADATA_HASH", array_bytes::bytes2hex("0x", &hash));
-	}
+		.env(crate::SKIP_BUILD_ENV, "")
+		// Give the runtime access to the files generated by the `before_build` hooks.
+		.env(crate::WASM_BUILD_PROJECT_DIR, project);
 
 	if super::color_output_enabled() {
 		build_cmd.arg("--color=always");
@@ -873,10 +2541,88 @@ fn build_bloaty_blob(
 	build_cmd.arg("--profile");
 	build_cmd.arg(blob_build_profile.name());
 
-	if offline_build() {
+	if let Some(config) = options.cargo_config()? {
+		build_cmd.arg("--config").arg(config);
+	}
+
+	if let Some(linker) = options.linker() {
+		build_cmd.arg("--config").arg(format!(
+			"target.{}.linker={}",
+			options.target_dir_name(target, cargo_cmd)?,
+			toml::Value::String(linker)
+		));
+	}
+
+	if options.locked_enabled()? {
+		// `--frozen` implies `--offline`.
+		build_cmd.arg(if offline_build() { "--frozen" } else { "--locked" });
+	} else if offline_build() {
 		build_cmd.arg("--offline");
 	}
 
+	// Split the jobs of the outer build between the concurrent builds.
+	if let Some(jobs) = env::var("NUM_JOBS").ok().and_then(|jobs| jobs.parse::<usize>().ok()) {
+		if options.concurrent_builds > 1 {
+			build_cmd.arg(format!("--jobs={}", (jobs / options.concurrent_builds).max(1)));
+		}
+	}
+
+	// Override the profile and any `CARGO_INCREMENTAL` of the outer build.
+	let incremental = options.incremental_enabled(blob_build_profile)?;
+	build_cmd.env("CARGO_INCREMENTAL", if incremental { "1" } else { "0" });
+
+	if options.timings_enabled()? {
+		build_cmd.arg("--timings=html");
+	}
+
+	for var in [RustcEnvVar::Rustc, RustcEnvVar::RustcWrapper, RustcEnvVar::RustcWorkspaceWrapper] {
+		let value = match options.rustc_env_policy(var)? {
+			None => continue,
+			// Cargo passes the wrapper of the outer build to build scripts, which includes the one
+			// configured with `build.rustc-wrapper`.
+			Some(EnvPolicy::Inherit) => env::var_os(var.name()).filter(|v| !v.is_empty()),
+			Some(EnvPolicy::Set(value)) => Some(value),
+			// An empty wrapper also overrides the one of the cargo configuration.
+			Some(EnvPolicy::Clear) if var != RustcEnvVar::Rustc => Some(OsString::new()),
+			Some(EnvPolicy::Clear) => {
+				build_cmd.env_remove(var.name());
+				continue
+			},
+		};
+
+		match value {
+			Some(value) => {
+				// Pass on the configuration of `sccache`, so the runtime build is cached as well.
+				if var == RustcEnvVar::RustcWrapper && !value.is_empty() {
+					build_cmd.envs(env::vars_os().filter(|(name, _)| {
+						name.to_str().map_or(false, |name| name.starts_with("SCCACHE_"))
+					}));
+				}
+				build_cmd.env(var.name(), value);
+			},
+			None => {
+				build_cmd.env_remove(var.name());
+			},
+		}
+	}
+
+	if options.deterministic {
+		// Let build scripts and proc macros that embed a timestamp embed the same one.
+		build_cmd.env("SOURCE_DATE_EPOCH", "0");
+	}
+
+	build_cmd.args(&options.cargo_args);
+	if let Some(args) = env::var_os(crate::WASM_BUILD_CARGO_ARGS) {
+		let args = args.to_string_lossy();
+		build_cmd.args(crate::split_shell_words(&args).ok_or_else(|| {
+			WasmBuilderError::InvalidEnvironmentVariable {
+				name: crate::WASM_BUILD_CARGO_ARGS,
+				value: args.to_string(),
+				expected: "shell words without unterminated quotes".into(),
+			}
+		})?);
+	}
+
 	// Our executor currently only supports the WASM MVP feature set, however nowadays
 	// when compiling WASM the Rust compiler has more features enabled by default.
 	//
@@ -887,37 +2633,190 @@ fn build_bloaty_blob(
 	//
 	// So here we force the compiler to also compile the standard library crates for us
 	// to make sure that they also only use the MVP features.
-	if crate::build_std_required() {
+	if prebuilt_sysroot.is_some() {
+		// The standard library of the sysroot is already built with the same features.
+	} else if options.build_std_required(target, cargo_cmd)? &&
+		options.stable_build_std(cargo_cmd)?
+	{
+		build_cmd.arg("--build-std");
+		if !options.build_std_features.is_empty() {
+			build_cmd.arg(format!("--build-std-features={}", options.build_std_features.join(",")));
+		}
+	} else if options.build_std_required(target, cargo_cmd)? {
 		// Unfortunately this is still a nightly-only flag, but FWIW it is pretty widely used
 		// so it's unlikely to break without a replacement.
 		build_cmd.arg("-Z").arg("build-std");
+		if !options.build_std_features.is_empty() {
+			build_cmd
+				.arg("-Z")
+				.arg(format!("build-std-features={}", options.build_std_features.join(",")));
+		}
 		if !cargo_cmd.supports_nightly_features() {
-			build_cmd.env("RUSTC_BOOTSTRAP", "1");
+			build_cmd.env(crate::RUSTC_BOOTSTRAP, "1");
+		}
+	} else if !options.build_std_features.is_empty() {
+		build_helper::warning!(
+			"The standard library features `{}` are ignored, because the standard library is \
+			 not built from source.",
+			options.build_std_features.join(",")
+		);
+	}
+
+	Ok(build_cmd)
+}
+
+/// Returns the prebuilt sysroot the standard library is taken from instead of building it, if one
+/// is given.
+///
+/// Returns an error if the fingerprint of the sysroot doesn't match the build of the runtime.
+fn prebuilt_sysroot(
+	target: RuntimeTarget,
+	blob_build_profile: &Profile,
+	rustflags: &str,
+	options: &BuildOptions,
+	cargo_cmd: &CargoCommandVersioned,
+) -> Result<Option<PathBuf>, WasmBuilderError> {
+	let Some(sysroot) = options.sysroot() else { return Ok(None) };
+
+	// Only the flags that change the code of the standard library matter.
+	let mut codegen_flags = Vec::new();
+	let mut words = rustflags.split_whitespace();
+	while let Some(word) = words.next() {
+		let flag = match word {
+			"-C" => words.next().unwrap_or_default(),
+			word => word.strip_prefix("-C").unwrap_or_default(),
+		};
+		if flag.starts_with("target-cpu=") || flag.starts_with("target-feature=") {
+			codegen_flags.push(flag);
+		}
+	}
+	let mut std_features = options.build_std_features.clone();
+	std_features.sort();
+
+	let target_arg = options.target_arg(target, cargo_cmd)?;
+	let rustc_version = cargo_cmd.rustc_version().trim();
+	let profile = blob_build_profile.name();
+	let expected = crate::to_hex(&sp_crypto_hashing::blake2_256(
+		format!("{rustc_version} {target_arg} {profile} {std_features:?} {codegen_flags:?}")
+			.as_bytes(),
+	));
+
+	let fingerprint_path = sysroot.join(SYSROOT_FINGERPRINT);
+	rerun_if_changed(&fingerprint_path);
+	let fingerprint = fs::read_to_string(&fingerprint_path).unwrap_or_default();
+	if fingerprint.trim() != expected {
+		return Err(WasmBuilderError::InvalidConfiguration(format!(
+			"The prebuilt sysroot `{}` doesn't match the build of the runtime, `{}` needs to \
+			 contain `{expected}`. It is the fingerprint of `{rustc_version}` for `{target_arg}` \
+			 with the profile `{profile}`, the standard library features {std_features:?} and \
+			 the flags {codegen_flags:?}.",
+			sysroot.display(),
+			fingerprint_path.display(),
+		)))
+	}
+
+	Ok(Some(sysroot))
+}
+
+/// Run the cargo command `build_cmd` for the runtime at `manifest_path`.
+///
+/// The output of cargo is forwarded, prefixed with the name of the runtime.
+fn run_cargo_command(
+	build_cmd: &mut Command,
+	target: RuntimeTarget,
+	manifest_path: &Path,
+	options: &BuildOptions,
+	cargo_cmd: &CargoCommandVersioned,
+) -> Result<(), WasmBuilderError> {
+	if options.verbosity >= Verbosity::Normal {
+		println!(
+			"{}",
+			colorize_info_message("Information that should be included in a bug report.")
+		);
+		println!("{} {:?}", colorize_info_message("Executing build command:"), build_cmd);
+		println!("{} {}", colorize_info_message("Using rustc version:"), cargo_cmd.rustc_version());
+	}
+	if options.verbosity >= Verbosity::Verbose {
+		for (name, value) in build_cmd.get_envs() {
+			let name = name.to_string_lossy();
+			match value {
+				Some(value) => println!(
+					"{} {name}={}",
+					colorize_info_message("Setting environment variable:"),
+					value.to_string_lossy()
+				),
+				None =>
+					println!("{} {name}", colorize_info_message("Removing environment variable:")),
+			}
 		}
 	}
 
-	println!("{}", colorize_info_message("Information that should be included in a bug report."));
-	println!("{} {:?}", colorize_info_message("Executing build command:"), build_cmd);
-	println!("{} {}", colorize_info_message("Using rustc version:"), cargo_cmd.rustc_version());
+	// The compiler already printed the errors, so we only need to report the failure.
+	let crate_name = get_crate_name(manifest_path);
+	let crate_name = crate_name.strip_suffix("-blob").unwrap_or(&crate_name);
+	let prefix = if options.concurrent_builds > 1 {
+		format!("[{crate_name} {}] ", target.rustc_target())
+	} else {
+		format!("[{crate_name}] ")
+	};
+	let program = PathBuf::from(build_cmd.get_program());
+	if run_with_prefixed_output(build_cmd, &prefix).map_err(WasmBuilderError::io(program))? {
+		Ok(())
+	} else {
+		Err(WasmBuilderError::CompilationFailed(target))
+	}
+}
+
+/// Build the project and create the bloaty runtime blob.
+///
+/// Returns the path to the generated bloaty runtime blob.
+fn build_bloaty_blob(
+	target: RuntimeTarget,
+	blob_build_profile: &Profile,
+	project: &Path,
+	manifest: &RuntimeManifest,
+	rustflags: &str,
+	options: &BuildOptions,
+	cargo_cmd: CargoCommandVersioned,
+	#[cfg(feature = "metadata-hash")] metadata_hash: Option<[u8; 32]>,
+) -> Result<PathBuf, WasmBuilderError> {
+	let manifest_path = &manifest.path;
+	let cargo_target_dir = options.cargo_target_dir(project)?;
+	let mut build_cmd = runtime_cargo_command(
+		"rustc",
+		target,
+		blob_build_profile,
+		project,
+		manifest,
+		rustflags,
+		options,
+		&cargo_cmd,
+	)?;
+
+	#[cfg(feature = "metadata-hash")]
+	if let Some(hash) = metadata_hash {
+		build_cmd.env("RUNTIME_METADATA_HASH", array_bytes::bytes2hex("0x", &hash));
+	}
+
+	run_cargo_command(&mut build_cmd, target, manifest_path, options, &cargo_cmd)?;
 
-	// Use `process::exit(1)` to have a clean error output.
-	if !build_cmd.status().map_or(false, |s| s.success()) {
-		process::exit(1);
+	let blob_name = get_blob_name(target, manifest_path);
+	if options.timings_enabled()? {
+		let report = cargo_target_dir.join("cargo-timings/cargo-timing.html");
+		let out = crate::out_dir().join(format!("cargo-timing-{blob_name}.html"));
+		fs::copy(&report, &out).map_err(WasmBuilderError::io(&out))?;
+		if options.verbosity >= Verbosity::Normal {
+			println!("{} {}", colorize_info_message("Timings report:"), out.display());
+		}
 	}
 
-	let blob_name = get_blob_name(target, &manifest_path);
-	let target_directory = project
-		.join("target")
-		.join(target.rustc_target())
+	let target_directory = cargo_target_dir
+		.join(options.target_dir_name(target, &cargo_cmd)?)
 		.join(blob_build_profile.directory());
 	match target {
 		RuntimeTarget::Riscv => {
 			let elf_path = target_directory.join(&blob_name);
-			let elf_metadata = match elf_path.metadata() {
-				Ok(path) => path,
-				Err(error) =>
-					panic!("internal error: couldn't read the metadata of {elf_path:?}: {error}"),
-			};
+			let elf_metadata = elf_path.metadata().map_err(WasmBuilderError::io(&elf_path))?;
 
 			let polkavm_path = target_directory.join(format!("{}.polkavm", blob_name));
 			if polkavm_path
@@ -928,52 +2827,77 @@ fn build_bloaty_blob(
 				.unwrap_or(true)
 			{
 				let blob_bytes =
-					std::fs::read(elf_path).expect("binary always exists after its built");
+					std::fs::read(&elf_path).map_err(WasmBuilderError::io(&elf_path))?;
 
 				let mut config = polkavm_linker::Config::default();
 				config.set_strip(true); // TODO: This shouldn't always be done.
 
-				let program = match polkavm_linker::program_from_elf(config, &blob_bytes) {
-					Ok(program) => program,
-					Err(error) => {
-						println!("Failed to link the runtime blob; this is probably a bug!");
-						println!("Linking error: {error}");
-						process::exit(1);
-					},
-				};
+				let program =
+					polkavm_linker::program_from_elf(config, &blob_bytes).map_err(|error| {
+						WasmBuilderError::PostProcessing(format!(
+							"Failed to link the runtime blob; this is probably a bug!\n\
+							 Linking error: {error}"
+						))
+					})?;
 
 				std::fs::write(&polkavm_path, program.as_bytes())
-					.expect("writing the blob to a file always works");
+					.map_err(WasmBuilderError::io(&polkavm_path))?;
 			}
 
-			polkavm_path
+			Ok(polkavm_path)
 		},
-		RuntimeTarget::Wasm => target_directory.join(format!("{}.wasm", blob_name)),
+		RuntimeTarget::Wasm | RuntimeTarget::Wasm64 =>
+			Ok(target_directory.join(format!("{}.wasm", blob_name))),
 	}
 }
 
+/// Compact the bloaty binary.
+///
+/// The binary is also optimized by `wasm-opt` if an `opt_level` is given. The DWARF debug info is
+/// stripped, unless `keep_dwarf` is set.
 fn compact_wasm(
 	project: &Path,
 	blob_name: &str,
 	bloaty_binary: &WasmBinaryBloaty,
-) -> Option<WasmBinary> {
+	opt_level: Option<OptLevel>,
+	keep_dwarf: bool,
+	verbosity: Verbosity,
+) -> Result<WasmBinary, WasmBuilderError> {
 	let wasm_compact_path = project.join(format!("{blob_name}.compact.wasm"));
 	let start = std::time::Instant::now();
-	wasm_opt::OptimizationOptions::new_opt_level_0()
-		.mvp_features_only()
-		.debug_info(true)
-		.add_pass(wasm_opt::Pass::StripDwarf)
+	let mut wasm_opt_options =
+		opt_level.map_or_else(wasm_opt::OptimizationOptions::new_opt_level_0, OptLevel::options);
+	wasm_opt_options.mvp_features_only().debug_info(true);
+	if !keep_dwarf {
+		wasm_opt_options.add_pass(wasm_opt::Pass::StripDwarf);
+	}
+	wasm_opt_options
 		.run(bloaty_binary.bloaty_path(), &wasm_compact_path)
-		.expect("Failed to compact generated WASM binary.");
-	println!(
-		"{} {}",
-		colorize_info_message("Compacted wasm in"),
-		colorize_info_message(format!("{:?}", start.elapsed()).as_str())
-	);
-	Some(WasmBinary(wasm_compact_path))
+		.map_err(|e| {
+			WasmBuilderError::PostProcessing(format!(
+				"Failed to compact generated WASM binary: {e}"
+			))
+		})?;
+	if verbosity >= Verbosity::Normal {
+		println!(
+			"{} {}",
+			colorize_info_message("Compacted wasm in"),
+			colorize_info_message(format!("{:?}", start.elapsed()).as_str())
+		);
+	}
+	Ok(WasmBinary(wasm_compact_path))
 }
 
-fn try_compress_blob(compact_blob_path: &Path, out_name: &str) -> Option<WasmBinary> {
+/// Compress the compact blob.
+///
+/// If a `blob_size_limit` is given, it replaces the default bomb limit and the build fails if the
+/// blob exceeds it. Otherwise, the blob is left uncompressed when exceeding the bomb limit.
+fn try_compress_blob(
+	compact_blob_path: &Path,
+	out_name: &str,
+	blob_size_limit: Option<usize>,
+	verbosity: Verbosity,
+) -> Result<Option<WasmBinary>, WasmBuilderError> {
 	use sp_maybe_compressed_blob::CODE_BLOB_BOMB_LIMIT;
 
 	let project = compact_blob_path.parent().expect("blob path should have a parent directory");
@@ -981,152 +2905,186 @@ fn try_compress_blob(compact_blob_path: &Path, out_name: &str) -> Option<WasmBin
 		project.join(format!("{}.compact.compressed.wasm", out_name));
 
 	let start = std::time::Instant::now();
-	let data = fs::read(compact_blob_path).expect("Failed to read WASM binary");
-	if let Some(compressed) = sp_maybe_compressed_blob::compress(&data, CODE_BLOB_BOMB_LIMIT) {
+	let data = fs::read(compact_blob_path).map_err(WasmBuilderError::io(compact_blob_path))?;
+	let bomb_limit = blob_size_limit.unwrap_or(CODE_BLOB_BOMB_LIMIT);
+	if let Some(compressed) = sp_maybe_compressed_blob::compress(&data, bomb_limit) {
+		if blob_size_limit.map_or(false, |limit| compressed.len() > limit) {
+			return Err(WasmBuilderError::InvalidRuntimeBlob(format!(
+				"The compressed runtime has {} bytes, which exceeds the blob size limit of {} bytes.",
+				compressed.len(),
+				bomb_limit,
+			)))
+		}
+
 		fs::write(&compact_compressed_blob_path, &compressed[..])
-			.expect("Failed to write WASM binary");
+			.map_err(WasmBuilderError::io(&compact_compressed_blob_path))?;
 
-		println!(
-			"{} {}",
-			colorize_info_message("Compressed blob in"),
-			colorize_info_message(format!("{:?}", start.elapsed()).as_str())
-		);
-		Some(WasmBinary(compact_compressed_blob_path))
+		if verbosity >= Verbosity::Normal {
+			println!(
+				"{} {}",
+				colorize_info_message("Compressed blob in"),
+				colorize_info_message(format!("{:?}", start.elapsed()).as_str())
+			);
+		}
+		Ok(Some(WasmBinary(compact_compressed_blob_path)))
+	} else if blob_size_limit.is_some() {
+		Err(WasmBuilderError::InvalidRuntimeBlob(format!(
+			"The runtime has {} bytes before compression, which exceeds the blob size limit of {} \
+			 bytes.",
+			data.len(),
+			bomb_limit,
+		)))
 	} else {
-		build_helper::warning!(
-			"Writing uncompressed blob. Exceeded maximum size {}",
-			CODE_BLOB_BOMB_LIMIT,
-		);
-		println!("{}", colorize_info_message("Skipping blob compression"));
-		None
-	}
-}
-
-/// Custom wrapper for a [`cargo_metadata::Package`] to store it in
-/// a `HashSet`.
-#[derive(Debug)]
-struct DeduplicatePackage<'a> {
-	package: &'a cargo_metadata::Package,
-	identifier: String,
-}
-
-impl<'a> From<&'a cargo_metadata::Package> for DeduplicatePackage<'a> {
-	fn from(package: &'a cargo_metadata::Package) -> Self {
-		Self {
-			package,
-			identifier: format!("{}{}{:?}", package.name, package.version, package.source),
+		build_helper::warning!("Writing uncompressed blob. Exceeded maximum size {}", bomb_limit);
+		if verbosity >= Verbosity::Normal {
+			println!("{}", colorize_info_message("Skipping blob compression"));
 		}
+		Ok(None)
 	}
 }
 
-impl<'a> Hash for DeduplicatePackage<'a> {
-	fn hash<H: Hasher>(&self, state: &mut H) {
-		self.identifier.hash(state);
-	}
-}
-
-impl<'a> PartialEq for DeduplicatePackage<'a> {
-	fn eq(&self, other: &Self) -> bool {
-		self.identifier == other.identifier
-	}
-}
-
-impl<'a> Eq for DeduplicatePackage<'a> {}
-
-impl<'a> Deref for DeduplicatePackage<'a> {
-	type Target = cargo_metadata::Package;
-
-	fn deref(&self) -> &Self::Target {
-		self.package
-	}
-}
-
-fn create_metadata_command(path: impl Into<PathBuf>) -> MetadataCommand {
+pub(crate) fn create_metadata_command(path: impl Into<PathBuf>) -> MetadataCommand {
 	let mut metadata_command = MetadataCommand::new();
 	metadata_command.manifest_path(path);
+	if let Some(hermetic) = crate::hermetic::get() {
+		metadata_command.cargo_path(&hermetic.cargo);
+	}
 
 	if offline_build() {
 		metadata_command.other_options(vec!["--offline".to_owned()]);
+		metadata_command.env(crate::RUSTUP_AUTO_INSTALL, "0");
 	}
 	metadata_command
 }
 
 /// Generate the `rerun-if-changed` instructions for cargo to make sure that the WASM binary is
 /// rebuilt when needed.
+///
+/// Returns the watched source files, i.e. without the binaries.
 fn generate_rerun_if_changed_instructions(
 	cargo_manifest: &Path,
-	project_folder: &Path,
-	wasm_workspace: &Path,
+	project_manifest: &Path,
+	platform: Option<&str>,
+	cargo_config: Option<&Path>,
+	exclusions: &[String],
+	tracked_env_vars: &[String],
+	verbosity: Verbosity,
 	compressed_or_compact_wasm: Option<&WasmBinary>,
-	bloaty_wasm: &WasmBinaryBloaty,
-) {
+	bloaty_wasm: Option<&WasmBinaryBloaty>,
+) -> Result<Vec<PathBuf>, WasmBuilderError> {
+	let mut sources = Vec::new();
+
+	// A hermetic build is rerun by the build system that declared its inputs.
+	if crate::hermetic::get().is_some() {
+		return Ok(sources)
+	}
+
 	// Rerun `build.rs` if the `Cargo.lock` changes
 	if let Some(cargo_lock) = find_cargo_lock(cargo_manifest) {
-		rerun_if_changed(cargo_lock);
+		watch_for_changes(&cargo_lock, verbosity);
+		sources.push(cargo_lock);
+	}
+
+	// Make sure that if any file/folder of a dependency change, we need to rerun the `build.rs`
+	let package_sources = dependency_sources(
+		cargo_manifest,
+		project_manifest,
+		platform,
+		cargo_config,
+		exclusions,
+		|_| true,
+	)?;
+	package_sources.iter().for_each(|source| watch_for_changes(source, verbosity));
+	sources.extend(package_sources);
+
+	compressed_or_compact_wasm.map(|w| watch_for_changes(w.wasm_binary_path(), verbosity));
+	bloaty_wasm.map(|w| watch_for_changes(w.bloaty_path(), verbosity));
+
+	// Register our env variables
+	for name in tracked_env_vars {
+		rerun_if_env_changed(name);
 	}
 
-	let metadata = create_metadata_command(project_folder.join("Cargo.toml"))
+	Ok(sources)
+}
+
+/// Returns the source files of the packages in the dependency closure of the crate at
+/// `cargo_manifest`, as resolved for the runtime project at `project_manifest`.
+///
+/// Only the packages for which `include_package` returns `true` are walked, see
+/// [`package_sources`] for the files of a package.
+fn dependency_sources(
+	cargo_manifest: &Path,
+	project_manifest: &Path,
+	platform: Option<&str>,
+	cargo_config: Option<&Path>,
+	exclusions: &[String],
+	include_package: impl Fn(&cargo_metadata::Package) -> bool,
+) -> Result<Vec<PathBuf>, WasmBuilderError> {
+	// Only resolve the dependencies for the target platform, so that e.g. dependencies that are
+	// only used by `std` builds are not watched.
+	let mut metadata_command = create_metadata_command(project_manifest);
+	let mut other_options = Vec::new();
+	if offline_build() {
+		other_options.push("--offline".to_owned());
+	}
+	if let Some(platform) = platform {
+		other_options.extend(["--filter-platform".to_owned(), platform.to_owned()]);
+	}
+	if let Some(config) = cargo_config {
+		other_options.extend(["--config".to_owned(), config.display().to_string()]);
+	}
+	let metadata = metadata_command
+		.other_options(other_options)
 		.exec()
-		.expect("`cargo metadata` can not fail!");
+		.map_err(|e| WasmBuilderError::CargoMetadata(e.to_string()))?;
 
 	let package = metadata
 		.packages
 		.iter()
 		.find(|p| p.manifest_path == cargo_manifest)
 		.expect("The crate package is contained in its own metadata; qed");
-
-	// Start with the dependencies of the crate we want to compile for wasm.
-	let mut dependencies = package.dependencies.iter().collect::<Vec<_>>();
-
-	// Collect all packages by follow the dependencies of all packages we find.
-	let mut packages = HashSet::new();
-	packages.insert(DeduplicatePackage::from(package));
-
-	while let Some(dependency) = dependencies.pop() {
-		// Ignore all dev dependencies
-		if dependency.kind == DependencyKind::Development {
+	let resolve = metadata.resolve.as_ref().ok_or_else(|| {
+		WasmBuilderError::CargoMetadata("The metadata doesn't contain the dependency graph".into())
+	})?;
+	let nodes = resolve.nodes.iter().map(|node| (&node.id, node)).collect::<HashMap<_, _>>();
+
+	// Collect the dependency closure of the crate we want to compile for wasm, as resolved by
+	// cargo for the enabled features. Dev dependencies can't affect the wasm binary.
+	let mut closure = HashSet::new();
+	let mut to_visit = vec![&package.id];
+	while let Some(id) = to_visit.pop() {
+		if !closure.insert(id) {
 			continue
 		}
 
-		let path_or_git_dep =
-			dependency.source.as_ref().map(|s| s.starts_with("git+")).unwrap_or(true);
-
-		let package = metadata
-			.packages
-			.iter()
-			.filter(|p| !p.manifest_path.starts_with(wasm_workspace))
-			.find(|p| {
-				// Check that the name matches and that the version matches or this is
-				// a git or path dep. A git or path dependency can only occur once, so we don't
-				// need to check the version.
-				(path_or_git_dep || dependency.req.matches(&p.version)) && dependency.name == p.name
-			});
-
-		if let Some(package) = package {
-			if packages.insert(DeduplicatePackage::from(package)) {
-				dependencies.extend(package.dependencies.iter());
-			}
-		}
+		let Some(node) = nodes.get(id) else { continue };
+		to_visit.extend(
+			node.deps
+				.iter()
+				.filter(|dep| {
+					// The kinds are only reported since Rust 1.41.
+					dep.dep_kinds.is_empty() ||
+						dep.dep_kinds.iter().any(|kind| kind.kind != DependencyKind::Development)
+				})
+				.map(|dep| &dep.pkg),
+		);
 	}
 
-	// Make sure that if any file/folder of a dependency change, we need to rerun the `build.rs`
-	packages.iter().for_each(package_rerun_if_changed);
-
-	compressed_or_compact_wasm.map(|w| rerun_if_changed(w.wasm_binary_path()));
-	rerun_if_changed(bloaty_wasm.bloaty_path());
-
-	// Register our env variables
-	println!("cargo:rerun-if-env-changed={}", crate::SKIP_BUILD_ENV);
-	println!("cargo:rerun-if-env-changed={}", crate::WASM_BUILD_TYPE_ENV);
-	println!("cargo:rerun-if-env-changed={}", crate::WASM_BUILD_RUSTFLAGS_ENV);
-	println!("cargo:rerun-if-env-changed={}", crate::WASM_TARGET_DIRECTORY);
-	println!("cargo:rerun-if-env-changed={}", crate::WASM_BUILD_TOOLCHAIN);
-	println!("cargo:rerun-if-env-changed={}", crate::WASM_BUILD_STD);
-	println!("cargo:rerun-if-env-changed={}", crate::RUNTIME_TARGET);
+	Ok(metadata
+		.packages
+		.iter()
+		.filter(|package| closure.contains(&package.id) && include_package(package))
+		.flat_map(|package| package_sources(package, exclusions))
+		.collect())
 }
 
-/// Track files and paths related to the given package to rerun `build.rs` on any relevant change.
-fn package_rerun_if_changed(package: &DeduplicatePackage) {
+/// Returns the files of the given package that are relevant for the build, i.e. the `.rs` and
+/// `.toml` files.
+///
+/// Paths matching the [`DEFAULT_RERUN_EXCLUSIONS`] or the `exclusions`, relative to the directory
+/// of the package, are skipped.
+fn package_sources(package: &cargo_metadata::Package, exclusions: &[String]) -> Vec<PathBuf> {
 	let mut manifest_path = package.manifest_path.clone();
 	if manifest_path.ends_with("Cargo.toml") {
 		manifest_path.pop();
@@ -1139,36 +3097,154 @@ fn package_rerun_if_changed(package: &DeduplicatePackage) {
 			// `Cargo.toml` related to the current package. This is done to ignore sub-crates of a
 			// crate. If such a sub-crate is a dependency, it will be processed independently
 			// anyway.
-			p.path() == manifest_path || !p.path().is_dir() || !p.path().join("Cargo.toml").exists()
+			let is_sub_crate = p.path() != manifest_path &&
+				p.path().is_dir() &&
+				p.path().join("Cargo.toml").exists();
+
+			let relative = p.path().strip_prefix(&manifest_path).unwrap_or(p.path());
+			let relative = relative.to_string_lossy().replace('\\', "/");
+			let is_excluded = DEFAULT_RERUN_EXCLUSIONS
+				.iter()
+				.copied()
+				.chain(exclusions.iter().map(String::as_str))
+				.any(|pattern| crate::glob_matches(pattern, &relative));
+
+			!is_sub_crate && !is_excluded
 		})
 		.filter_map(|p| p.ok().map(|p| p.into_path()))
 		.filter(|p| p.extension().map(|e| e == "rs" || e == "toml").unwrap_or_default())
-		.for_each(rerun_if_changed);
+		.collect()
+}
+
+/// Rerun `build.rs` if the file at `path` changes.
+///
+/// The path is also printed with the [`Verbosity::Debug`], to find out why a rebuild happened.
+fn watch_for_changes(path: impl AsRef<Path>, verbosity: Verbosity) {
+	if verbosity >= Verbosity::Debug {
+		println!("{} {}", colorize_info_message("Watching for changes:"), path.as_ref().display());
+	}
+	rerun_if_changed(path);
 }
 
 /// Copy the blob binary to the target directory set in `WASM_TARGET_DIRECTORY` environment
 /// variable. If the variable is not set, this is a no-op.
-fn copy_blob_to_target_directory(cargo_manifest: &Path, blob_binary: &WasmBinary) {
+fn copy_blob_to_target_directory(
+	cargo_manifest: &Path,
+	blob_binary: &WasmBinary,
+	verbosity: Verbosity,
+) -> Result<(), WasmBuilderError> {
 	let target_dir = match env::var(crate::WASM_TARGET_DIRECTORY) {
 		Ok(path) => PathBuf::from(path),
-		Err(_) => return,
+		Err(_) => return Ok(()),
 	};
 
 	if !target_dir.is_absolute() {
-		// We use println! + exit instead of a panic in order to have a cleaner output.
+		return Err(WasmBuilderError::InvalidEnvironmentVariable {
+			name: crate::WASM_TARGET_DIRECTORY,
+			value: target_dir.display().to_string(),
+			expected: "an absolute path".into(),
+		})
+	}
+
+	fs::create_dir_all(&target_dir).map_err(WasmBuilderError::io(&target_dir))?;
+
+	let target_path =
+		target_dir.join(format!("{}.wasm", get_blob_name(RuntimeTarget::Wasm, cargo_manifest)));
+	fs::copy(blob_binary.wasm_binary_path(), &target_path)
+		.map_err(WasmBuilderError::io(&target_path))?;
+
+	if verbosity >= Verbosity::Verbose {
 		println!(
-			"Environment variable `{}` with `{}` is not an absolute path!",
-			crate::WASM_TARGET_DIRECTORY,
-			target_dir.display(),
+			"{} {}",
+			colorize_info_message("Copied the wasm binary to:"),
+			target_path.display()
 		);
-		process::exit(1);
 	}
 
-	fs::create_dir_all(&target_dir).expect("Creates `WASM_TARGET_DIRECTORY`.");
+	Ok(())
+}
 
-	fs::copy(
-		blob_binary.wasm_binary_path(),
-		target_dir.join(format!("{}.wasm", get_blob_name(RuntimeTarget::Wasm, cargo_manifest))),
-	)
-	.expect("Copies blob binary to `WASM_TARGET_DIRECTORY`.");
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn encode_compact_len_works() {
+		assert_eq!(encode_compact_len(0), [0x00]);
+		assert_eq!(encode_compact_len(0x3f), [0xfc]);
+		assert_eq!(encode_compact_len(0x40), [0x01, 0x01]);
+		assert_eq!(encode_compact_len(0x3fff), [0xfd, 0xff]);
+		assert_eq!(encode_compact_len(0x4000), [0x02, 0x00, 0x01, 0x00]);
+		assert_eq!(encode_compact_len(0x3fff_ffff), [0xfe, 0xff, 0xff, 0xff]);
+		assert_eq!(encode_compact_len(0x4000_0000), [0x03, 0x00, 0x00, 0x00, 0x40]);
+	}
+
+	#[test]
+	fn run_with_prefixed_output_reports_spawn_errors() {
+		let mut missing = Command::new("wasm-builder-missing-program");
+		assert!(run_with_prefixed_output(&mut missing, "[test] ").is_err());
+
+		let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".into());
+		let mut succeeding = Command::new(&cargo);
+		succeeding.arg("--version");
+		assert!(run_with_prefixed_output(&mut succeeding, "[test] ").unwrap());
+
+		let mut failing = Command::new(&cargo);
+		failing.arg("wasm-builder-missing-subcommand");
+		assert!(!run_with_prefixed_output(&mut failing, "[test] ").unwrap());
+	}
+
+	#[test]
+	fn dependency_sources_only_contains_the_watched_files_of_the_closure() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path().canonicalize().unwrap();
+		let write = |name: &str, content: &str| {
+			let path = root.join(name);
+			fs::create_dir_all(path.parent().unwrap()).unwrap();
+			fs::write(&path, content).unwrap();
+			path
+		};
+		let package = |name: &str, dependencies: &str| {
+			format!(
+				"[package]\nname = \"{name}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n{dependencies}"
+			)
+		};
+
+		write(
+			"Cargo.toml",
+			"[workspace]\nmembers = [\"runtime\", \"dep\", \"dev-dep\", \"unrelated\"]\n",
+		);
+		let runtime_manifest = write(
+			"runtime/Cargo.toml",
+			&package(
+				"runtime",
+				"[dependencies]\ndep = { path = \"../dep\" }\n[dev-dependencies]\ndev-dep = { path = \"../dev-dep\" }\n",
+			),
+		);
+		let runtime_lib = write("runtime/src/lib.rs", "");
+		write("runtime/tests/it.rs", "");
+		write("runtime/src/notes.md", "");
+		write("runtime/src/generated.rs", "");
+		let dep_manifest = write("dep/Cargo.toml", &package("dep", ""));
+		let dep_lib = write("dep/src/lib.rs", "");
+		write("dev-dep/Cargo.toml", &package("dev-dep", ""));
+		write("dev-dep/src/lib.rs", "");
+		write("unrelated/Cargo.toml", &package("unrelated", ""));
+		write("unrelated/src/lib.rs", "");
+
+		let mut sources = dependency_sources(
+			&runtime_manifest,
+			&runtime_manifest,
+			None,
+			None,
+			&["src/generated.rs".into()],
+			|_| true,
+		)
+		.unwrap();
+		sources.sort();
+
+		let mut expected = vec![runtime_manifest, runtime_lib, dep_manifest, dep_lib];
+		expected.sort();
+		assert_eq!(sources, expected);
+	}
 }
@@ -0,0 +1,180 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Watch mode that rebuilds the runtime whenever one of its sources changes.
+
+use crate::{BuildOutput, WasmBuilder, WasmBuilderError};
+
+use std::{
+	collections::BTreeSet,
+	fs,
+	ops::ControlFlow,
+	path::{Path, PathBuf},
+	sync::Mutex,
+	thread,
+	time::{Duration, SystemTime},
+};
+use walkdir::WalkDir;
+
+/// The interval the watched files are polled with by default.
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The paths passed to [`rerun_if_changed`] while a build is running in [`watch`].
+///
+/// Is `None` outside of [`watch`], in which case the paths are printed for cargo.
+static RECORDED_PATHS: Mutex<Option<BTreeSet<PathBuf>>> = Mutex::new(None);
+
+/// All instructions passed to [`rerun_if_changed`] and [`rerun_if_env_changed`] by this process,
+/// to replay them when a build is skipped because nothing changed.
+static INSTRUCTIONS: Mutex<Vec<Rerun>> = Mutex::new(Vec::new());
+
+/// An instruction to rerun the build.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Rerun {
+	/// Rerun the build if the file at the path changes.
+	IfChanged(PathBuf),
+	/// Rerun the build if the environment variable changes.
+	IfEnvChanged(String),
+}
+
+impl Rerun {
+	/// Returns the instructions that were passed since `start` instructions were passed, see
+	/// [`instruction_count`].
+	///
+	/// Builds running concurrently in this process may add their instructions as well, which is
+	/// fine as cargo reruns the whole `build.rs` anyway.
+	pub(crate) fn since(start: usize) -> BTreeSet<Rerun> {
+		INSTRUCTIONS.lock().expect("The lock is never poisoned; qed")[start..]
+			.iter()
+			.cloned()
+			.collect()
+	}
+
+	/// Pass the instruction again.
+	pub(crate) fn replay(&self) {
+		match self {
+			Self::IfChanged(path) => rerun_if_changed(path),
+			Self::IfEnvChanged(name) => rerun_if_env_changed(name),
+		}
+	}
+}
+
+/// Returns the number of instructions that were passed so far, see [`Rerun::since`].
+pub(crate) fn instruction_count() -> usize {
+	INSTRUCTIONS.lock().expect("The lock is never poisoned; qed").len()
+}
+
+/// Rerun the build if the file at `path` changes.
+///
+/// In a `build.rs` this prints the instruction for cargo, in [`watch`] the path is recorded to be
+/// polled for changes.
+pub(crate) fn rerun_if_changed(path: impl AsRef<Path>) {
+	INSTRUCTIONS
+		.lock()
+		.expect("The lock is never poisoned; qed")
+		.push(Rerun::IfChanged(path.as_ref().to_path_buf()));
+	match RECORDED_PATHS.lock().expect("The lock is never poisoned; qed").as_mut() {
+		Some(paths) => {
+			paths.insert(path.as_ref().to_path_buf());
+		},
+		None => build_helper::rerun_if_changed(path),
+	}
+}
+
+/// Rerun the build if the environment variable `name` changes.
+///
+/// In a `build.rs` this prints the instruction for cargo. In [`watch`] nothing is printed, as the
+/// environment of the process doesn't change between the builds.
+pub(crate) fn rerun_if_env_changed(name: &str) {
+	INSTRUCTIONS
+		.lock()
+		.expect("The lock is never poisoned; qed")
+		.push(Rerun::IfEnvChanged(name.to_owned()));
+	if RECORDED_PATHS.lock().expect("The lock is never poisoned; qed").is_none() {
+		println!("cargo:rerun-if-env-changed={name}");
+	}
+}
+
+/// Build the runtime and rebuild it whenever one of its sources changes.
+///
+/// Every build uses a fresh builder returned by `make_builder` and the result is passed to
+/// `on_build`, which returns [`ControlFlow::Break`] to stop watching. The watched files are the
+/// ones a `build.rs` would be rerun for, i.e. the sources of the dependency closure of the runtime,
+/// and they are polled every `poll_interval`. When a build fails before the files are known, the
+/// files of the last successful build are watched, or the sources of the runtime crate itself
+/// for the first build.
+///
+/// As the wasm project, its target directory and the generated files in `OUT_DIR` are kept between
+/// the builds, only the changed crates are recompiled. This avoids the startup cost of going
+/// through the `build.rs` of the node crate for every change of the runtime. Consider enabling
+/// [`WasmBuilder::incremental`] to speed up the rebuilds even further.
+///
+/// The environment needs to look like the one of a `build.rs`, in particular `OUT_DIR` needs to be
+/// set. The `wasm-builder-watch` binary of this crate sets it up for a given runtime. Only one
+/// watch should run at a time per process.
+pub fn watch(
+	mut make_builder: impl FnMut() -> WasmBuilder,
+	poll_interval: Duration,
+	mut on_build: impl FnMut(Result<BuildOutput, WasmBuilderError>) -> ControlFlow<()>,
+) {
+	let mut watched = BTreeSet::new();
+
+	loop {
+		let builder = make_builder();
+		let project_cargo_toml = builder.project_cargo_toml().to_path_buf();
+
+		*RECORDED_PATHS.lock().expect("The lock is never poisoned; qed") = Some(BTreeSet::new());
+		let result = builder.try_build();
+		let recorded = RECORDED_PATHS
+			.lock()
+			.expect("The lock is never poisoned; qed")
+			.take()
+			.unwrap_or_default();
+
+		if !recorded.is_empty() {
+			watched = recorded;
+		} else if watched.is_empty() {
+			watched = project_sources(&project_cargo_toml);
+		}
+
+		if on_build(result).is_break() {
+			return
+		}
+
+		let last_modified = modification_times(&watched);
+		while modification_times(&watched) == last_modified {
+			thread::sleep(poll_interval);
+		}
+	}
+}
+
+/// Returns the `.rs` and `.toml` files in the directory of the project at `project_cargo_toml`.
+fn project_sources(project_cargo_toml: &Path) -> BTreeSet<PathBuf> {
+	let project_dir = project_cargo_toml.parent().unwrap_or(project_cargo_toml);
+
+	WalkDir::new(project_dir)
+		.into_iter()
+		.filter_entry(|e| e.file_name() != "target")
+		.filter_map(|e| e.ok().map(|e| e.into_path()))
+		.filter(|p| p.extension().map_or(false, |e| e == "rs" || e == "toml"))
+		.collect()
+}
+
+/// Returns the modification times of the `paths`, `None` for paths that don't exist.
+fn modification_times(paths: &BTreeSet<PathBuf>) -> Vec<Option<SystemTime>> {
+	paths.iter().map(|p| fs::metadata(p).and_then(|m| m.modified()).ok()).collect()
+}